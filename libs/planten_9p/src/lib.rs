@@ -1,4 +1,5 @@
 pub mod messages;
+pub mod server;
 
 use std::io::{self, Cursor, Read, Write};
 use std::net::TcpStream;
@@ -398,6 +399,12 @@ pub fn decode_string(cursor: &mut Cursor<&[u8]>) -> io::Result<String> {
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8 string"))
 }
 
+pub fn decode_u8(cursor: &mut Cursor<&[u8]>) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    cursor.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
 pub fn decode_u16(cursor: &mut Cursor<&[u8]>) -> io::Result<u16> {
     let mut buf = [0u8; 2];
     cursor.read_exact(&mut buf)?;
@@ -0,0 +1,967 @@
+//! Generic 9P runtime that serves any `FsServer` over TCP, Unix domain
+//! sockets, or any pair of byte streams such as standard input and output.
+//!
+//! Requests on a connection are answered in order, except reads of files
+//! that block (`FsServer::read_wait`): those park on their own thread
+//! without holding the filesystem lock, so the connection keeps serving
+//! other requests and a Tflush can cancel the wait.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use planten_fs_core::perm::{
+    AWRITE, check_wstat, may_create, may_open, may_remove, may_walk, parent_allows,
+};
+use planten_fs_core::{
+    DMEXCL, Flush, FsServer, Inode, OEXCL, ORCLOSE, ORDWR, OTRUNC, OWRITE, ReadWait, Wstat,
+    resolve_child, resolve_step, take_errstr,
+};
+
+use crate::messages::*;
+use crate::{
+    Qid, RawMessage, Stat, build_frame, decode_stat, decode_string, decode_u8, decode_u16,
+    decode_u32, decode_u64, encode_qid_bytes, encode_stat_payload, encode_string,
+};
+
+const MAX_MSG_SIZE: u32 = 131072;
+const VERSION_STRING: &str = "9P2000";
+/// Space reserved for the Rread/Twrite header inside a message.
+const IOHDRSZ: u32 = 24;
+
+/// Smallest msize negotiated: room for the I/O header and one byte.
+pub const MIN_MSIZE: u32 = IOHDRSZ + 1;
+/// Error for requests refused by a read-only server.
+pub const READ_ONLY: &str = "read-only file system";
+
+/// A filesystem every connection shares, and how a request gets at it.
+/// Behind a `Mutex` requests take turns; a server that locks inside,
+/// like RAMFS, implements this to hand out shared references instead, so
+/// connections run in parallel.
+pub trait Shared: Send + Sync + 'static {
+    type Fs<'a>: FsServer
+    where
+        Self: 'a;
+
+    fn lock(&self) -> impl DerefMut<Target = Self::Fs<'_>>;
+}
+
+impl<S: FsServer + Send + 'static> Shared for Mutex<S> {
+    type Fs<'a> = S;
+
+    fn lock(&self) -> impl DerefMut<Target = S> {
+        Mutex::lock(self).unwrap()
+    }
+}
+
+/// How a server treats its clients.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The largest msize offered in Rversion; clients asking for less get
+    /// what they asked for.
+    pub max_msize: u32,
+    /// Refuse every request that would change the tree.
+    pub read_only: bool,
+    /// Connections beyond this many are closed as soon as they arrive.
+    pub max_connections: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_msize: MAX_MSG_SIZE,
+            read_only: false,
+            max_connections: None,
+        }
+    }
+}
+
+/// A stream the runtime can split into a reading and a writing half.
+pub trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        std::os::unix::net::UnixStream::try_clone(self)
+    }
+}
+
+pub fn run_server<L: Shared>(listener: TcpListener, fs: Arc<L>) -> io::Result<()> {
+    serve_all(listener.incoming(), fs, Config::default())
+}
+
+/// Serves every connection `incoming` yields, each on a thread of its
+/// own, so the same loop runs over TCP and Unix domain sockets alike.
+pub fn serve_all<C, L>(
+    incoming: impl Iterator<Item = io::Result<C>>,
+    fs: Arc<L>,
+    config: Config,
+) -> io::Result<()>
+where
+    C: Connection,
+    L: Shared,
+{
+    let config = Arc::new(config);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                let open = active.fetch_add(1, Ordering::SeqCst);
+                if config.max_connections.is_some_and(|max| open >= max) {
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    eprintln!("refusing connection: {} already open", open);
+                    continue;
+                }
+                let fs = Arc::clone(&fs);
+                let config = Arc::clone(&config);
+                let active = Arc::clone(&active);
+                thread::spawn(move || {
+                    if let Err(err) = handle_configured(stream, fs, &config) {
+                        eprintln!("connection error: {}", err);
+                    }
+                    active.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(err) => eprintln!("accept error: {}", err),
+        }
+    }
+    Ok(())
+}
+
+pub fn run_single<L: Shared>(listener: TcpListener, fs: Arc<L>) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    handle_client(stream, fs)
+}
+
+pub fn handle_client<C: Connection, L: Shared>(stream: C, fs: Arc<L>) -> io::Result<()> {
+    handle_configured(stream, fs, &Config::default())
+}
+
+pub fn handle_configured<C: Connection, L: Shared>(
+    stream: C,
+    fs: Arc<L>,
+    config: &Config,
+) -> io::Result<()> {
+    let reader = stream.try_clone()?;
+    serve_configured(reader, stream, fs, config)
+}
+
+/// Serves one client speaking 9P on standard input and output, the way
+/// Plan 9 servers run when their file descriptors are a pipe.
+pub fn run_stdio<L: Shared>(fs: Arc<L>) -> io::Result<()> {
+    serve_io(io::stdin().lock(), io::stdout(), fs)
+}
+
+/// Serves one client reading requests from `reader` and writing replies
+/// to `writer` until `reader` reaches end of file.
+pub fn serve_io<L, R, W>(reader: R, writer: W, fs: Arc<L>) -> io::Result<()>
+where
+    L: Shared,
+    R: Read,
+    W: Write + Send + 'static,
+{
+    serve_configured(reader, writer, fs, &Config::default())
+}
+
+/// `serve_io` under `config`.
+pub fn serve_configured<L, R, W>(
+    mut reader: R,
+    writer: W,
+    fs: Arc<L>,
+    config: &Config,
+) -> io::Result<()>
+where
+    L: Shared,
+    R: Read,
+    W: Write + Send + 'static,
+{
+    let mut session = Session {
+        fs,
+        writer: Arc::new(Mutex::new(Box::new(writer))),
+        fids: HashMap::new(),
+        pending: Arc::new(Mutex::new(HashMap::new())),
+        msize: config.max_msize,
+        max_msize: config.max_msize,
+        read_only: config.read_only,
+    };
+
+    let result = loop {
+        let message = match RawMessage::read_from(&mut reader) {
+            Ok(msg) => msg,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break Ok(()),
+            Err(err) => break Err(err),
+        };
+        if let Err(err) = session.dispatch(message) {
+            break Err(err);
+        }
+    };
+
+    // Nobody is left to answer; release any reads still parked, and clunk
+    // every fid the client still held.
+    for (_, pending) in session.pending.lock().unwrap().drain() {
+        pending.flush.raise();
+    }
+    session.release_all();
+    result
+}
+
+#[derive(Clone)]
+struct Fid {
+    /// Path the fid was walked to. Fids on files with a node id go by
+    /// `node` instead, so they follow renames.
+    path: String,
+    node: Option<u64>,
+    qid: Qid,
+    open_mode: Option<u8>,
+    /// User named in the Tattach this fid descends from.
+    uname: String,
+    /// Attach point; walking `..` never leaves it.
+    root: String,
+    /// Stream offset that the client's offset 0 stands for once open,
+    /// from `FsServer::open_offset`.
+    base: u64,
+    /// Set while this fid holds the one open of a DMEXCL file.
+    exclusive: bool,
+    /// Remove the file when the fid is clunked (ORCLOSE).
+    remove_on_clunk: bool,
+}
+
+impl Fid {
+    /// Another fid on the same file; only the original holds the exclusive
+    /// open and the ORCLOSE removal.
+    fn duplicate(&self) -> Self {
+        Fid {
+            exclusive: false,
+            remove_on_clunk: false,
+            ..self.clone()
+        }
+    }
+}
+
+struct Pending {
+    flush: Arc<Flush>,
+    handle: Option<JoinHandle<()>>,
+}
+
+type Reply = Result<(u8, Vec<u8>), String>;
+type Writer = Mutex<Box<dyn Write + Send>>;
+
+struct Session<L> {
+    fs: Arc<L>,
+    writer: Arc<Writer>,
+    fids: HashMap<u32, Fid>,
+    pending: Arc<Mutex<HashMap<u16, Pending>>>,
+    msize: u32,
+    max_msize: u32,
+    read_only: bool,
+}
+
+impl<L: Shared> Session<L> {
+    fn dispatch(&mut self, message: RawMessage) -> io::Result<()> {
+        let tag = message.tag;
+        let mut cursor = Cursor::new(message.body.as_slice());
+        take_errstr();
+        if self.read_only && modifies(&message) {
+            // Tremove clunks the fid even when it is refused.
+            if message.msg_type == TREMOVE
+                && let Ok(fid) = decode_u32(&mut cursor)
+                && let Some(state) = self.fids.remove(&fid)
+            {
+                release(&mut *self.fs.lock(), state);
+            }
+            return send_reply(&self.writer, tag, Err(READ_ONLY.to_string()));
+        }
+        let reply = match message.msg_type {
+            TVERSION => self.version(&mut cursor)?,
+            TAUTH => Err("authentication not required".to_string()),
+            TATTACH => self.attach(&mut cursor)?,
+            TFLUSH => self.flush(&mut cursor)?,
+            TWALK => self.walk(&mut cursor)?,
+            TCLONE => self.clone_fid(&mut cursor)?,
+            TOPEN => self.open(&mut cursor)?,
+            TCREATE => self.create(&mut cursor)?,
+            TREAD => match self.read(tag, &mut cursor)? {
+                Some(reply) => reply,
+                None => return Ok(()),
+            },
+            TWRITE => self.write(&mut cursor)?,
+            TCLUNK => self.clunk(&mut cursor)?,
+            TREMOVE => self.remove(&mut cursor)?,
+            TSTAT => self.stat(&mut cursor)?,
+            TWSTAT => self.wstat(&mut cursor)?,
+            _ => Err("unsupported message".to_string()),
+        };
+        send_reply(&self.writer, tag, explain(reply))
+    }
+
+    fn version(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let msize = decode_u32(cursor)?;
+        let version = decode_string(cursor)?;
+        self.msize = msize.clamp(MIN_MSIZE, self.max_msize.max(MIN_MSIZE));
+        self.release_all();
+        let version = if version.starts_with(VERSION_STRING) {
+            VERSION_STRING
+        } else {
+            "unknown"
+        };
+        let mut body = Vec::new();
+        body.extend_from_slice(&self.msize.to_le_bytes());
+        body.extend_from_slice(&encode_string(version));
+        Ok(Ok((RVERSION, body)))
+    }
+
+    fn attach(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let _afid = decode_u32(cursor)?;
+        let uname = decode_string(cursor)?;
+        let aname = decode_string(cursor)?;
+        let guard = self.fs.lock();
+        let root = match guard.attach(&aname) {
+            Some(root) => root,
+            None => return Ok(Err(format!("unknown aname: '{}'", aname))),
+        };
+        let inode = match guard.metadata(&root) {
+            Some((inode, _)) => inode,
+            None => return Ok(Err("root missing".to_string())),
+        };
+        let qid = qid_of(&*guard, &root, &inode);
+        let node = guard.node_id(&root);
+        drop(guard);
+        self.fids.insert(
+            fid,
+            Fid {
+                path: root.clone(),
+                node,
+                qid: qid.clone(),
+                open_mode: None,
+                uname,
+                root,
+                base: 0,
+                exclusive: false,
+                remove_on_clunk: false,
+            },
+        );
+        Ok(Ok((RATTACH, encode_qid_bytes(&qid).to_vec())))
+    }
+
+    fn flush(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let oldtag = decode_u16(cursor)?;
+        let pending = self.pending.lock().unwrap().remove(&oldtag);
+        if let Some(pending) = pending {
+            pending.flush.raise();
+            if let Some(handle) = pending.handle {
+                let _ = handle.join();
+            }
+        }
+        Ok(Ok((RFLUSH, Vec::new())))
+    }
+
+    fn walk(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let newfid = decode_u32(cursor)?;
+        let nwname = decode_u16(cursor)?;
+        let mut names = Vec::with_capacity(nwname as usize);
+        for _ in 0..nwname {
+            names.push(decode_string(cursor)?);
+        }
+
+        let base = match self.fids.get(&fid) {
+            Some(state) => state.clone(),
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if newfid != fid && self.fids.contains_key(&newfid) {
+            return Ok(Err("fid in use".to_string()));
+        }
+
+        let guard = self.fs.lock();
+        let mut current = match locate(&*guard, &base) {
+            Ok(path) => path,
+            Err(message) => return Ok(Err(message.to_string())),
+        };
+        let mut qids = Vec::new();
+        let mut denied = false;
+        for name in &names {
+            if may_walk(&*guard, &current, &base.uname).is_err() {
+                denied = true;
+                break;
+            }
+            let next = if name == ".." && current == base.root {
+                Some(current.clone())
+            } else {
+                resolve_step(&current, name)
+            };
+            let next = match next {
+                Some(next) => next,
+                None => break,
+            };
+            match guard.metadata(&next) {
+                Some((inode, _)) => {
+                    qids.push(qid_of(&*guard, &next, &inode));
+                    current = next;
+                }
+                None => break,
+            }
+        }
+        let node = guard.node_id(&current);
+        drop(guard);
+
+        if qids.is_empty() && !names.is_empty() {
+            if denied {
+                return Ok(Err("permission denied".to_string()));
+            }
+            return Ok(Err(format!("file does not exist: '{}'", names[0])));
+        }
+        if qids.len() == names.len() {
+            let qid = qids.last().cloned().unwrap_or(base.qid);
+            self.fids.insert(
+                newfid,
+                Fid {
+                    path: current,
+                    node,
+                    qid,
+                    open_mode: None,
+                    uname: base.uname,
+                    root: base.root,
+                    base: 0,
+                    exclusive: false,
+                    remove_on_clunk: false,
+                },
+            );
+        }
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&(qids.len() as u16).to_le_bytes());
+        for qid in &qids {
+            body.extend_from_slice(&encode_qid_bytes(qid));
+        }
+        Ok(Ok((RWALK, body)))
+    }
+
+    /// Tclone, the walk of no names kept from older 9P, which also copies
+    /// the open mode.
+    fn clone_fid(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let newfid = decode_u32(cursor)?;
+        let state = match self.fids.get(&fid) {
+            Some(state) => state.duplicate(),
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if newfid != fid && self.fids.contains_key(&newfid) {
+            return Ok(Err("fid in use".to_string()));
+        }
+        self.fids.insert(newfid, state);
+        Ok(Ok((RCLONE, Vec::new())))
+    }
+
+    fn open(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let mode = decode_u8(cursor)?;
+        let iounit = self.iounit();
+        let state = match self.fids.get_mut(&fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if state.open_mode.is_some() {
+            return Ok(Err("fid already open".to_string()));
+        }
+
+        let mut guard = self.fs.lock();
+        let path = match locate(&*guard, state) {
+            Ok(path) => path,
+            Err(message) => return Ok(Err(message.to_string())),
+        };
+        let inode = match guard.metadata(&path) {
+            Some((inode, _)) => inode,
+            None => return Ok(Err("file not found".to_string())),
+        };
+        if let Err(message) = may_open(&*guard, &path, &state.uname, mode as u32) {
+            return Ok(Err(message.to_string()));
+        }
+        let exclusive = inode.mode & DMEXCL != 0;
+        if exclusive && !guard.claim_exclusive(&path) {
+            return Ok(Err("exclusive use file already open".to_string()));
+        }
+        let truncate = mode as u32 & OTRUNC != 0 && !inode.is_dir();
+        let failure = if truncate && guard.truncate(&path, &state.uname).is_none() {
+            Some("truncate failed")
+        } else if guard.open(&path).is_none() {
+            Some("open failed")
+        } else {
+            None
+        };
+        if let Some(message) = failure {
+            if exclusive {
+                guard.release_exclusive(&path);
+            }
+            return Ok(Err(message.to_string()));
+        }
+        state.base = guard.open_offset(&path);
+        state.qid = qid_of(&*guard, &path, &inode);
+        drop(guard);
+
+        state.open_mode = Some(mode);
+        state.exclusive = exclusive;
+        state.remove_on_clunk = mode as u32 & ORCLOSE != 0;
+        let mut body = Vec::new();
+        body.extend_from_slice(&encode_qid_bytes(&state.qid));
+        body.extend_from_slice(&iounit.to_le_bytes());
+        Ok(Ok((ROPEN, body)))
+    }
+
+    fn create(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let name = decode_string(cursor)?;
+        let perm = decode_u32(cursor)?;
+        let mode = decode_u8(cursor)?;
+        let iounit = self.iounit();
+        let state = match self.fids.get_mut(&fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if state.open_mode.is_some() {
+            return Ok(Err("fid already open".to_string()));
+        }
+
+        let mut guard = self.fs.lock();
+        let parent = match locate(&*guard, state) {
+            Ok(path) => path,
+            Err(message) => return Ok(Err(message.to_string())),
+        };
+        let new_path = match resolve_child(&parent, &name) {
+            Some(path) => path,
+            None => return Ok(Err("invalid file name".to_string())),
+        };
+        if let Err(message) = may_create(&*guard, &parent, &state.uname) {
+            return Ok(Err(message.to_string()));
+        }
+        if guard.metadata(&new_path).is_some() {
+            return Ok(Err("file exists".to_string()));
+        }
+        let created = guard
+            .create(&parent, &name, perm, mode as u32 | OEXCL, &state.uname)
+            .and_then(|_| guard.metadata(&new_path));
+        let inode = match created {
+            Some((inode, _)) => inode,
+            None => return Ok(Err("create failed".to_string())),
+        };
+        // Nobody else can have the new file open yet.
+        let exclusive = perm & DMEXCL != 0 && guard.claim_exclusive(&new_path);
+        state.base = guard.open_offset(&new_path);
+        state.qid = qid_of(&*guard, &new_path, &inode);
+        state.node = guard.node_id(&new_path);
+        drop(guard);
+
+        state.path = new_path;
+        state.open_mode = Some(mode);
+        state.exclusive = exclusive;
+        state.remove_on_clunk = mode as u32 & ORCLOSE != 0;
+        let mut body = Vec::new();
+        body.extend_from_slice(&encode_qid_bytes(&state.qid));
+        body.extend_from_slice(&iounit.to_le_bytes());
+        Ok(Ok((RCREATE, body)))
+    }
+
+    /// Returns `None` when the read was handed to a parked thread that will
+    /// answer on its own.
+    fn read(&mut self, tag: u16, cursor: &mut Cursor<&[u8]>) -> io::Result<Option<Reply>> {
+        let fid = decode_u32(cursor)?;
+        let offset = decode_u64(cursor)?;
+        let count = decode_u32(cursor)?.min(self.iounit());
+        let state = match self.fids.get(&fid) {
+            Some(state) => state,
+            None => return Ok(Some(Err("unknown fid".to_string()))),
+        };
+        match state.open_mode {
+            Some(mode) if mode_allows_read(mode) => {}
+            Some(_) => return Ok(Some(Err("fid not open for read".to_string()))),
+            None => return Ok(Some(Err("fid not open".to_string()))),
+        }
+
+        let base = state.base;
+        let guard = self.fs.lock();
+        let path = match locate(&*guard, state) {
+            Ok(path) => path,
+            Err(message) => return Ok(Some(Err(message.to_string()))),
+        };
+        let inode = match guard.metadata(&path) {
+            Some((inode, _)) => inode,
+            None => return Ok(Some(Err("file not found".to_string()))),
+        };
+        if inode.is_dir() {
+            let entries = directory_entries(&*guard, &path);
+            return Ok(Some(Ok((RREAD, dir_chunk(&entries, offset, count)))));
+        }
+        let offset = base.saturating_add(offset);
+        match guard.read_wait(&path, offset) {
+            None => Ok(Some(read_reply(guard.read_at(&path, offset, count), count))),
+            Some(wait) => {
+                drop(guard);
+                self.park_read(tag, path, offset, count, wait);
+                Ok(None)
+            }
+        }
+    }
+
+    fn park_read(&mut self, tag: u16, path: String, offset: u64, count: u32, wait: ReadWait) {
+        let flush = Flush::new();
+        self.pending.lock().unwrap().insert(
+            tag,
+            Pending {
+                flush: Arc::clone(&flush),
+                handle: None,
+            },
+        );
+
+        let fs = Arc::clone(&self.fs);
+        let writer = Arc::clone(&self.writer);
+        let pending = Arc::clone(&self.pending);
+        let handle = thread::spawn(move || {
+            take_errstr();
+            let mut wait = wait;
+            let reply = loop {
+                if !wait.wait(&flush) {
+                    return;
+                }
+                let guard = fs.lock();
+                match guard.read_wait(&path, offset) {
+                    Some(next) => wait = next,
+                    None => break read_reply(guard.read_at(&path, offset, count), count),
+                }
+            };
+            // Answer only if Tflush has not claimed the request meanwhile.
+            let mut pending = pending.lock().unwrap();
+            if pending.remove(&tag).is_some() {
+                let _ = send_reply(&writer, tag, explain(reply));
+            }
+        });
+
+        if let Some(entry) = self.pending.lock().unwrap().get_mut(&tag) {
+            entry.handle = Some(handle);
+        }
+    }
+
+    fn write(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let offset = decode_u64(cursor)?;
+        let count = decode_u32(cursor)?;
+        let mut data = vec![0u8; count as usize];
+        cursor.read_exact(&mut data)?;
+        let state = match self.fids.get(&fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        match state.open_mode {
+            Some(mode) if mode_allows_write(mode) => {}
+            Some(_) => return Ok(Err("fid not open for write".to_string())),
+            None => return Ok(Err("fid not open".to_string())),
+        }
+
+        let mut guard = self.fs.lock();
+        let path = match locate(&*guard, state) {
+            Ok(path) => path,
+            Err(message) => return Ok(Err(message.to_string())),
+        };
+        match guard.write(&path, offset, &data, &state.uname) {
+            Some(count) => Ok(Ok((RWRITE, count.to_le_bytes().to_vec()))),
+            None => Ok(Err("write failed".to_string())),
+        }
+    }
+
+    fn clunk(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        match self.fids.remove(&fid) {
+            Some(state) => {
+                release(&mut *self.fs.lock(), state);
+                Ok(Ok((RCLUNK, Vec::new())))
+            }
+            None => Ok(Err("unknown fid".to_string())),
+        }
+    }
+
+    fn remove(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        // Tremove clunks the fid even when the remove itself fails.
+        let mut state = match self.fids.remove(&fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        let mut guard = self.fs.lock();
+        let target = locate(&*guard, &state).and_then(|path| {
+            may_remove(&*guard, &path, &state.uname)?;
+            Ok(path)
+        });
+        // Release the fid while its file is still there to be found; the
+        // remove below stands in for ORCLOSE.
+        if target.is_ok() {
+            state.remove_on_clunk = false;
+        }
+        release(&mut *guard, state);
+        let removed = target.and_then(|path| guard.remove(&path).ok_or("remove failed"));
+        match removed {
+            Ok(()) => Ok(Ok((RREMOVE, Vec::new()))),
+            Err(message) => Ok(Err(message.to_string())),
+        }
+    }
+
+    fn stat(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let state = match self.fids.get(&fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        let guard = self.fs.lock();
+        let path = match locate(&*guard, state) {
+            Ok(path) => path,
+            Err(message) => return Ok(Err(message.to_string())),
+        };
+        match guard.metadata(&path) {
+            Some((inode, length)) => {
+                let stat = stat_of(&*guard, &path, &inode, length);
+                Ok(Ok((RSTAT, encode_stat_payload(&stat))))
+            }
+            None => Ok(Err("file not found".to_string())),
+        }
+    }
+
+    fn wstat(&mut self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let stat = decode_stat(cursor)?;
+        let state = match self.fids.get(&fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+
+        let mut guard = self.fs.lock();
+        let path = match locate(&*guard, state) {
+            Ok(path) => path,
+            Err(message) => return Ok(Err(message.to_string())),
+        };
+        let (old, length) = match guard.metadata(&path) {
+            Some(metadata) => metadata,
+            None => return Ok(Err("file not found".to_string())),
+        };
+        let change = stat_change(&stat);
+        let inode = change.apply(&old);
+        let length_changed = change.new_length(length).is_some();
+        let parent_writable =
+            inode.name == old.name || parent_allows(&*guard, &path, &state.uname, AWRITE);
+        let member = |group: &str| guard.is_member(&path, &state.uname, group);
+        if let Err(message) = check_wstat(
+            &old,
+            &inode,
+            &state.uname,
+            member,
+            length_changed,
+            parent_writable,
+        ) {
+            return Ok(Err(message.to_string()));
+        }
+        match guard.wstat(&path, &change) {
+            Some(()) => Ok(Ok((RWSTAT, Vec::new()))),
+            None => Ok(Err("wstat failed".to_string())),
+        }
+    }
+
+    fn iounit(&self) -> u32 {
+        self.msize - IOHDRSZ
+    }
+
+    /// Clunks every fid, as a new Tversion or a hangup does.
+    fn release_all(&mut self) {
+        if self.fids.is_empty() {
+            return;
+        }
+        let mut guard = self.fs.lock();
+        for (_, state) in self.fids.drain() {
+            release(&mut *guard, state);
+        }
+    }
+}
+
+/// The current path of the file `state` is on. Fids bound to a node
+/// follow renames, and once it is removed they fail, even if a new file
+/// takes its name.
+fn locate<F: FsServer + ?Sized>(fs: &F, state: &Fid) -> Result<String, &'static str> {
+    match state.node {
+        Some(id) => fs.node_path(id).ok_or("file has been removed"),
+        None => Ok(state.path.clone()),
+    }
+}
+
+/// Drops what a fid holds when it goes: its exclusive open and, with
+/// ORCLOSE, the file itself, whose parent's write permission was checked
+/// at open. A file already removed has nothing left to release.
+fn release<F: FsServer + ?Sized>(fs: &mut F, state: Fid) {
+    let Ok(path) = locate(fs, &state) else {
+        return;
+    };
+    if state.exclusive {
+        fs.release_exclusive(&path);
+    }
+    if state.remove_on_clunk {
+        let _ = fs.remove(&path);
+    }
+    fs.clunk(&path);
+}
+
+/// Whether `message` could change the tree, which a read-only server
+/// refuses without looking further.
+fn modifies(message: &RawMessage) -> bool {
+    match message.msg_type {
+        TCREATE | TWRITE | TWSTAT | TREMOVE => true,
+        TOPEN => message.body.get(4).is_some_and(|&mode| {
+            let mode = mode as u32;
+            matches!(mode & 3, OWRITE | ORDWR) || mode & (OTRUNC | ORCLOSE) != 0
+        }),
+        _ => false,
+    }
+}
+
+/// `qid_from_inode`, with the server's node id as the qid path when it
+/// has one.
+fn qid_of<F: FsServer + ?Sized>(fs: &F, path: &str, inode: &Inode) -> Qid {
+    let mut qid = qid_from_inode(path, inode);
+    if let Some(id) = fs.node_id(path) {
+        qid.path = id;
+    }
+    qid
+}
+
+/// `inode_to_stat`, with the qid `qid_of` gives.
+fn stat_of<F: FsServer + ?Sized>(fs: &F, path: &str, inode: &Inode, length: u64) -> Stat {
+    Stat {
+        qid: qid_of(fs, path, inode),
+        ..inode_to_stat(path, inode, length)
+    }
+}
+
+/// `reply` with its error replaced by the server's own, when it left one.
+fn explain(reply: Reply) -> Reply {
+    reply.map_err(|message| take_errstr().unwrap_or(message))
+}
+
+fn send_reply(writer: &Writer, tag: u16, reply: Reply) -> io::Result<()> {
+    let frame = match reply {
+        Ok((msg_type, body)) => build_frame(msg_type, tag, &body),
+        Err(message) => build_frame(RERROR, tag, &encode_string(&message)),
+    };
+    let mut writer = writer.lock().unwrap();
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+fn read_reply(data: Option<Vec<u8>>, count: u32) -> Reply {
+    match data {
+        Some(data) => Ok((RREAD, read_body(&data, 0, count))),
+        None => Err("read failed".to_string()),
+    }
+}
+
+/// Rread body carrying at most `count` bytes of `data` from `offset`.
+pub fn read_body(data: &[u8], offset: u64, count: u32) -> Vec<u8> {
+    let start = (offset as usize).min(data.len());
+    let end = start.saturating_add(count as usize).min(data.len());
+    let mut body = Vec::with_capacity(4 + end - start);
+    body.extend_from_slice(&((end - start) as u32).to_le_bytes());
+    body.extend_from_slice(&data[start..end]);
+    body
+}
+
+fn directory_entries<S: FsServer + ?Sized>(fs: &S, path: &str) -> Vec<Vec<u8>> {
+    let mut names = fs.walk(path).unwrap_or_default();
+    names.sort();
+    names
+        .iter()
+        .filter_map(|name| {
+            let child = resolve_child(path, name)?;
+            let (inode, length) = fs.metadata(&child)?;
+            Some(encode_stat_payload(&stat_of(fs, &child, &inode, length)))
+        })
+        .collect()
+}
+
+/// Directory reads return whole stat entries only; `offset` is the byte
+/// position within the concatenated listing.
+pub fn dir_chunk(entries: &[Vec<u8>], offset: u64, count: u32) -> Vec<u8> {
+    let mut position = 0u64;
+    let mut chunk = Vec::new();
+    for entry in entries {
+        let len = entry.len() as u64;
+        if position >= offset {
+            if chunk.len() + entry.len() > count as usize {
+                break;
+            }
+            chunk.extend_from_slice(entry);
+        }
+        position += len;
+    }
+    let mut body = Vec::with_capacity(4 + chunk.len());
+    body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    body.extend_from_slice(&chunk);
+    body
+}
+
+/// The changes `stat` asks for, leaving out every 9P "don't touch" value.
+pub fn stat_change(stat: &Stat) -> Wstat {
+    let text = |value: &str| (!value.is_empty()).then(|| value.to_string());
+    Wstat {
+        name: text(&stat.name),
+        mode: (stat.mode != !0u32).then_some(stat.mode),
+        atime: (stat.atime != !0u32).then_some(stat.atime),
+        mtime: (stat.mtime != !0u32).then_some(stat.mtime),
+        length: (stat.length != !0u64).then_some(stat.length),
+        uid: text(&stat.uid),
+        gid: text(&stat.gid),
+    }
+}
+
+pub fn qid_from_inode(path: &str, inode: &Inode) -> Qid {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    Qid {
+        // QTDIR, QTAPPEND and QTEXCL mirror the top bits of the mode.
+        qtype: (inode.mode >> 24) as u8 & 0xe0,
+        version: inode.mtime,
+        path: hasher.finish(),
+    }
+}
+
+/// The stat of `inode` at `path`, a file `length` bytes long.
+pub fn inode_to_stat(path: &str, inode: &Inode, length: u64) -> Stat {
+    Stat {
+        type_: 0,
+        dev: 0,
+        qid: qid_from_inode(path, inode),
+        mode: inode.mode,
+        atime: inode.atime,
+        mtime: inode.mtime,
+        length: if inode.is_dir() { 0 } else { length },
+        name: inode.name.clone(),
+        uid: inode.uid.clone(),
+        gid: inode.gid.clone(),
+        muid: inode.muid.clone(),
+    }
+}
+
+pub fn mode_allows_read(mode: u8) -> bool {
+    matches!(mode & 0x3, 0 | 2 | 3)
+}
+
+pub fn mode_allows_write(mode: u8) -> bool {
+    let typ = mode & 0x3;
+    typ == 1 || typ == 2 || (mode & 0x10 != 0)
+}
@@ -0,0 +1,194 @@
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use planten_9p::messages::*;
+use planten_9p::{
+    RawMessage, build_frame, decode_string, encode_attach_body, encode_flush_body,
+    encode_open_body, encode_read_body, encode_stat_body, encode_version_body, encode_walk_body,
+    server,
+};
+use planten_fs_core::{DMDIR, FsServer, Inode, Notifier, ReadWait, Wstat};
+
+/// A single `pipe` file whose reads block until a write reaches `offset`.
+struct PipeFs {
+    data: Vec<u8>,
+    notifier: Arc<Notifier>,
+}
+
+impl FsServer for PipeFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        match path {
+            "/" => Some(vec!["pipe".to_string()]),
+            "/pipe" => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.stat(path).map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        (path == "/pipe").then(|| self.data.clone())
+    }
+
+    fn write(&mut self, path: &str, _offset: u64, data: &[u8], _uname: &str) -> Option<u32> {
+        if path != "/pipe" {
+            return None;
+        }
+        self.data.extend_from_slice(data);
+        self.notifier.notify();
+        Some(data.len() as u32)
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        match path {
+            "/" => Some(Inode::new("/", 0o555 | DMDIR, "pipe", "pipe")),
+            "/pipe" => Some(Inode::new("pipe", 0o666, "pipe", "pipe")),
+            _ => None,
+        }
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        if path == "/pipe" && offset >= self.data.len() as u64 {
+            Some(self.notifier.waiter())
+        } else {
+            None
+        }
+    }
+}
+
+fn start_server() -> (String, Arc<Mutex<PipeFs>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let fs = Arc::new(Mutex::new(PipeFs {
+        data: Vec::new(),
+        notifier: Notifier::new(),
+    }));
+    let server_fs = Arc::clone(&fs);
+    thread::spawn(move || server::run_server(listener, server_fs));
+    (addr, fs)
+}
+
+fn send(stream: &mut TcpStream, msg_type: u8, tag: u16, body: Vec<u8>) {
+    stream
+        .write_all(&build_frame(msg_type, tag, &body))
+        .unwrap();
+}
+
+fn recv(stream: &mut TcpStream) -> RawMessage {
+    RawMessage::read_from(stream).unwrap()
+}
+
+/// Attaches, walks fid 2 to the pipe and opens it for reading.
+fn open_pipe(addr: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    send(
+        &mut stream,
+        TVERSION,
+        0,
+        encode_version_body(8192, "9P2000"),
+    );
+    assert_eq!(recv(&mut stream).msg_type, RVERSION);
+    send(
+        &mut stream,
+        TATTACH,
+        0,
+        encode_attach_body(1, None, "glenda", ""),
+    );
+    assert_eq!(recv(&mut stream).msg_type, RATTACH);
+    send(&mut stream, TWALK, 0, encode_walk_body(1, 2, &["pipe"]));
+    assert_eq!(recv(&mut stream).msg_type, RWALK);
+    send(&mut stream, TOPEN, 0, encode_open_body(2, 0));
+    assert_eq!(recv(&mut stream).msg_type, ROPEN);
+    stream
+}
+
+fn read_payload(message: &RawMessage) -> Vec<u8> {
+    let mut cursor = Cursor::new(message.body.as_slice());
+    let mut len = [0u8; 4];
+    cursor.read_exact(&mut len).unwrap();
+    let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+    cursor.read_exact(&mut payload).unwrap();
+    payload
+}
+
+#[test]
+fn blocked_read_does_not_stall_connection_or_lock() {
+    let (addr, fs) = start_server();
+    let mut stream = open_pipe(&addr);
+
+    send(&mut stream, TREAD, 10, encode_read_body(2, 0, 64));
+    send(&mut stream, TSTAT, 11, encode_stat_body(2));
+    let stat = recv(&mut stream);
+    assert_eq!((stat.msg_type, stat.tag), (RSTAT, 11));
+
+    // The parked read must not hold the filesystem lock.
+    fs.lock()
+        .unwrap()
+        .write("/pipe", 0, b"wake up", "pipe")
+        .unwrap();
+
+    let read = recv(&mut stream);
+    assert_eq!((read.msg_type, read.tag), (RREAD, 10));
+    assert_eq!(read_payload(&read), b"wake up");
+}
+
+#[test]
+fn flush_cancels_blocked_read() {
+    let (addr, fs) = start_server();
+    let mut stream = open_pipe(&addr);
+
+    send(&mut stream, TREAD, 20, encode_read_body(2, 0, 64));
+    thread::sleep(Duration::from_millis(20));
+    send(&mut stream, TFLUSH, 21, encode_flush_body(20));
+    let flush = recv(&mut stream);
+    assert_eq!((flush.msg_type, flush.tag), (RFLUSH, 21));
+
+    // Data arriving after the flush must not resurrect the old request.
+    fs.lock()
+        .unwrap()
+        .write("/pipe", 0, b"late", "pipe")
+        .unwrap();
+    send(&mut stream, TREAD, 22, encode_read_body(2, 0, 64));
+    let read = recv(&mut stream);
+    assert_eq!((read.msg_type, read.tag), (RREAD, 22));
+    assert_eq!(read_payload(&read), b"late");
+}
+
+#[test]
+fn unknown_fid_reports_error() {
+    let (addr, _fs) = start_server();
+    let mut stream = open_pipe(&addr);
+    send(&mut stream, TREAD, 30, encode_read_body(99, 0, 64));
+    let reply = recv(&mut stream);
+    assert_eq!(reply.msg_type, RERROR);
+    let mut cursor = Cursor::new(reply.body.as_slice());
+    assert_eq!(decode_string(&mut cursor).unwrap(), "unknown fid");
+}
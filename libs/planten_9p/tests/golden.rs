@@ -4,10 +4,10 @@ use std::path::PathBuf;
 
 use planten_9p::RawMessage;
 use planten_9p::messages::{
-    RATTACH, RAUTH, RCLONE, RERROR, RFLUSH, ROPEN, RREAD, RREMOVE, RSTAT, RVERSION, RWALK, RWRITE,
-    RWSTAT, TATTACH, TAUTH, TCLONE, TFLUSH, TREAD, TREMOVE, TSTAT, TVERSION, TWALK, TWSTAT,
+    RATTACH, RCLONE, RERROR, RFLUSH, ROPEN, RREAD, RREMOVE, RSTAT, RVERSION, RWALK, RWRITE, RWSTAT,
+    TATTACH, TAUTH, TCLONE, TFLUSH, TREAD, TREMOVE, TSTAT, TVERSION, TWALK, TWSTAT,
 };
-use planten_9p::{decode_qid, decode_stat};
+use planten_9p::{decode_qid, decode_stat, decode_string};
 
 fn read_u16(cursor: &mut Cursor<&[u8]>) -> u16 {
     let mut buf = [0u8; 2];
@@ -71,10 +71,14 @@ fn golden_walk_response_parses() {
     let count = read_u16(&mut cursor);
     assert_eq!(count, 1);
 
+    // Qid paths are RAMFS node ids and versions the capture's mtimes, so
+    // the walk and the open that follows name the same qid.
     let qid = decode_qid(&mut cursor).unwrap();
     assert_eq!(qid.qtype, 0);
-    assert_eq!(qid.version, 0);
-    assert_eq!(qid.path, 10185248811969538662);
+    assert_eq!(qid.path, 2);
+    let open = read_trace_messages("ropen_data_response.bin");
+    let opened = decode_qid(&mut Cursor::new(open[0].body.as_slice())).unwrap();
+    assert_eq!(opened, qid);
 }
 
 #[test]
@@ -87,11 +91,10 @@ fn golden_open_response_parses() {
 
     let mut cursor = Cursor::new(frame.body.as_slice());
     let qid = decode_qid(&mut cursor).unwrap();
-    assert_eq!(qid.qtype, 0);
-    assert_eq!(qid.version, 0);
-    assert_eq!(qid.path, 14924153705535855226);
+    assert_eq!(qid.qtype, 0x80);
+    assert_eq!(qid.path, 1);
     let iounit = read_u32(&mut cursor);
-    assert_eq!(iounit, 0);
+    assert_eq!(iounit, 131_072 - 24);
 }
 
 #[test]
@@ -305,12 +308,13 @@ fn golden_auth_trace_parses() {
     assert_eq!(frame.msg_type, TAUTH);
     assert_eq!(frame.tag, 0x000e);
 
+    // Servers that need no authentication refuse Tauth outright.
     let bytes = fs::read(repo_trace_path("rauth_response.bin")).unwrap();
     let frame = RawMessage::from_bytes(&bytes).unwrap();
-    assert_eq!(frame.msg_type, RAUTH);
+    assert_eq!(frame.msg_type, RERROR);
     assert_eq!(frame.tag, 0x000e);
 
     let mut cursor = Cursor::new(frame.body.as_slice());
-    let aqid = decode_qid(&mut cursor).unwrap();
-    assert_eq!(aqid.qtype, 0);
+    let message = decode_string(&mut cursor).unwrap();
+    assert_eq!(message, "authentication not required");
 }
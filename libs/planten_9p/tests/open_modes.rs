@@ -0,0 +1,165 @@
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use planten_9p::P9Client;
+use planten_9p::server::{self, Shared};
+use planten_fs_core::{DMDIR, FsServer, Inode, OTRUNC, OWRITE, Wstat};
+
+/// One stored file, `data`, that keeps the default `truncate`.
+struct FileFs {
+    data: Vec<u8>,
+}
+
+impl FsServer for FileFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        match path {
+            "/" => Some(vec!["data".to_string()]),
+            "/data" => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.stat(path).map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        (path == "/data").then(|| self.data.clone())
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], _uname: &str) -> Option<u32> {
+        if path != "/data" {
+            return None;
+        }
+        let end = offset as usize + data.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[offset as usize..end].copy_from_slice(data);
+        Some(data.len() as u32)
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        match path {
+            "/" => Some(Inode::new("/", 0o777 | DMDIR, "glenda", "glenda")),
+            "/data" => {
+                let mut inode = Inode::new("data", 0o666, "glenda", "glenda");
+                inode.data = self.data.clone();
+                Some(inode)
+            }
+            _ => None,
+        }
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+}
+
+/// `FileFs` with a `truncate` that empties the file.
+struct TruncatingFs(FileFs);
+
+impl FsServer for TruncatingFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.0.walk(path)
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.0.open(path)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.0.read(path)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.0.write(path, offset, data, uname)
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.0.clunk(path)
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.0.remove(path)
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.0.stat(path)
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.0.wstat(path, change)
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.0.create(parent, name, perm, mode, uname)
+    }
+
+    fn truncate(&mut self, path: &str, _uname: &str) -> Option<()> {
+        (path == "/data").then(|| self.0.data.clear())
+    }
+}
+
+fn connect<L: Shared>(fs: Arc<L>) -> P9Client {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || server::run_server(listener, fs));
+    let mut client = P9Client::new(&addr).unwrap();
+    client.version(8192, "9P2000").unwrap();
+    client.attach(1, None, "glenda", "").unwrap();
+    client
+}
+
+#[test]
+fn otrunc_empties_the_file() {
+    let fs = Arc::new(Mutex::new(TruncatingFs(FileFs {
+        data: b"old contents".to_vec(),
+    })));
+    let mut client = connect(Arc::clone(&fs));
+    client.walk(1, 2, &["data"]).unwrap();
+    client.open(2, (OWRITE | OTRUNC) as u8).unwrap();
+    assert_eq!(client.stat(2).unwrap().length, 0);
+    assert_eq!(client.write(2, 0, b"new").unwrap(), 3);
+    assert_eq!(fs.lock().unwrap().0.data, b"new");
+}
+
+#[test]
+fn otrunc_is_refused_by_servers_that_cannot_truncate() {
+    let fs = Arc::new(Mutex::new(FileFs {
+        data: b"old contents".to_vec(),
+    }));
+    let mut client = connect(Arc::clone(&fs));
+    client.walk(1, 2, &["data"]).unwrap();
+    assert!(client.open(2, (OWRITE | OTRUNC) as u8).is_err());
+    assert_eq!(client.stat(2).unwrap().length, 12);
+    // The refused open leaves the fid usable.
+    client.open(2, OWRITE as u8).unwrap();
+}
@@ -0,0 +1,353 @@
+//! Adapters between `FsServer` and `AsyncFsServer`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use planten_fs_core::{Flush, FsServer, Inode, Notifier, ReadWait, Wstat};
+use tokio::runtime::Handle;
+use tokio::task;
+
+use crate::AsyncFsServer;
+
+/// Serves a blocking `FsServer` from the async runtime. Each call runs on
+/// tokio's blocking pool, so a slow server never stalls the executor, and
+/// reads that `read_wait` park there until data arrives or the request is
+/// flushed.
+pub struct FromSync<S> {
+    inner: Arc<Mutex<S>>,
+}
+
+impl<S> FromSync<S>
+where
+    S: FsServer + Send + 'static,
+{
+    pub fn new(inner: S) -> Self {
+        Self::shared(Arc::new(Mutex::new(inner)))
+    }
+
+    /// Wraps a server that is also served elsewhere, e.g. by the
+    /// threaded runtime in `planten_9p::server`.
+    pub fn shared(inner: Arc<Mutex<S>>) -> Self {
+        FromSync { inner }
+    }
+
+    pub fn inner(&self) -> &Arc<Mutex<S>> {
+        &self.inner
+    }
+
+    async fn call<T, F>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut S) -> Option<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        task::spawn_blocking(move || f(&mut inner.lock().unwrap()))
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+/// Raises a read's flush when its future is dropped, which releases the
+/// blocking thread parked in `ReadWait::wait`.
+struct RaiseOnDrop(Arc<Flush>);
+
+impl Drop for RaiseOnDrop {
+    fn drop(&mut self) {
+        self.0.raise();
+    }
+}
+
+impl<S> AsyncFsServer for FromSync<S>
+where
+    S: FsServer + Send + 'static,
+{
+    async fn walk(&self, path: &str) -> Option<Vec<String>> {
+        let path = path.to_string();
+        self.call(move |fs| fs.walk(&path)).await
+    }
+
+    async fn open(&self, path: &str) -> Option<()> {
+        let path = path.to_string();
+        self.call(move |fs| fs.open(&path)).await
+    }
+
+    async fn read(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        let path = path.to_string();
+        let flush = Flush::new();
+        let _cancel = RaiseOnDrop(Arc::clone(&flush));
+        let inner = Arc::clone(&self.inner);
+        // Relock after every wait so writers can get in between.
+        task::spawn_blocking(move || {
+            loop {
+                let guard = inner.lock().unwrap();
+                match guard.read_wait(&path, offset) {
+                    Some(wait) => {
+                        drop(guard);
+                        if !wait.wait(&flush) {
+                            return None;
+                        }
+                    }
+                    None => return guard.read_at(&path, offset, count),
+                }
+            }
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    async fn write(&self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        let (path, data, uname) = (path.to_string(), data.to_vec(), uname.to_string());
+        self.call(move |fs| fs.write(&path, offset, &data, &uname))
+            .await
+    }
+
+    async fn clunk(&self, path: &str) -> Option<()> {
+        let path = path.to_string();
+        self.call(move |fs| fs.clunk(&path)).await
+    }
+
+    async fn remove(&self, path: &str) -> Option<()> {
+        let path = path.to_string();
+        self.call(move |fs| fs.remove(&path)).await
+    }
+
+    async fn stat(&self, path: &str) -> Option<Inode> {
+        let path = path.to_string();
+        self.call(move |fs| fs.stat(&path)).await
+    }
+
+    async fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        let path = path.to_string();
+        self.call(move |fs| fs.metadata(&path)).await
+    }
+
+    async fn wstat(&self, path: &str, change: &Wstat) -> Option<()> {
+        let path = path.to_string();
+        let change = change.clone();
+        self.call(move |fs| fs.wstat(&path, &change)).await
+    }
+
+    async fn create(
+        &self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        let (parent, name, uname) = (parent.to_string(), name.to_string(), uname.to_string());
+        self.call(move |fs| fs.create(&parent, &name, perm, mode, &uname))
+            .await
+    }
+
+    async fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        let (path, uname, group) = (path.to_string(), uname.to_string(), group.to_string());
+        self.call(move |fs| Some(fs.is_member(&path, &uname, &group)))
+            .await
+            .unwrap_or(false)
+    }
+
+    async fn attach(&self, aname: &str) -> Option<String> {
+        let aname = aname.to_string();
+        self.call(move |fs| fs.attach(&aname)).await
+    }
+
+    async fn truncate(&self, path: &str, uname: &str) -> Option<()> {
+        let (path, uname) = (path.to_string(), uname.to_string());
+        self.call(move |fs| fs.truncate(&path, &uname)).await
+    }
+
+    async fn claim_exclusive(&self, path: &str) -> bool {
+        let path = path.to_string();
+        self.call(move |fs| Some(fs.claim_exclusive(&path)))
+            .await
+            .unwrap_or(false)
+    }
+
+    async fn release_exclusive(&self, path: &str) {
+        let path = path.to_string();
+        self.call(move |fs| {
+            fs.release_exclusive(&path);
+            Some(())
+        })
+        .await;
+    }
+
+    async fn open_offset(&self, path: &str) -> u64 {
+        let path = path.to_string();
+        self.call(move |fs| Some(fs.open_offset(&path)))
+            .await
+            .unwrap_or(0)
+    }
+}
+
+/// Serves an `AsyncFsServer` through the blocking trait by driving each
+/// call to completion on `handle`. Use it from threads outside the tokio
+/// runtime, such as those of `planten_9p::server`. A read that does not
+/// finish at once goes on as a task on `handle`, and `read_wait` parks
+/// that runtime on it outside its filesystem lock.
+pub struct FromAsync<S> {
+    inner: Arc<S>,
+    handle: Handle,
+    reads: Mutex<HashMap<(String, u64), Arc<Read>>>,
+}
+
+/// A read `read_wait` started, kept until `read_at` takes its result.
+/// Reads run whole; `read_at` cuts them to the count asked for.
+struct Read {
+    notifier: Arc<Notifier>,
+    result: Mutex<Option<Option<Vec<u8>>>>,
+}
+
+impl<S: AsyncFsServer + 'static> FromAsync<S> {
+    pub fn new(inner: S, handle: Handle) -> Self {
+        FromAsync {
+            inner: Arc::new(inner),
+            handle,
+            reads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn inner(&self) -> &Arc<S> {
+        &self.inner
+    }
+}
+
+impl<S: AsyncFsServer + 'static> FsServer for FromAsync<S> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.handle.block_on(self.inner.walk(path))
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.handle.block_on(self.inner.open(path))
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.handle.block_on(self.inner.read(path, 0, u32::MAX))
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        let key = (path.to_string(), offset);
+        let mut reads = self.reads.lock().unwrap();
+        let finished = reads
+            .get(&key)
+            .is_some_and(|read| read.result.lock().unwrap().is_some());
+        if finished {
+            let read = reads.remove(&key)?;
+            let mut data = read.result.lock().unwrap().take()??;
+            data.truncate(count as usize);
+            return Some(data);
+        }
+        drop(reads);
+        self.handle.block_on(self.inner.read(path, offset, count))
+    }
+
+    /// Polls the read once; one that is not ready yet carries on as a
+    /// task, and the wait ends when it does. A read flushed meanwhile
+    /// leaves its result for the next read at the same offset.
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        let key = (path.to_string(), offset);
+        let mut reads = self.reads.lock().unwrap();
+        if let Some(read) = reads.get(&key) {
+            // Take the waiter first so a result stored in between wakes it.
+            let wait = read.notifier.waiter();
+            return read.result.lock().unwrap().is_none().then_some(wait);
+        }
+
+        let inner = Arc::clone(&self.inner);
+        let path = path.to_string();
+        let mut future = Box::pin(async move { inner.read(&path, offset, u32::MAX).await });
+        let polled = {
+            let _runtime = self.handle.enter();
+            future
+                .as_mut()
+                .poll(&mut Context::from_waker(Waker::noop()))
+        };
+        let read = Arc::new(Read {
+            notifier: Notifier::new(),
+            result: Mutex::new(None),
+        });
+        reads.insert(key, Arc::clone(&read));
+        match polled {
+            Poll::Ready(result) => {
+                *read.result.lock().unwrap() = Some(result);
+                None
+            }
+            Poll::Pending => {
+                let wait = read.notifier.waiter();
+                self.handle.spawn(async move {
+                    let result = future.await;
+                    *read.result.lock().unwrap() = Some(result);
+                    read.notifier.notify();
+                });
+                Some(wait)
+            }
+        }
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.handle
+            .block_on(self.inner.write(path, offset, data, uname))
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.handle.block_on(self.inner.clunk(path))
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.handle.block_on(self.inner.remove(path))
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.handle.block_on(self.inner.stat(path))
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        self.handle.block_on(self.inner.metadata(path))
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.handle.block_on(self.inner.wstat(path, change))
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.handle
+            .block_on(self.inner.create(parent, name, perm, mode, uname))
+    }
+
+    fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        self.handle
+            .block_on(self.inner.is_member(path, uname, group))
+    }
+
+    fn attach(&self, aname: &str) -> Option<String> {
+        self.handle.block_on(self.inner.attach(aname))
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        self.handle.block_on(self.inner.truncate(path, uname))
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        self.handle.block_on(self.inner.claim_exclusive(path))
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        self.handle.block_on(self.inner.release_exclusive(path))
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        self.handle.block_on(self.inner.open_offset(path))
+    }
+}
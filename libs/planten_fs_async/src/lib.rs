@@ -0,0 +1,93 @@
+//! Async counterpart of `FsServer` for file servers that wait on network or
+//! disk I/O. `server` runs them on tokio without a thread per request, and
+//! `adapt` converts between the two traits in both directions.
+
+use std::future::Future;
+
+use planten_fs_core::{Inode, Wstat};
+
+pub mod adapt;
+pub mod server;
+
+pub use adapt::{FromAsync, FromSync};
+
+/// Mirrors `FsServer`, except that every method takes `&self` (servers keep
+/// their own interior locking, so the runtime can run requests on the same
+/// tree concurrently) and reads are ranged.
+pub trait AsyncFsServer: Send + Sync {
+    fn walk(&self, path: &str) -> impl Future<Output = Option<Vec<String>>> + Send;
+    fn open(&self, path: &str) -> impl Future<Output = Option<()>> + Send;
+    /// Returns at most `count` bytes from `offset`. Files whose reads block
+    /// until something happens simply do not resolve until then; the
+    /// runtime drops the future when a Tflush names the request.
+    fn read(
+        &self,
+        path: &str,
+        offset: u64,
+        count: u32,
+    ) -> impl Future<Output = Option<Vec<u8>>> + Send;
+    /// Writes on behalf of `uname`, which the server records as the muid.
+    fn write(
+        &self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        uname: &str,
+    ) -> impl Future<Output = Option<u32>> + Send;
+    fn clunk(&self, path: &str) -> impl Future<Output = Option<()>> + Send;
+    fn remove(&self, path: &str) -> impl Future<Output = Option<()>> + Send;
+    fn stat(&self, path: &str) -> impl Future<Output = Option<Inode>> + Send;
+    /// The inode without its contents, and the length; see
+    /// `FsServer::metadata`.
+    fn metadata(&self, path: &str) -> impl Future<Output = Option<(Inode, u64)>> + Send {
+        async move {
+            let mut inode = self.stat(path).await?;
+            let length = inode.data.len() as u64;
+            inode.data = Vec::new();
+            Some((inode, length))
+        }
+    }
+    fn wstat(&self, path: &str, change: &Wstat) -> impl Future<Output = Option<()>> + Send;
+    /// Same contract as `FsServer::create`.
+    fn create(
+        &self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> impl Future<Output = Option<()>> + Send;
+    /// Group membership for the permission checks; see `FsServer::is_member`.
+    fn is_member(
+        &self,
+        _path: &str,
+        uname: &str,
+        group: &str,
+    ) -> impl Future<Output = bool> + Send {
+        let member = uname == group;
+        async move { member }
+    }
+    /// Empties the file at `path` for an open with OTRUNC; see
+    /// `FsServer::truncate`. The default refuses.
+    fn truncate(&self, _path: &str, _uname: &str) -> impl Future<Output = Option<()>> + Send {
+        async { None }
+    }
+    /// Claims the one open a DMEXCL file allows; see
+    /// `FsServer::claim_exclusive`.
+    fn claim_exclusive(&self, _path: &str) -> impl Future<Output = bool> + Send {
+        async { true }
+    }
+    /// Gives back a claim `claim_exclusive` granted.
+    fn release_exclusive(&self, _path: &str) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+    /// Stream offset that offset 0 of `path` stands for when opened now;
+    /// see `FsServer::open_offset`.
+    fn open_offset(&self, _path: &str) -> impl Future<Output = u64> + Send {
+        async { 0 }
+    }
+    /// Path a Tattach naming `aname` starts at; see `FsServer::attach`.
+    fn attach(&self, _aname: &str) -> impl Future<Output = Option<String>> + Send {
+        async { Some("/".to_string()) }
+    }
+}
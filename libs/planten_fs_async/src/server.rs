@@ -0,0 +1,700 @@
+//! 9P runtime for `AsyncFsServer`s on tokio.
+//!
+//! Every request except Tversion and Tflush runs as its own task, so a
+//! request waiting on I/O holds neither a thread nor the connection. Replies
+//! leave in completion order; a Tflush aborts the named task and the request
+//! is then never answered.
+
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use planten_9p::messages::*;
+use planten_9p::server::{
+    dir_chunk, inode_to_stat, mode_allows_read, mode_allows_write, qid_from_inode, read_body,
+    stat_change,
+};
+use planten_9p::{
+    Qid, RawMessage, build_frame, decode_stat, decode_string, decode_u8, decode_u16, decode_u32,
+    decode_u64, encode_qid_bytes, encode_stat_payload, encode_string,
+};
+use planten_fs_core::perm::{
+    check_create, check_open, check_remove, check_walk, check_wstat, open_needs_parent_write,
+};
+use planten_fs_core::{DMEXCL, Inode, OEXCL, ORCLOSE, OTRUNC, resolve_child, resolve_step};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+
+use crate::AsyncFsServer;
+
+const MAX_MSG_SIZE: u32 = 131072;
+const VERSION_STRING: &str = "9P2000";
+/// Space reserved for the Rread/Twrite header inside a message.
+const IOHDRSZ: u32 = 24;
+
+pub async fn run_server<S>(listener: TcpListener, fs: Arc<S>) -> io::Result<()>
+where
+    S: AsyncFsServer + 'static,
+{
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let fs = Arc::clone(&fs);
+        tokio::spawn(async move {
+            if let Err(err) = handle_client(stream, fs).await {
+                eprintln!("connection error: {}", err);
+            }
+        });
+    }
+}
+
+pub async fn handle_client<S>(stream: TcpStream, fs: Arc<S>) -> io::Result<()>
+where
+    S: AsyncFsServer + 'static,
+{
+    let (mut reader, mut writer) = stream.into_split();
+    let (out, mut replies) = mpsc::unbounded_channel::<Vec<u8>>();
+    let writer_task = tokio::spawn(async move {
+        while let Some(frame) = replies.recv().await {
+            if writer.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let session = Arc::new(Session {
+        fs,
+        fids: Mutex::new(HashMap::new()),
+        pending: Mutex::new(HashMap::new()),
+        out,
+        msize: AtomicU32::new(MAX_MSG_SIZE),
+    });
+
+    let result = loop {
+        let message = match read_message(&mut reader).await {
+            Ok(message) => message,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break Ok(()),
+            Err(err) => break Err(err),
+        };
+        let tag = message.tag;
+        match message.msg_type {
+            TVERSION => {
+                let reply = session.version(&message.body).await;
+                session.send(tag, reply);
+            }
+            TFLUSH => {
+                let reply = session.flush(&message.body);
+                session.send(tag, reply);
+            }
+            _ => session.spawn(message),
+        }
+    };
+
+    session.abort_all();
+    session.release_all().await;
+    drop(session);
+    let _ = writer_task.await;
+    result
+}
+
+async fn read_message<R>(reader: &mut R) -> io::Result<RawMessage>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let mut size_bytes = [0u8; 4];
+    reader.read_exact(&mut size_bytes).await?;
+    let size = u32::from_le_bytes(size_bytes);
+    if !(7..=MAX_MSG_SIZE).contains(&size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad message size",
+        ));
+    }
+    let mut frame = vec![0u8; size as usize];
+    frame[..4].copy_from_slice(&size_bytes);
+    reader.read_exact(&mut frame[4..]).await?;
+    RawMessage::from_bytes(&frame)
+}
+
+#[derive(Clone)]
+struct Fid {
+    path: String,
+    qid: Qid,
+    open_mode: Option<u8>,
+    uname: String,
+    root: String,
+    /// Stream offset that the client's offset 0 stands for once open,
+    /// from `AsyncFsServer::open_offset`.
+    base: u64,
+    /// Set while this fid holds the one open of a DMEXCL file.
+    exclusive: bool,
+    /// Remove the file when the fid is clunked (ORCLOSE).
+    remove_on_clunk: bool,
+}
+
+type Reply = Result<(u8, Vec<u8>), String>;
+
+struct Session<S> {
+    fs: Arc<S>,
+    fids: Mutex<HashMap<u32, Fid>>,
+    pending: Mutex<HashMap<u16, AbortHandle>>,
+    out: mpsc::UnboundedSender<Vec<u8>>,
+    msize: AtomicU32,
+}
+
+impl<S> Session<S>
+where
+    S: AsyncFsServer + 'static,
+{
+    fn send(&self, tag: u16, reply: Reply) {
+        let frame = match reply {
+            Ok((msg_type, body)) => build_frame(msg_type, tag, &body),
+            Err(message) => build_frame(RERROR, tag, &encode_string(&message)),
+        };
+        let _ = self.out.send(frame);
+    }
+
+    fn spawn(self: &Arc<Self>, message: RawMessage) {
+        let tag = message.tag;
+        // Hold the table while spawning so the task cannot finish before
+        // it is registered.
+        let mut pending = self.pending.lock().unwrap();
+        let session = Arc::clone(self);
+        let task = tokio::spawn(async move {
+            let reply = match session.dispatch(&message).await {
+                Ok(reply) => reply,
+                Err(err) => Err(err.to_string()),
+            };
+            // Answer only if Tflush has not claimed the request meanwhile.
+            let mut pending = session.pending.lock().unwrap();
+            if pending.remove(&tag).is_some() {
+                session.send(tag, reply);
+            }
+        });
+        pending.insert(tag, task.abort_handle());
+    }
+
+    fn abort_all(&self) {
+        for (_, task) in self.pending.lock().unwrap().drain() {
+            task.abort();
+        }
+    }
+
+    async fn dispatch(&self, message: &RawMessage) -> io::Result<Reply> {
+        let mut cursor = Cursor::new(message.body.as_slice());
+        match message.msg_type {
+            TAUTH => Ok(Err("authentication not required".to_string())),
+            TATTACH => self.attach(&mut cursor).await,
+            TWALK => self.walk(&mut cursor).await,
+            TOPEN => self.open(&mut cursor).await,
+            TCREATE => self.create(&mut cursor).await,
+            TREAD => self.read(&mut cursor).await,
+            TWRITE => self.write(&mut cursor).await,
+            TCLUNK => self.clunk(&mut cursor).await,
+            TREMOVE => self.remove(&mut cursor).await,
+            TSTAT => self.stat(&mut cursor).await,
+            TWSTAT => self.wstat(&mut cursor).await,
+            _ => Ok(Err("unsupported message".to_string())),
+        }
+    }
+
+    async fn version(&self, body: &[u8]) -> Reply {
+        let mut cursor = Cursor::new(body);
+        let (msize, version) = match (decode_u32(&mut cursor), decode_string(&mut cursor)) {
+            (Ok(msize), Ok(version)) => (msize, version),
+            _ => return Err("malformed Tversion".to_string()),
+        };
+        let msize = msize.clamp(IOHDRSZ + 1, MAX_MSG_SIZE);
+        self.msize.store(msize, Ordering::SeqCst);
+        self.abort_all();
+        self.release_all().await;
+        let version = if version.starts_with(VERSION_STRING) {
+            VERSION_STRING
+        } else {
+            "unknown"
+        };
+        let mut body = Vec::new();
+        body.extend_from_slice(&msize.to_le_bytes());
+        body.extend_from_slice(&encode_string(version));
+        Ok((RVERSION, body))
+    }
+
+    fn flush(&self, body: &[u8]) -> Reply {
+        let oldtag = decode_u16(&mut Cursor::new(body)).map_err(|err| err.to_string())?;
+        if let Some(task) = self.pending.lock().unwrap().remove(&oldtag) {
+            task.abort();
+        }
+        Ok((RFLUSH, Vec::new()))
+    }
+
+    fn iounit(&self) -> u32 {
+        self.msize.load(Ordering::SeqCst) - IOHDRSZ
+    }
+
+    /// Drops what a fid holds when it goes: its exclusive open and, with
+    /// ORCLOSE, the file itself, whose parent's write permission was checked
+    /// at open.
+    async fn release(&self, state: Fid) {
+        if state.exclusive {
+            self.fs.release_exclusive(&state.path).await;
+        }
+        if state.remove_on_clunk {
+            let _ = self.fs.remove(&state.path).await;
+        }
+        self.fs.clunk(&state.path).await;
+    }
+
+    /// Clunks every fid, as a new Tversion or a hangup does.
+    async fn release_all(&self) {
+        let fids: Vec<Fid> = self
+            .fids
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, state)| state)
+            .collect();
+        for state in fids {
+            self.release(state).await;
+        }
+    }
+
+    fn fid(&self, fid: u32) -> Option<Fid> {
+        self.fids.lock().unwrap().get(&fid).cloned()
+    }
+
+    /// `path`'s inode, without asking the server for the contents.
+    async fn inode(&self, path: &str) -> Option<Inode> {
+        self.fs.metadata(path).await.map(|(inode, _)| inode)
+    }
+
+    /// The directory holding `path`, with its path.
+    async fn parent(&self, path: &str) -> Option<(String, Inode)> {
+        let parent = resolve_step(path, "..")?;
+        let inode = self.inode(&parent).await?;
+        Some((parent, inode))
+    }
+
+    /// Membership of `uname` in `groups` for the file at `path`, looked up
+    /// ahead of the checks in `perm`, which ask as they go.
+    async fn member(
+        &self,
+        path: &str,
+        uname: &str,
+        groups: &[&str],
+    ) -> impl Fn(&str) -> bool + use<S> {
+        let mut member_of = Vec::new();
+        for group in groups {
+            if self.fs.is_member(path, uname, group).await {
+                member_of.push(group.to_string());
+            }
+        }
+        move |group: &str| member_of.iter().any(|member| member == group)
+    }
+
+    async fn attach(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let _afid = decode_u32(cursor)?;
+        let uname = decode_string(cursor)?;
+        let aname = decode_string(cursor)?;
+        let root = match self.fs.attach(&aname).await {
+            Some(root) => root,
+            None => return Ok(Err(format!("unknown aname: '{}'", aname))),
+        };
+        let inode = match self.inode(&root).await {
+            Some(inode) => inode,
+            None => return Ok(Err("root missing".to_string())),
+        };
+        let qid = qid_from_inode(&root, &inode);
+        self.fids.lock().unwrap().insert(
+            fid,
+            Fid {
+                path: root.clone(),
+                qid: qid.clone(),
+                open_mode: None,
+                uname,
+                root,
+                base: 0,
+                exclusive: false,
+                remove_on_clunk: false,
+            },
+        );
+        Ok(Ok((RATTACH, encode_qid_bytes(&qid).to_vec())))
+    }
+
+    async fn walk(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let newfid = decode_u32(cursor)?;
+        let nwname = decode_u16(cursor)?;
+        let mut names = Vec::with_capacity(nwname as usize);
+        for _ in 0..nwname {
+            names.push(decode_string(cursor)?);
+        }
+
+        let base = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if newfid != fid && self.fids.lock().unwrap().contains_key(&newfid) {
+            return Ok(Err("fid in use".to_string()));
+        }
+
+        let mut current = base.path.clone();
+        let mut qids = Vec::new();
+        let mut denied = false;
+        for name in &names {
+            let searchable = match self.inode(&current).await {
+                Some(dir) => {
+                    let member = self.member(&current, &base.uname, &[&dir.gid]).await;
+                    check_walk(&dir, &base.uname, member).is_ok()
+                }
+                None => false,
+            };
+            if !searchable {
+                denied = true;
+                break;
+            }
+            let next = if name == ".." && current == base.root {
+                Some(current.clone())
+            } else {
+                resolve_step(&current, name)
+            };
+            let next = match next {
+                Some(next) => next,
+                None => break,
+            };
+            match self.inode(&next).await {
+                Some(inode) => {
+                    qids.push(qid_from_inode(&next, &inode));
+                    current = next;
+                }
+                None => break,
+            }
+        }
+
+        if qids.is_empty() && !names.is_empty() {
+            if denied {
+                return Ok(Err("permission denied".to_string()));
+            }
+            return Ok(Err(format!("file does not exist: '{}'", names[0])));
+        }
+        if qids.len() == names.len() {
+            let qid = qids.last().cloned().unwrap_or(base.qid);
+            self.fids.lock().unwrap().insert(
+                newfid,
+                Fid {
+                    path: current,
+                    qid,
+                    open_mode: None,
+                    uname: base.uname,
+                    root: base.root,
+                    base: 0,
+                    exclusive: false,
+                    remove_on_clunk: false,
+                },
+            );
+        }
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&(qids.len() as u16).to_le_bytes());
+        for qid in &qids {
+            body.extend_from_slice(&encode_qid_bytes(qid));
+        }
+        Ok(Ok((RWALK, body)))
+    }
+
+    async fn open(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let mode = decode_u8(cursor)?;
+        let state = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if state.open_mode.is_some() {
+            return Ok(Err("fid already open".to_string()));
+        }
+
+        let inode = match self.inode(&state.path).await {
+            Some(inode) => inode,
+            None => return Ok(Err("file not found".to_string())),
+        };
+        let member = self.member(&state.path, &state.uname, &[&inode.gid]).await;
+        if let Err(message) = check_open(&inode, &state.uname, mode as u32, member) {
+            return Ok(Err(message.to_string()));
+        }
+        if open_needs_parent_write(mode as u32) {
+            let allowed = match self.parent(&state.path).await {
+                Some((parent, dir)) => {
+                    let member = self.member(&parent, &state.uname, &[&dir.gid]).await;
+                    check_create(&dir, &state.uname, member)
+                }
+                None => Err("permission denied"),
+            };
+            if let Err(message) = allowed {
+                return Ok(Err(message.to_string()));
+            }
+        }
+        let exclusive = inode.mode & DMEXCL != 0;
+        if exclusive && !self.fs.claim_exclusive(&state.path).await {
+            return Ok(Err("exclusive use file already open".to_string()));
+        }
+        let truncate = mode as u32 & OTRUNC != 0 && !inode.is_dir();
+        let failure = if truncate && self.fs.truncate(&state.path, &state.uname).await.is_none() {
+            Some("truncate failed")
+        } else if self.fs.open(&state.path).await.is_none() {
+            Some("open failed")
+        } else {
+            None
+        };
+        if let Some(message) = failure {
+            if exclusive {
+                self.fs.release_exclusive(&state.path).await;
+            }
+            return Ok(Err(message.to_string()));
+        }
+        let base = self.fs.open_offset(&state.path).await;
+
+        let qid = qid_from_inode(&state.path, &inode);
+        if let Some(state) = self.fids.lock().unwrap().get_mut(&fid) {
+            state.qid = qid.clone();
+            state.open_mode = Some(mode);
+            state.base = base;
+            state.exclusive = exclusive;
+            state.remove_on_clunk = mode as u32 & ORCLOSE != 0;
+        }
+        let mut body = Vec::new();
+        body.extend_from_slice(&encode_qid_bytes(&qid));
+        body.extend_from_slice(&self.iounit().to_le_bytes());
+        Ok(Ok((ROPEN, body)))
+    }
+
+    async fn create(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let name = decode_string(cursor)?;
+        let perm = decode_u32(cursor)?;
+        let mode = decode_u8(cursor)?;
+        let state = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        if state.open_mode.is_some() {
+            return Ok(Err("fid already open".to_string()));
+        }
+        let new_path = match resolve_child(&state.path, &name) {
+            Some(path) => path,
+            None => return Ok(Err("invalid file name".to_string())),
+        };
+
+        let dir = match self.inode(&state.path).await {
+            Some(dir) => dir,
+            None => return Ok(Err("file not found".to_string())),
+        };
+        let member = self.member(&state.path, &state.uname, &[&dir.gid]).await;
+        if let Err(message) = check_create(&dir, &state.uname, member) {
+            return Ok(Err(message.to_string()));
+        }
+        if self.inode(&new_path).await.is_some() {
+            return Ok(Err("file exists".to_string()));
+        }
+        let created = self
+            .fs
+            .create(&state.path, &name, perm, mode as u32 | OEXCL, &state.uname)
+            .await;
+        let inode = match created {
+            Some(()) => self.inode(&new_path).await,
+            None => None,
+        };
+        let inode = match inode {
+            Some(inode) => inode,
+            None => return Ok(Err("create failed".to_string())),
+        };
+        // Nobody else can have the new file open yet.
+        let exclusive = perm & DMEXCL != 0 && self.fs.claim_exclusive(&new_path).await;
+        let base = self.fs.open_offset(&new_path).await;
+
+        let qid = qid_from_inode(&new_path, &inode);
+        if let Some(state) = self.fids.lock().unwrap().get_mut(&fid) {
+            state.qid = qid.clone();
+            state.path = new_path;
+            state.open_mode = Some(mode);
+            state.base = base;
+            state.exclusive = exclusive;
+            state.remove_on_clunk = mode as u32 & ORCLOSE != 0;
+        }
+        let mut body = Vec::new();
+        body.extend_from_slice(&encode_qid_bytes(&qid));
+        body.extend_from_slice(&self.iounit().to_le_bytes());
+        Ok(Ok((RCREATE, body)))
+    }
+
+    async fn read(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let offset = decode_u64(cursor)?;
+        let count = decode_u32(cursor)?.min(self.iounit());
+        let state = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        match state.open_mode {
+            Some(mode) if mode_allows_read(mode) => {}
+            Some(_) => return Ok(Err("fid not open for read".to_string())),
+            None => return Ok(Err("fid not open".to_string())),
+        }
+
+        let inode = match self.inode(&state.path).await {
+            Some(inode) => inode,
+            None => return Ok(Err("file not found".to_string())),
+        };
+        if inode.is_dir() {
+            let entries = self.directory_entries(&state.path).await;
+            return Ok(Ok((RREAD, dir_chunk(&entries, offset, count))));
+        }
+        let offset = state.base.saturating_add(offset);
+        match self.fs.read(&state.path, offset, count).await {
+            Some(data) => Ok(Ok((RREAD, read_body(&data, 0, count)))),
+            None => Ok(Err("read failed".to_string())),
+        }
+    }
+
+    async fn directory_entries(&self, path: &str) -> Vec<Vec<u8>> {
+        let mut names = self.fs.walk(path).await.unwrap_or_default();
+        names.sort();
+        let mut entries = Vec::with_capacity(names.len());
+        for name in &names {
+            let child = match resolve_child(path, name) {
+                Some(child) => child,
+                None => continue,
+            };
+            if let Some((inode, length)) = self.fs.metadata(&child).await {
+                entries.push(encode_stat_payload(&inode_to_stat(&child, &inode, length)));
+            }
+        }
+        entries
+    }
+
+    async fn write(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let offset = decode_u64(cursor)?;
+        let count = decode_u32(cursor)?;
+        let mut data = vec![0u8; count as usize];
+        Read::read_exact(cursor, &mut data)?;
+        let state = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        match state.open_mode {
+            Some(mode) if mode_allows_write(mode) => {}
+            Some(_) => return Ok(Err("fid not open for write".to_string())),
+            None => return Ok(Err("fid not open".to_string())),
+        }
+
+        match self
+            .fs
+            .write(&state.path, offset, &data, &state.uname)
+            .await
+        {
+            Some(count) => Ok(Ok((RWRITE, count.to_le_bytes().to_vec()))),
+            None => Ok(Err("write failed".to_string())),
+        }
+    }
+
+    async fn clunk(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let state = self.fids.lock().unwrap().remove(&fid);
+        match state {
+            Some(state) => {
+                self.release(state).await;
+                Ok(Ok((RCLUNK, Vec::new())))
+            }
+            None => Ok(Err("unknown fid".to_string())),
+        }
+    }
+
+    async fn remove(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        // Tremove clunks the fid even when the remove itself fails.
+        let state = self.fids.lock().unwrap().remove(&fid);
+        let mut state = match state {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        let allowed = match self.parent(&state.path).await {
+            Some((parent, dir)) => {
+                let member = self.member(&parent, &state.uname, &[&dir.gid]).await;
+                check_remove(&dir, &state.uname, member)
+            }
+            None => Err("file not found"),
+        };
+        let path = state.path.clone();
+        // Release the fid while its file is still there to be found; the
+        // remove below stands in for ORCLOSE.
+        if allowed.is_ok() {
+            state.remove_on_clunk = false;
+        }
+        self.release(state).await;
+        let removed = match allowed {
+            Ok(()) => self.fs.remove(&path).await.ok_or("remove failed"),
+            Err(message) => Err(message),
+        };
+        match removed {
+            Ok(()) => Ok(Ok((RREMOVE, Vec::new()))),
+            Err(message) => Ok(Err(message.to_string())),
+        }
+    }
+
+    async fn stat(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let state = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        match self.fs.metadata(&state.path).await {
+            Some((inode, length)) => {
+                let stat = inode_to_stat(&state.path, &inode, length);
+                Ok(Ok((RSTAT, encode_stat_payload(&stat))))
+            }
+            None => Ok(Err("file not found".to_string())),
+        }
+    }
+
+    async fn wstat(&self, cursor: &mut Cursor<&[u8]>) -> io::Result<Reply> {
+        let fid = decode_u32(cursor)?;
+        let stat = decode_stat(cursor)?;
+        let state = match self.fid(fid) {
+            Some(state) => state,
+            None => return Ok(Err("unknown fid".to_string())),
+        };
+        let (old, length) = match self.fs.metadata(&state.path).await {
+            Some(metadata) => metadata,
+            None => return Ok(Err("file not found".to_string())),
+        };
+        let change = stat_change(&stat);
+        let inode = change.apply(&old);
+        let length_changed = change.new_length(length).is_some();
+        let parent_writable = inode.name == old.name
+            || match self.parent(&state.path).await {
+                Some((parent, dir)) => {
+                    let member = self.member(&parent, &state.uname, &[&dir.gid]).await;
+                    check_create(&dir, &state.uname, member).is_ok()
+                }
+                None => false,
+            };
+        let groups = [old.gid.as_str(), inode.gid.as_str()];
+        let member = self.member(&state.path, &state.uname, &groups).await;
+        if let Err(message) = check_wstat(
+            &old,
+            &inode,
+            &state.uname,
+            member,
+            length_changed,
+            parent_writable,
+        ) {
+            return Ok(Err(message.to_string()));
+        }
+        match self.fs.wstat(&state.path, &change).await {
+            Some(()) => Ok(Ok((RWSTAT, Vec::new()))),
+            None => Ok(Err("wstat failed".to_string())),
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::io::{Cursor, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+
+use planten_9p::messages::*;
+use planten_9p::{
+    RawMessage, build_frame, decode_stat, decode_string, encode_attach_body, encode_clunk_body,
+    encode_create_body, encode_flush_body, encode_open_body, encode_read_body, encode_stat_body,
+    encode_version_body, encode_walk_body, encode_write_body,
+};
+use planten_fs_async::server::run_server;
+use planten_fs_async::{AsyncFsServer, FromAsync, FromSync};
+use planten_fs_core::{
+    DMDIR, DMEXCL, Flush, FsServer, Inode, ORCLOSE, OREAD, OTRUNC, OWRITE, Wstat,
+};
+use planten_fs_ramfs::RamFs;
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+
+/// `slow` answers reads only once `gate` is opened; `fast` answers at once.
+struct GateFs {
+    gate: Notify,
+}
+
+impl AsyncFsServer for GateFs {
+    async fn walk(&self, path: &str) -> Option<Vec<String>> {
+        (path == "/").then(|| vec!["fast".to_string(), "slow".to_string()])
+    }
+
+    async fn open(&self, path: &str) -> Option<()> {
+        self.stat(path).await.map(|_| ())
+    }
+
+    async fn read(&self, path: &str, offset: u64, _count: u32) -> Option<Vec<u8>> {
+        let data: &[u8] = match path {
+            "/fast" => b"fast",
+            "/slow" => {
+                self.gate.notified().await;
+                b"slow"
+            }
+            _ => return None,
+        };
+        Some(data.get(offset as usize..).unwrap_or_default().to_vec())
+    }
+
+    async fn write(&self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+        None
+    }
+
+    async fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    async fn remove(&self, _path: &str) -> Option<()> {
+        None
+    }
+
+    async fn stat(&self, path: &str) -> Option<Inode> {
+        match path {
+            "/" => Some(Inode::new("/", 0o555 | DMDIR, "gate", "gate")),
+            "/fast" | "/slow" => Some(Inode::new(&path[1..], 0o444, "gate", "gate")),
+            _ => None,
+        }
+    }
+
+    async fn wstat(&self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    async fn create(
+        &self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+}
+
+fn start<S: AsyncFsServer + 'static>(runtime: &Runtime, fs: Arc<S>, uname: &str) -> TcpStream {
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    runtime.spawn(run_server(listener, fs));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    send(
+        &mut stream,
+        TVERSION,
+        0,
+        encode_version_body(8192, "9P2000"),
+    );
+    assert_eq!(recv(&mut stream).msg_type, RVERSION);
+    send(
+        &mut stream,
+        TATTACH,
+        0,
+        encode_attach_body(1, None, uname, ""),
+    );
+    assert_eq!(recv(&mut stream).msg_type, RATTACH);
+    stream
+}
+
+fn send(stream: &mut TcpStream, msg_type: u8, tag: u16, body: Vec<u8>) {
+    stream
+        .write_all(&build_frame(msg_type, tag, &body))
+        .unwrap();
+}
+
+fn recv(stream: &mut TcpStream) -> RawMessage {
+    RawMessage::read_from(stream).unwrap()
+}
+
+fn call(stream: &mut TcpStream, msg_type: u8, body: Vec<u8>) -> RawMessage {
+    send(stream, msg_type, 0, body);
+    recv(stream)
+}
+
+/// Walks `newfid` to `name` and opens it with `mode`.
+fn open(stream: &mut TcpStream, newfid: u32, name: &str, mode: u8) {
+    let walk = call(stream, TWALK, encode_walk_body(1, newfid, &[name]));
+    assert_eq!(walk.msg_type, RWALK);
+    let open = call(stream, TOPEN, encode_open_body(newfid, mode));
+    assert_eq!(open.msg_type, ROPEN);
+}
+
+fn read_payload(message: &RawMessage) -> Vec<u8> {
+    let mut cursor = Cursor::new(message.body.as_slice());
+    let mut len = [0u8; 4];
+    cursor.read_exact(&mut len).unwrap();
+    let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+    cursor.read_exact(&mut payload).unwrap();
+    payload
+}
+
+#[test]
+fn slow_read_does_not_hold_up_the_connection() {
+    let runtime = Runtime::new().unwrap();
+    let fs = Arc::new(GateFs {
+        gate: Notify::new(),
+    });
+    let mut stream = start(&runtime, Arc::clone(&fs), "glenda");
+    open(&mut stream, 2, "slow", 0);
+    open(&mut stream, 3, "fast", 0);
+
+    send(&mut stream, TREAD, 10, encode_read_body(2, 0, 64));
+    send(&mut stream, TREAD, 11, encode_read_body(3, 0, 64));
+    let fast = recv(&mut stream);
+    assert_eq!((fast.msg_type, fast.tag), (RREAD, 11));
+    assert_eq!(read_payload(&fast), b"fast");
+
+    fs.gate.notify_one();
+    let slow = recv(&mut stream);
+    assert_eq!((slow.msg_type, slow.tag), (RREAD, 10));
+    assert_eq!(read_payload(&slow), b"slow");
+}
+
+#[test]
+fn flush_abandons_a_pending_read() {
+    let runtime = Runtime::new().unwrap();
+    let fs = Arc::new(GateFs {
+        gate: Notify::new(),
+    });
+    let mut stream = start(&runtime, Arc::clone(&fs), "glenda");
+    open(&mut stream, 2, "slow", 0);
+
+    send(&mut stream, TREAD, 20, encode_read_body(2, 0, 64));
+    send(&mut stream, TFLUSH, 21, encode_flush_body(20));
+    let flush = recv(&mut stream);
+    assert_eq!((flush.msg_type, flush.tag), (RFLUSH, 21));
+
+    // The flushed read must never be answered.
+    fs.gate.notify_one();
+    send(&mut stream, TSTAT, 22, encode_stat_body(2));
+    let stat = recv(&mut stream);
+    assert_eq!((stat.msg_type, stat.tag), (RSTAT, 22));
+}
+
+#[test]
+fn blocking_server_runs_on_the_async_runtime() {
+    let runtime = Runtime::new().unwrap();
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+    let fs = Arc::new(FromSync::new(ramfs));
+    let mut stream = start(&runtime, Arc::clone(&fs), "user");
+
+    open(&mut stream, 2, "hello.txt", 2);
+    let write = call(&mut stream, TWRITE, encode_write_body(2, 0, b"J"));
+    assert_eq!(write.msg_type, RWRITE);
+    let read = call(&mut stream, TREAD, encode_read_body(2, 1, 3));
+    assert_eq!(read_payload(&read), b"ell");
+
+    let stat = call(&mut stream, TSTAT, encode_stat_body(2));
+    let stat = decode_stat(&mut Cursor::new(stat.body.as_slice())).unwrap();
+    assert_eq!(stat.muid, "user");
+    assert_eq!(
+        fs.inner().lock().unwrap().read_file("/hello.txt").unwrap(),
+        b"Jello"
+    );
+
+    // Permission checks apply on this runtime as well.
+    let create = call(&mut stream, TCREATE, encode_create_body(1, "x", 0o644, 1));
+    assert_eq!(create.msg_type, RCREATE);
+    let mut other = start(&runtime, fs, "glenda");
+    let walk = call(&mut other, TWALK, encode_walk_body(1, 2, &["hello.txt"]));
+    assert_eq!(walk.msg_type, RWALK);
+    let open = call(&mut other, TOPEN, encode_open_body(2, 1));
+    assert_eq!(open.msg_type, RERROR);
+}
+
+#[test]
+fn open_modes_apply_on_the_async_runtime() {
+    let runtime = Runtime::new().unwrap();
+    let ramfs = RamFs::new();
+    ramfs.create_file("/log", b"old contents");
+    ramfs.create_file("/scratch", b"temp");
+    ramfs
+        .create("/", "lock", 0o666 | DMEXCL, OREAD, "user")
+        .unwrap();
+    let fs = Arc::new(FromSync::new(ramfs));
+    let mut stream = start(&runtime, Arc::clone(&fs), "user");
+
+    open(&mut stream, 2, "log", (OWRITE | OTRUNC) as u8);
+    let stat = call(&mut stream, TSTAT, encode_stat_body(2));
+    let stat = decode_stat(&mut Cursor::new(stat.body.as_slice())).unwrap();
+    assert_eq!(stat.length, 0);
+
+    // One fid at a time may have a DMEXCL file open.
+    open(&mut stream, 3, "lock", OREAD as u8);
+    let walk = call(&mut stream, TWALK, encode_walk_body(1, 4, &["lock"]));
+    assert_eq!(walk.msg_type, RWALK);
+    let refused = call(&mut stream, TOPEN, encode_open_body(4, OREAD as u8));
+    assert_eq!(refused.msg_type, RERROR);
+    let message = decode_string(&mut Cursor::new(refused.body.as_slice())).unwrap();
+    assert_eq!(message, "exclusive use file already open");
+    let clunk = call(&mut stream, TCLUNK, encode_clunk_body(3));
+    assert_eq!(clunk.msg_type, RCLUNK);
+    let reopen = call(&mut stream, TOPEN, encode_open_body(4, OREAD as u8));
+    assert_eq!(reopen.msg_type, ROPEN);
+
+    open(&mut stream, 5, "scratch", (OREAD | ORCLOSE) as u8);
+    assert!(fs.inner().lock().unwrap().read_file("/scratch").is_some());
+    let clunk = call(&mut stream, TCLUNK, encode_clunk_body(5));
+    assert_eq!(clunk.msg_type, RCLUNK);
+    assert!(fs.inner().lock().unwrap().read_file("/scratch").is_none());
+
+    // Hanging up gives back the exclusive open.
+    drop(stream);
+    let mut other = start(&runtime, fs, "user");
+    let mut reopened = false;
+    for _ in 0..100 {
+        let walk = call(&mut other, TWALK, encode_walk_body(1, 2, &["lock"]));
+        assert_eq!(walk.msg_type, RWALK);
+        if call(&mut other, TOPEN, encode_open_body(2, OREAD as u8)).msg_type == ROPEN {
+            reopened = true;
+            break;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(reopened);
+}
+
+#[test]
+fn async_server_serves_the_blocking_trait() {
+    let runtime = Runtime::new().unwrap();
+    let fs = FromAsync::new(
+        GateFs {
+            gate: Notify::new(),
+        },
+        runtime.handle().clone(),
+    );
+    let handle = thread::spawn(move || {
+        assert_eq!(fs.walk("/").unwrap(), vec!["fast", "slow"]);
+        assert_eq!(fs.read("/fast").unwrap(), b"fast");
+        assert!(fs.stat("/slow").is_some());
+        assert!(fs.read("/missing").is_none());
+    });
+    handle.join().unwrap();
+}
+
+#[test]
+fn blocking_reads_of_async_servers_wait_outside_the_call() {
+    let runtime = Runtime::new().unwrap();
+    let fs = FromAsync::new(
+        GateFs {
+            gate: Notify::new(),
+        },
+        runtime.handle().clone(),
+    );
+    let handle = thread::spawn(move || {
+        assert!(fs.read_wait("/fast", 0).is_none());
+        assert_eq!(fs.read_at("/fast", 0, 2).unwrap(), b"fa");
+
+        // The slow read carries on as a task; other calls go ahead
+        // while the caller waits on it.
+        let wait = fs.read_wait("/slow", 0).unwrap();
+        assert_eq!(fs.read_at("/fast", 1, 16).unwrap(), b"ast");
+        fs.inner().gate.notify_one();
+        assert!(wait.wait(&Flush::new()));
+        assert!(fs.read_wait("/slow", 0).is_none());
+        assert_eq!(fs.read_at("/slow", 0, 16).unwrap(), b"slow");
+    });
+    handle.join().unwrap();
+}
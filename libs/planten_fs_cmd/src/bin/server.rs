@@ -0,0 +1,52 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::{run_server, run_stdio};
+use planten_fs_cmd::CmdFs;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5675";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_cmd_server [--listen host:port | --stdio] [--owner user] [--group group] config\n\
+         Serves the files named in config: reads return a command's output and\n\
+         writes are piped into one. Each [file] section sets read, write, ttl,\n\
+         timeout, mode and dir."
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut stdio = false;
+    let mut owner = "user".to_string();
+    let mut group = "group".to_string();
+    let mut config = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--stdio" => stdio = true,
+            "--owner" => owner = args.next().unwrap_or_else(|| usage()),
+            "--group" => group = args.next().unwrap_or_else(|| usage()),
+            _ if arg.starts_with('-') || config.is_some() => usage(),
+            _ => config = Some(arg),
+        }
+    }
+    let config = config.unwrap_or_else(|| usage());
+
+    let fs = CmdFs::open(&config, &owner, &group).unwrap_or_else(|err| {
+        eprintln!("cannot load {}: {}", config, err);
+        process::exit(1);
+    });
+    let fs = Arc::new(Mutex::new(fs));
+    if stdio {
+        return run_stdio(fs);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!("CmdFs 9P server for {} on {}", config, listen);
+    run_server(listener, fs)
+}
@@ -0,0 +1,107 @@
+//! Running a configured command under a timeout.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::FileSpec;
+
+/// How often a running command is checked on.
+const POLL: Duration = Duration::from_millis(5);
+
+/// How long past the timeout the pipes may take to close once the command
+/// has exited or been killed.
+const GRACE: Duration = Duration::from_millis(100);
+
+fn drain(mut pipe: impl Read + Send + 'static) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        let _ = pipe.read_to_end(&mut data);
+        let _ = sender.send(data);
+    });
+    receiver
+}
+
+/// Runs `command` for `spec` with `sh -c`, feeding it `input` if there is
+/// any, and returns what it printed. A command that exits unsuccessfully
+/// fails with the last line of its standard error; one still running at
+/// the timeout is killed, along with anything it started. Anything that
+/// left its process group and holds the pipes open past the timeout is
+/// left behind, and the command fails as timed out.
+pub(crate) fn run(
+    spec: &FileSpec,
+    command: &str,
+    input: Option<&[u8]>,
+    env: &[(&str, &str)],
+) -> io::Result<Vec<u8>> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .env("CMDFS_FILE", &spec.path)
+        .envs(env.iter().copied())
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own process group, so a timeout can kill the lot.
+        .process_group(0);
+    if let Some(dir) = &spec.dir {
+        cmd.current_dir(dir);
+    }
+    let mut child = cmd.spawn()?;
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        let input = input.to_vec();
+        // A command that stops reading early is its own business.
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + spec.timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            // SAFETY: kill only sends a signal; the group is the child's.
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(POLL);
+    };
+    let closed = |pipe: &Receiver<Vec<u8>>| {
+        let left = deadline
+            .saturating_duration_since(Instant::now())
+            .max(GRACE);
+        pipe.recv_timeout(left).ok()
+    };
+    let (status, output, errors) = match (status, closed(&stdout), closed(&stderr)) {
+        (Some(status), Some(output), Some(errors)) => (Some(status), output, errors),
+        _ => (None, Vec::new(), Vec::new()),
+    };
+    match status {
+        None => Err(io::Error::new(
+            ErrorKind::TimedOut,
+            format!("{:?} ran for over {:?}", command, spec.timeout),
+        )),
+        Some(status) if status.success() => Ok(output),
+        Some(status) => {
+            let errors = String::from_utf8_lossy(&errors);
+            let last = errors.lines().rfind(|line| !line.trim().is_empty());
+            Err(io::Error::other(match last {
+                Some(line) => format!("{}: {}", status, line.trim()),
+                None => status.to_string(),
+            }))
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! The config naming each file and the commands behind it.
+//!
+//! ```text
+//! # Comments and blank lines are skipped.
+//! [load]
+//! read = cat /proc/loadavg
+//! ttl = 5
+//!
+//! [svc/log]
+//! write = logger -t planten
+//! ```
+//!
+//! A section is a file, with slashes making directories. Its keys are
+//! `read` and `write`, commands given to `sh -c`, at least one of which
+//! must be set; `ttl`, the seconds a read's output is reused (0 runs the
+//! command on every open); `timeout`, the seconds a command may run
+//! before it is killed; `mode`, the octal permissions, by default read
+//! and write for whichever commands there are; and `dir`, the directory
+//! commands run in.
+
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long a command may run unless its section says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A file and the commands behind it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileSpec {
+    /// Where it appears, like `/svc/log`.
+    pub path: String,
+    /// Run on open; its standard output is the file's contents.
+    pub read: Option<String>,
+    /// Run on every write, with the data written on its standard input.
+    pub write: Option<String>,
+    /// How long a read's output is served before the command runs again.
+    pub ttl: Duration,
+    pub timeout: Duration,
+    pub mode: u32,
+    pub dir: Option<PathBuf>,
+}
+
+impl FileSpec {
+    /// A file at `path` with neither command, the defaults for the rest
+    /// and a mode worked out once the commands are known.
+    pub fn new(path: &str) -> FileSpec {
+        FileSpec {
+            path: normalize(path),
+            read: None,
+            write: None,
+            ttl: Duration::ZERO,
+            timeout: DEFAULT_TIMEOUT,
+            mode: 0,
+            dir: None,
+        }
+    }
+
+    /// Read permission for everyone if there is a read command, write
+    /// permission for the owner and group if there is a write command.
+    pub fn default_mode(&self) -> u32 {
+        let read = if self.read.is_some() { 0o444 } else { 0 };
+        let write = if self.write.is_some() { 0o220 } else { 0 };
+        read | write
+    }
+}
+
+pub(crate) fn normalize(path: &str) -> String {
+    let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+    format!("/{}", parts.join("/"))
+}
+
+fn bad(line: usize, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+fn finish(spec: FileSpec, mode_set: bool, line: usize) -> io::Result<FileSpec> {
+    if spec.read.is_none() && spec.write.is_none() {
+        return Err(bad(
+            line,
+            format!("{} has neither a read nor a write command", spec.path),
+        ));
+    }
+    let mode = if mode_set {
+        spec.mode
+    } else {
+        spec.default_mode()
+    };
+    Ok(FileSpec { mode, ..spec })
+}
+
+/// The files a config describes, in the order given.
+pub fn parse(text: &str) -> io::Result<Vec<FileSpec>> {
+    let mut files = Vec::new();
+    // The section being read, whether it set a mode, and its line.
+    let mut current: Option<(FileSpec, bool, usize)> = None;
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(path) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            if let Some((spec, mode_set, start)) = current.take() {
+                files.push(finish(spec, mode_set, start)?);
+            }
+            let spec = FileSpec::new(path);
+            if spec.path == "/" || path.split('/').any(|part| part == "." || part == "..") {
+                return Err(bad(number, format!("bad file name {:?}", path)));
+            }
+            current = Some((spec, false, number));
+            continue;
+        }
+        let Some((spec, mode_set, _)) = current.as_mut() else {
+            return Err(bad(number, "expected a [file] section first"));
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(bad(number, "expected `key = value`"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let seconds = || {
+            value
+                .parse::<u64>()
+                .map(Duration::from_secs)
+                .map_err(|_| bad(number, format!("{} takes whole seconds", key)))
+        };
+        match key {
+            "read" => spec.read = Some(value.to_string()),
+            "write" => spec.write = Some(value.to_string()),
+            "ttl" => spec.ttl = seconds()?,
+            "timeout" => spec.timeout = seconds()?,
+            "mode" => {
+                spec.mode = u32::from_str_radix(value, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| bad(number, "mode takes octal permissions"))?;
+                *mode_set = true;
+            }
+            "dir" => spec.dir = Some(PathBuf::from(value)),
+            _ => return Err(bad(number, format!("unknown key {:?}", key))),
+        }
+    }
+    if let Some((spec, mode_set, start)) = current {
+        files.push(finish(spec, mode_set, start)?);
+    }
+    Ok(files)
+}
@@ -0,0 +1,457 @@
+//! Files backed by shell commands, for exposing a script without writing
+//! an `FsServer` for it.
+//!
+//! Each file in the config (see [`config`]) may have a read command,
+//! whose standard output is the file's contents, and a write command,
+//! which is run for every write with the data on its standard input.
+//! Opening a file runs its read command unless the last output is still
+//! within its TTL; reads are then served from that output, so a long one
+//! read in pieces stays whole. Stat never runs anything and reports the
+//! length of the last output. Directories are implied by the files'
+//! paths, and nothing can be created or removed.
+//!
+//! Commands see the file's path in `CMDFS_FILE`, and write commands the
+//! writing user in `CMDFS_USER`. Through `FsServer` they run on worker
+//! threads rather than under the runtime's lock: open starts the read
+//! command and reads wait for it in `read_wait`, while writes queue up
+//! per file and run in order, a failed one failing the file's next write.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use planten_fs_core::{DMDIR, Flush, FsServer, Inode, Notifier, ReadWait, Wstat, werrstr};
+
+use config::{FileSpec, normalize};
+
+/// The last output of a read command.
+struct Output {
+    data: Vec<u8>,
+    taken: Instant,
+    mtime: u32,
+}
+
+/// What is known of one file's read command.
+#[derive(Default)]
+struct Slot {
+    output: Option<Output>,
+    /// Set while the command runs on a worker, which notifies when done.
+    running: Option<Arc<Notifier>>,
+    /// Why the last run failed, until the next one starts.
+    error: Option<String>,
+}
+
+/// Writes to one file waiting for its write command.
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<(Vec<u8>, String)>,
+    /// Whether a worker is running `jobs`.
+    draining: bool,
+    /// Why a queued write failed, until a write or `sync` reports it.
+    error: Option<String>,
+}
+
+type Queues = (Mutex<HashMap<String, Queue>>, Condvar);
+
+pub struct CmdFs {
+    files: BTreeMap<String, FileSpec>,
+    dirs: BTreeSet<String>,
+    slots: Arc<Mutex<HashMap<String, Slot>>>,
+    queues: Arc<Queues>,
+    uid: String,
+    gid: String,
+    /// When the server started, the mtime of directories.
+    started: u32,
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(at) => &path[..at],
+    }
+}
+
+fn not_found() -> io::Error {
+    ErrorKind::NotFound.into()
+}
+
+impl CmdFs {
+    /// Serves `files`, owned by `uid` and `gid`. Fails if two files
+    /// share a path or one would have to be a directory.
+    pub fn new(files: Vec<FileSpec>, uid: &str, gid: &str) -> io::Result<CmdFs> {
+        let mut dirs = BTreeSet::from(["/".to_string()]);
+        let mut by_path = BTreeMap::new();
+        for file in files {
+            let mut dir = parent(&file.path);
+            while dirs.insert(dir.to_string()) {
+                dir = parent(dir);
+            }
+            let path = file.path.clone();
+            if by_path.insert(path.clone(), file).is_some() {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} is configured twice", path),
+                ));
+            }
+        }
+        if let Some(path) = by_path.keys().find(|path| dirs.contains(*path)) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} is both a file and a directory", path),
+            ));
+        }
+        Ok(CmdFs {
+            files: by_path,
+            dirs,
+            slots: Arc::new(Mutex::new(HashMap::new())),
+            queues: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+            uid: uid.to_string(),
+            gid: gid.to_string(),
+            started: now(),
+        })
+    }
+
+    /// Serves the files described by the config `text`.
+    pub fn from_config(text: &str, uid: &str, gid: &str) -> io::Result<CmdFs> {
+        CmdFs::new(config::parse(text)?, uid, gid)
+    }
+
+    /// Serves the files described by the config file at `path`.
+    pub fn open(path: impl AsRef<Path>, uid: &str, gid: &str) -> io::Result<CmdFs> {
+        CmdFs::from_config(&fs::read_to_string(path)?, uid, gid)
+    }
+
+    pub fn file(&self, path: &str) -> Option<&FileSpec> {
+        self.files.get(&normalize(path))
+    }
+
+    /// The names in the directory at `path`.
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let path = normalize(path);
+        if !self.dirs.contains(&path) {
+            return Err(if self.files.contains_key(&path) {
+                ErrorKind::NotADirectory.into()
+            } else {
+                not_found()
+            });
+        }
+        let children = self
+            .dirs
+            .iter()
+            .chain(self.files.keys())
+            .filter(|child| *child != "/" && parent(child) == path)
+            .map(|child| child[child.rfind('/').unwrap() + 1..].to_string())
+            .collect::<BTreeSet<_>>();
+        Ok(children.into_iter().collect())
+    }
+
+    fn readable(&self, path: &str) -> io::Result<(&FileSpec, &str)> {
+        let spec = self.file(path).ok_or_else(not_found)?;
+        let command = spec
+            .read
+            .as_deref()
+            .ok_or_else(|| io::Error::new(ErrorKind::PermissionDenied, "file is write-only"))?;
+        Ok((spec, command))
+    }
+
+    /// Runs the read command of `path` and keeps its output.
+    pub fn refresh(&self, path: &str) -> io::Result<Vec<u8>> {
+        let (spec, command) = self.readable(path)?;
+        let data = command::run(spec, command, None, &[]);
+        keep(&self.slots, &spec.path, &data);
+        data
+    }
+
+    /// The output of the read command of `path`: the last one if it is
+    /// within the TTL, otherwise a new one. Waits for one already running.
+    pub fn output(&self, path: &str) -> io::Result<Vec<u8>> {
+        let (spec, _) = self.readable(path)?;
+        self.settle(&spec.path);
+        if let Some(output) = self.slots.lock().unwrap().get(&spec.path)
+            && let Some(output) = &output.output
+            && output.taken.elapsed() < spec.ttl
+        {
+            return Ok(output.data.clone());
+        }
+        self.refresh(path)
+    }
+
+    /// Starts the read command of `path` on a worker unless its last
+    /// output is within the TTL or it is running already.
+    fn start(&self, path: &str) -> io::Result<()> {
+        let (spec, command) = self.readable(path)?;
+        let mut slots = self.slots.lock().unwrap();
+        let slot = slots.entry(spec.path.clone()).or_default();
+        let fresh = slot
+            .output
+            .as_ref()
+            .is_some_and(|output| output.taken.elapsed() < spec.ttl);
+        if fresh || slot.running.is_some() {
+            return Ok(());
+        }
+        slot.running = Some(Notifier::new());
+        slot.error = None;
+        let (spec, command) = (spec.clone(), command.to_string());
+        let slots = Arc::clone(&self.slots);
+        thread::spawn(move || {
+            let data = command::run(&spec, &command, None, &[]);
+            keep(&slots, &spec.path, &data);
+        });
+        Ok(())
+    }
+
+    /// Waits for the read command of `path` if it is running.
+    fn settle(&self, path: &str) {
+        if let Some(wait) = self.read_wait(path, 0) {
+            wait.wait(&Flush::new());
+        }
+    }
+
+    /// Runs the write command of `path` with `data` on its standard
+    /// input, as `uname`, on the calling thread.
+    pub fn try_write(&self, path: &str, data: &[u8], uname: &str) -> io::Result<()> {
+        let (spec, command) = self.writable(path)?;
+        command::run(spec, command, Some(data), &[("CMDFS_USER", uname)]).map(|_| ())
+    }
+
+    fn writable(&self, path: &str) -> io::Result<(&FileSpec, &str)> {
+        let spec = self.file(path).ok_or_else(not_found)?;
+        let command = spec
+            .write
+            .as_deref()
+            .ok_or_else(|| io::Error::new(ErrorKind::PermissionDenied, "file is read-only"))?;
+        Ok((spec, command))
+    }
+
+    /// Queues a write of `data` to `path` as `uname`, to run after those
+    /// queued before it. Fails instead if an earlier one failed.
+    pub fn queue_write(&self, path: &str, data: &[u8], uname: &str) -> io::Result<()> {
+        let (spec, command) = self.writable(path)?;
+        let (queues, _) = &*self.queues;
+        let mut queues = queues.lock().unwrap();
+        let queue = queues.entry(spec.path.clone()).or_default();
+        if let Some(error) = queue.error.take() {
+            return Err(io::Error::other(error));
+        }
+        queue.jobs.push_back((data.to_vec(), uname.to_string()));
+        if queue.draining {
+            return Ok(());
+        }
+        queue.draining = true;
+        let (spec, command) = (spec.clone(), command.to_string());
+        let shared = Arc::clone(&self.queues);
+        thread::spawn(move || drain(&shared, &spec, &command));
+        Ok(())
+    }
+
+    /// Waits until the writes queued to `path` have run, and reports the
+    /// first that failed since the last report.
+    pub fn sync(&self, path: &str) -> io::Result<()> {
+        let (spec, _) = self.writable(path)?;
+        let (queues, done) = &*self.queues;
+        let mut queues = queues.lock().unwrap();
+        while queues.get(&spec.path).is_some_and(|queue| queue.draining) {
+            queues = done.wait(queues).unwrap();
+        }
+        match queues
+            .get_mut(&spec.path)
+            .and_then(|queue| queue.error.take())
+        {
+            Some(error) => Err(io::Error::other(error)),
+            None => Ok(()),
+        }
+    }
+
+    /// The inode at `path` without the command's output, and the length
+    /// of the output last taken.
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        let path = normalize(path);
+        let name = match path.rfind('/') {
+            Some(at) if path.len() > 1 => &path[at + 1..],
+            _ => "/",
+        };
+        let mut inode = Inode::new(name, 0, &self.uid, &self.gid);
+        let mut length = 0;
+        if self.dirs.contains(&path) {
+            inode.mode = DMDIR | 0o555;
+            inode.mtime = self.started;
+        } else {
+            let spec = self.files.get(&path)?;
+            inode.mode = spec.mode;
+            inode.mtime = self.started;
+            if let Some(Slot {
+                output: Some(output),
+                ..
+            }) = self.slots.lock().unwrap().get(&path)
+            {
+                length = output.data.len() as u64;
+                inode.mtime = output.mtime;
+            }
+        }
+        inode.atime = inode.mtime;
+        Some((inode, length))
+    }
+
+    fn inode(&self, path: &str) -> Option<Inode> {
+        let (mut inode, _) = self.metadata(path)?;
+        if let Some(Slot {
+            output: Some(output),
+            ..
+        }) = self.slots.lock().unwrap().get(&normalize(path))
+        {
+            inode.data = output.data.clone();
+        }
+        Some(inode)
+    }
+
+    /// The output of the last run of `path`'s read command, or why it
+    /// failed; `None` if it never ran.
+    fn last(&self, path: &str) -> Option<Result<Vec<u8>, String>> {
+        let path = normalize(path);
+        let slots = self.slots.lock().unwrap();
+        let slot = slots.get(&path)?;
+        if let Some(error) = &slot.error {
+            return Some(Err(error.clone()));
+        }
+        slot.output.as_ref().map(|output| Ok(output.data.clone()))
+    }
+}
+
+/// Records the end of a run of `path`'s read command and wakes readers
+/// waiting for it.
+fn keep(slots: &Mutex<HashMap<String, Slot>>, path: &str, data: &io::Result<Vec<u8>>) {
+    let mut slots = slots.lock().unwrap();
+    let slot = slots.entry(path.to_string()).or_default();
+    match data {
+        Ok(data) => {
+            slot.output = Some(Output {
+                data: data.clone(),
+                taken: Instant::now(),
+                mtime: now(),
+            });
+            slot.error = None;
+        }
+        Err(err) => slot.error = Some(err.to_string()),
+    }
+    if let Some(notifier) = slot.running.take() {
+        notifier.notify();
+    }
+}
+
+/// Runs the writes queued for `spec` until there are none left.
+fn drain(queues: &Queues, spec: &FileSpec, command: &str) {
+    let (queue_map, done) = queues;
+    loop {
+        let job = {
+            let mut queue_map = queue_map.lock().unwrap();
+            let queue = queue_map.entry(spec.path.clone()).or_default();
+            match queue.jobs.pop_front() {
+                Some(job) => job,
+                None => {
+                    queue.draining = false;
+                    done.notify_all();
+                    return;
+                }
+            }
+        };
+        let (data, uname) = job;
+        if let Err(err) = command::run(spec, command, Some(&data), &[("CMDFS_USER", &uname)]) {
+            let mut queue_map = queue_map.lock().unwrap();
+            let queue = queue_map.entry(spec.path.clone()).or_default();
+            queue.error.get_or_insert(err.to_string());
+        }
+    }
+}
+
+impl FsServer for CmdFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    /// Starts the read command; reads wait for it.
+    fn open(&self, path: &str) -> Option<()> {
+        match self.file(path) {
+            Some(spec) if spec.read.is_some() => self.start(path).ok(),
+            Some(_) => Some(()),
+            None => self.dirs.contains(&normalize(path)).then_some(()),
+        }
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.readable(path).ok()?;
+        self.settle(path);
+        match self.last(path) {
+            Some(Ok(data)) => Some(data),
+            Some(Err(error)) => {
+                werrstr(&error);
+                None
+            }
+            None => self.output(path).ok(),
+        }
+    }
+
+    fn read_wait(&self, path: &str, _offset: u64) -> Option<ReadWait> {
+        let slots = self.slots.lock().unwrap();
+        let running = slots.get(&normalize(path))?.running.as_ref()?;
+        Some(running.waiter())
+    }
+
+    /// Queues the write; see `queue_write`.
+    fn write(&mut self, path: &str, _offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        if let Err(err) = self.queue_write(path, data, uname) {
+            werrstr(&err.to_string());
+            return None;
+        }
+        Some(data.len() as u32)
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inode(path)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        CmdFs::metadata(self, path)
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    /// Writes go to a command; there is no stored file to empty.
+    fn truncate(&mut self, _path: &str, _uname: &str) -> Option<()> {
+        Some(())
+    }
+}
+
+mod command;
+pub mod config;
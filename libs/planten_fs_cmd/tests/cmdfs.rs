@@ -0,0 +1,253 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use planten_9p::P9Client;
+use planten_9p::server::run_server;
+use planten_fs_cmd::CmdFs;
+use planten_fs_cmd::config::{DEFAULT_TIMEOUT, parse};
+use planten_fs_core::{DMDIR, FsServer, take_errstr};
+
+fn text(data: Vec<u8>) -> String {
+    String::from_utf8(data).unwrap()
+}
+
+#[test]
+fn configs_describe_files() {
+    let files = parse(
+        "# status files\n\
+         [sys/load]\n\
+         read = echo 0.5\n\
+         ttl = 5\n\
+         \n\
+         [log]\n\
+         write = cat >> log\n\
+         dir = /tmp\n\
+         timeout = 2\n\
+         [knob]\n\
+         read = cat knob\n\
+         write = cat > knob\n\
+         mode = 640\n",
+    )
+    .unwrap();
+    assert_eq!(files.len(), 3);
+    assert_eq!(files[0].path, "/sys/load");
+    assert_eq!(files[0].read.as_deref(), Some("echo 0.5"));
+    assert_eq!(files[0].ttl, Duration::from_secs(5));
+    assert_eq!(files[0].timeout, DEFAULT_TIMEOUT);
+    assert_eq!(files[0].mode, 0o444);
+    assert_eq!(files[1].write.as_deref(), Some("cat >> log"));
+    assert_eq!(files[1].mode, 0o220);
+    assert_eq!(files[1].timeout, Duration::from_secs(2));
+    assert_eq!(files[1].dir.as_deref(), Some("/tmp".as_ref()));
+    assert_eq!(files[2].mode, 0o640);
+
+    for (config, line) in [
+        ("read = true\n", "line 1"),
+        ("[a]\nread = true\nttl = soon\n", "line 3"),
+        ("[a]\nmode = 999\nread = true\n", "line 2"),
+        ("[a]\nshell = rc\n", "line 2"),
+        ("[a]\nread\n", "line 2"),
+        ("[a]\nttl = 1\n[b]\nread = true\n", "line 1"),
+        ("[../a]\nread = true\n", "line 1"),
+        ("[/]\nread = true\n", "line 1"),
+    ] {
+        let err = parse(config).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", config);
+        assert!(err.to_string().starts_with(line), "{}: {}", config, err);
+    }
+
+    let twice = "[a]\nread = true\n[/a/]\nwrite = true\n";
+    assert!(CmdFs::from_config(twice, "bootes", "sys").is_err());
+    let shadowed = "[a]\nread = true\n[a/b]\nread = true\n";
+    assert!(CmdFs::from_config(shadowed, "bootes", "sys").is_err());
+}
+
+#[test]
+fn reads_run_the_command_and_cache_within_the_ttl() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = format!(
+        "[fresh]\n\
+         read = echo run >> runs; wc -l < runs | tr -d ' '\n\
+         dir = {dir}\n\
+         [cached]\n\
+         read = echo run >> cached; wc -l < cached | tr -d ' '\n\
+         ttl = 3600\n\
+         dir = {dir}\n\
+         [sys/name]\n\
+         read = echo $CMDFS_FILE\n",
+        dir = dir.path().display()
+    );
+    let fs = CmdFs::from_config(&config, "bootes", "sys").unwrap();
+
+    assert_eq!(fs.list("/").unwrap(), ["cached", "fresh", "sys"]);
+    assert_eq!(fs.list("/sys").unwrap(), ["name"]);
+    assert_eq!(
+        fs.list("/fresh").unwrap_err().kind(),
+        ErrorKind::NotADirectory
+    );
+    assert_eq!(fs.stat("/sys").unwrap().mode, DMDIR | 0o555);
+    assert!(fs.stat("/missing").is_none());
+
+    // Stat runs nothing; open does, and reads share what it printed.
+    assert_eq!(fs.stat("/fresh").unwrap().data.len(), 0);
+    assert!(!dir.path().join("runs").exists());
+    fs.open("/fresh").unwrap();
+    assert_eq!(text(fs.read("/fresh").unwrap()), "1\n");
+    assert_eq!(text(fs.read("/fresh").unwrap()), "1\n");
+    assert_eq!(fs.stat("/fresh").unwrap().data, b"1\n");
+    fs.open("/fresh").unwrap();
+    assert_eq!(text(fs.read("/fresh").unwrap()), "2\n");
+
+    fs.open("/cached").unwrap();
+    fs.open("/cached").unwrap();
+    assert_eq!(text(fs.output("/cached").unwrap()), "1\n");
+    assert_eq!(text(fs.refresh("/cached").unwrap()), "2\n");
+    assert_eq!(text(fs.read("/sys/name").unwrap()), "/sys/name\n");
+}
+
+#[test]
+fn writes_pipe_into_the_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = format!(
+        "[log]\n\
+         write = (echo \"$CMDFS_USER\"; cat) >> log\n\
+         dir = {}\n\
+         [status]\n\
+         read = echo ok\n",
+        dir.path().display()
+    );
+    let mut fs = CmdFs::from_config(&config, "bootes", "sys").unwrap();
+    assert_eq!(fs.write("/log", 0, b"first\n", "glenda"), Some(6));
+    assert_eq!(fs.write("/log", 99, b"second\n", "bootes"), Some(7));
+    fs.sync("/log").unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("log")).unwrap(),
+        "glenda\nfirst\nbootes\nsecond\n"
+    );
+    assert!(fs.read("/log").is_none());
+    assert!(fs.write("/status", 0, b"x", "glenda").is_none());
+    assert!(fs.create("/", "new", 0o644, 1, "glenda").is_none());
+    assert!(fs.remove("/status").is_none());
+}
+
+#[test]
+fn failures_and_timeouts_are_errors() {
+    let config = "[broken]\n\
+                  read = echo partial; echo 'no such device' >&2; exit 3\n\
+                  [slow]\n\
+                  read = sleep 30; echo late\n\
+                  timeout = 1\n\
+                  [refuse]\n\
+                  write = cat > /dev/null; exit 1\n";
+    let mut fs = CmdFs::from_config(config, "bootes", "sys").unwrap();
+    let err = fs.output("/broken").unwrap_err();
+    assert!(err.to_string().ends_with("no such device"), "{}", err);
+    // Open only starts the command; the read reports how it failed.
+    fs.open("/broken").unwrap();
+    assert!(fs.read("/broken").is_none());
+    assert!(take_errstr().unwrap().ends_with("no such device"));
+
+    let start = Instant::now();
+    assert_eq!(fs.output("/slow").unwrap_err().kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(fs.try_write("/refuse", b"data", "glenda").is_err());
+
+    // Queued writes report failures later, once.
+    assert_eq!(fs.write("/refuse", 0, b"data", "glenda"), Some(4));
+    assert!(fs.sync("/refuse").is_err());
+    assert!(fs.sync("/refuse").is_ok());
+
+    // A command that leaves something holding its pipes still times out.
+    let config = "[daemon]\n\
+                  read = setsid sleep 5 & echo started\n\
+                  timeout = 1\n";
+    let fs = CmdFs::from_config(config, "bootes", "sys").unwrap();
+    let start = Instant::now();
+    assert_eq!(
+        fs.output("/daemon").unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn serves_commands_over_9p() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = format!(
+        "[hello]\n\
+         read = seq 1 2000\n\
+         [ctl]\n\
+         write = cat >> ctl\n\
+         dir = {}\n",
+        dir.path().display()
+    );
+    let fs = CmdFs::from_config(&config, "glenda", "sys").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || run_server(listener, Arc::new(Mutex::new(fs))));
+    let mut client = P9Client::new(&addr).unwrap();
+    client.version(8192, "9P2000").unwrap();
+    client.attach(1, None, "glenda", "").unwrap();
+
+    assert_eq!(client.walk(1, 2, &["hello"]).unwrap(), 1);
+    client.open(2, 0).unwrap();
+    let mut data = Vec::new();
+    loop {
+        let chunk = client.read(2, data.len() as u64, 1000).unwrap();
+        if chunk.is_empty() {
+            break;
+        }
+        data.extend(chunk);
+    }
+    let expected: String = (1..=2000).map(|n| format!("{}\n", n)).collect();
+    assert_eq!(text(data), expected);
+
+    assert_eq!(client.walk(1, 3, &["ctl"]).unwrap(), 1);
+    assert!(client.open(3, 0).is_err());
+    client.open(3, 1).unwrap();
+    assert_eq!(client.write(3, 0, b"reload\n").unwrap(), 7);
+    // The write command runs after Rwrite, on its own thread.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while fs::read_to_string(dir.path().join("ctl")).unwrap_or_default() != "reload\n" {
+        assert!(Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn slow_commands_do_not_hold_up_other_clients() {
+    let config = "[slow]\n\
+                  read = sleep 2; echo late\n\
+                  [fast]\n\
+                  read = echo soon\n";
+    let fs = CmdFs::from_config(config, "glenda", "sys").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || run_server(listener, Arc::new(Mutex::new(fs))));
+    let connect = || {
+        let mut client = P9Client::new(&addr).unwrap();
+        client.version(8192, "9P2000").unwrap();
+        client.attach(1, None, "glenda", "").unwrap();
+        client
+    };
+
+    let mut slow = connect();
+    let reader = thread::spawn(move || {
+        slow.walk(1, 2, &["slow"]).unwrap();
+        slow.open(2, 0).unwrap();
+        slow.read(2, 0, 100).unwrap()
+    });
+    thread::sleep(Duration::from_millis(200));
+
+    let start = Instant::now();
+    let mut fast = connect();
+    fast.walk(1, 2, &["fast"]).unwrap();
+    fast.open(2, 0).unwrap();
+    assert_eq!(text(fast.read(2, 0, 100).unwrap()), "soon\n");
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(text(reader.join().unwrap()), "late\n");
+}
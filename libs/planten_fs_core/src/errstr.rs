@@ -0,0 +1,21 @@
+//! Error strings a server leaves for the runtime, as Plan 9's `werrstr`.
+//! `FsServer` calls fail with a bare `None`, so a server with more to say
+//! than the runtime's own message for the request sets it here before
+//! failing. The string belongs to the calling thread, which is the one
+//! the threaded 9P runtime answers the request from.
+
+use std::cell::RefCell;
+
+thread_local! {
+    static ERRSTR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets the error the current request fails with.
+pub fn werrstr(message: &str) {
+    ERRSTR.with(|errstr| *errstr.borrow_mut() = Some(message.to_string()));
+}
+
+/// Takes the error set since the last call, leaving none.
+pub fn take_errstr() -> Option<String> {
+    ERRSTR.with(|errstr| errstr.borrow_mut().take())
+}
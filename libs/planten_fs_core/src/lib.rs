@@ -1,15 +1,45 @@
 use std::collections::HashMap;
 
+pub mod errstr;
+pub mod middleware;
+pub mod perm;
+pub mod wait;
+pub mod wstat;
+
+pub use errstr::{take_errstr, werrstr};
+pub use middleware::{Logged, RateLimited, ReadOnly, Subtree};
+pub use wait::{Flush, Notifier, ReadWait};
+pub use wstat::Wstat;
+
+/// Directory bit of the 9P `perm`/`mode` word.
+pub const DMDIR: u32 = 0x80000000;
+/// Append-only file: writes always land at the end of the file.
+pub const DMAPPEND: u32 = 0x40000000;
+/// Exclusive-use file: at most one client may have it open at a time.
+pub const DMEXCL: u32 = 0x20000000;
+
+pub const OREAD: u32 = 0;
+pub const OWRITE: u32 = 1;
+pub const ORDWR: u32 = 2;
+pub const OEXEC: u32 = 3;
+pub const OTRUNC: u32 = 0x10;
+pub const ORCLOSE: u32 = 0x40;
+/// Fail `create` instead of truncating when the file already exists.
+/// As in Plan 9's libc this bit does not fit the 9P mode byte; servers add
+/// it themselves when handling Tcreate, which is always exclusive on the wire.
+pub const OEXCL: u32 = 0x1000;
+
 #[derive(Clone, Debug)]
 pub struct Inode {
     pub name: String,
     pub data: Vec<u8>,
     pub children: HashMap<String, Inode>,
-    pub mode: u32,   // Permissions and file type
-    pub uid: String, // Owner user ID
-    pub gid: String, // Group ID
-    pub atime: u32,  // Access time
-    pub mtime: u32,  // Modification time
+    pub mode: u32,    // Permissions and file type
+    pub uid: String,  // Owner user ID
+    pub gid: String,  // Group ID
+    pub atime: u32,   // Access time
+    pub mtime: u32,   // Modification time
+    pub muid: String, // Last user to modify the file
 }
 
 impl Inode {
@@ -27,17 +57,139 @@ impl Inode {
             gid: gid.to_string(),
             atime: now,
             mtime: now,
+            muid: uid.to_string(),
         }
     }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & DMDIR != 0
+    }
+}
+
+/// Permission bits for a file created with `perm` inside a directory whose
+/// mode is `dir_mode`, following create(5): files inherit at most the
+/// directory's rw bits, directories its rwx bits.
+pub fn create_perm(perm: u32, dir_mode: u32) -> u32 {
+    if perm & DMDIR != 0 {
+        perm & (!0o777 | (dir_mode & 0o777))
+    } else {
+        perm & (!0o666 | (dir_mode & 0o666))
+    }
+}
+
+/// Path of the entry `name` inside `base`; `.`, `..` and names containing
+/// a slash are not valid entry names.
+pub fn resolve_child(base: &str, name: &str) -> Option<String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return None;
+    }
+    resolve_step(base, name)
+}
+
+/// Path reached by walking one `component` from `base`.
+pub fn resolve_step(base: &str, component: &str) -> Option<String> {
+    match component {
+        "" => None,
+        "." => Some(base.to_string()),
+        ".." => match base.trim_end_matches('/').rfind('/') {
+            Some(0) | None => Some("/".to_string()),
+            Some(idx) => Some(base[..idx].to_string()),
+        },
+        _ if base == "/" => Some(format!("/{}", component)),
+        _ => Some(format!("{}/{}", base, component)),
+    }
 }
 
 pub trait FsServer {
     fn walk(&self, path: &str) -> Option<Vec<String>>;
     fn open(&self, path: &str) -> Option<()>;
     fn read(&self, path: &str) -> Option<Vec<u8>>;
-    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> Option<u32>;
+    /// Writes on behalf of `uname`, which the server records as the muid.
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32>;
     fn clunk(&self, path: &str) -> Option<()>;
     fn remove(&mut self, path: &str) -> Option<()>;
     fn stat(&self, path: &str) -> Option<Inode>;
-    fn wstat(&mut self, path: &str, inode: Inode) -> Option<()>;
+    /// `path`'s inode without its contents, and the file's length. The
+    /// runtimes stat through this on every walk step, directory entry and
+    /// read, so servers whose `stat` copies the contents override it.
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        let mut inode = self.stat(path)?;
+        let length = inode.data.len() as u64;
+        inode.data = Vec::new();
+        Some((inode, length))
+    }
+    /// Applies `change` whole or not at all. Runtimes check the client's
+    /// permissions first; servers still refuse what `Wstat::check` does
+    /// and lengths they cannot hold.
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()>;
+    /// Creates `name` inside the directory `parent`, owned by `uname`.
+    /// `perm` carries the permission bits plus DMDIR, DMAPPEND and DMEXCL;
+    /// `mode` is the open mode of the caller. An existing file is truncated
+    /// unless `mode` includes OEXCL; an existing directory is never replaced.
+    fn create(&mut self, parent: &str, name: &str, perm: u32, mode: u32, uname: &str)
+    -> Option<()>;
+    /// Files whose reads block until something happens (consoles, event
+    /// streams, pipes) return a `ReadWait` while nothing is readable at
+    /// `offset`. The runtime then releases the filesystem lock, waits, and
+    /// asks again before calling `read`.
+    fn read_wait(&self, _path: &str, _offset: u64) -> Option<ReadWait> {
+        None
+    }
+    /// Up to `count` bytes of `path` from `offset`. Files that hold only
+    /// a window of a longer stream override it to read by stream offset;
+    /// the default slices what `read` returns.
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        let mut data = self.read(path)?;
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        data.truncate(start.saturating_add(count as usize));
+        data.drain(..start);
+        Some(data)
+    }
+    /// The stream offset that offset 0 of `path` stands for when opened
+    /// now. A file keeping only the tail of a stream returns where its
+    /// oldest byte sits, so a new reader starts with what is kept; the
+    /// 9P runtime adds it to every offset read through the open fid.
+    fn open_offset(&self, _path: &str) -> u64 {
+        0
+    }
+    /// Empties the file at `path` for an open with OTRUNC, on behalf of
+    /// `uname`. The default refuses, so a server that stores files cannot
+    /// report success while keeping the old contents; devices with nothing
+    /// to empty override it to return `Some(())`.
+    fn truncate(&mut self, _path: &str, _uname: &str) -> Option<()> {
+        werrstr("permission denied");
+        None
+    }
+    /// Records an open of the DMEXCL file at `path`, refusing it while an
+    /// earlier one is outstanding; `release_exclusive` ends it. Servers
+    /// without such files keep the defaults, which refuse nothing.
+    fn claim_exclusive(&self, _path: &str) -> bool {
+        true
+    }
+    fn release_exclusive(&self, _path: &str) {}
+    /// An id for the file at `path` that survives renames and is never
+    /// reused. The 9P runtime binds fids to it, so an open fid follows its
+    /// file, and uses it as the qid path. Servers without one track fids
+    /// by path.
+    fn node_id(&self, _path: &str) -> Option<u64> {
+        None
+    }
+    /// Where the file `node_id` named is now, or `None` once removed.
+    fn node_path(&self, _id: u64) -> Option<String> {
+        None
+    }
+    /// Group membership used by the permission checks in `perm`, for the
+    /// file at `path`, so a server made of others can ask the one holding
+    /// it. By Plan 9 convention every user is the sole member of a group
+    /// with its name.
+    fn is_member(&self, _path: &str, uname: &str, group: &str) -> bool {
+        uname == group
+    }
+    /// Path a Tattach naming `aname` starts at, or `None` to refuse the
+    /// attach. Single-tree servers ignore `aname`.
+    fn attach(&self, _aname: &str) -> Option<String> {
+        Some("/".to_string())
+    }
 }
@@ -0,0 +1,589 @@
+//! Wrappers that implement `FsServer` by delegating to an inner server, so
+//! access policy and auditing can be layered onto any tree without forking
+//! it. They compose: `Logged::new(ReadOnly::new(Subtree::new(fs, "/pub")))`.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{FsServer, Inode, ReadWait, Wstat, werrstr};
+
+/// Refuses every modification and hides the write bits, so the runtimes'
+/// permission checks turn away opens for writing as well.
+pub struct ReadOnly<S> {
+    inner: S,
+}
+
+impl<S> ReadOnly<S> {
+    pub fn new(inner: S) -> Self {
+        ReadOnly { inner }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: FsServer> FsServer for ReadOnly<S> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.inner.walk(path)
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.inner.open(path)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.inner.read(path)
+    }
+
+    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+        None
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.inner.clunk(path)
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        let mut inode = self.inner.stat(path)?;
+        inode.mode &= !0o222;
+        Some(inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        let (mut inode, length) = self.inner.metadata(path)?;
+        inode.mode &= !0o222;
+        Some((inode, length))
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        self.inner.read_wait(path, offset)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.inner.read_at(path, offset, count)
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        self.inner.open_offset(path)
+    }
+
+    fn truncate(&mut self, _path: &str, _uname: &str) -> Option<()> {
+        None
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        self.inner.claim_exclusive(path)
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        self.inner.release_exclusive(path)
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        self.inner.node_id(path)
+    }
+
+    fn node_path(&self, id: u64) -> Option<String> {
+        self.inner.node_path(id)
+    }
+
+    fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        self.inner.is_member(path, uname, group)
+    }
+
+    fn attach(&self, aname: &str) -> Option<String> {
+        self.inner.attach(aname)
+    }
+}
+
+/// Serves the directory `prefix` of the inner server as the whole tree,
+/// like a chroot. Paths that try to climb out with `..` are refused.
+pub struct Subtree<S> {
+    inner: S,
+    prefix: String,
+}
+
+impl<S> Subtree<S> {
+    pub fn new(inner: S, prefix: &str) -> Self {
+        let trimmed = prefix.trim_matches('/');
+        let prefix = if trimmed.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", trimmed)
+        };
+        Subtree { inner, prefix }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn inner_path(&self, path: &str) -> Option<String> {
+        let mut mapped = self.prefix.clone();
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if component == ".." {
+                return None;
+            }
+            mapped.push('/');
+            mapped.push_str(component);
+        }
+        if mapped.is_empty() {
+            mapped.push('/');
+        }
+        Some(mapped)
+    }
+
+    /// `path` of the inner server as seen from the subtree, or `None`
+    /// when it lies outside.
+    fn outer_path(&self, path: &str) -> Option<String> {
+        match path.strip_prefix(self.prefix.as_str()) {
+            Some("") => Some("/".to_string()),
+            Some(rest) if rest.starts_with('/') => Some(rest.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl<S: FsServer> FsServer for Subtree<S> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.inner.walk(&self.inner_path(path)?)
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.inner.open(&self.inner_path(path)?)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.inner.read(&self.inner_path(path)?)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        let path = self.inner_path(path)?;
+        self.inner.write(&path, offset, data, uname)
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.inner.clunk(&self.inner_path(path)?)
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        // The root of the subtree is not ours to remove.
+        if path.split('/').all(|c| c.is_empty() || c == ".") {
+            return None;
+        }
+        let path = self.inner_path(path)?;
+        self.inner.remove(&path)
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        let mapped = self.inner_path(path)?;
+        let mut inode = self.inner.stat(&mapped)?;
+        if mapped == self.prefix || mapped == "/" {
+            inode.name = "/".to_string();
+        }
+        Some(inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        let mapped = self.inner_path(path)?;
+        let (mut inode, length) = self.inner.metadata(&mapped)?;
+        if mapped == self.prefix || mapped == "/" {
+            inode.name = "/".to_string();
+        }
+        Some((inode, length))
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        let path = self.inner_path(path)?;
+        self.inner.wstat(&path, change)
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        let parent = self.inner_path(parent)?;
+        self.inner.create(&parent, name, perm, mode, uname)
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        self.inner.read_wait(&self.inner_path(path)?, offset)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.inner.read_at(&self.inner_path(path)?, offset, count)
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        self.inner_path(path)
+            .map_or(0, |path| self.inner.open_offset(&path))
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        let path = self.inner_path(path)?;
+        self.inner.truncate(&path, uname)
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        self.inner_path(path)
+            .is_some_and(|path| self.inner.claim_exclusive(&path))
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        if let Some(path) = self.inner_path(path) {
+            self.inner.release_exclusive(&path);
+        }
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        self.inner.node_id(&self.inner_path(path)?)
+    }
+
+    /// Files moved out of the subtree are as good as removed.
+    fn node_path(&self, id: u64) -> Option<String> {
+        self.outer_path(&self.inner.node_path(id)?)
+    }
+
+    fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        self.inner_path(path)
+            .is_some_and(|path| self.inner.is_member(&path, uname, group))
+    }
+
+    /// `aname` names a directory within the subtree. Servers that ignore
+    /// it answer with their own root, which stands for the subtree's.
+    fn attach(&self, aname: &str) -> Option<String> {
+        let root = self.inner.attach(&self.inner_path(aname)?)?;
+        self.outer_path(&root)
+            .or_else(|| (root == "/").then_some(root))
+    }
+}
+
+/// Reports every operation and its outcome to a sink, by default stderr.
+pub struct Logged<S> {
+    inner: S,
+    sink: Box<dyn Fn(&str) + Send>,
+}
+
+impl<S> Logged<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_sink(inner, |line| eprintln!("{}", line))
+    }
+
+    pub fn with_sink<F>(inner: S, sink: F) -> Self
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        Logged {
+            inner,
+            sink: Box::new(sink),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn log<T>(&self, op: &str, detail: String, result: Option<T>) -> Option<T> {
+        let outcome = if result.is_some() { "ok" } else { "failed" };
+        (self.sink)(&format!("{} {}: {}", op, detail, outcome));
+        result
+    }
+}
+
+impl<S: FsServer> FsServer for Logged<S> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.log("walk", path.to_string(), self.inner.walk(path))
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.log("open", path.to_string(), self.inner.open(path))
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.log("read", path.to_string(), self.inner.read(path))
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        let result = self.inner.write(path, offset, data, uname);
+        let detail = format!(
+            "{} offset={} count={} uname={}",
+            path,
+            offset,
+            data.len(),
+            uname
+        );
+        self.log("write", detail, result)
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.log("clunk", path.to_string(), self.inner.clunk(path))
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        let result = self.inner.remove(path);
+        self.log("remove", path.to_string(), result)
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.log("stat", path.to_string(), self.inner.stat(path))
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        self.log("stat", path.to_string(), self.inner.metadata(path))
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        let detail = format!("{} mode={:?} name={:?}", path, change.mode, change.name);
+        let result = self.inner.wstat(path, change);
+        self.log("wstat", detail, result)
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        let result = self.inner.create(parent, name, perm, mode, uname);
+        let detail = format!("{} name={} perm={:#o} uname={}", parent, name, perm, uname);
+        self.log("create", detail, result)
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        self.inner.read_wait(path, offset)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        let detail = format!("{} offset={} count={}", path, offset, count);
+        self.log("read", detail, self.inner.read_at(path, offset, count))
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        self.inner.open_offset(path)
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        let result = self.inner.truncate(path, uname);
+        self.log("truncate", format!("{} uname={}", path, uname), result)
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        self.inner.claim_exclusive(path)
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        self.inner.release_exclusive(path)
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        self.inner.node_id(path)
+    }
+
+    fn node_path(&self, id: u64) -> Option<String> {
+        self.inner.node_path(id)
+    }
+
+    fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        self.inner.is_member(path, uname, group)
+    }
+
+    fn attach(&self, aname: &str) -> Option<String> {
+        self.log(
+            "attach",
+            format!("aname={}", aname),
+            self.inner.attach(aname),
+        )
+    }
+}
+
+/// Token bucket: `burst` operations at once, refilled at `per_second`.
+/// Operations beyond the budget fail with "rate limited" instead of
+/// queueing, since the runtimes call in with the filesystem lock held.
+/// Stats, clunks and the hooks an open or clunk calls besides always
+/// pass: the runtimes stat several times in one request, and clients must
+/// be able to release their fids.
+pub struct RateLimited<S> {
+    inner: S,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    burst: f64,
+    per_second: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<S> RateLimited<S> {
+    pub fn new(inner: S, per_second: u32, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        RateLimited {
+            inner,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                burst,
+                per_second: per_second as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Time until the next operation would be admitted.
+    pub fn retry_after(&self) -> Duration {
+        let bucket = self.bucket.lock().unwrap();
+        if bucket.tokens >= 1.0 || bucket.per_second <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.per_second)
+    }
+
+    fn admit(&self) -> Option<()> {
+        if self.bucket.lock().unwrap().take(Instant::now()) {
+            Some(())
+        } else {
+            werrstr("rate limited");
+            None
+        }
+    }
+}
+
+impl<S: FsServer> FsServer for RateLimited<S> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.admit()?;
+        self.inner.walk(path)
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.admit()?;
+        self.inner.open(path)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.admit()?;
+        self.inner.read(path)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.admit()?;
+        self.inner.write(path, offset, data, uname)
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.inner.clunk(path)
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.admit()?;
+        self.inner.remove(path)
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inner.stat(path)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        self.inner.metadata(path)
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.admit()?;
+        self.inner.wstat(path, change)
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.admit()?;
+        self.inner.create(parent, name, perm, mode, uname)
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        self.inner.read_wait(path, offset)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.admit()?;
+        self.inner.read_at(path, offset, count)
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        self.inner.open_offset(path)
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        self.inner.truncate(path, uname)
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        self.inner.claim_exclusive(path)
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        self.inner.release_exclusive(path)
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        self.inner.node_id(path)
+    }
+
+    fn node_path(&self, id: u64) -> Option<String> {
+        self.inner.node_path(id)
+    }
+
+    fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        self.inner.is_member(path, uname, group)
+    }
+
+    fn attach(&self, aname: &str) -> Option<String> {
+        self.inner.attach(aname)
+    }
+}
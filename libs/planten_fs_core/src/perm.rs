@@ -0,0 +1,358 @@
+//! Plan 9 owner/group/other permission checks shared by the server runtimes.
+//!
+//! The `check_*` functions judge inodes the caller has looked up, and
+//! the `may_*` ones look them up through an `FsServer` first. Each says
+//! why it refuses, in words fit for an Rerror.
+
+use crate::{DMDIR, FsServer, Inode, ORCLOSE, OTRUNC, resolve_step};
+
+pub const AEXEC: u32 = 1;
+pub const AWRITE: u32 = 2;
+pub const AREAD: u32 = 4;
+
+/// Access bits needed to open a file with the 9P open `mode`.
+pub fn open_access(mode: u32) -> u32 {
+    let mut want = match mode & 3 {
+        0 => AREAD,
+        1 => AWRITE,
+        2 => AREAD | AWRITE,
+        _ => AEXEC,
+    };
+    if mode & OTRUNC != 0 {
+        want |= AWRITE;
+    }
+    want
+}
+
+/// Whether an open with `mode` also needs write permission on the parent
+/// directory, as ORCLOSE removes the file on clunk.
+pub fn open_needs_parent_write(mode: u32) -> bool {
+    mode & ORCLOSE != 0
+}
+
+/// Grants `want` when the union of the other bits, the owner bits (for the
+/// owner) and the group bits (for group members) covers it.
+/// `inode` is the one at `path`, where group membership is looked up.
+pub fn has_access<S: FsServer + ?Sized>(
+    fs: &S,
+    path: &str,
+    inode: &Inode,
+    uname: &str,
+    want: u32,
+) -> bool {
+    allows(inode, uname, fs.is_member(path, uname, &inode.gid), want)
+}
+
+/// `has_access` for callers that resolved group membership themselves.
+pub fn allows(inode: &Inode, uname: &str, in_group: bool, want: u32) -> bool {
+    let mut granted = inode.mode & 7;
+    if inode.uid == uname {
+        granted |= (inode.mode >> 6) & 7;
+    }
+    if in_group {
+        granted |= (inode.mode >> 3) & 7;
+    }
+    granted & want == want
+}
+
+/// Checks `want` against the directory containing `path`.
+pub fn parent_allows<S: FsServer + ?Sized>(fs: &S, path: &str, uname: &str, want: u32) -> bool {
+    resolve_step(path, "..").is_some_and(|parent| {
+        fs.metadata(&parent)
+            .is_some_and(|(dir, _)| has_access(fs, &parent, &dir, uname, want))
+    })
+}
+
+/// Walking out of the directory `dir` needs search permission on it.
+pub fn check_walk(
+    dir: &Inode,
+    uname: &str,
+    is_member: impl Fn(&str) -> bool,
+) -> Result<(), &'static str> {
+    if !allows(dir, uname, is_member(&dir.gid), AEXEC) {
+        return Err("permission denied");
+    }
+    Ok(())
+}
+
+/// Opening `file` with the 9P `mode` needs the access `open_access`
+/// names, and directories open only for reading. With ORCLOSE the caller
+/// also makes `check_create` pass on the directory holding the file.
+pub fn check_open(
+    file: &Inode,
+    uname: &str,
+    mode: u32,
+    is_member: impl Fn(&str) -> bool,
+) -> Result<(), &'static str> {
+    let want = open_access(mode);
+    if file.is_dir() && want & AWRITE != 0 {
+        return Err("is a directory");
+    }
+    if !allows(file, uname, is_member(&file.gid), want) {
+        return Err("permission denied");
+    }
+    Ok(())
+}
+
+/// Creating a file in `dir`, or renaming one there, needs write
+/// permission on the directory.
+pub fn check_create(
+    dir: &Inode,
+    uname: &str,
+    is_member: impl Fn(&str) -> bool,
+) -> Result<(), &'static str> {
+    if !allows(dir, uname, is_member(&dir.gid), AWRITE) {
+        return Err("permission denied");
+    }
+    Ok(())
+}
+
+/// Removing a file from `dir` needs write permission on the directory.
+pub fn check_remove(
+    dir: &Inode,
+    uname: &str,
+    is_member: impl Fn(&str) -> bool,
+) -> Result<(), &'static str> {
+    check_create(dir, uname, is_member)
+}
+
+/// `check_walk` for the directory at `dir`.
+pub fn may_walk<S: FsServer + ?Sized>(fs: &S, dir: &str, uname: &str) -> Result<(), &'static str> {
+    let (inode, _) = fs.metadata(dir).ok_or("file not found")?;
+    check_walk(&inode, uname, |group| fs.is_member(dir, uname, group))
+}
+
+/// `check_open` for the file at `path`, and with ORCLOSE `check_create`
+/// for its directory.
+pub fn may_open<S: FsServer + ?Sized>(
+    fs: &S,
+    path: &str,
+    uname: &str,
+    mode: u32,
+) -> Result<(), &'static str> {
+    let (file, _) = fs.metadata(path).ok_or("file not found")?;
+    check_open(&file, uname, mode, |group| fs.is_member(path, uname, group))?;
+    if open_needs_parent_write(mode) {
+        let parent = resolve_step(path, "..").ok_or("permission denied")?;
+        may_create(fs, &parent, uname)?;
+    }
+    Ok(())
+}
+
+/// `check_create` for the directory at `dir`.
+pub fn may_create<S: FsServer + ?Sized>(
+    fs: &S,
+    dir: &str,
+    uname: &str,
+) -> Result<(), &'static str> {
+    let (inode, _) = fs.metadata(dir).ok_or("file not found")?;
+    check_create(&inode, uname, |group| fs.is_member(dir, uname, group))
+}
+
+/// `check_remove` for the file at `path`.
+pub fn may_remove<S: FsServer + ?Sized>(
+    fs: &S,
+    path: &str,
+    uname: &str,
+) -> Result<(), &'static str> {
+    let dir = resolve_step(path, "..").ok_or("file not found")?;
+    let (inode, _) = fs.metadata(&dir).ok_or("file not found")?;
+    check_remove(&inode, uname, |group| fs.is_member(&dir, uname, group))
+}
+
+/// Whether `uname` may wstat a file from `old` to `new`, following
+/// stat(5): only the owner may change the mode or times, and the group
+/// only to one the owner is a member of; the owner itself never changes,
+/// nor does the DMDIR bit. A rename stays within the directory and needs
+/// write permission there, and a new length needs write permission on the
+/// file. The caller says whether the length changes, since inodes need not
+/// carry their contents, and whether the parent is writable.
+pub fn check_wstat(
+    old: &Inode,
+    new: &Inode,
+    uname: &str,
+    is_member: impl Fn(&str) -> bool,
+    length_changed: bool,
+    parent_writable: bool,
+) -> Result<(), &'static str> {
+    let owner = old.uid == uname;
+    if new.uid != old.uid {
+        return Err("cannot change owner");
+    }
+    if (new.mode ^ old.mode) & DMDIR != 0 {
+        return Err("cannot change directory bit");
+    }
+    if (new.mode != old.mode || new.atime != old.atime || new.mtime != old.mtime) && !owner {
+        return Err("not owner");
+    }
+    if new.gid != old.gid && !(owner && is_member(&new.gid)) {
+        return Err("not owner or not in group");
+    }
+    if new.name != old.name {
+        if new.name.contains('/') {
+            return Err("cannot rename across directories");
+        }
+        if new.name.is_empty() || new.name == "." || new.name == ".." {
+            return Err("bad file name");
+        }
+        if !parent_writable {
+            return Err("permission denied");
+        }
+    }
+    if length_changed {
+        if old.is_dir() {
+            return Err("cannot change directory length");
+        }
+        if !allows(old, uname, is_member(&old.gid), AWRITE) {
+            return Err("permission denied");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ORDWR, OREAD, OWRITE, Wstat};
+
+    struct Groups;
+
+    impl FsServer for Groups {
+        fn walk(&self, _path: &str) -> Option<Vec<String>> {
+            None
+        }
+        fn open(&self, _path: &str) -> Option<()> {
+            None
+        }
+        fn read(&self, _path: &str) -> Option<Vec<u8>> {
+            None
+        }
+        fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+            None
+        }
+        fn clunk(&self, _path: &str) -> Option<()> {
+            None
+        }
+        fn remove(&mut self, _path: &str) -> Option<()> {
+            None
+        }
+        fn stat(&self, _path: &str) -> Option<Inode> {
+            None
+        }
+        fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+            None
+        }
+        fn create(
+            &mut self,
+            _parent: &str,
+            _name: &str,
+            _perm: u32,
+            _mode: u32,
+            _uname: &str,
+        ) -> Option<()> {
+            None
+        }
+        fn is_member(&self, _path: &str, uname: &str, group: &str) -> bool {
+            uname == group || (group == "sys" && uname == "bootes")
+        }
+    }
+
+    #[test]
+    fn owner_group_other_bits() {
+        let inode = Inode::new("f", 0o640, "glenda", "sys");
+        assert!(has_access(
+            &Groups,
+            "/f",
+            &inode,
+            "glenda",
+            open_access(ORDWR)
+        ));
+        assert!(has_access(
+            &Groups,
+            "/f",
+            &inode,
+            "bootes",
+            open_access(OREAD)
+        ));
+        assert!(!has_access(
+            &Groups,
+            "/f",
+            &inode,
+            "bootes",
+            open_access(OWRITE)
+        ));
+        assert!(!has_access(
+            &Groups,
+            "/f",
+            &inode,
+            "none",
+            open_access(OREAD)
+        ));
+    }
+
+    #[test]
+    fn wstat_rules() {
+        let old = Inode::new("f", 0o644, "glenda", "glenda");
+        let member = |group: &str| group == "glenda" || group == "sys";
+        let check = |new: &Inode, uname: &str| check_wstat(&old, new, uname, member, false, true);
+
+        let mut chmod = old.clone();
+        chmod.mode = 0o600;
+        assert_eq!(check(&chmod, "glenda"), Ok(()));
+        assert_eq!(check(&chmod, "bootes"), Err("not owner"));
+        chmod.mode |= DMDIR;
+        assert_eq!(check(&chmod, "glenda"), Err("cannot change directory bit"));
+
+        let mut chgrp = old.clone();
+        chgrp.gid = "sys".to_string();
+        assert_eq!(check(&chgrp, "glenda"), Ok(()));
+        chgrp.gid = "adm".to_string();
+        assert_eq!(check(&chgrp, "glenda"), Err("not owner or not in group"));
+
+        let mut chown = old.clone();
+        chown.uid = "bootes".to_string();
+        assert_eq!(check(&chown, "glenda"), Err("cannot change owner"));
+
+        let mut rename = old.clone();
+        rename.name = "../g".to_string();
+        assert_eq!(
+            check(&rename, "glenda"),
+            Err("cannot rename across directories")
+        );
+        rename.name = "g".to_string();
+        assert_eq!(check(&rename, "bootes"), Ok(()));
+        assert_eq!(
+            check_wstat(&old, &rename, "glenda", member, false, false),
+            Err("permission denied")
+        );
+
+        assert_eq!(
+            check_wstat(&old, &old, "bootes", member, true, true),
+            Err("permission denied")
+        );
+        assert_eq!(
+            check_wstat(&old, &old, "glenda", member, true, true),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn truncate_needs_write() {
+        let inode = Inode::new("f", 0o444, "glenda", "glenda");
+        assert!(has_access(
+            &Groups,
+            "/f",
+            &inode,
+            "glenda",
+            open_access(OREAD)
+        ));
+        assert!(!has_access(
+            &Groups,
+            "/f",
+            &inode,
+            "glenda",
+            open_access(OREAD | OTRUNC)
+        ));
+        assert!(open_needs_parent_write(OREAD | ORCLOSE));
+    }
+}
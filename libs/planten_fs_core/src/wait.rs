@@ -0,0 +1,126 @@
+//! Blocking reads. A file with nothing to read yet hands the runtime a
+//! `ReadWait` from its `Notifier`; the runtime parks the request on it
+//! without the filesystem lock until the server notifies or a Tflush
+//! raises the request's `Flush`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Wakes readers parked on a blocking file. A server keeps one per file (or
+/// per tree) and calls `notify` whenever new data becomes readable.
+#[derive(Default)]
+pub struct Notifier {
+    generation: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl Notifier {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Notifier::default())
+    }
+
+    /// Records that something happened and wakes every parked reader.
+    pub fn notify(&self) {
+        let mut generation = self.generation.lock().unwrap();
+        *generation = generation.wrapping_add(1);
+        self.cond.notify_all();
+    }
+
+    /// Captures the current generation. Call it while still holding the
+    /// filesystem lock so a `notify` between unlock and wait is not lost.
+    pub fn waiter(self: &Arc<Self>) -> ReadWait {
+        let generation = *self.generation.lock().unwrap();
+        ReadWait {
+            notifier: Arc::clone(self),
+            generation,
+        }
+    }
+
+    fn wake(&self) {
+        let _generation = self.generation.lock().unwrap();
+        self.cond.notify_all();
+    }
+}
+
+/// Returned by `FsServer::read_wait` when a read has nothing to return yet.
+pub struct ReadWait {
+    notifier: Arc<Notifier>,
+    generation: u64,
+}
+
+impl ReadWait {
+    /// Blocks until the notifier fires or `flush` is raised. Returns `false`
+    /// when the request was flushed and must not be answered.
+    pub fn wait(&self, flush: &Flush) -> bool {
+        *flush.parked_on.lock().unwrap() = Some(Arc::clone(&self.notifier));
+        let mut generation = self.notifier.generation.lock().unwrap();
+        while *generation == self.generation && !flush.is_raised() {
+            generation = self.notifier.cond.wait(generation).unwrap();
+        }
+        drop(generation);
+        *flush.parked_on.lock().unwrap() = None;
+        !flush.is_raised()
+    }
+}
+
+/// Cancellation state of one outstanding request, raised by Tflush.
+#[derive(Default)]
+pub struct Flush {
+    raised: AtomicBool,
+    parked_on: Mutex<Option<Arc<Notifier>>>,
+}
+
+impl Flush {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Flush::default())
+    }
+
+    pub fn raise(&self) {
+        self.raised.store(true, Ordering::SeqCst);
+        if let Some(notifier) = self.parked_on.lock().unwrap().as_ref() {
+            notifier.wake();
+        }
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn notify_releases_waiter() {
+        let notifier = Notifier::new();
+        let wait = notifier.waiter();
+        let flush = Flush::new();
+        let handle = thread::spawn(move || wait.wait(&flush));
+        thread::sleep(Duration::from_millis(20));
+        notifier.notify();
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn notify_before_wait_is_not_lost() {
+        let notifier = Notifier::new();
+        let wait = notifier.waiter();
+        notifier.notify();
+        assert!(wait.wait(&Flush::new()));
+    }
+
+    #[test]
+    fn flush_cancels_waiter() {
+        let notifier = Notifier::new();
+        let wait = notifier.waiter();
+        let flush = Flush::new();
+        let flush_clone = Arc::clone(&flush);
+        let handle = thread::spawn(move || wait.wait(&flush_clone));
+        thread::sleep(Duration::from_millis(20));
+        flush.raise();
+        assert!(!handle.join().unwrap());
+    }
+}
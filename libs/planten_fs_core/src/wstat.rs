@@ -0,0 +1,81 @@
+//! The changes a Twstat asks for, and the rules every server applies to
+//! them whoever asks.
+
+use crate::{DMDIR, Inode};
+
+/// A wstat request. `None` is the protocol's don't-touch value (`!0` or
+/// an empty string) and leaves that field as it is. The length is a
+/// number rather than contents, so no server has to hold a file's bytes,
+/// or allocate them, to learn or change its size.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Wstat {
+    pub name: Option<String>,
+    pub mode: Option<u32>,
+    pub atime: Option<u32>,
+    pub mtime: Option<u32>,
+    pub length: Option<u64>,
+    pub uid: Option<String>,
+    pub gid: Option<String>,
+}
+
+impl Wstat {
+    /// `old` with every field this sets, except the length, which inodes
+    /// do not carry.
+    pub fn apply(&self, old: &Inode) -> Inode {
+        let mut new = Inode {
+            data: Vec::new(),
+            children: Default::default(),
+            ..old.clone()
+        };
+        if let Some(name) = &self.name {
+            new.name = name.clone();
+        }
+        if let Some(mode) = self.mode {
+            new.mode = mode;
+        }
+        if let Some(atime) = self.atime {
+            new.atime = atime;
+        }
+        if let Some(mtime) = self.mtime {
+            new.mtime = mtime;
+        }
+        if let Some(uid) = &self.uid {
+            new.uid = uid.clone();
+        }
+        if let Some(gid) = &self.gid {
+            new.gid = gid.clone();
+        }
+        new
+    }
+
+    /// The new length, if there is one and it differs from `length`.
+    pub fn new_length(&self, length: u64) -> Option<u64> {
+        self.length.filter(|&new| new != length)
+    }
+
+    /// Whether the name changes from `old`.
+    pub fn renames(&self, old: &str) -> bool {
+        self.name.as_deref().is_some_and(|name| name != old)
+    }
+
+    /// Refuses what no one may do, the host included: changing DMDIR or
+    /// the length of a directory, and renaming to a bad name or into
+    /// another directory. `length` is the file's current length.
+    pub fn check(&self, old: &Inode, length: u64) -> Result<(), &'static str> {
+        if self.mode.is_some_and(|mode| (mode ^ old.mode) & DMDIR != 0) {
+            return Err("cannot change directory bit");
+        }
+        if old.is_dir() && self.new_length(length).is_some() {
+            return Err("cannot change directory length");
+        }
+        if let Some(name) = self.name.as_deref().filter(|name| *name != old.name) {
+            if name.contains('/') {
+                return Err("cannot rename across directories");
+            }
+            if name == "." || name == ".." {
+                return Err("bad file name");
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use planten_fs_core::{FsServer, Inode};
+use planten_fs_core::{FsServer, Inode, Wstat};
 use rand::random;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -93,7 +93,7 @@ impl FsServer for DevFs {
         }
     }
 
-    fn write(&mut self, path: &str, _offset: u64, data: &[u8]) -> Option<u32> {
+    fn write(&mut self, path: &str, _offset: u64, data: &[u8], _uname: &str) -> Option<u32> {
         let comps: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
         match comps.as_slice() {
             [name] if DEV_ENTRIES.contains(name) => {
@@ -131,9 +131,25 @@ impl FsServer for DevFs {
         }
     }
 
-    fn wstat(&mut self, _path: &str, _inode: Inode) -> Option<()> {
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
         None
     }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    /// `/dev/null` and the console hold nothing to truncate.
+    fn truncate(&mut self, _path: &str, _uname: &str) -> Option<()> {
+        Some(())
+    }
 }
 
 pub mod server;
@@ -3,9 +3,10 @@ use planten_9p::messages::{
     TSTAT, TVERSION, TWALK,
 };
 use planten_9p::{
-    build_frame, decode_string, decode_u16, decode_u32, decode_u64, encode_qid_bytes,
+    build_frame, decode_string, decode_u16, decode_u32, decode_u64, decode_u8, encode_qid_bytes,
     encode_stat_payload, encode_string, Qid, RawMessage, Stat,
 };
+use planten_fs_core::perm::{may_open, may_walk};
 use planten_fs_core::FsServer;
 use planten_fs_core::Inode;
 use std::collections::HashMap;
@@ -26,6 +27,7 @@ pub fn run_single(listener: TcpListener, fs: Arc<Mutex<DevFs>>) -> io::Result<()
 struct FidState {
     path: String,
     qid: Qid,
+    uname: String,
 }
 
 fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<DevFs>>) -> io::Result<()> {
@@ -61,7 +63,7 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<DevFs>>) -> io::Result<()>
             TATTACH => {
                 let fid = decode_u32(&mut cursor)?;
                 let _ = decode_u32(&mut cursor)?;
-                let _ = decode_string(&mut cursor)?;
+                let uname = decode_string(&mut cursor)?;
                 let _ = decode_string(&mut cursor)?;
                 let root = root_inode();
                 let root_qid = qid_from_inode(&root);
@@ -70,6 +72,7 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<DevFs>>) -> io::Result<()>
                     FidState {
                         path: "/".to_string(),
                         qid: root_qid.clone(),
+                        uname,
                     },
                 );
                 let mut body = Vec::new();
@@ -89,22 +92,32 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<DevFs>>) -> io::Result<()>
                     .get(&fid)
                     .map(|state| state.path.clone())
                     .unwrap_or_else(|| "/".to_string());
+                let uname = fids
+                    .get(&fid)
+                    .map(|state| state.uname.clone())
+                    .unwrap_or_else(|| "none".to_string());
                 let mut qids = Vec::new();
-                let mut success = true;
+                let mut failure = None;
 
                 for name in names {
+                    if let Err(message) = may_walk(&*fs_locked, &current_path, &uname) {
+                        failure = Some(message);
+                        break;
+                    }
                     let next_path = resolve_path(&current_path, &name);
                     if let Some(inode) = fs_locked.stat(&next_path) {
                         let qid = qid_from_inode(&inode);
                         qids.push(qid.clone());
                         current_path = next_path;
                     } else {
-                        success = false;
+                        failure = Some("walk failed");
                         break;
                     }
                 }
 
-                if success {
+                if let Some(message) = failure {
+                    send_error(&mut stream, tag, message)?;
+                } else {
                     let mut body = Vec::new();
                     body.extend_from_slice(&(qids.len() as u16).to_le_bytes());
                     for qid in &qids {
@@ -115,11 +128,10 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<DevFs>>) -> io::Result<()>
                         FidState {
                             path: current_path,
                             qid: qids.last().cloned().unwrap_or_else(|| root_qid()),
+                            uname,
                         },
                     );
                     send_response(&mut stream, RWALK, tag, &body)?;
-                } else {
-                    send_error(&mut stream, tag, "walk failed")?;
                 }
             }
             TREAD => {
@@ -145,7 +157,15 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<DevFs>>) -> io::Result<()>
             }
             TOPEN => {
                 let fid = decode_u32(&mut cursor)?;
+                let mode = decode_u8(&mut cursor)?;
                 if let Some(state) = fids.get(&fid) {
+                    let fs_locked = fs.lock().unwrap();
+                    let allowed = may_open(&*fs_locked, &state.path, &state.uname, mode as u32);
+                    drop(fs_locked);
+                    if let Err(message) = allowed {
+                        send_error(&mut stream, tag, message)?;
+                        continue;
+                    }
                     let mut body = Vec::new();
                     body.extend_from_slice(&encode_qid_bytes(&state.qid));
                     body.extend_from_slice(&MAX_MSG_SIZE.to_le_bytes());
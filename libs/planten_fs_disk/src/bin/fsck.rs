@@ -0,0 +1,52 @@
+use std::env;
+use std::process;
+
+use planten_fs_disk::device::FileDevice;
+use planten_fs_disk::fsck::fsck;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_disk_fsck [--repair] image\n\
+         Exits 0 when the image is clean, 1 when problems were found."
+    );
+    process::exit(2);
+}
+
+fn main() {
+    let mut repair = false;
+    let mut image = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            _ if arg.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(arg),
+        }
+    }
+    let image = image.unwrap_or_else(|| usage());
+
+    let report = match FileDevice::open(&image).and_then(|mut dev| fsck(&mut dev, repair)) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("fsck {}: {}", image, err);
+            process::exit(2);
+        }
+    };
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!(
+        "{}: {} files, {} directories, {} blocks in use{}",
+        image,
+        report.files,
+        report.directories,
+        report.blocks,
+        match (report.is_clean(), repair) {
+            (true, _) => "",
+            (false, true) => "; repaired",
+            (false, false) => "; run with --repair to fix",
+        }
+    );
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,59 @@
+use std::env;
+use std::process;
+
+use planten_fs_disk::device::FileDevice;
+use planten_fs_disk::format::BLOCK_SIZE;
+use planten_fs_disk::mkfs::{Options, mkfs};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_disk_mkfs [--size bytes] [--inodes n] [--journal blocks]\n\
+         \t[--owner user] [--group group] image\n\
+         Without --size the image keeps its current size."
+    );
+    process::exit(1);
+}
+
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut options = Options::default();
+    let mut size: Option<u64> = None;
+    let mut image = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = Some(value(&mut args)),
+            "--inodes" => options.inodes = Some(value(&mut args)),
+            "--journal" => options.journal_blocks = Some(value(&mut args)),
+            "--owner" => options.uid = value(&mut args),
+            "--group" => options.gid = value(&mut args),
+            _ if arg.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(arg),
+        }
+    }
+    let image = image.unwrap_or_else(|| usage());
+
+    let dev = match size {
+        Some(size) => FileDevice::create(&image, size / BLOCK_SIZE as u64),
+        None => FileDevice::open(&image),
+    };
+    let result = dev.and_then(|mut dev| mkfs(&mut dev, &options));
+    match result {
+        Ok(sb) => println!(
+            "{}: {} blocks, {} inodes, {} journal blocks",
+            image,
+            sb.blocks,
+            sb.inodes - 1,
+            sb.journal_blocks
+        ),
+        Err(err) => {
+            eprintln!("mkfs {}: {}", image, err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::run_server;
+use planten_fs_core::ReadOnly;
+use planten_fs_disk::DiskFs;
+use planten_fs_disk::device::FileDevice;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5660";
+
+fn usage() -> ! {
+    eprintln!("usage: planten_fs_disk_server [--listen host:port] [--read-only] image");
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut read_only = false;
+    let mut image = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--read-only" => read_only = true,
+            _ if arg.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(arg),
+        }
+    }
+    let image = image.unwrap_or_else(|| usage());
+
+    let fs = DiskFs::mount(FileDevice::open(&image)?).unwrap_or_else(|err| {
+        eprintln!("cannot mount {}: {}", image, err);
+        process::exit(1);
+    });
+    let listener = TcpListener::bind(&listen)?;
+    println!("DiskFs 9P server serving {} on {}", image, listen);
+    if read_only {
+        run_server(listener, Arc::new(Mutex::new(ReadOnly::new(fs))))
+    } else {
+        run_server(listener, Arc::new(Mutex::new(fs)))
+    }
+}
@@ -0,0 +1,165 @@
+//! Block devices an image lives on: a host file, or memory for tests and
+//! scratch trees.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::format::BLOCK_SIZE;
+
+/// Fixed-size blocks that can be read, written and flushed. The
+/// filesystem relies on `sync` for ordering: a write issued after a
+/// `sync` returns never reaches the medium before those issued ahead of
+/// it.
+pub trait Device: Send {
+    /// Size of the device in blocks.
+    fn blocks(&self) -> u64;
+    /// Fills `buf`, which is `BLOCK_SIZE` long, with block `block`.
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> io::Result<()>;
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> io::Result<()>;
+    /// Makes every earlier write durable.
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+impl<D: Device + ?Sized> Device for &mut D {
+    fn blocks(&self) -> u64 {
+        (**self).blocks()
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> io::Result<()> {
+        (**self).read_block(block, buf)
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> io::Result<()> {
+        (**self).write_block(block, buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+fn out_of_range(block: u64) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("block {} is past the end of the device", block),
+    )
+}
+
+/// An image file on the host.
+pub struct FileDevice {
+    file: File,
+    blocks: u64,
+}
+
+impl FileDevice {
+    /// Opens an existing image for reading and writing.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let blocks = file.metadata()?.len() / BLOCK_SIZE as u64;
+        Ok(FileDevice { file, blocks })
+    }
+
+    /// Creates, or truncates, an image of `blocks` zeroed blocks.
+    pub fn create(path: impl AsRef<Path>, blocks: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(blocks * BLOCK_SIZE as u64)?;
+        Ok(FileDevice { file, blocks })
+    }
+}
+
+impl Device for FileDevice {
+    fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> io::Result<()> {
+        if block >= self.blocks {
+            return Err(out_of_range(block));
+        }
+        self.file.seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+        self.file.read_exact(buf)
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> io::Result<()> {
+        if block >= self.blocks {
+            return Err(out_of_range(block));
+        }
+        self.file.seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+        self.file.write_all(buf)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+/// An image held in memory.
+#[derive(Clone)]
+pub struct MemDevice {
+    bytes: Vec<u8>,
+}
+
+impl MemDevice {
+    /// A zeroed device of `blocks` blocks.
+    pub fn new(blocks: u64) -> Self {
+        MemDevice {
+            bytes: vec![0; blocks as usize * BLOCK_SIZE],
+        }
+    }
+
+    /// A device holding `bytes`, whose length must be a whole number of
+    /// blocks.
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<Self> {
+        if !bytes.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "image is not a whole number of blocks",
+            ));
+        }
+        Ok(MemDevice { bytes })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl Device for MemDevice {
+    fn blocks(&self) -> u64 {
+        (self.bytes.len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> io::Result<()> {
+        let start = usize::try_from(block)
+            .ok()
+            .and_then(|block| block.checked_mul(BLOCK_SIZE))
+            .filter(|start| start + BLOCK_SIZE <= self.bytes.len())
+            .ok_or_else(|| out_of_range(block))?;
+        buf.copy_from_slice(&self.bytes[start..start + BLOCK_SIZE]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> io::Result<()> {
+        let start = usize::try_from(block)
+            .ok()
+            .and_then(|block| block.checked_mul(BLOCK_SIZE))
+            .filter(|start| start + BLOCK_SIZE <= self.bytes.len())
+            .ok_or_else(|| out_of_range(block))?;
+        self.bytes[start..start + BLOCK_SIZE].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
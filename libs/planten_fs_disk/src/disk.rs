@@ -0,0 +1,422 @@
+//! Block-level access to a mounted image: the transaction being built,
+//! block and inode allocation, and the mapping from file offsets to
+//! blocks.
+//!
+//! Every change lands in the open transaction first and reads see it
+//! there, so an operation works on its own view until `commit` journals
+//! the lot. The allocation bitmap and which inodes are in use are cached
+//! in memory; `abort` throws the transaction away and reloads them.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+
+use crate::device::Device;
+use crate::format::{
+    BLOCK_SIZE, DIRECT, DiskInode, INODE_SIZE, INODES_PER_BLOCK, MAX_FILE_BLOCKS, POINTERS,
+    ROOT_INODE, Superblock, corrupt, decode_dir, encode_dir,
+};
+use crate::journal::{self, Transaction};
+
+/// Blocks kept free in a transaction for the inode, bitmap and indirect
+/// blocks a step of a large write may dirty.
+const SLACK: usize = 8;
+
+pub(crate) fn no_space() -> io::Error {
+    io::Error::new(ErrorKind::StorageFull, "no space")
+}
+
+pub(crate) struct Disk<D> {
+    dev: D,
+    sb: Superblock,
+    bitmap: Vec<u8>,
+    inode_used: Vec<bool>,
+    /// Where the search for a free block starts.
+    next_block: u64,
+    seq: u64,
+    tx: Transaction,
+}
+
+impl<D: Device> Disk<D> {
+    /// Mounts the image on `dev`, first replaying its journal.
+    pub fn mount(mut dev: D) -> io::Result<Self> {
+        let mut block = vec![0; BLOCK_SIZE];
+        dev.read_block(0, &mut block)?;
+        let sb = Superblock::decode(&block)?;
+        if sb.blocks > dev.blocks() {
+            return Err(corrupt("image is shorter than its superblock says"));
+        }
+        let seq = journal::recover(&mut dev, &sb)?;
+        let mut disk = Disk {
+            dev,
+            next_block: sb.data_start,
+            sb,
+            bitmap: Vec::new(),
+            inode_used: Vec::new(),
+            seq,
+            tx: Transaction::new(),
+        };
+        disk.load_caches()?;
+        if !disk.inode(ROOT_INODE)?.is_dir() {
+            return Err(corrupt("root inode is not a directory"));
+        }
+        Ok(disk)
+    }
+
+    fn load_caches(&mut self) -> io::Result<()> {
+        let mut bitmap = Vec::with_capacity(self.sb.bitmap_blocks as usize * BLOCK_SIZE);
+        for n in 0..self.sb.bitmap_blocks {
+            bitmap.extend_from_slice(&self.read(self.sb.bitmap_start + n)?);
+        }
+        let mut inode_used = vec![false; self.sb.inodes as usize];
+        for n in 0..self.sb.inode_blocks {
+            let block = self.read(self.sb.inode_start + n)?;
+            for (slot, bytes) in block.chunks(INODE_SIZE).enumerate() {
+                let inum = n as usize * INODES_PER_BLOCK as usize + slot;
+                // An inode that does not decode is left alone for fsck.
+                inode_used[inum] = DiskInode::decode(bytes).map_or(true, |inode| inode.used);
+            }
+        }
+        inode_used[0] = true;
+        self.bitmap = bitmap;
+        self.inode_used = inode_used;
+        self.next_block = self.sb.data_start;
+        Ok(())
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.sb
+    }
+
+    pub fn into_device(self) -> D {
+        self.dev
+    }
+
+    pub fn read(&mut self, block: u64) -> io::Result<Vec<u8>> {
+        if let Some(data) = self.tx.get(&block) {
+            return Ok(data.clone());
+        }
+        let mut data = vec![0; BLOCK_SIZE];
+        self.dev.read_block(block, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn write(&mut self, block: u64, data: Vec<u8>) {
+        debug_assert_eq!(data.len(), BLOCK_SIZE);
+        self.tx.insert(block, data);
+    }
+
+    /// Journals everything written since the last commit.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.tx.is_empty() {
+            return Ok(());
+        }
+        let tx = std::mem::take(&mut self.tx);
+        journal::commit(&mut self.dev, &self.sb, self.seq, &tx)?;
+        self.seq += 1;
+        Ok(())
+    }
+
+    /// Forgets everything written since the last commit.
+    pub fn abort(&mut self) -> io::Result<()> {
+        self.tx.clear();
+        self.load_caches()
+    }
+
+    /// Whether the transaction has room for another step of a write.
+    fn has_room(&self) -> bool {
+        self.tx.len() + SLACK <= journal::capacity(&self.sb)
+    }
+
+    pub fn set_bit(&mut self, block: u64, used: bool) {
+        let (bitmap_block, _) = self.sb.bitmap_location(block);
+        let byte = (block / 8) as usize;
+        if used {
+            self.bitmap[byte] |= 1 << (block % 8);
+        } else {
+            self.bitmap[byte] &= !(1 << (block % 8));
+        }
+        let start = (bitmap_block - self.sb.bitmap_start) as usize * BLOCK_SIZE;
+        self.write(
+            bitmap_block,
+            self.bitmap[start..start + BLOCK_SIZE].to_vec(),
+        );
+    }
+
+    pub fn is_allocated(&self, block: u64) -> bool {
+        self.bitmap[(block / 8) as usize] & (1 << (block % 8)) != 0
+    }
+
+    /// A zeroed block, taken from the data area.
+    pub fn alloc_block(&mut self) -> io::Result<u32> {
+        let (start, end) = (self.sb.data_start, self.sb.blocks);
+        let found = (self.next_block..end)
+            .chain(start..self.next_block)
+            .find(|&block| !self.is_allocated(block))
+            .ok_or_else(no_space)?;
+        let block = u32::try_from(found).map_err(|_| no_space())?;
+        self.set_bit(found, true);
+        self.write(found, vec![0; BLOCK_SIZE]);
+        self.next_block = found + 1;
+        Ok(block)
+    }
+
+    pub fn free_block(&mut self, block: u32) -> io::Result<()> {
+        let block = block as u64;
+        if block < self.sb.data_start || block >= self.sb.blocks || !self.is_allocated(block) {
+            return Err(corrupt(format!("block {} is not in use", block)));
+        }
+        self.tx.remove(&block);
+        self.set_bit(block, false);
+        Ok(())
+    }
+
+    pub fn inode(&mut self, inum: u32) -> io::Result<DiskInode> {
+        if inum == 0 || inum >= self.sb.inodes {
+            return Err(corrupt(format!("inode {} out of range", inum)));
+        }
+        let (block, at) = self.sb.inode_location(inum);
+        let data = self.read(block)?;
+        DiskInode::decode(&data[at..at + INODE_SIZE])
+    }
+
+    pub fn put_inode(&mut self, inum: u32, inode: &DiskInode) -> io::Result<()> {
+        let (block, at) = self.sb.inode_location(inum);
+        let mut data = self.read(block)?;
+        inode.encode_into(&mut data[at..at + INODE_SIZE]);
+        self.write(block, data);
+        self.inode_used[inum as usize] = inode.used;
+        Ok(())
+    }
+
+    /// Number of a free inode, which the caller must `put_inode`.
+    pub fn free_inode(&self) -> io::Result<u32> {
+        self.inode_used
+            .iter()
+            .position(|used| !used)
+            .map(|inum| inum as u32)
+            .ok_or_else(no_space)
+    }
+
+    /// Counts of used inodes and blocks.
+    pub fn usage(&self) -> (u64, u64) {
+        let inodes = self.inode_used.iter().filter(|used| **used).count() as u64 - 1;
+        let blocks = (0..self.sb.blocks)
+            .filter(|&block| self.is_allocated(block))
+            .count() as u64;
+        (inodes, blocks)
+    }
+
+    pub fn pointer(&mut self, block: u32, index: u64) -> io::Result<u32> {
+        let data = self.read(block as u64)?;
+        let at = index as usize * 4;
+        Ok(u32::from_le_bytes(data[at..at + 4].try_into().unwrap()))
+    }
+
+    pub fn set_pointer(&mut self, block: u32, index: u64, value: u32) -> io::Result<()> {
+        let mut data = self.read(block as u64)?;
+        let at = index as usize * 4;
+        data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        self.write(block as u64, data);
+        Ok(())
+    }
+
+    /// Reads `pointer`, allocating the block it should point at when it is
+    /// a hole and `alloc` is set.
+    fn follow(&mut self, pointer: &mut u32, alloc: bool) -> io::Result<u32> {
+        if *pointer == 0 && alloc {
+            *pointer = self.alloc_block()?;
+        }
+        Ok(*pointer)
+    }
+
+    /// Block holding block `index` of the file, or 0 for a hole. With
+    /// `alloc`, holes are filled, which may change the inode.
+    pub fn bmap(&mut self, inode: &mut DiskInode, index: u64, alloc: bool) -> io::Result<u32> {
+        if index >= MAX_FILE_BLOCKS {
+            return Err(io::Error::new(ErrorKind::FileTooLarge, "file too large"));
+        }
+        if let Some(pointer) = inode.direct.get_mut(index as usize) {
+            return self.follow(pointer, alloc);
+        }
+        let index = index - DIRECT as u64;
+        let (table, index) = if index < POINTERS {
+            let table = self.follow(&mut inode.indirect, alloc)?;
+            (table, index)
+        } else {
+            let index = index - POINTERS;
+            let double = self.follow(&mut inode.double, alloc)?;
+            if double == 0 {
+                return Ok(0);
+            }
+            let mut table = self.pointer(double, index / POINTERS)?;
+            if table == 0 && alloc {
+                table = self.alloc_block()?;
+                self.set_pointer(double, index / POINTERS, table)?;
+            }
+            (table, index % POINTERS)
+        };
+        if table == 0 {
+            return Ok(0);
+        }
+        let mut block = self.pointer(table, index)?;
+        if block == 0 && alloc {
+            block = self.alloc_block()?;
+            self.set_pointer(table, index, block)?;
+        }
+        Ok(block)
+    }
+
+    /// Up to `count` bytes of the file from `offset`.
+    pub fn read_data(
+        &mut self,
+        inode: &DiskInode,
+        offset: u64,
+        count: usize,
+    ) -> io::Result<Vec<u8>> {
+        let end = inode.length.min(offset.saturating_add(count as u64));
+        let mut data = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let mut inode = inode.clone();
+        let mut at = offset;
+        while at < end {
+            let within = (at % BLOCK_SIZE as u64) as usize;
+            let take = (BLOCK_SIZE - within).min((end - at) as usize);
+            match self.bmap(&mut inode, at / BLOCK_SIZE as u64, false)? {
+                0 => data.resize(data.len() + take, 0),
+                block => data.extend_from_slice(&self.read(block as u64)?[within..within + take]),
+            }
+            at += take as u64;
+        }
+        Ok(data)
+    }
+
+    /// Writes `data` at `offset` into inode `inum`. A write too big for one
+    /// transaction is committed in steps, each leaving a consistent tree
+    /// holding a prefix of it.
+    pub fn write_data(
+        &mut self,
+        inum: u32,
+        inode: &mut DiskInode,
+        offset: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let mut done = 0;
+        while done < data.len() {
+            if !self.has_room() {
+                self.put_inode(inum, inode)?;
+                self.commit()?;
+            }
+            let at = offset + done as u64;
+            let within = (at % BLOCK_SIZE as u64) as usize;
+            let take = (BLOCK_SIZE - within).min(data.len() - done);
+            let block = self.bmap(inode, at / BLOCK_SIZE as u64, true)? as u64;
+            let mut contents = self.read(block)?;
+            contents[within..within + take].copy_from_slice(&data[done..done + take]);
+            self.write(block, contents);
+            done += take;
+            inode.length = inode.length.max(at + take as u64);
+        }
+        self.put_inode(inum, inode)
+    }
+
+    /// Sets the file's length, freeing the blocks past the new end and
+    /// zeroing the rest of the last one, so growing it again reads zeros.
+    /// Lengths past the largest file the block map can address fail.
+    pub fn truncate(&mut self, inode: &mut DiskInode, length: u64) -> io::Result<()> {
+        if length > MAX_FILE_BLOCKS * BLOCK_SIZE as u64 {
+            return Err(io::Error::new(ErrorKind::FileTooLarge, "file too large"));
+        }
+        if length >= inode.length {
+            inode.length = length;
+            return Ok(());
+        }
+        let keep = length.div_ceil(BLOCK_SIZE as u64);
+        for pointer in inode.direct.iter_mut().skip(keep as usize) {
+            if *pointer != 0 {
+                let block = std::mem::take(pointer);
+                self.free_block(block)?;
+            }
+        }
+        let keep_indirect = keep.saturating_sub(DIRECT as u64);
+        inode.indirect = self.trim(inode.indirect, 1, keep_indirect)?;
+        inode.double = self.trim(inode.double, 2, keep_indirect.saturating_sub(POINTERS))?;
+
+        let within = (length % BLOCK_SIZE as u64) as usize;
+        if within != 0 {
+            let block = self.bmap(inode, length / BLOCK_SIZE as u64, false)?;
+            if block != 0 {
+                let mut contents = self.read(block as u64)?;
+                contents[within..].fill(0);
+                self.write(block as u64, contents);
+            }
+        }
+        inode.length = length;
+        Ok(())
+    }
+
+    /// Frees everything the `level`-deep pointer block `table` maps past
+    /// its first `keep` data blocks. Returns what the parent should point
+    /// at afterwards: `table`, or 0 once it maps nothing.
+    fn trim(&mut self, table: u32, level: u32, keep: u64) -> io::Result<u32> {
+        if table == 0 {
+            return Ok(0);
+        }
+        let span = POINTERS.pow(level - 1);
+        for index in keep.div_ceil(span).saturating_sub(1)..POINTERS {
+            let child = self.pointer(table, index)?;
+            if child == 0 {
+                continue;
+            }
+            let child_keep = keep.saturating_sub(index * span).min(span);
+            let left = if level == 1 {
+                if child_keep == 0 {
+                    self.free_block(child)?;
+                    0
+                } else {
+                    child
+                }
+            } else {
+                self.trim(child, level - 1, child_keep)?
+            };
+            if left != child {
+                self.set_pointer(table, index, left)?;
+            }
+        }
+        if keep == 0 {
+            self.free_block(table)?;
+            return Ok(0);
+        }
+        Ok(table)
+    }
+
+    pub fn read_dir(&mut self, inode: &DiskInode) -> io::Result<BTreeMap<String, u32>> {
+        let bytes = self.read_data(inode, 0, inode.length as usize)?;
+        decode_dir(&bytes)
+    }
+
+    pub fn write_dir(
+        &mut self,
+        inum: u32,
+        inode: &mut DiskInode,
+        entries: &BTreeMap<String, u32>,
+    ) -> io::Result<()> {
+        let bytes = encode_dir(entries);
+        self.truncate(inode, bytes.len() as u64)?;
+        self.write_data(inum, inode, 0, &bytes)
+    }
+
+    /// Inode number of `path`, walking from the root.
+    pub fn resolve(&mut self, path: &str) -> io::Result<u32> {
+        let mut inum = ROOT_INODE;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let inode = self.inode(inum)?;
+            if !inode.is_dir() {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            inum = *self
+                .read_dir(&inode)?
+                .get(name)
+                .ok_or(ErrorKind::NotFound)?;
+        }
+        Ok(inum)
+    }
+}
@@ -0,0 +1,335 @@
+//! The on-disk layout. Integers are little-endian.
+//!
+//! ```text
+//! block 0               superblock
+//! journal_start..       journal: a descriptor, then the blocks it carries
+//! bitmap_start..        one bit per block of the image, set while in use
+//! inode_start..         the inode table, INODES_PER_BLOCK to a block
+//! data_start..          contents of files and directories, indirect blocks
+//! ```
+//!
+//! Inode 0 is never used, so a zero inode number means "none", and inode
+//! 1 is the root directory. A directory's contents are its entries, each
+//! an inode number, a name length and the name. File contents are mapped
+//! through `DIRECT` direct pointers, one indirect and one double-indirect
+//! block; a zero pointer is a hole that reads as zeros.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+
+pub const BLOCK_SIZE: usize = 4096;
+pub const MAGIC: &[u8; 8] = b"PLTNDISK";
+pub const VERSION: u32 = 1;
+pub const INODE_SIZE: usize = 256;
+pub const INODES_PER_BLOCK: u32 = (BLOCK_SIZE / INODE_SIZE) as u32;
+pub const ROOT_INODE: u32 = 1;
+/// Block pointers held in the inode itself.
+pub const DIRECT: usize = 12;
+/// Block pointers in an indirect block.
+pub const POINTERS: u64 = (BLOCK_SIZE / 4) as u64;
+/// Blocks a file can map.
+pub const MAX_FILE_BLOCKS: u64 = DIRECT as u64 + POINTERS + POINTERS * POINTERS;
+pub const MAX_NAME: usize = 255;
+/// Longest uid, gid or muid an inode can hold.
+pub const MAX_OWNER: usize = 31;
+
+const INODE_USED: u32 = 1;
+const OWNER_FIELD: usize = MAX_OWNER + 1;
+
+pub(crate) fn corrupt(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// Running CRC-32 (the zlib polynomial) over several pieces.
+#[derive(Clone, Copy)]
+pub(crate) struct Crc(u32);
+
+impl Crc {
+    pub fn new() -> Self {
+        Crc(!0)
+    }
+
+    pub fn update(mut self, bytes: &[u8]) -> Self {
+        for byte in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+        self
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Where everything is. Written once by mkfs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Superblock {
+    pub blocks: u64,
+    /// Slots in the inode table, counting the unused inode 0.
+    pub inodes: u32,
+    pub journal_start: u64,
+    pub journal_blocks: u64,
+    pub bitmap_start: u64,
+    pub bitmap_blocks: u64,
+    pub inode_start: u64,
+    pub inode_blocks: u64,
+    pub data_start: u64,
+}
+
+impl Superblock {
+    /// Lays out an image of `blocks` blocks with room for `inodes` inodes
+    /// and a journal of `journal_blocks`.
+    pub fn layout(blocks: u64, inodes: u32, journal_blocks: u64) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidInput, message);
+        if journal_blocks < 2 {
+            return Err(invalid("the journal needs at least two blocks"));
+        }
+        let inodes = inodes
+            .checked_add(1)
+            .ok_or_else(|| invalid("too many inodes"))?;
+        let bits = BLOCK_SIZE as u64 * 8;
+        let journal_start = 1;
+        let bitmap_start = journal_start + journal_blocks;
+        let bitmap_blocks = blocks.div_ceil(bits);
+        let inode_start = bitmap_start + bitmap_blocks;
+        let inode_blocks = (inodes as u64).div_ceil(INODES_PER_BLOCK as u64);
+        let data_start = inode_start + inode_blocks;
+        if data_start + 1 > blocks {
+            return Err(invalid("image too small for its metadata"));
+        }
+        Ok(Superblock {
+            blocks,
+            inodes: (inode_blocks * INODES_PER_BLOCK as u64) as u32,
+            journal_start,
+            journal_blocks,
+            bitmap_start,
+            bitmap_blocks,
+            inode_start,
+            inode_blocks,
+            data_start,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+        block.extend_from_slice(MAGIC);
+        block.extend_from_slice(&VERSION.to_le_bytes());
+        block.extend_from_slice(&self.inodes.to_le_bytes());
+        for field in [
+            self.blocks,
+            self.journal_start,
+            self.journal_blocks,
+            self.bitmap_start,
+            self.bitmap_blocks,
+            self.inode_start,
+            self.inode_blocks,
+            self.data_start,
+        ] {
+            block.extend_from_slice(&field.to_le_bytes());
+        }
+        let crc = Crc::new().update(&block).finish();
+        block.extend_from_slice(&crc.to_le_bytes());
+        block.resize(BLOCK_SIZE, 0);
+        block
+    }
+
+    pub fn decode(block: &[u8]) -> io::Result<Self> {
+        if &block[..8] != MAGIC {
+            return Err(corrupt("not a planten disk image"));
+        }
+        if u32_at(block, 8) != VERSION {
+            return Err(corrupt(format!(
+                "unsupported image version {}",
+                u32_at(block, 8)
+            )));
+        }
+        const END: usize = 16 + 8 * 8;
+        if Crc::new().update(&block[..END]).finish() != u32_at(block, END) {
+            return Err(corrupt("superblock checksum mismatch"));
+        }
+        let field = |n: usize| u64_at(block, 16 + 8 * n);
+        let sb = Superblock {
+            inodes: u32_at(block, 12),
+            blocks: field(0),
+            journal_start: field(1),
+            journal_blocks: field(2),
+            bitmap_start: field(3),
+            bitmap_blocks: field(4),
+            inode_start: field(5),
+            inode_blocks: field(6),
+            data_start: field(7),
+        };
+        let expected = sb
+            .inodes
+            .checked_sub(1)
+            .and_then(|inodes| Superblock::layout(sb.blocks, inodes, sb.journal_blocks).ok());
+        if expected.as_ref() != Some(&sb) {
+            return Err(corrupt("superblock layout is inconsistent"));
+        }
+        Ok(sb)
+    }
+
+    /// Block holding inode `inum`, and the inode's offset within it.
+    pub fn inode_location(&self, inum: u32) -> (u64, usize) {
+        let block = self.inode_start + (inum / INODES_PER_BLOCK) as u64;
+        (block, (inum % INODES_PER_BLOCK) as usize * INODE_SIZE)
+    }
+
+    /// Bitmap block holding the bit of `block`, and the bit's index in it.
+    pub fn bitmap_location(&self, block: u64) -> (u64, usize) {
+        let bits = BLOCK_SIZE as u64 * 8;
+        (self.bitmap_start + block / bits, (block % bits) as usize)
+    }
+}
+
+/// An inode as stored in the table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiskInode {
+    pub used: bool,
+    pub mode: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub length: u64,
+    pub uid: String,
+    pub gid: String,
+    pub muid: String,
+    pub direct: [u32; DIRECT],
+    pub indirect: u32,
+    pub double: u32,
+}
+
+impl DiskInode {
+    pub fn is_dir(&self) -> bool {
+        self.mode & planten_fs_core::DMDIR != 0
+    }
+
+    pub fn encode_into(&self, slot: &mut [u8]) {
+        slot.fill(0);
+        let flags = if self.used { INODE_USED } else { 0 };
+        slot[0..4].copy_from_slice(&flags.to_le_bytes());
+        slot[4..8].copy_from_slice(&self.mode.to_le_bytes());
+        slot[8..12].copy_from_slice(&self.atime.to_le_bytes());
+        slot[12..16].copy_from_slice(&self.mtime.to_le_bytes());
+        slot[16..24].copy_from_slice(&self.length.to_le_bytes());
+        for (n, owner) in [&self.uid, &self.gid, &self.muid].into_iter().enumerate() {
+            let at = 28 + n * OWNER_FIELD;
+            let bytes = &owner.as_bytes()[..owner.len().min(MAX_OWNER)];
+            slot[at] = bytes.len() as u8;
+            slot[at + 1..at + 1 + bytes.len()].copy_from_slice(bytes);
+        }
+        let pointers = 28 + 3 * OWNER_FIELD;
+        let all = self.direct.iter().chain([&self.indirect, &self.double]);
+        for (n, pointer) in all.enumerate() {
+            let at = pointers + 4 * n;
+            slot[at..at + 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+    }
+
+    pub fn decode(slot: &[u8]) -> io::Result<Self> {
+        let mut owners = Vec::with_capacity(3);
+        for n in 0..3 {
+            let at = 28 + n * OWNER_FIELD;
+            let len = slot[at] as usize;
+            if len > MAX_OWNER {
+                return Err(corrupt("inode owner name too long"));
+            }
+            let name = std::str::from_utf8(&slot[at + 1..at + 1 + len])
+                .map_err(|_| corrupt("inode owner name is not UTF-8"))?;
+            owners.push(name.to_string());
+        }
+        let pointers = 28 + 3 * OWNER_FIELD;
+        let pointer = |n: usize| u32_at(slot, pointers + 4 * n);
+        let mut direct = [0; DIRECT];
+        for (n, block) in direct.iter_mut().enumerate() {
+            *block = pointer(n);
+        }
+        let muid = owners.pop().unwrap();
+        let gid = owners.pop().unwrap();
+        let uid = owners.pop().unwrap();
+        Ok(DiskInode {
+            used: u32_at(slot, 0) & INODE_USED != 0,
+            mode: u32_at(slot, 4),
+            atime: u32_at(slot, 8),
+            mtime: u32_at(slot, 12),
+            length: u64_at(slot, 16),
+            uid,
+            gid,
+            muid,
+            direct,
+            indirect: pointer(DIRECT),
+            double: pointer(DIRECT + 1),
+        })
+    }
+}
+
+pub fn encode_dir(entries: &BTreeMap<String, u32>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (name, inum) in entries {
+        bytes.extend_from_slice(&inum.to_le_bytes());
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+    }
+    bytes
+}
+
+pub fn decode_dir(mut bytes: &[u8]) -> io::Result<BTreeMap<String, u32>> {
+    let mut entries = BTreeMap::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 5 {
+            return Err(corrupt("truncated directory entry"));
+        }
+        let inum = u32_at(bytes, 0);
+        let len = bytes[4] as usize;
+        let name = bytes
+            .get(5..5 + len)
+            .ok_or_else(|| corrupt("truncated directory entry"))?;
+        let name =
+            std::str::from_utf8(name).map_err(|_| corrupt("directory entry name is not UTF-8"))?;
+        if inum == 0 || !valid_name(name) {
+            return Err(corrupt(format!("bad directory entry {:?}", name)));
+        }
+        if entries.insert(name.to_string(), inum).is_some() {
+            return Err(corrupt(format!("duplicate directory entry {:?}", name)));
+        }
+        bytes = &bytes[5 + len..];
+    }
+    Ok(entries)
+}
+
+/// Whether `name` can be stored as a directory entry.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME
+        && name != "."
+        && name != ".."
+        && !name.contains('/')
+        && !name.contains('\0')
+}
@@ -0,0 +1,342 @@
+//! Checking an image, and repairing what can be repaired.
+//!
+//! The tree is walked from the root, claiming every block each reachable
+//! inode maps. Entries naming free, broken or already reached inodes are
+//! dropped, pointers outside the data area or to a block some other file
+//! claimed first become holes, inodes in use that nothing reaches are
+//! freed, and the bitmap is rebuilt from the claims. Every fix is its own
+//! journaled transaction, so an interrupted repair can simply be run
+//! again.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+
+use crate::device::Device;
+use crate::disk::Disk;
+use crate::format::{BLOCK_SIZE, DiskInode, POINTERS, ROOT_INODE};
+
+/// What `fsck` found.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// One line per problem.
+    pub problems: Vec<String>,
+    pub files: u64,
+    pub directories: u64,
+    /// Blocks in use once the check is done, metadata included.
+    pub blocks: u64,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks the image on `dev`. With `repair` the problems found are fixed
+/// on the device; without, nothing is written to it, the journal replay
+/// included, and the report describes the image a repair would leave.
+pub fn fsck<D: Device>(dev: &mut D, repair: bool) -> io::Result<Report> {
+    if repair {
+        check(Disk::mount(dev)?)
+    } else {
+        check(Disk::mount(Overlay {
+            dev,
+            written: HashMap::new(),
+        })?)
+    }
+}
+
+/// Keeps writes in memory, in front of a device only read from.
+struct Overlay<'a, D> {
+    dev: &'a mut D,
+    written: HashMap<u64, Vec<u8>>,
+}
+
+impl<D: Device> Device for Overlay<'_, D> {
+    fn blocks(&self) -> u64 {
+        self.dev.blocks()
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self.written.get(&block) {
+            Some(data) => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            None => self.dev.read_block(block, buf),
+        }
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> io::Result<()> {
+        self.written.insert(block, buf.to_vec());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A block pointer, by where it is stored.
+#[derive(Clone, Copy)]
+enum Slot {
+    Direct(usize),
+    Indirect,
+    Double,
+    Table(u32, u64),
+}
+
+enum Fix {
+    Hole(u32, Slot),
+    Unlink(u32, String),
+    Empty(u32),
+}
+
+/// One walk of the tree.
+struct Scan {
+    problems: Vec<String>,
+    fixes: Vec<Fix>,
+    claimed: Vec<bool>,
+    reached: Vec<bool>,
+    files: u64,
+    directories: u64,
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+impl Scan {
+    /// Claims `block` for the file at `path`, unless it is outside the data
+    /// area or already someone else's.
+    fn claim(&mut self, data_start: u64, path: &str, block: u32) -> bool {
+        let block = block as u64;
+        if block < data_start || block >= self.claimed.len() as u64 {
+            self.problems.push(format!(
+                "{}: block {} is outside the data area",
+                path, block
+            ));
+            return false;
+        }
+        if self.claimed[block as usize] {
+            self.problems
+                .push(format!("{}: block {} belongs to another file", path, block));
+            return false;
+        }
+        self.claimed[block as usize] = true;
+        true
+    }
+}
+
+fn scan<D: Device>(disk: &mut Disk<D>) -> io::Result<Scan> {
+    let sb = disk.superblock().clone();
+    let mut scan = Scan {
+        problems: Vec::new(),
+        fixes: Vec::new(),
+        claimed: vec![false; sb.blocks as usize],
+        reached: vec![false; sb.inodes as usize],
+        files: 0,
+        directories: 0,
+    };
+    scan.claimed[..sb.data_start as usize].fill(true);
+    scan.reached[ROOT_INODE as usize] = true;
+
+    let mut queue = VecDeque::from([(ROOT_INODE, "/".to_string())]);
+    while let Some((inum, path)) = queue.pop_front() {
+        let mut inode = disk.inode(inum)?;
+        let holes = scan.fixes.len();
+        claim_inode(disk, &mut scan, inum, &inode, &path)?;
+        if !inode.is_dir() {
+            scan.files += 1;
+            continue;
+        }
+        scan.directories += 1;
+        // A directory read through pointers about to become holes would
+        // not decode, so read it as the repair will leave it.
+        for fix in &scan.fixes[holes..] {
+            if let Fix::Hole(_, slot) = fix {
+                match *slot {
+                    Slot::Direct(n) => inode.direct[n] = 0,
+                    Slot::Indirect => inode.indirect = 0,
+                    Slot::Double => inode.double = 0,
+                    Slot::Table(..) => {}
+                }
+            }
+        }
+        let entries = match disk.read_dir(&inode) {
+            Ok(entries) => entries,
+            Err(err) => {
+                scan.problems.push(format!("{}: {}", path, err));
+                scan.fixes.push(Fix::Empty(inum));
+                continue;
+            }
+        };
+        for (name, child) in entries {
+            let child_path = join(&path, &name);
+            let problem = if child == 0 || child >= sb.inodes {
+                Some(format!("names inode {}, which does not exist", child))
+            } else if scan.reached[child as usize] {
+                Some(format!("names inode {}, which is linked elsewhere", child))
+            } else {
+                match disk.inode(child) {
+                    Ok(inode) if inode.used => None,
+                    Ok(_) => Some(format!("names inode {}, which is free", child)),
+                    Err(err) => Some(format!("names inode {}: {}", child, err)),
+                }
+            };
+            match problem {
+                Some(problem) => {
+                    scan.problems.push(format!("{} {}", child_path, problem));
+                    scan.fixes.push(Fix::Unlink(inum, name));
+                }
+                None => {
+                    scan.reached[child as usize] = true;
+                    queue.push_back((child, child_path));
+                }
+            }
+        }
+    }
+    Ok(scan)
+}
+
+fn claim_inode<D: Device>(
+    disk: &mut Disk<D>,
+    scan: &mut Scan,
+    inum: u32,
+    inode: &DiskInode,
+    path: &str,
+) -> io::Result<()> {
+    let data_start = disk.superblock().data_start;
+    for (n, &block) in inode.direct.iter().enumerate() {
+        if block != 0 && !scan.claim(data_start, path, block) {
+            scan.fixes.push(Fix::Hole(inum, Slot::Direct(n)));
+        }
+    }
+    for (table, slot, level) in [
+        (inode.indirect, Slot::Indirect, 1),
+        (inode.double, Slot::Double, 2),
+    ] {
+        if table == 0 {
+            continue;
+        }
+        if scan.claim(data_start, path, table) {
+            claim_table(disk, scan, inum, table, level, path)?;
+        } else {
+            scan.fixes.push(Fix::Hole(inum, slot));
+        }
+    }
+    Ok(())
+}
+
+fn claim_table<D: Device>(
+    disk: &mut Disk<D>,
+    scan: &mut Scan,
+    inum: u32,
+    table: u32,
+    level: u32,
+    path: &str,
+) -> io::Result<()> {
+    let data_start = disk.superblock().data_start;
+    for index in 0..POINTERS {
+        let block = disk.pointer(table, index)?;
+        if block == 0 {
+            continue;
+        }
+        if !scan.claim(data_start, path, block) {
+            scan.fixes.push(Fix::Hole(inum, Slot::Table(table, index)));
+        } else if level > 1 {
+            claim_table(disk, scan, inum, block, level - 1, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the bitmap to `claimed` for every block where they differ and
+/// `claimed` is `mark`. Returns how many bits changed.
+fn reconcile<D: Device>(disk: &mut Disk<D>, claimed: &[bool], mark: bool) -> io::Result<u64> {
+    let mut changed = 0;
+    // One transaction per bitmap block keeps each within the journal.
+    for (n, chunk) in claimed.chunks(BLOCK_SIZE * 8).enumerate() {
+        let first = (n * BLOCK_SIZE * 8) as u64;
+        for (block, &claim) in (first..).zip(chunk) {
+            if claim == mark && disk.is_allocated(block) != mark {
+                disk.set_bit(block, mark);
+                changed += 1;
+            }
+        }
+        disk.commit()?;
+    }
+    Ok(changed)
+}
+
+fn check<D: Device>(mut disk: Disk<D>) -> io::Result<Report> {
+    let sb = disk.superblock().clone();
+    let first = scan(&mut disk)?;
+    let mut problems = first.problems;
+
+    // Claimed blocks go into the bitmap before anything is freed or
+    // allocated, so neither can touch a block a file still uses.
+    let unmarked = reconcile(&mut disk, &first.claimed, true)?;
+    if unmarked > 0 {
+        problems.push(format!("{} blocks in use were marked free", unmarked));
+    }
+
+    let mut unlinks: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for fix in first.fixes {
+        match fix {
+            Fix::Hole(_, Slot::Table(table, index)) => disk.set_pointer(table, index, 0)?,
+            Fix::Hole(inum, slot) => {
+                let mut inode = disk.inode(inum)?;
+                match slot {
+                    Slot::Direct(n) => inode.direct[n] = 0,
+                    Slot::Indirect => inode.indirect = 0,
+                    Slot::Double => inode.double = 0,
+                    Slot::Table(..) => unreachable!(),
+                }
+                disk.put_inode(inum, &inode)?;
+            }
+            Fix::Unlink(dir, name) => unlinks.entry(dir).or_default().push(name),
+            Fix::Empty(dir) => {
+                let mut inode = disk.inode(dir)?;
+                disk.write_dir(dir, &mut inode, &BTreeMap::new())?;
+            }
+        }
+        disk.commit()?;
+    }
+    for (dir, names) in unlinks {
+        let mut inode = disk.inode(dir)?;
+        let mut entries = disk.read_dir(&inode)?;
+        for name in names {
+            entries.remove(&name);
+        }
+        disk.write_dir(dir, &mut inode, &entries)?;
+        disk.commit()?;
+    }
+
+    for inum in 1..sb.inodes {
+        if first.reached[inum as usize] {
+            continue;
+        }
+        match disk.inode(inum) {
+            Ok(inode) if !inode.used => continue,
+            Ok(_) => problems.push(format!("inode {} is in use but unreachable", inum)),
+            Err(err) => problems.push(format!("inode {}: {}", inum, err)),
+        }
+        disk.put_inode(inum, &DiskInode::default())?;
+        disk.commit()?;
+    }
+
+    // The tree is sound now; what it claims is what is in use.
+    let last = scan(&mut disk)?;
+    debug_assert!(last.problems.is_empty(), "{:?}", last.problems);
+    let leaked = reconcile(&mut disk, &last.claimed, false)?;
+    if leaked > 0 {
+        problems.push(format!("{} blocks marked in use belong to no file", leaked));
+    }
+    Ok(Report {
+        problems,
+        files: last.files,
+        directories: last.directories,
+        blocks: last.claimed.iter().filter(|claimed| **claimed).count() as u64,
+    })
+}
@@ -0,0 +1,131 @@
+//! The write-ahead journal that makes every change atomic.
+//!
+//! A transaction is the new contents of some set of blocks. Committing
+//! one writes those contents into the journal's data blocks, then a
+//! descriptor naming where they belong with a checksum over all of it;
+//! once the descriptor is durable the transaction has happened. Only then
+//! are the blocks written to their homes. Mounting replays whatever valid
+//! transaction the journal holds, which is harmless when it was already
+//! written home, so a crash at any point leaves either the old tree or
+//! the new one.
+//!
+//! The descriptor is never cleared: the next transaction overwrites the
+//! journal's data blocks first, which breaks the old checksum before the
+//! new descriptor replaces it.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::device::Device;
+use crate::format::{BLOCK_SIZE, Crc, Superblock, corrupt};
+
+const MAGIC: &[u8; 8] = b"PLTNJRNL";
+/// magic[8] seq[8] count[4] crc[4], then count block numbers.
+const HEADER: usize = 24;
+
+/// New contents of blocks, by block number.
+pub type Transaction = BTreeMap<u64, Vec<u8>>;
+
+/// Most blocks one transaction can carry.
+pub fn capacity(sb: &Superblock) -> usize {
+    ((sb.journal_blocks - 1) as usize).min((BLOCK_SIZE - HEADER) / 8)
+}
+
+fn checksum(seq: u64, blocks: &Transaction) -> u32 {
+    let mut crc = Crc::new().update(&seq.to_le_bytes());
+    for (home, data) in blocks {
+        crc = crc.update(&home.to_le_bytes()).update(data);
+    }
+    crc.finish()
+}
+
+/// Makes `blocks` part of the image atomically, as transaction `seq`.
+pub fn commit<D: Device>(
+    dev: &mut D,
+    sb: &Superblock,
+    seq: u64,
+    blocks: &Transaction,
+) -> io::Result<()> {
+    if blocks.len() > capacity(sb) {
+        return Err(io::Error::other("transaction larger than the journal"));
+    }
+    for (slot, data) in blocks.values().enumerate() {
+        dev.write_block(sb.journal_start + 1 + slot as u64, data)?;
+    }
+    dev.sync()?;
+
+    let mut descriptor = Vec::with_capacity(BLOCK_SIZE);
+    descriptor.extend_from_slice(MAGIC);
+    descriptor.extend_from_slice(&seq.to_le_bytes());
+    descriptor.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+    descriptor.extend_from_slice(&checksum(seq, blocks).to_le_bytes());
+    for home in blocks.keys() {
+        descriptor.extend_from_slice(&home.to_le_bytes());
+    }
+    descriptor.resize(BLOCK_SIZE, 0);
+    dev.write_block(sb.journal_start, &descriptor)?;
+    dev.sync()?;
+
+    for (home, data) in blocks {
+        dev.write_block(*home, data)?;
+    }
+    dev.sync()
+}
+
+/// The last transaction the journal holds: its sequence number, and its
+/// blocks if they are intact. Half-written transactions have none.
+pub fn last<D: Device>(
+    dev: &mut D,
+    sb: &Superblock,
+) -> io::Result<Option<(u64, Option<Transaction>)>> {
+    let mut descriptor = vec![0; BLOCK_SIZE];
+    dev.read_block(sb.journal_start, &mut descriptor)?;
+    if &descriptor[..8] != MAGIC {
+        return Ok(None);
+    }
+    let seq = u64::from_le_bytes(descriptor[8..16].try_into().unwrap());
+    let count = u32::from_le_bytes(descriptor[16..20].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(descriptor[20..24].try_into().unwrap());
+    if count > capacity(sb) {
+        return Ok(Some((seq, None)));
+    }
+    let mut blocks = BTreeMap::new();
+    for slot in 0..count {
+        let at = HEADER + 8 * slot;
+        let home = u64::from_le_bytes(descriptor[at..at + 8].try_into().unwrap());
+        let mut data = vec![0; BLOCK_SIZE];
+        dev.read_block(sb.journal_start + 1 + slot as u64, &mut data)?;
+        blocks.insert(home, data);
+    }
+    if blocks.len() != count || checksum(seq, &blocks) != crc {
+        return Ok(Some((seq, None)));
+    }
+    let outside = |home: &u64| {
+        *home == 0
+            || *home >= sb.blocks
+            || (sb.journal_start..sb.journal_start + sb.journal_blocks).contains(home)
+    };
+    if blocks.keys().any(outside) {
+        return Err(corrupt(format!(
+            "journal transaction {} writes outside the image",
+            seq
+        )));
+    }
+    Ok(Some((seq, Some(blocks))))
+}
+
+/// Writes the journal's last intact transaction home again. Returns the
+/// sequence number the next transaction should use.
+pub fn recover<D: Device>(dev: &mut D, sb: &Superblock) -> io::Result<u64> {
+    match last(dev, sb)? {
+        None => Ok(1),
+        Some((seq, None)) => Ok(seq + 1),
+        Some((seq, Some(blocks))) => {
+            for (home, data) in &blocks {
+                dev.write_block(*home, data)?;
+            }
+            dev.sync()?;
+            Ok(seq + 1)
+        }
+    }
+}
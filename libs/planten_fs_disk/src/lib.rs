@@ -0,0 +1,376 @@
+//! A persistent filesystem kept in an image file and served through
+//! `FsServer`, so the generic 9P runtime can export it.
+//!
+//! The image is a classic block-structured filesystem (see `format`):
+//! an inode table, an allocation bitmap and indirect blocks. Every
+//! operation runs as one transaction of whole blocks that goes through a
+//! write-ahead journal before anything is written in place, so after a
+//! crash the next mount finds each operation either done or not begun.
+//! `mkfs` formats a device and `fsck` checks, and can repair, one.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use planten_fs_core::{DMAPPEND, DMDIR, FsServer, Inode, OEXCL, Wstat, create_perm};
+
+use device::Device;
+use disk::Disk;
+use format::{DiskInode, MAX_OWNER, valid_name};
+
+/// Figures for a mounted filesystem, as `df` would show them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Usage {
+    pub blocks: u64,
+    pub used_blocks: u64,
+    pub inodes: u64,
+    pub used_inodes: u64,
+}
+
+/// A mounted image.
+pub struct DiskFs<D> {
+    disk: Mutex<Disk<D>>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// `path` split into its parent directory and final name.
+fn split(path: &str) -> io::Result<(String, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "the root has no parent",
+        ));
+    }
+    Ok((format!("/{}", parent.trim_start_matches('/')), name))
+}
+
+fn to_inode(name: &str, inode: &DiskInode) -> Inode {
+    Inode {
+        name: name.to_string(),
+        data: Vec::new(),
+        children: HashMap::new(),
+        mode: inode.mode,
+        uid: inode.uid.clone(),
+        gid: inode.gid.clone(),
+        atime: inode.atime,
+        mtime: inode.mtime,
+        muid: inode.muid.clone(),
+    }
+}
+
+fn check_owner(owner: &str) -> io::Result<()> {
+    if owner.len() > MAX_OWNER {
+        return Err(invalid("user name too long"));
+    }
+    Ok(())
+}
+
+impl<D: Device> DiskFs<D> {
+    /// Mounts the filesystem on `dev`, replaying its journal first.
+    pub fn mount(dev: D) -> io::Result<Self> {
+        Ok(DiskFs {
+            disk: Mutex::new(Disk::mount(dev)?),
+        })
+    }
+
+    /// Unmounts, handing back the device. Every operation was committed
+    /// when it returned, so there is nothing left to write.
+    pub fn into_device(self) -> D {
+        self.disk.into_inner().unwrap().into_device()
+    }
+
+    /// Runs `change` as one transaction: committed if it succeeds, rolled
+    /// back if it fails.
+    fn change<T>(&self, change: impl FnOnce(&mut Disk<D>) -> io::Result<T>) -> io::Result<T> {
+        let mut disk = self.disk.lock().unwrap();
+        match change(&mut disk).and_then(|value| disk.commit().map(|()| value)) {
+            Ok(value) => Ok(value),
+            Err(err) => {
+                disk.abort()?;
+                Err(err)
+            }
+        }
+    }
+
+    fn look<T>(&self, look: impl FnOnce(&mut Disk<D>) -> io::Result<T>) -> io::Result<T> {
+        look(&mut self.disk.lock().unwrap())
+    }
+
+    pub fn usage(&self) -> Usage {
+        let disk = self.disk.lock().unwrap();
+        let sb = disk.superblock();
+        let (used_inodes, used_blocks) = disk.usage();
+        Usage {
+            blocks: sb.blocks,
+            used_blocks,
+            inodes: sb.inodes as u64 - 1,
+            used_inodes,
+        }
+    }
+
+    /// The inode at `path` without its contents, and its length.
+    pub fn metadata(&self, path: &str) -> io::Result<(Inode, u64)> {
+        self.look(|disk| {
+            let inum = disk.resolve(path)?;
+            let inode = disk.inode(inum)?;
+            let name = split(path).map_or("/", |(_, name)| name);
+            Ok((to_inode(name, &inode), inode.length))
+        })
+    }
+
+    /// Names in the directory at `path`.
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        self.look(|disk| {
+            let inum = disk.resolve(path)?;
+            let inode = disk.inode(inum)?;
+            if !inode.is_dir() {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            Ok(disk.read_dir(&inode)?.into_keys().collect())
+        })
+    }
+
+    /// Up to `count` bytes of the file at `path` from `offset`.
+    pub fn read_at(&self, path: &str, offset: u64, count: usize) -> io::Result<Vec<u8>> {
+        self.look(|disk| {
+            let inum = disk.resolve(path)?;
+            let inode = disk.inode(inum)?;
+            if inode.is_dir() {
+                return Err(ErrorKind::IsADirectory.into());
+            }
+            disk.read_data(&inode, offset, count)
+        })
+    }
+
+    /// Creates `name` in the directory `parent` for `uname`, with the
+    /// semantics of `FsServer::create`.
+    pub fn try_create(
+        &self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> io::Result<()> {
+        if !valid_name(name) {
+            return Err(invalid("bad file name"));
+        }
+        check_owner(uname)?;
+        let now = current_timestamp();
+        self.change(|disk| {
+            let dir_inum = disk.resolve(parent)?;
+            let mut dir = disk.inode(dir_inum)?;
+            if !dir.is_dir() {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            let mut entries = disk.read_dir(&dir)?;
+            if let Some(&existing) = entries.get(name) {
+                let mut inode = disk.inode(existing)?;
+                if mode & OEXCL != 0 || perm & DMDIR != 0 || inode.is_dir() {
+                    return Err(ErrorKind::AlreadyExists.into());
+                }
+                disk.truncate(&mut inode, 0)?;
+                inode.mtime = now;
+                inode.muid = uname.to_string();
+                return disk.put_inode(existing, &inode);
+            }
+
+            let inum = disk.free_inode()?;
+            let inode = DiskInode {
+                used: true,
+                mode: create_perm(perm, dir.mode),
+                atime: now,
+                mtime: now,
+                uid: uname.to_string(),
+                gid: dir.gid.clone(),
+                muid: uname.to_string(),
+                ..DiskInode::default()
+            };
+            disk.put_inode(inum, &inode)?;
+            entries.insert(name.to_string(), inum);
+            dir.mtime = now;
+            disk.write_dir(dir_inum, &mut dir, &entries)
+        })
+    }
+
+    /// Writes `data` at `offset` for `uname`; append-only files take it at
+    /// their end. Returns the count written.
+    pub fn try_write(&self, path: &str, offset: u64, data: &[u8], uname: &str) -> io::Result<u32> {
+        check_owner(uname)?;
+        self.change(|disk| {
+            let inum = disk.resolve(path)?;
+            let mut inode = disk.inode(inum)?;
+            if inode.is_dir() {
+                return Err(ErrorKind::IsADirectory.into());
+            }
+            let offset = if inode.mode & DMAPPEND != 0 {
+                inode.length
+            } else {
+                offset
+            };
+            if offset.checked_add(data.len() as u64).is_none() {
+                return Err(ErrorKind::FileTooLarge.into());
+            }
+            inode.mtime = current_timestamp();
+            inode.muid = uname.to_string();
+            disk.write_data(inum, &mut inode, offset, data)?;
+            Ok(data.len() as u32)
+        })
+    }
+
+    /// Empties the file at `path` for `uname`, as an open with OTRUNC does,
+    /// freeing its blocks in one transaction.
+    pub fn try_truncate(&self, path: &str, uname: &str) -> io::Result<()> {
+        check_owner(uname)?;
+        self.change(|disk| {
+            let inum = disk.resolve(path)?;
+            let mut inode = disk.inode(inum)?;
+            if inode.is_dir() {
+                return Err(ErrorKind::IsADirectory.into());
+            }
+            disk.truncate(&mut inode, 0)?;
+            inode.mtime = current_timestamp();
+            inode.muid = uname.to_string();
+            disk.put_inode(inum, &inode)
+        })
+    }
+
+    /// Removes the file or empty directory at `path`.
+    pub fn try_remove(&self, path: &str) -> io::Result<()> {
+        let (parent, name) = split(path)?;
+        self.change(|disk| {
+            let dir_inum = disk.resolve(&parent)?;
+            let mut dir = disk.inode(dir_inum)?;
+            let mut entries = disk.read_dir(&dir)?;
+            let inum = *entries.get(name).ok_or(ErrorKind::NotFound)?;
+            let mut inode = disk.inode(inum)?;
+            if inode.is_dir() && !disk.read_dir(&inode)?.is_empty() {
+                return Err(ErrorKind::DirectoryNotEmpty.into());
+            }
+            disk.truncate(&mut inode, 0)?;
+            disk.put_inode(inum, &DiskInode::default())?;
+            entries.remove(name);
+            dir.mtime = current_timestamp();
+            disk.write_dir(dir_inum, &mut dir, &entries)
+        })
+    }
+
+    /// Applies `change` whole or not at all, without checking who asks.
+    /// Names must also fit a directory entry and lengths the block map.
+    pub fn try_wstat(&self, path: &str, change: &Wstat) -> io::Result<()> {
+        for owner in [&change.uid, &change.gid].into_iter().flatten() {
+            check_owner(owner)?;
+        }
+        self.change(|disk| {
+            let inum = disk.resolve(path)?;
+            let mut inode = disk.inode(inum)?;
+            let name = split(path).map_or("/", |(_, name)| name);
+            let old = to_inode(name, &inode);
+            change.check(&old, inode.length).map_err(invalid)?;
+            let new = change.apply(&old);
+            (inode.mode, inode.atime, inode.mtime) = (new.mode, new.atime, new.mtime);
+            (inode.uid, inode.gid) = (new.uid, new.gid);
+            if let Some(length) = change.new_length(inode.length) {
+                disk.truncate(&mut inode, length)?;
+            }
+            disk.put_inode(inum, &inode)?;
+
+            if change.renames(name) {
+                let new_name = new.name.as_str();
+                if !valid_name(new_name) {
+                    return Err(invalid("bad file name"));
+                }
+                let (parent, _) = split(path)?;
+                let dir_inum = disk.resolve(&parent)?;
+                let mut dir = disk.inode(dir_inum)?;
+                let mut entries = disk.read_dir(&dir)?;
+                if entries.contains_key(new_name) {
+                    return Err(ErrorKind::AlreadyExists.into());
+                }
+                entries.remove(name);
+                entries.insert(new_name.to_string(), inum);
+                disk.write_dir(dir_inum, &mut dir, &entries)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<D: Device> FsServer for DiskFs<D> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.metadata(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.read_at(path, 0, usize::MAX).ok()
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.try_write(path, offset, data, uname).ok()
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.try_remove(path).ok()
+    }
+
+    /// Leaves the contents out; `read_at` reads them.
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.metadata(path).ok().map(|(inode, _)| inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        DiskFs::metadata(self, path).ok()
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        DiskFs::read_at(self, path, offset, count as usize).ok()
+    }
+
+    /// Runtimes check the client's permissions first.
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.try_wstat(path, change).ok()
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.try_create(parent, name, perm, mode, uname).ok()
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        self.try_truncate(path, uname).ok()
+    }
+}
+
+fn current_timestamp() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32
+}
+
+pub mod device;
+mod disk;
+pub mod format;
+pub mod fsck;
+mod journal;
+pub mod mkfs;
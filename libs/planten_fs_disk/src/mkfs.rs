@@ -0,0 +1,96 @@
+//! Writing an empty filesystem onto a device.
+
+use std::io::{self, ErrorKind};
+
+use planten_fs_core::DMDIR;
+
+use crate::device::Device;
+use crate::format::{BLOCK_SIZE, DiskInode, INODE_SIZE, MAX_OWNER, ROOT_INODE, Superblock};
+
+/// Shape of a new filesystem. `Default` sizes the inode table and the
+/// journal from the device.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Inodes to make room for; `None` is one per four blocks.
+    pub inodes: Option<u32>,
+    /// Journal size in blocks, which bounds how much one transaction can
+    /// change at once; `None` is a sixty-fourth of the device, between 16
+    /// and 1024 blocks.
+    pub journal_blocks: Option<u64>,
+    /// Owner of the root directory.
+    pub uid: String,
+    pub gid: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            inodes: None,
+            journal_blocks: None,
+            uid: "user".to_string(),
+            gid: "group".to_string(),
+        }
+    }
+}
+
+/// Formats `dev` as an empty filesystem whose root directory belongs to
+/// `options.uid`. Everything on the device is lost.
+pub fn mkfs<D: Device>(dev: &mut D, options: &Options) -> io::Result<Superblock> {
+    let blocks = dev.blocks();
+    let inodes = options
+        .inodes
+        .unwrap_or_else(|| u32::try_from(blocks / 4).unwrap_or(u32::MAX - 1).max(16));
+    let journal_blocks = options
+        .journal_blocks
+        .unwrap_or_else(|| (blocks / 64).clamp(16, 1024));
+    let sb = Superblock::layout(blocks, inodes, journal_blocks)?;
+    for owner in [&options.uid, &options.gid] {
+        if owner.len() > MAX_OWNER {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "owner name too long",
+            ));
+        }
+    }
+
+    let zero = vec![0; BLOCK_SIZE];
+    // Whatever image was here stops being one before anything else changes.
+    dev.write_block(0, &zero)?;
+    dev.sync()?;
+    // Clear the journal and the metadata; data blocks are free whatever
+    // they hold.
+    for block in sb.journal_start..sb.data_start {
+        dev.write_block(block, &zero)?;
+    }
+
+    let mut bitmap = vec![0u8; sb.bitmap_blocks as usize * BLOCK_SIZE];
+    for block in 0..sb.data_start {
+        bitmap[(block / 8) as usize] |= 1 << (block % 8);
+    }
+    for (n, chunk) in bitmap.chunks(BLOCK_SIZE).enumerate() {
+        dev.write_block(sb.bitmap_start + n as u64, chunk)?;
+    }
+
+    let now = crate::current_timestamp();
+    let root = DiskInode {
+        used: true,
+        mode: DMDIR | 0o755,
+        atime: now,
+        mtime: now,
+        uid: options.uid.clone(),
+        gid: options.gid.clone(),
+        muid: options.uid.clone(),
+        ..DiskInode::default()
+    };
+    let (block, at) = sb.inode_location(ROOT_INODE);
+    let mut table = zero.clone();
+    root.encode_into(&mut table[at..at + INODE_SIZE]);
+    dev.write_block(block, &table)?;
+
+    dev.sync()?;
+    // The superblock goes last, so a half-made image is never mistaken for
+    // a filesystem.
+    dev.write_block(0, &sb.encode())?;
+    dev.sync()?;
+    Ok(sb)
+}
@@ -0,0 +1,176 @@
+use std::io;
+
+use planten_fs_core::{DMDIR, OWRITE};
+use planten_fs_disk::DiskFs;
+use planten_fs_disk::device::{Device, MemDevice};
+use planten_fs_disk::format::BLOCK_SIZE;
+use planten_fs_disk::fsck::fsck;
+use planten_fs_disk::mkfs::{Options, mkfs};
+
+/// A device that loses power after a number of writes. Writes since the
+/// last sync are not durable: when the power goes, only some of them, in
+/// no particular order, make it to the medium.
+struct Failing {
+    durable: MemDevice,
+    pending: Vec<(u64, Vec<u8>)>,
+    writes_left: usize,
+    failed: bool,
+}
+
+impl Failing {
+    fn new(durable: MemDevice, writes_left: usize) -> Self {
+        Failing {
+            durable,
+            pending: Vec::new(),
+            writes_left,
+            failed: false,
+        }
+    }
+
+    fn flush(&mut self, keep: impl Fn(usize) -> bool) {
+        for (n, (block, data)) in std::mem::take(&mut self.pending).into_iter().enumerate() {
+            if keep(n) {
+                self.durable.write_block(block, &data).unwrap();
+            }
+        }
+    }
+}
+
+impl Device for Failing {
+    fn blocks(&self) -> u64 {
+        self.durable.blocks()
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self.pending.iter().rev().find(|(at, _)| *at == block) {
+            Some((_, data)) => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            None => self.durable.read_block(block, buf),
+        }
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> io::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        if self.writes_left == 0 {
+            self.failed = true;
+            // Every other pending write, the later ones first.
+            let count = self.pending.len();
+            self.pending.reverse();
+            self.flush(|n| n % 2 == 0 || n + 1 == count);
+            return Ok(());
+        }
+        self.writes_left -= 1;
+        self.pending.push((block, buf.to_vec()));
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if !self.failed {
+            self.flush(|_| true);
+        }
+        Ok(())
+    }
+}
+
+fn old_image() -> MemDevice {
+    let mut dev = MemDevice::new(512);
+    mkfs(&mut dev, &Options::default()).unwrap();
+    let fs = DiskFs::mount(dev).unwrap();
+    fs.try_create("/", "dir", DMDIR | 0o777, 0, "user").unwrap();
+    fs.try_create("/dir", "file", 0o666, OWRITE, "user")
+        .unwrap();
+    fs.try_write("/dir/file", 0, &vec![b'o'; 3 * BLOCK_SIZE], "user")
+        .unwrap();
+    fs.into_device()
+}
+
+/// Runs `change` against `image` with the power failing after each
+/// possible number of writes, and hands every surviving image, checked by
+/// fsck, to `judge`. Stops once `change` ran to completion.
+fn crash_everywhere(
+    image: &MemDevice,
+    change: impl Fn(&DiskFs<Failing>),
+    judge: impl Fn(&DiskFs<MemDevice>),
+) {
+    for writes in 0.. {
+        let dev = Failing::new(image.clone(), writes);
+        let fs = DiskFs::mount(dev).unwrap();
+        change(&fs);
+        let failing = fs.into_device();
+        let mut survivor = failing.durable;
+        let report = fsck(&mut survivor, false).unwrap();
+        assert!(
+            report.is_clean(),
+            "after {} writes: {:?}",
+            writes,
+            report.problems
+        );
+        judge(&DiskFs::mount(survivor).unwrap());
+        if !failing.failed {
+            return;
+        }
+    }
+}
+
+#[test]
+fn overwrites_are_all_or_nothing() {
+    crash_everywhere(
+        &old_image(),
+        |fs| {
+            let _ = fs.try_write("/dir/file", 100, &vec![b'n'; 3 * BLOCK_SIZE], "user");
+        },
+        |fs| {
+            let data = fs.read_at("/dir/file", 0, usize::MAX).unwrap();
+            let old = vec![b'o'; 3 * BLOCK_SIZE];
+            let mut new = vec![b'o'; 100];
+            new.extend(vec![b'n'; 3 * BLOCK_SIZE]);
+            assert!(data == old || data == new, "torn write");
+        },
+    );
+}
+
+#[test]
+fn creates_and_removes_are_all_or_nothing() {
+    crash_everywhere(
+        &old_image(),
+        |fs| {
+            let _ = fs.try_create("/", "new", 0o666, OWRITE, "user");
+            let _ = fs.try_write("/new", 0, b"fresh", "user");
+            let _ = fs.try_remove("/dir/file");
+        },
+        |fs| {
+            let root = fs.list("/").unwrap();
+            if root.contains(&"new".to_string()) {
+                let data = fs.read_at("/new", 0, 100).unwrap();
+                assert!(data.is_empty() || data == b"fresh");
+            }
+            let dir = fs.list("/dir").unwrap();
+            if dir.is_empty() {
+                assert_eq!(fs.read_at("/new", 0, 100).unwrap(), b"fresh");
+            } else {
+                assert_eq!(fs.read_at("/dir/file", 0, 10).unwrap(), b"oooooooooo");
+            }
+        },
+    );
+}
+
+#[test]
+fn interrupted_truncation_leaves_no_leaked_blocks() {
+    crash_everywhere(
+        &old_image(),
+        |fs| {
+            let _ = fs.try_remove("/dir/file");
+            let _ = fs.try_remove("/dir");
+        },
+        |fs| {
+            let root = fs.list("/").unwrap();
+            if root.is_empty() {
+                assert!(fs.metadata("/dir/file").is_err());
+            }
+        },
+    );
+}
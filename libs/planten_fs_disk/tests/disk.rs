@@ -0,0 +1,305 @@
+use planten_fs_core::{DMAPPEND, DMDIR, FsServer, OEXCL, OWRITE, Wstat};
+use planten_fs_disk::DiskFs;
+use planten_fs_disk::device::{FileDevice, MemDevice};
+use planten_fs_disk::format::BLOCK_SIZE;
+use planten_fs_disk::fsck::fsck;
+use planten_fs_disk::mkfs::{Options, mkfs};
+
+fn formatted(blocks: u64, options: &Options) -> MemDevice {
+    let mut dev = MemDevice::new(blocks);
+    mkfs(&mut dev, options).unwrap();
+    dev
+}
+
+fn mounted() -> DiskFs<MemDevice> {
+    DiskFs::mount(formatted(2048, &Options::default())).unwrap()
+}
+
+fn remount(fs: DiskFs<MemDevice>) -> DiskFs<MemDevice> {
+    DiskFs::mount(fs.into_device()).unwrap()
+}
+
+fn assert_clean(fs: DiskFs<MemDevice>) -> DiskFs<MemDevice> {
+    let mut dev = fs.into_device();
+    let report = fsck(&mut dev, false).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+    DiskFs::mount(dev).unwrap()
+}
+
+#[test]
+fn fresh_image_has_an_empty_root() {
+    let fs = mounted();
+    assert!(fs.list("/").unwrap().is_empty());
+    let root = fs.stat("/").unwrap();
+    assert_eq!(root.mode, DMDIR | 0o755);
+    assert_eq!(root.uid, "user");
+    assert_eq!(root.gid, "group");
+    assert_clean(fs);
+}
+
+#[test]
+fn garbage_is_not_mounted() {
+    assert!(DiskFs::mount(MemDevice::new(64)).is_err());
+}
+
+#[test]
+fn files_and_directories_survive_a_remount() {
+    let mut fs = mounted();
+    fs.create("/", "lib", DMDIR | 0o775, 0, "glenda").unwrap();
+    fs.create("/lib", "profile", 0o664, OWRITE, "glenda")
+        .unwrap();
+    fs.write("/lib/profile", 0, b"bind -a $home/bin /bin\n", "glenda")
+        .unwrap();
+
+    let fs = remount(fs);
+    assert_eq!(fs.walk("/").unwrap(), vec!["lib"]);
+    assert_eq!(fs.walk("/lib").unwrap(), vec!["profile"]);
+    assert_eq!(
+        fs.read("/lib/profile").unwrap(),
+        b"bind -a $home/bin /bin\n"
+    );
+    let profile = fs.stat("/lib/profile").unwrap();
+    assert_eq!(profile.mode, 0o644);
+    assert_eq!(profile.uid, "glenda");
+    assert_eq!(profile.gid, "group");
+    assert_eq!(profile.muid, "glenda");
+    assert_clean(fs);
+}
+
+#[test]
+fn create_follows_the_create_rules() {
+    let mut fs = mounted();
+    fs.create("/", "ro", DMDIR | 0o555, 0, "user").unwrap();
+    fs.create("/ro", "f", 0o666, OWRITE, "user").unwrap();
+    assert_eq!(fs.stat("/ro/f").unwrap().mode, 0o444);
+
+    fs.write("/ro/f", 0, b"contents", "user").unwrap();
+    fs.create("/ro", "f", 0o666, OWRITE, "user").unwrap();
+    assert_eq!(fs.read("/ro/f").unwrap(), b"");
+    assert!(
+        fs.create("/ro", "f", 0o666, OWRITE | OEXCL, "user")
+            .is_none()
+    );
+    assert!(fs.create("/", "ro", DMDIR | 0o777, 0, "user").is_none());
+    assert!(fs.create("/ro/f", "g", 0o666, 0, "user").is_none());
+    assert!(fs.create("/", "..", 0o666, 0, "user").is_none());
+    assert!(fs.create("/", &"n".repeat(256), 0o666, 0, "user").is_none());
+    assert!(fs.create("/", "f", 0o666, 0, &"u".repeat(32)).is_none());
+}
+
+#[test]
+fn writes_extend_overwrite_and_append() {
+    let mut fs = mounted();
+    fs.create("/", "f", 0o666, OWRITE, "user").unwrap();
+    fs.write("/f", 0, b"hello world", "user").unwrap();
+    fs.write("/f", 6, b"there", "user").unwrap();
+    assert_eq!(fs.read("/f").unwrap(), b"hello there");
+    fs.write("/f", 13, b"!", "user").unwrap();
+    assert_eq!(fs.read("/f").unwrap(), b"hello there\0\0!");
+    assert_eq!(fs.read_at("/f", 6, 3).unwrap(), b"the");
+
+    fs.create("/", "log", DMAPPEND | 0o666, OWRITE, "user")
+        .unwrap();
+    fs.write("/log", 0, b"one\n", "user").unwrap();
+    fs.write("/log", 0, b"two\n", "user").unwrap();
+    assert_eq!(fs.read("/log").unwrap(), b"one\ntwo\n");
+    assert!(fs.write("/", 0, b"x", "user").is_none());
+}
+
+#[test]
+fn large_sparse_files_use_indirect_blocks() {
+    let mut fs = mounted();
+    fs.create("/", "big", 0o666, OWRITE, "user").unwrap();
+    let empty = fs.usage().used_blocks;
+    let far = (12 + 1024 + 5) * BLOCK_SIZE as u64 + 100;
+    let pattern: Vec<u8> = (0..3 * BLOCK_SIZE).map(|n| (n % 251) as u8).collect();
+    fs.write("/big", 0, &pattern, "user").unwrap();
+    fs.write("/big", 20 * BLOCK_SIZE as u64, &pattern, "user")
+        .unwrap();
+    fs.write("/big", far, b"tail", "user").unwrap();
+    // Three data blocks, three more with the indirect block, and the tail
+    // with the double-indirect block and its table.
+    assert_eq!(fs.usage().used_blocks, empty + 10);
+
+    let fs = remount(fs);
+    assert_eq!(fs.read_at("/big", 0, pattern.len()).unwrap(), pattern);
+    assert_eq!(
+        fs.read_at("/big", 20 * BLOCK_SIZE as u64, pattern.len())
+            .unwrap(),
+        pattern
+    );
+    assert_eq!(fs.read_at("/big", far - 2, 6).unwrap(), b"\0\0tail");
+    assert_eq!(
+        fs.read_at("/big", 500 * BLOCK_SIZE as u64, 4).unwrap(),
+        vec![0; 4]
+    );
+    let (_, length) = fs.metadata("/big").unwrap();
+    assert_eq!(length, far + 4);
+    let fs = assert_clean(fs);
+    fs.try_remove("/big").unwrap();
+    // The root's one block of entries went with its last entry.
+    assert_eq!(fs.usage().used_blocks, empty - 1);
+}
+
+#[test]
+fn truncation_frees_blocks_and_zeroes_the_tail() {
+    let mut fs = mounted();
+    fs.create("/", "f", 0o666, OWRITE, "user").unwrap();
+    let before = fs.usage();
+    fs.write("/f", 0, &vec![7; 40 * BLOCK_SIZE], "user")
+        .unwrap();
+    assert_eq!(fs.usage().used_blocks, before.used_blocks + 41);
+
+    let mut stat = Wstat {
+        length: Some(10),
+        ..Wstat::default()
+    };
+    fs.try_wstat("/f", &stat).unwrap();
+    assert_eq!(fs.usage().used_blocks, before.used_blocks + 1);
+    stat.length = Some(20);
+    fs.try_wstat("/f", &stat).unwrap();
+    let mut expected = vec![7; 10];
+    expected.resize(20, 0);
+    assert_eq!(fs.read("/f").unwrap(), expected);
+
+    stat.length = Some(u64::MAX);
+    assert!(fs.try_wstat("/f", &stat).is_err());
+    assert_eq!(fs.read("/f").unwrap(), expected);
+
+    stat.length = Some(0);
+    fs.wstat("/f", &stat).unwrap();
+    assert_eq!(fs.read("/f").unwrap(), b"");
+    assert_eq!(fs.usage().used_blocks, before.used_blocks);
+    assert_clean(fs);
+}
+
+#[test]
+fn otrunc_empties_the_file_durably() {
+    let mut fs = mounted();
+    fs.create("/", "f", 0o666, OWRITE, "user").unwrap();
+    let before = fs.usage();
+    fs.write("/f", 0, &vec![7; 3 * BLOCK_SIZE], "user").unwrap();
+    fs.truncate("/f", "glenda").unwrap();
+    assert_eq!(fs.usage().used_blocks, before.used_blocks);
+    assert!(fs.truncate("/", "glenda").is_none());
+
+    let fs = assert_clean(remount(fs));
+    let (inode, length) = fs.metadata("/f").unwrap();
+    assert_eq!(length, 0);
+    assert_eq!(inode.muid, "glenda");
+}
+
+#[test]
+fn wstat_renames_and_changes_attributes_atomically() {
+    let mut fs = mounted();
+    fs.create("/", "a", 0o644, OWRITE, "user").unwrap();
+    fs.create("/", "b", 0o644, OWRITE, "user").unwrap();
+    fs.write("/a", 0, b"data", "user").unwrap();
+
+    let stat = Wstat {
+        name: Some("c".to_string()),
+        mode: Some(0o600),
+        gid: Some("sys".to_string()),
+        mtime: Some(1234),
+        ..Wstat::default()
+    };
+    fs.try_wstat("/a", &stat).unwrap();
+    assert_eq!(fs.walk("/").unwrap(), vec!["b", "c"]);
+    let c = fs.stat("/c").unwrap();
+    assert_eq!((c.mode, c.gid.as_str(), c.mtime), (0o600, "sys", 1234));
+    assert_eq!(fs.read("/c").unwrap(), b"data");
+
+    let stat = Wstat {
+        name: Some("b".to_string()),
+        mode: Some(0o666),
+        ..Wstat::default()
+    };
+    assert!(fs.try_wstat("/c", &stat).is_err());
+    assert_eq!(fs.stat("/c").unwrap().mode, 0o600);
+
+    let stat = Wstat {
+        mode: Some(DMDIR | 0o777),
+        ..Wstat::default()
+    };
+    assert!(fs.try_wstat("/c", &stat).is_err());
+}
+
+#[test]
+fn remove_refuses_the_root_and_full_directories() {
+    let mut fs = mounted();
+    let before = fs.usage();
+    fs.create("/", "d", DMDIR | 0o777, 0, "user").unwrap();
+    fs.create("/d", "f", 0o666, OWRITE, "user").unwrap();
+    fs.write("/d/f", 0, b"x", "user").unwrap();
+    assert!(fs.remove("/").is_none());
+    assert!(fs.remove("/d").is_none());
+    fs.remove("/d/f").unwrap();
+    fs.remove("/d").unwrap();
+    assert!(fs.stat("/d").is_none());
+    assert_eq!(fs.usage(), before);
+    assert_clean(fs);
+}
+
+#[test]
+fn running_out_of_space_changes_nothing_but_the_written_prefix() {
+    let mut fs = DiskFs::mount(formatted(96, &Options::default())).unwrap();
+    fs.create("/", "f", 0o666, OWRITE, "user").unwrap();
+    assert!(
+        fs.write("/f", 0, &vec![1; 200 * BLOCK_SIZE], "user")
+            .is_none()
+    );
+    let (_, length) = fs.metadata("/f").unwrap();
+    assert_eq!(length % BLOCK_SIZE as u64, 0);
+    assert!(fs.read("/f").unwrap().iter().all(|byte| *byte == 1));
+
+    let mut fs = assert_clean(fs);
+    fs.remove("/f").unwrap();
+    fs.create("/", "g", 0o666, OWRITE, "user").unwrap();
+    fs.write("/g", 0, b"fits", "user").unwrap();
+    assert_clean(fs);
+}
+
+#[test]
+fn running_out_of_inodes_is_an_error() {
+    let options = Options {
+        inodes: Some(15),
+        ..Options::default()
+    };
+    let mut fs = DiskFs::mount(formatted(256, &options)).unwrap();
+    let free = fs.usage().inodes - fs.usage().used_inodes;
+    for n in 0..free {
+        fs.create("/", &n.to_string(), 0o666, 0, "user").unwrap();
+    }
+    assert!(fs.create("/", "one-more", 0o666, 0, "user").is_none());
+    assert_eq!(fs.walk("/").unwrap().len() as u64, free);
+    assert_clean(fs);
+}
+
+#[test]
+fn writes_larger_than_the_journal_are_committed_in_steps() {
+    let options = Options {
+        journal_blocks: Some(16),
+        ..Options::default()
+    };
+    let mut fs = DiskFs::mount(formatted(1024, &options)).unwrap();
+    fs.create("/", "f", 0o666, OWRITE, "user").unwrap();
+    let data: Vec<u8> = (0..100 * BLOCK_SIZE).map(|n| (n / 7) as u8).collect();
+    fs.write("/f", 0, &data, "user").unwrap();
+    let fs = assert_clean(fs);
+    assert_eq!(fs.read("/f").unwrap(), data);
+}
+
+#[test]
+fn images_live_in_host_files() {
+    let path = std::env::temp_dir().join(format!("planten_fs_disk_{}.img", std::process::id()));
+    let mut dev = FileDevice::create(&path, 256).unwrap();
+    mkfs(&mut dev, &Options::default()).unwrap();
+    let mut fs = DiskFs::mount(dev).unwrap();
+    fs.create("/", "f", 0o666, OWRITE, "user").unwrap();
+    fs.write("/f", 0, b"on disk", "user").unwrap();
+    drop(fs);
+
+    let fs = DiskFs::mount(FileDevice::open(&path).unwrap()).unwrap();
+    assert_eq!(fs.read("/f").unwrap(), b"on disk");
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,201 @@
+use planten_fs_core::{DMDIR, OWRITE};
+use planten_fs_disk::DiskFs;
+use planten_fs_disk::device::{Device, MemDevice};
+use planten_fs_disk::format::{BLOCK_SIZE, DiskInode, INODE_SIZE, Superblock};
+use planten_fs_disk::fsck::fsck;
+use planten_fs_disk::mkfs::{Options, mkfs};
+
+fn populated() -> MemDevice {
+    let mut dev = MemDevice::new(1024);
+    mkfs(&mut dev, &Options::default()).unwrap();
+    let fs = DiskFs::mount(dev).unwrap();
+    fs.try_create("/", "usr", DMDIR | 0o775, 0, "user").unwrap();
+    for name in ["a", "b"] {
+        fs.try_create("/usr", name, 0o664, OWRITE, "user").unwrap();
+        fs.try_write(
+            &format!("/usr/{}", name),
+            0,
+            &vec![name.as_bytes()[0]; 2 * BLOCK_SIZE],
+            "user",
+        )
+        .unwrap();
+    }
+    fs.into_device()
+}
+
+/// Direct access to the metadata of an unmounted image.
+struct Image {
+    dev: MemDevice,
+    sb: Superblock,
+}
+
+impl Image {
+    /// Opens `dev` for tampering. The journal is emptied first, since
+    /// replaying it at the next mount could undo the damage.
+    fn new(mut dev: MemDevice) -> Self {
+        let mut block = vec![0; BLOCK_SIZE];
+        dev.read_block(0, &mut block).unwrap();
+        let sb = Superblock::decode(&block).unwrap();
+        dev.write_block(sb.journal_start, &vec![0; BLOCK_SIZE])
+            .unwrap();
+        Image { dev, sb }
+    }
+
+    fn inode(&mut self, inum: u32) -> DiskInode {
+        let (block, at) = self.sb.inode_location(inum);
+        let mut data = vec![0; BLOCK_SIZE];
+        self.dev.read_block(block, &mut data).unwrap();
+        DiskInode::decode(&data[at..at + INODE_SIZE]).unwrap()
+    }
+
+    fn put_inode(&mut self, inum: u32, inode: &DiskInode) {
+        let (block, at) = self.sb.inode_location(inum);
+        let mut data = vec![0; BLOCK_SIZE];
+        self.dev.read_block(block, &mut data).unwrap();
+        inode.encode_into(&mut data[at..at + INODE_SIZE]);
+        self.dev.write_block(block, &data).unwrap();
+    }
+
+    fn set_bit(&mut self, block: u64, used: bool) {
+        let (bitmap, bit) = self.sb.bitmap_location(block);
+        let mut data = vec![0; BLOCK_SIZE];
+        self.dev.read_block(bitmap, &mut data).unwrap();
+        if used {
+            data[bit / 8] |= 1 << (bit % 8);
+        } else {
+            data[bit / 8] &= !(1 << (bit % 8));
+        }
+        self.dev.write_block(bitmap, &data).unwrap();
+    }
+}
+
+/// Inode numbers in creation order: the root, /usr, /usr/a, /usr/b.
+const USR: u32 = 2;
+const A: u32 = 3;
+const B: u32 = 4;
+
+fn repaired(mut dev: MemDevice, expected: &[&str]) -> DiskFs<MemDevice> {
+    let untouched = dev.clone();
+    let report = fsck(&mut dev, false).unwrap();
+    assert_eq!(dev.bytes(), untouched.bytes(), "a check must not write");
+    for (problem, expected) in report.problems.iter().zip(expected) {
+        assert!(
+            problem.contains(expected),
+            "{:?} lacks {:?}",
+            problem,
+            expected
+        );
+    }
+    assert_eq!(
+        report.problems.len(),
+        expected.len(),
+        "{:?}",
+        report.problems
+    );
+
+    let repair = fsck(&mut dev, true).unwrap();
+    assert_eq!(repair, report);
+    let again = fsck(&mut dev, false).unwrap();
+    assert!(again.is_clean(), "{:?}", again.problems);
+    DiskFs::mount(dev).unwrap()
+}
+
+#[test]
+fn fresh_and_populated_images_are_clean() {
+    let mut dev = MemDevice::new(256);
+    let sb = mkfs(&mut dev, &Options::default()).unwrap();
+    let report = fsck(&mut dev, false).unwrap();
+    assert!(report.is_clean());
+    assert_eq!((report.files, report.directories), (0, 1));
+    assert_eq!(report.blocks, sb.data_start);
+
+    let mut dev = populated();
+    let report = fsck(&mut dev, true).unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems);
+    assert_eq!((report.files, report.directories), (2, 2));
+}
+
+#[test]
+fn dangling_entries_are_removed_and_their_blocks_freed() {
+    let mut image = Image::new(populated());
+    image.put_inode(A, &DiskInode::default());
+
+    let fs = repaired(
+        image.dev,
+        &[
+            "/usr/a names inode 3, which is free",
+            "2 blocks marked in use",
+        ],
+    );
+    assert_eq!(fs.list("/usr").unwrap(), vec!["b"]);
+    assert_eq!(fs.read_at("/usr/b", 0, 2).unwrap(), b"bb");
+    assert_eq!(fs.usage().used_inodes, 3);
+}
+
+#[test]
+fn unreachable_inodes_are_freed() {
+    let mut image = Image::new(populated());
+    let mut usr = image.inode(USR);
+    // Point /usr at no blocks at all, orphaning both files.
+    usr.direct[0] = 0;
+    usr.length = 0;
+    image.put_inode(USR, &usr);
+
+    let fs = repaired(
+        image.dev,
+        &[
+            "inode 3 is in use but unreachable",
+            "inode 4 is in use but unreachable",
+            "5 blocks marked in use",
+        ],
+    );
+    assert!(fs.list("/usr").unwrap().is_empty());
+    assert_eq!(fs.usage().used_inodes, 2);
+}
+
+#[test]
+fn shared_and_wild_pointers_become_holes() {
+    let mut image = Image::new(populated());
+    let a = image.inode(A);
+    let mut b = image.inode(B);
+    b.direct[0] = a.direct[0];
+    b.direct[1] = image.sb.blocks as u32 + 7;
+    image.put_inode(B, &b);
+
+    let fs = repaired(
+        image.dev,
+        &["/usr/b: block", "/usr/b: block", "2 blocks marked in use"],
+    );
+    assert_eq!(
+        fs.read_at("/usr/a", 0, 2 * BLOCK_SIZE).unwrap(),
+        vec![b'a'; 2 * BLOCK_SIZE]
+    );
+    assert_eq!(
+        fs.read_at("/usr/b", 0, 2 * BLOCK_SIZE).unwrap(),
+        vec![0; 2 * BLOCK_SIZE]
+    );
+}
+
+#[test]
+fn bitmap_errors_are_corrected_both_ways() {
+    let mut image = Image::new(populated());
+    let a = image.inode(A);
+    image.set_bit(a.direct[1] as u64, false);
+    image.set_bit(image.sb.blocks - 1, true);
+
+    let fs = repaired(
+        image.dev,
+        &[
+            "1 blocks in use were marked free",
+            "1 blocks marked in use belong to no file",
+        ],
+    );
+    // The freed block must not be handed out again while /usr/a has it.
+    fs.try_create("/", "c", 0o666, OWRITE, "user").unwrap();
+    fs.try_write("/c", 0, &vec![b'c'; BLOCK_SIZE], "user")
+        .unwrap();
+    assert_eq!(
+        fs.read_at("/usr/a", BLOCK_SIZE as u64, BLOCK_SIZE).unwrap(),
+        vec![b'a'; BLOCK_SIZE]
+    );
+}
@@ -0,0 +1,67 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::process;
+
+use planten_fs_fat::format::FatType;
+use planten_fs_fat::mkfs::{Options, mkfs};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_fat_mkfs [--size bytes] [--type 12|16|32] [--cluster sectors]\n\
+         \t[--label name] image\n\
+         Without --size the image keeps its current size."
+    );
+    process::exit(1);
+}
+
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut options = Options::default();
+    let mut size: Option<u64> = None;
+    let mut image = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = Some(value(&mut args)),
+            "--type" => {
+                options.fat_type = Some(match value::<u32>(&mut args) {
+                    12 => FatType::Fat12,
+                    16 => FatType::Fat16,
+                    32 => FatType::Fat32,
+                    _ => usage(),
+                })
+            }
+            "--cluster" => options.sectors_per_cluster = Some(value(&mut args)),
+            "--label" => options.label = value(&mut args),
+            _ if arg.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(arg),
+        }
+    }
+    let image = image.unwrap_or_else(|| usage());
+
+    let file = match size {
+        Some(size) => File::create(&image).and_then(|file| {
+            file.set_len(size)?;
+            Ok(file)
+        }),
+        None => OpenOptions::new().read(true).write(true).open(&image),
+    };
+    match file.and_then(|mut file| mkfs(&mut file, &options)) {
+        Ok(boot) => println!(
+            "{}: {}, {} clusters of {} bytes",
+            image,
+            boot.fat_type(),
+            boot.clusters(),
+            boot.cluster_size()
+        ),
+        Err(err) => {
+            eprintln!("mkfs {}: {}", image, err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::{run_server, run_stdio};
+use planten_fs_core::ReadOnly;
+use planten_fs_fat::FatFs;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5673";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_fat_server [--listen host:port | --stdio] [--read-only]\n\
+         \t[--owner user] [--group group] image\n\
+         Serves a FAT12, FAT16 or FAT32 image; --stdio speaks 9P on standard\n\
+         input and output instead of listening."
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut stdio = false;
+    let mut read_only = false;
+    let mut owner = "user".to_string();
+    let mut group = "group".to_string();
+    let mut image = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--stdio" => stdio = true,
+            "--read-only" => read_only = true,
+            "--owner" => owner = args.next().unwrap_or_else(|| usage()),
+            "--group" => group = args.next().unwrap_or_else(|| usage()),
+            _ if arg.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(arg),
+        }
+    }
+    let image = image.unwrap_or_else(|| usage());
+
+    let mounted = if read_only {
+        File::open(&image).and_then(|file| FatFs::mount(file, &owner, &group))
+    } else {
+        FatFs::open(&image, &owner, &group)
+    };
+    let fs = mounted.unwrap_or_else(|err| {
+        eprintln!("cannot mount {}: {}", image, err);
+        process::exit(1);
+    });
+    let usage = fs.usage();
+    if read_only {
+        let fs = Arc::new(Mutex::new(ReadOnly::new(fs)));
+        if stdio {
+            return run_stdio(fs);
+        }
+        let listener = TcpListener::bind(&listen)?;
+        println!(
+            "FatFs 9P server for {} ({}, read-only) on {}",
+            image, usage.fat_type, listen
+        );
+        return run_server(listener, fs);
+    }
+    let fs = Arc::new(Mutex::new(fs));
+    if stdio {
+        return run_stdio(fs);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!(
+        "FatFs 9P server for {} ({}) on {}",
+        image, usage.fat_type, listen
+    );
+    run_server(listener, fs)
+}
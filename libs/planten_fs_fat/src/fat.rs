@@ -0,0 +1,690 @@
+//! A mounted volume: the allocation table, cluster chains and directory
+//! slots, below the paths and names of `FatFs`.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, ErrorKind, SeekFrom};
+
+use crate::Volume;
+use crate::format::{
+    ATTR_DIRECTORY, ATTR_LONG_NAME, ATTR_VOLUME_ID, BootSector, DELETED, DIR_ENTRY_SIZE, FatType,
+    FsInfo, LAST_LONG_ENTRY, LONG_ENTRY_CHARS, LongEntry, SECTOR_SIZE, ShortEntry, corrupt,
+    long_entries, name_checksum,
+};
+use crate::names::{basis_name, exact_short_name, same_name, short_name_string, with_tail};
+
+/// Most slots a directory may have.
+const MAX_DIR_SLOTS: usize = 65536;
+
+/// The value of entry `cluster` of a table.
+pub(crate) fn get_entry(table: &[u8], fat_type: FatType, cluster: u32) -> u32 {
+    let n = cluster as usize;
+    match fat_type {
+        FatType::Fat12 => {
+            let at = n * 3 / 2;
+            let pair = u16::from_le_bytes([table[at], table[at + 1]]) as u32;
+            if n % 2 == 1 { pair >> 4 } else { pair & 0x0fff }
+        }
+        FatType::Fat16 => u16::from_le_bytes([table[n * 2], table[n * 2 + 1]]) as u32,
+        FatType::Fat32 => {
+            u32::from_le_bytes(table[n * 4..n * 4 + 4].try_into().unwrap()) & 0x0fff_ffff
+        }
+    }
+}
+
+/// Sets entry `cluster` of a table, returning the byte range it touched.
+pub(crate) fn put_entry(
+    table: &mut [u8],
+    fat_type: FatType,
+    cluster: u32,
+    value: u32,
+) -> (usize, usize) {
+    let n = cluster as usize;
+    match fat_type {
+        FatType::Fat12 => {
+            let at = n * 3 / 2;
+            let mut pair = u16::from_le_bytes([table[at], table[at + 1]]);
+            let value = value as u16 & 0x0fff;
+            pair = if n % 2 == 1 {
+                (pair & 0x000f) | (value << 4)
+            } else {
+                (pair & 0xf000) | value
+            };
+            table[at..at + 2].copy_from_slice(&pair.to_le_bytes());
+            (at, at + 2)
+        }
+        FatType::Fat16 => {
+            table[n * 2..n * 2 + 2].copy_from_slice(&(value as u16).to_le_bytes());
+            (n * 2, n * 2 + 2)
+        }
+        FatType::Fat32 => {
+            // The top four bits are reserved and kept.
+            let at = n * 4;
+            let old = u32::from_le_bytes(table[at..at + 4].try_into().unwrap());
+            let value = (old & 0xf000_0000) | (value & 0x0fff_ffff);
+            table[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            (at, at + 4)
+        }
+    }
+}
+
+fn full() -> io::Error {
+    io::Error::new(ErrorKind::StorageFull, "volume is full")
+}
+
+/// A directory: the fixed root of FAT12 and FAT16, or a cluster chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Dir {
+    FixedRoot,
+    Chain(u32),
+}
+
+/// A file or directory as listed in its parent.
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) short: ShortEntry,
+    /// First slot the entry takes, its first long-name entry if it has
+    /// any.
+    pub(crate) first_slot: usize,
+    /// Slot of the short entry.
+    pub(crate) slot: usize,
+}
+
+/// What a path names: the root, or an entry of a directory.
+pub(crate) struct Node {
+    pub(crate) parent: Dir,
+    pub(crate) entry: Option<Entry>,
+}
+
+/// A long name being collected from its entries, last part first.
+struct Pending {
+    checksum: u8,
+    units: Vec<u16>,
+    /// Sequence number the next entry must have.
+    next: u8,
+    first_slot: usize,
+}
+
+pub(crate) struct Fat<V> {
+    vol: V,
+    boot: BootSector,
+    fat_type: FatType,
+    /// The allocation table, as in the active copy on disk.
+    table: Vec<u8>,
+    /// Sectors of `table` changed since the last flush.
+    dirty: BTreeSet<usize>,
+    free: u32,
+    next_free: u32,
+}
+
+impl<V: Volume> Fat<V> {
+    pub(crate) fn mount(mut vol: V) -> io::Result<Self> {
+        let mut sector = [0; SECTOR_SIZE];
+        vol.seek(SeekFrom::Start(0))?;
+        vol.read_exact(&mut sector)?;
+        let boot = BootSector::decode(&sector)?;
+        let fat_type = boot.fat_type();
+        let bps = boot.bytes_per_sector as u64;
+        let mut table = vec![0; boot.fat_sectors as usize * bps as usize];
+        if fat_type.table_bytes(boot.clusters() as u64 + 2) > table.len() as u64 {
+            return Err(corrupt("allocation table too small for the volume"));
+        }
+        let active = if fat_type == FatType::Fat32 && boot.ext_flags & 0x80 != 0 {
+            (boot.ext_flags & 0x0f) as u64
+        } else {
+            0
+        };
+        vol.seek(SeekFrom::Start(
+            (boot.reserved_sectors as u64 + active * boot.fat_sectors as u64) * bps,
+        ))?;
+        vol.read_exact(&mut table)?;
+        if fat_type == FatType::Fat32 && !(2..boot.clusters() + 2).contains(&boot.root_cluster) {
+            return Err(corrupt("root directory cluster out of range"));
+        }
+
+        let mut fat = Fat {
+            vol,
+            boot,
+            fat_type,
+            table,
+            dirty: BTreeSet::new(),
+            free: 0,
+            next_free: 2,
+        };
+        fat.free = (2..fat.end_cluster())
+            .filter(|&cluster| fat.entry(cluster) == 0)
+            .count() as u32;
+        Ok(fat)
+    }
+
+    pub(crate) fn into_volume(self) -> V {
+        self.vol
+    }
+
+    pub(crate) fn boot(&self) -> &BootSector {
+        &self.boot
+    }
+
+    pub(crate) fn free_clusters(&self) -> u32 {
+        self.free
+    }
+
+    /// One past the last cluster number.
+    fn end_cluster(&self) -> u32 {
+        self.boot.clusters() + 2
+    }
+
+    fn entry(&self, cluster: u32) -> u32 {
+        get_entry(&self.table, self.fat_type, cluster)
+    }
+
+    fn set_entry(&mut self, cluster: u32, value: u32) {
+        let (start, end) = put_entry(&mut self.table, self.fat_type, cluster, value);
+        let bps = self.boot.bytes_per_sector as usize;
+        self.dirty.insert(start / bps);
+        self.dirty.insert((end - 1) / bps);
+    }
+
+    fn is_end(&self, value: u32) -> bool {
+        value >= self.fat_type.end_of_chain() - 7
+    }
+
+    /// The clusters of the chain starting at `first`.
+    pub(crate) fn chain(&self, first: u32) -> io::Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        loop {
+            if !(2..self.end_cluster()).contains(&cluster) {
+                return Err(corrupt(format!("cluster {} out of range", cluster)));
+            }
+            if chain.len() >= self.boot.clusters() as usize {
+                return Err(corrupt("cluster chain loops"));
+            }
+            chain.push(cluster);
+            let next = self.entry(cluster);
+            if self.is_end(next) {
+                return Ok(chain);
+            }
+            if next == 0 || next == self.fat_type.bad_cluster() {
+                return Err(corrupt(format!("cluster chain runs into {:#x}", next)));
+            }
+            cluster = next;
+        }
+    }
+
+    /// Allocates `count` clusters as a chain, linked after `after` when
+    /// given.
+    fn alloc(&mut self, count: usize, after: Option<u32>) -> io::Result<Vec<u32>> {
+        if count > self.free as usize {
+            return Err(full());
+        }
+        let mut clusters = Vec::with_capacity(count);
+        let end = self.end_cluster();
+        let mut cluster = self.next_free.clamp(2, end - 1);
+        while clusters.len() < count {
+            if self.entry(cluster) == 0 {
+                clusters.push(cluster);
+            }
+            cluster = if cluster + 1 == end { 2 } else { cluster + 1 };
+        }
+        let eoc = self.fat_type.end_of_chain();
+        for (n, &cluster) in clusters.iter().enumerate() {
+            self.set_entry(cluster, clusters.get(n + 1).copied().unwrap_or(eoc));
+        }
+        if let (Some(last), Some(&first)) = (after, clusters.first()) {
+            self.set_entry(last, first);
+        }
+        self.free -= count as u32;
+        self.next_free = cluster;
+        Ok(clusters)
+    }
+
+    fn release(&mut self, clusters: &[u32]) {
+        for &cluster in clusters {
+            self.set_entry(cluster, 0);
+        }
+        self.free += clusters.len() as u32;
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        (self.boot.data_start() + (cluster as u64 - 2) * self.boot.sectors_per_cluster as u64)
+            * self.boot.bytes_per_sector as u64
+    }
+
+    fn read_bytes(&mut self, at: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.vol.seek(SeekFrom::Start(at))?;
+        self.vol.read_exact(buf)
+    }
+
+    fn write_bytes(&mut self, at: u64, data: &[u8]) -> io::Result<()> {
+        self.vol.seek(SeekFrom::Start(at))?;
+        self.vol.write_all(data)
+    }
+
+    /// Writes the changed parts of the table to every copy, and FAT32's
+    /// free cluster hint.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        let bps = self.boot.bytes_per_sector as usize;
+        let copies: Vec<u64> = if self.fat_type == FatType::Fat32 && self.boot.ext_flags & 0x80 != 0
+        {
+            vec![(self.boot.ext_flags & 0x0f) as u64]
+        } else {
+            (0..self.boot.fats as u64).collect()
+        };
+        for sector in std::mem::take(&mut self.dirty) {
+            let bytes = self.table[sector * bps..(sector + 1) * bps].to_vec();
+            for &copy in &copies {
+                let at = (self.boot.reserved_sectors as u64
+                    + copy * self.boot.fat_sectors as u64
+                    + sector as u64)
+                    * bps as u64;
+                self.write_bytes(at, &bytes)?;
+            }
+        }
+        if self.fat_type == FatType::Fat32 && self.boot.fs_info != 0 {
+            let at = self.boot.fs_info as u64 * bps as u64;
+            let mut sector = [0; SECTOR_SIZE];
+            self.read_bytes(at, &mut sector)?;
+            let info = FsInfo {
+                free_clusters: self.free,
+                next_free: self.next_free,
+            };
+            if FsInfo::decode(&sector) != Some(info) {
+                self.write_bytes(at, &info.encode())?;
+            }
+        }
+        self.vol.flush()
+    }
+
+    /// Reads from the contents of `chain` at `offset`.
+    fn read_span(&mut self, chain: &[u32], offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let cs = self.boot.cluster_size() as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let cluster = chain[(at / cs) as usize];
+            let within = at % cs;
+            let n = (buf.len() - done).min((cs - within) as usize);
+            self.read_bytes(
+                self.cluster_offset(cluster) + within,
+                &mut buf[done..done + n],
+            )?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn write_span(&mut self, chain: &[u32], offset: u64, data: &[u8]) -> io::Result<()> {
+        let cs = self.boot.cluster_size() as u64;
+        let mut done = 0;
+        while done < data.len() {
+            let at = offset + done as u64;
+            let cluster = chain[(at / cs) as usize];
+            let within = at % cs;
+            let n = (data.len() - done).min((cs - within) as usize);
+            self.write_bytes(self.cluster_offset(cluster) + within, &data[done..done + n])?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn zero_span(&mut self, chain: &[u32], mut offset: u64, end: u64) -> io::Result<()> {
+        let zeros = vec![0; self.boot.cluster_size()];
+        while offset < end {
+            let n = (end - offset).min(zeros.len() as u64) as usize;
+            self.write_span(chain, offset, &zeros[..n])?;
+            offset += n as u64;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn root_dir(&self) -> Dir {
+        if self.fat_type == FatType::Fat32 {
+            Dir::Chain(self.boot.root_cluster)
+        } else {
+            Dir::FixedRoot
+        }
+    }
+
+    /// The directory `node` names.
+    pub(crate) fn dir_of(&self, node: &Node) -> io::Result<Dir> {
+        match &node.entry {
+            None => Ok(self.root_dir()),
+            Some(entry) if !entry.short.is_dir() => Err(ErrorKind::NotADirectory.into()),
+            Some(entry) if entry.short.cluster == 0 => Err(corrupt("directory without clusters")),
+            Some(entry) => Ok(Dir::Chain(entry.short.cluster)),
+        }
+    }
+
+    /// Byte offsets of the pieces of `dir` and the length of each.
+    fn extents(&self, dir: Dir) -> io::Result<(Vec<u64>, usize)> {
+        match dir {
+            Dir::FixedRoot => Ok((
+                vec![self.boot.root_dir_start() * self.boot.bytes_per_sector as u64],
+                self.boot.root_entries as usize * DIR_ENTRY_SIZE,
+            )),
+            Dir::Chain(first) => Ok((
+                self.chain(first)?
+                    .into_iter()
+                    .map(|cluster| self.cluster_offset(cluster))
+                    .collect(),
+                self.boot.cluster_size(),
+            )),
+        }
+    }
+
+    fn read_slots(&mut self, dir: Dir) -> io::Result<Vec<u8>> {
+        let (extents, len) = self.extents(dir)?;
+        let mut slots = vec![0; extents.len() * len];
+        for (n, at) in extents.into_iter().enumerate() {
+            self.read_bytes(at, &mut slots[n * len..(n + 1) * len])?;
+        }
+        Ok(slots)
+    }
+
+    fn write_slot(
+        &mut self,
+        dir: Dir,
+        slot: usize,
+        bytes: &[u8; DIR_ENTRY_SIZE],
+    ) -> io::Result<()> {
+        let (extents, len) = self.extents(dir)?;
+        let at = slot * DIR_ENTRY_SIZE;
+        let extent = extents
+            .get(at / len)
+            .ok_or_else(|| corrupt("directory slot out of range"))?;
+        self.write_bytes(extent + (at % len) as u64, bytes)
+    }
+
+    /// The files and directories in `dir`, without `.`, `..` and volume
+    /// labels.
+    pub(crate) fn entries(&mut self, dir: Dir) -> io::Result<Vec<Entry>> {
+        let slots = self.read_slots(dir)?;
+        let mut entries = Vec::new();
+        let mut pending: Option<Pending> = None;
+        for (slot, bytes) in slots.chunks(DIR_ENTRY_SIZE).enumerate() {
+            match bytes[0] {
+                0 => break,
+                DELETED => {
+                    pending = None;
+                    continue;
+                }
+                _ => {}
+            }
+            if bytes[11] & 0x3f == ATTR_LONG_NAME {
+                let long = LongEntry::decode(bytes);
+                let order = long.order & !LAST_LONG_ENTRY;
+                if long.order & LAST_LONG_ENTRY != 0 {
+                    pending = (1..=20).contains(&order).then(|| Pending {
+                        checksum: long.checksum,
+                        units: vec![0; order as usize * LONG_ENTRY_CHARS],
+                        next: order,
+                        first_slot: slot,
+                    });
+                }
+                match &mut pending {
+                    Some(name) if name.next == order && name.checksum == long.checksum => {
+                        let at = (order as usize - 1) * LONG_ENTRY_CHARS;
+                        name.units[at..at + LONG_ENTRY_CHARS].copy_from_slice(&long.chars);
+                        name.next -= 1;
+                    }
+                    _ => pending = None,
+                }
+                continue;
+            }
+            let short = ShortEntry::decode(bytes);
+            let pending = pending.take();
+            if short.attr & ATTR_VOLUME_ID != 0 || short.name[0] == b'.' {
+                continue;
+            }
+            let long = pending
+                .filter(|name| name.next == 0 && name.checksum == name_checksum(&short.name));
+            let (name, first_slot) = match long {
+                Some(long) => {
+                    let end = long.units.iter().position(|&unit| unit == 0);
+                    let units = &long.units[..end.unwrap_or(long.units.len())];
+                    (String::from_utf16_lossy(units), long.first_slot)
+                }
+                None => (short_name_string(&short.name, short.case), slot),
+            };
+            entries.push(Entry {
+                name,
+                short,
+                first_slot,
+                slot,
+            });
+        }
+        Ok(entries)
+    }
+
+    pub(crate) fn find(&mut self, dir: Dir, name: &str) -> io::Result<Option<Entry>> {
+        Ok(self
+            .entries(dir)?
+            .into_iter()
+            .find(|entry| same_name(&entry.name, name)))
+    }
+
+    pub(crate) fn resolve(&mut self, path: &str) -> io::Result<Node> {
+        let mut node = Node {
+            parent: self.root_dir(),
+            entry: None,
+        };
+        for part in path.split('/').filter(|part| !part.is_empty()) {
+            let dir = self.dir_of(&node).map_err(|_| ErrorKind::NotFound)?;
+            let entry = self.find(dir, part)?.ok_or(ErrorKind::NotFound)?;
+            node = Node {
+                parent: dir,
+                entry: Some(entry),
+            };
+        }
+        Ok(node)
+    }
+
+    pub(crate) fn put_short(&mut self, dir: Dir, entry: &Entry) -> io::Result<()> {
+        self.write_slot(dir, entry.slot, &entry.short.encode())
+    }
+
+    /// Adds `name` to `dir` with the fields of `short`, choosing its short
+    /// name and writing long-name entries when the name needs them.
+    pub(crate) fn add(&mut self, dir: Dir, name: &str, mut short: ShortEntry) -> io::Result<Entry> {
+        let slots = self.read_slots(dir)?;
+        let taken: HashSet<[u8; 11]> = slots
+            .chunks(DIR_ENTRY_SIZE)
+            .take_while(|bytes| bytes[0] != 0)
+            .filter(|bytes| bytes[0] != DELETED && bytes[11] & 0x3f != ATTR_LONG_NAME)
+            .map(|bytes| ShortEntry::decode(bytes).name)
+            .collect();
+        let mut long = Vec::new();
+        match exact_short_name(name).filter(|(short, _)| !taken.contains(short)) {
+            Some((exact, case)) => {
+                short.name = exact;
+                short.case = case;
+            }
+            None => {
+                let basis = basis_name(name);
+                short.name = (1..1_000_000)
+                    .map(|n| with_tail(&basis, n))
+                    .find(|candidate| !taken.contains(candidate))
+                    .ok_or_else(|| io::Error::other("no short name left"))?;
+                short.case = 0;
+                long = long_entries(name, name_checksum(&short.name));
+            }
+        }
+
+        let need = long.len() + 1;
+        let free = |bytes: &[u8]| bytes[0] == 0 || bytes[0] == DELETED;
+        let count = slots.len() / DIR_ENTRY_SIZE;
+        let mut run = 0;
+        let mut start = None;
+        for (slot, bytes) in slots.chunks(DIR_ENTRY_SIZE).enumerate() {
+            run = if free(bytes) { run + 1 } else { 0 };
+            if run == need {
+                start = Some(slot + 1 - need);
+                break;
+            }
+        }
+        let start = match (start, dir) {
+            (Some(start), _) => start,
+            (None, Dir::FixedRoot) => {
+                return Err(io::Error::new(
+                    ErrorKind::StorageFull,
+                    "root directory is full",
+                ));
+            }
+            (None, Dir::Chain(first)) => {
+                let per_cluster = self.boot.cluster_size() / DIR_ENTRY_SIZE;
+                let clusters = (need - run).div_ceil(per_cluster);
+                if count + clusters * per_cluster > MAX_DIR_SLOTS {
+                    return Err(io::Error::new(ErrorKind::StorageFull, "directory is full"));
+                }
+                let last = *self.chain(first)?.last().unwrap();
+                let added = self.alloc(clusters, Some(last))?;
+                let zeros = vec![0; self.boot.cluster_size()];
+                for cluster in added {
+                    self.write_bytes(self.cluster_offset(cluster), &zeros)?;
+                }
+                count - run
+            }
+        };
+        for (n, long) in long.iter().enumerate() {
+            self.write_slot(dir, start + n, &long.encode())?;
+        }
+        let entry = Entry {
+            name: name.to_string(),
+            short,
+            first_slot: start,
+            slot: start + need - 1,
+        };
+        self.put_short(dir, &entry)?;
+        Ok(entry)
+    }
+
+    /// Frees the slots of `entry`, leaving its clusters alone.
+    pub(crate) fn unlink(&mut self, dir: Dir, entry: &Entry) -> io::Result<()> {
+        let (extents, len) = self.extents(dir)?;
+        let mut bytes = [0; DIR_ENTRY_SIZE];
+        for slot in entry.first_slot..=entry.slot {
+            let at = extents[slot * DIR_ENTRY_SIZE / len] + (slot * DIR_ENTRY_SIZE % len) as u64;
+            self.read_bytes(at, &mut bytes)?;
+            bytes[0] = DELETED;
+            self.write_bytes(at, &bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Makes a new directory's first cluster, holding `.` and `..`.
+    pub(crate) fn new_dir(&mut self, parent: Dir, template: &ShortEntry) -> io::Result<u32> {
+        let cluster = self.alloc(1, None)?[0];
+        let mut bytes = vec![0; self.boot.cluster_size()];
+        let parent_cluster = match parent {
+            Dir::Chain(first) if parent != self.root_dir() => first,
+            // `..` of a directory in the root is 0, even on FAT32.
+            _ => 0,
+        };
+        for (n, (name, cluster)) in [
+            (*b".          ", cluster),
+            (*b"..         ", parent_cluster),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let dot = ShortEntry {
+                name,
+                attr: ATTR_DIRECTORY,
+                cluster,
+                ..*template
+            };
+            bytes[n * DIR_ENTRY_SIZE..(n + 1) * DIR_ENTRY_SIZE].copy_from_slice(&dot.encode());
+        }
+        self.write_bytes(self.cluster_offset(cluster), &bytes)?;
+        Ok(cluster)
+    }
+
+    /// Frees every cluster of `entry`, which must not be the root.
+    pub(crate) fn free_clusters_of(&mut self, entry: &mut Entry) -> io::Result<()> {
+        if entry.short.cluster != 0 {
+            let chain = self.chain(entry.short.cluster)?;
+            self.release(&chain);
+            entry.short.cluster = 0;
+        }
+        Ok(())
+    }
+
+    /// Up to `count` bytes of the file `entry` from `offset`.
+    pub(crate) fn read_file(
+        &mut self,
+        entry: &Entry,
+        offset: u64,
+        count: usize,
+    ) -> io::Result<Vec<u8>> {
+        let size = entry.short.size as u64;
+        if offset >= size {
+            return Ok(Vec::new());
+        }
+        let len = (size - offset).min(count as u64) as usize;
+        let chain = self.chain(entry.short.cluster)?;
+        if (chain.len() as u64) < size.div_ceil(self.boot.cluster_size() as u64) {
+            return Err(corrupt("file is longer than its clusters"));
+        }
+        let mut data = vec![0; len];
+        self.read_span(&chain, offset, &mut data)?;
+        Ok(data)
+    }
+
+    /// Writes `data` at `offset` of the file `entry`, growing it and
+    /// filling any gap with zeros. The caller writes the entry back.
+    pub(crate) fn write_file(
+        &mut self,
+        entry: &mut Entry,
+        offset: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let size = entry.short.size as u64;
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(ErrorKind::FileTooLarge.into());
+        }
+        let cs = self.boot.cluster_size() as u64;
+        let mut chain = if entry.short.cluster == 0 {
+            Vec::new()
+        } else {
+            self.chain(entry.short.cluster)?
+        };
+        let need = end.max(size).div_ceil(cs) as usize;
+        if need > chain.len() {
+            let added = self.alloc(need - chain.len(), chain.last().copied())?;
+            if chain.is_empty() {
+                entry.short.cluster = added[0];
+            }
+            chain.extend(added);
+        }
+        if offset > size {
+            self.zero_span(&chain, size, offset)?;
+        }
+        self.write_span(&chain, offset, data)?;
+        entry.short.size = end.max(size) as u32;
+        Ok(())
+    }
+
+    /// Cuts or extends the file `entry` to `len` bytes. The caller writes
+    /// the entry back.
+    pub(crate) fn truncate(&mut self, entry: &mut Entry, len: u64) -> io::Result<()> {
+        let size = entry.short.size as u64;
+        if len >= size {
+            return self.write_file(entry, len, &[]);
+        }
+        if entry.short.cluster != 0 {
+            let chain = self.chain(entry.short.cluster)?;
+            let keep = len.div_ceil(self.boot.cluster_size() as u64) as usize;
+            if keep == 0 {
+                entry.short.cluster = 0;
+            } else if keep < chain.len() {
+                let eoc = self.fat_type.end_of_chain();
+                self.set_entry(chain[keep - 1], eoc);
+            }
+            self.release(&chain[keep.min(chain.len())..]);
+        }
+        entry.short.size = len as u32;
+        Ok(())
+    }
+}
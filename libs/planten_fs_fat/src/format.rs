@@ -0,0 +1,520 @@
+//! The on-disk structures of FAT12, FAT16 and FAT32. Integers are
+//! little-endian.
+//!
+//! ```text
+//! sector 0                 boot sector with the BIOS parameter block
+//! reserved_sectors..       `fats` copies of the file allocation table
+//! (FAT12 and FAT16 only)   the root directory, `root_entries` slots
+//! data_start..             clusters 2.. holding files and directories
+//! ```
+//!
+//! Which of the three a volume is follows from its cluster count alone.
+//! A directory is an array of 32-byte slots: a short (8.3) entry per file,
+//! each optionally preceded by long-name entries holding up to 13 UTF-16
+//! units of its name apiece, last part first.
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+pub const SECTOR_SIZE: usize = 512;
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// The attribute byte of a long-name entry.
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// First name byte of a free slot.
+pub const DELETED: u8 = 0xe5;
+/// Set in the sequence number of the long-name entry holding the end of
+/// a name, which comes first on disk.
+pub const LAST_LONG_ENTRY: u8 = 0x40;
+pub const LONG_ENTRY_CHARS: usize = 13;
+/// Longest name, in UTF-16 units.
+pub const MAX_NAME: usize = 255;
+
+/// Case bits of a short entry, used by Windows NT for names that are all
+/// lower case in one part.
+pub const LOWER_BASE: u8 = 0x08;
+pub const LOWER_EXT: u8 = 0x10;
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_TRAIL: u32 = 0xaa55_0000;
+
+pub(crate) fn corrupt(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// The type of a volume with `clusters` data clusters.
+    pub fn from_clusters(clusters: u32) -> FatType {
+        if clusters < 4085 {
+            FatType::Fat12
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// Fewest and most data clusters a volume of this type can have.
+    pub fn cluster_range(self) -> (u32, u32) {
+        match self {
+            FatType::Fat12 => (1, 4084),
+            FatType::Fat16 => (4085, 65524),
+            FatType::Fat32 => (65525, 0x0fff_fff4),
+        }
+    }
+
+    /// Bytes a table of `entries` entries takes.
+    pub fn table_bytes(self, entries: u64) -> u64 {
+        match self {
+            FatType::Fat12 => (entries * 3).div_ceil(2),
+            FatType::Fat16 => entries * 2,
+            FatType::Fat32 => entries * 4,
+        }
+    }
+
+    /// The largest value of an entry; values from `end_of_chain() - 7` up
+    /// end a chain.
+    pub fn end_of_chain(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0x0fff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    pub fn bad_cluster(self) -> u32 {
+        self.end_of_chain() - 8
+    }
+
+    fn label(self) -> &'static [u8; 8] {
+        match self {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+            FatType::Fat32 => b"FAT32   ",
+        }
+    }
+}
+
+impl fmt::Display for FatType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FatType::Fat12 => "FAT12",
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        })
+    }
+}
+
+/// The BIOS parameter block and the extended boot record after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootSector {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fats: u8,
+    /// Slots in the fixed root directory; 0 on FAT32.
+    pub root_entries: u16,
+    pub total_sectors: u32,
+    pub media: u8,
+    pub fat_sectors: u32,
+    /// FAT32 only: flags choosing a single active table.
+    pub ext_flags: u16,
+    /// FAT32 only: first cluster of the root directory.
+    pub root_cluster: u32,
+    /// FAT32 only: sector of the FSInfo block.
+    pub fs_info: u16,
+    /// FAT32 only: sector of the boot sector's backup copy.
+    pub backup_boot: u16,
+    pub volume_id: u32,
+    pub label: String,
+}
+
+impl BootSector {
+    pub fn decode(sector: &[u8]) -> io::Result<BootSector> {
+        if sector.len() < SECTOR_SIZE || sector[510..512] != [0x55, 0xaa] {
+            return Err(corrupt("not a FAT volume"));
+        }
+        let bytes_per_sector = u16_at(sector, 11);
+        let sectors_per_cluster = sector[13];
+        if !(512..=4096).contains(&bytes_per_sector)
+            || !bytes_per_sector.is_power_of_two()
+            || !sectors_per_cluster.is_power_of_two()
+        {
+            return Err(corrupt("bad sector or cluster size"));
+        }
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32),
+            total => total as u32,
+        };
+        let fat16_sectors = u16_at(sector, 22);
+        let fat32 = fat16_sectors == 0;
+        // The extended boot record moves on FAT32.
+        let ext = if fat32 { 64 } else { 36 };
+        let (volume_id, label) = if sector[ext + 2] == 0x29 {
+            let label = &sector[ext + 7..ext + 18];
+            (
+                u32_at(sector, ext + 3),
+                String::from_utf8_lossy(label).trim_end().to_string(),
+            )
+        } else {
+            (0, String::new())
+        };
+        let boot = BootSector {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors: u16_at(sector, 14),
+            fats: sector[16],
+            root_entries: u16_at(sector, 17),
+            total_sectors,
+            media: sector[21],
+            fat_sectors: if fat32 {
+                u32_at(sector, 36)
+            } else {
+                fat16_sectors as u32
+            },
+            ext_flags: if fat32 { u16_at(sector, 40) } else { 0 },
+            root_cluster: if fat32 { u32_at(sector, 44) } else { 0 },
+            fs_info: if fat32 { u16_at(sector, 48) } else { 0 },
+            backup_boot: if fat32 { u16_at(sector, 50) } else { 0 },
+            volume_id,
+            label: if label == "NO NAME" {
+                String::new()
+            } else {
+                label
+            },
+        };
+        if boot.reserved_sectors == 0 || boot.fats == 0 || boot.fat_sectors == 0 {
+            return Err(corrupt("bad BIOS parameter block"));
+        }
+        if boot.data_start() >= boot.total_sectors as u64 || boot.clusters() == 0 {
+            return Err(corrupt("volume too small for its own tables"));
+        }
+        if fat32 != (boot.fat_type() == FatType::Fat32) {
+            return Err(corrupt("cluster count does not match the FAT type"));
+        }
+        Ok(boot)
+    }
+
+    pub fn encode(&self) -> [u8; SECTOR_SIZE] {
+        let fat_type = self.fat_type();
+        let mut sector = [0u8; SECTOR_SIZE];
+        sector[..3].copy_from_slice(if fat_type == FatType::Fat32 {
+            &[0xeb, 0x58, 0x90]
+        } else {
+            &[0xeb, 0x3c, 0x90]
+        });
+        sector[3..11].copy_from_slice(b"PLANTEN ");
+        sector[11..13].copy_from_slice(&self.bytes_per_sector.to_le_bytes());
+        sector[13] = self.sectors_per_cluster;
+        sector[14..16].copy_from_slice(&self.reserved_sectors.to_le_bytes());
+        sector[16] = self.fats;
+        sector[17..19].copy_from_slice(&self.root_entries.to_le_bytes());
+        match u16::try_from(self.total_sectors) {
+            Ok(total) if fat_type != FatType::Fat32 => {
+                sector[19..21].copy_from_slice(&total.to_le_bytes())
+            }
+            _ => sector[32..36].copy_from_slice(&self.total_sectors.to_le_bytes()),
+        }
+        sector[21] = self.media;
+        // Sectors per track and heads, for the BIOS.
+        sector[24..26].copy_from_slice(&32u16.to_le_bytes());
+        sector[26..28].copy_from_slice(&64u16.to_le_bytes());
+        let ext = if fat_type == FatType::Fat32 {
+            sector[36..40].copy_from_slice(&self.fat_sectors.to_le_bytes());
+            sector[40..42].copy_from_slice(&self.ext_flags.to_le_bytes());
+            sector[44..48].copy_from_slice(&self.root_cluster.to_le_bytes());
+            sector[48..50].copy_from_slice(&self.fs_info.to_le_bytes());
+            sector[50..52].copy_from_slice(&self.backup_boot.to_le_bytes());
+            64
+        } else {
+            sector[22..24].copy_from_slice(&(self.fat_sectors as u16).to_le_bytes());
+            36
+        };
+        sector[ext] = 0x80;
+        sector[ext + 2] = 0x29;
+        sector[ext + 3..ext + 7].copy_from_slice(&self.volume_id.to_le_bytes());
+        sector[ext + 7..ext + 18].copy_from_slice(&pad_label(&self.label));
+        sector[ext + 18..ext + 26].copy_from_slice(fat_type.label());
+        sector[510] = 0x55;
+        sector[511] = 0xaa;
+        sector
+    }
+
+    pub fn root_dir_sectors(&self) -> u64 {
+        (self.root_entries as u64 * DIR_ENTRY_SIZE as u64).div_ceil(self.bytes_per_sector as u64)
+    }
+
+    /// First sector of the root directory on FAT12 and FAT16.
+    pub fn root_dir_start(&self) -> u64 {
+        self.reserved_sectors as u64 + self.fats as u64 * self.fat_sectors as u64
+    }
+
+    /// Sector of cluster 2.
+    pub fn data_start(&self) -> u64 {
+        self.root_dir_start() + self.root_dir_sectors()
+    }
+
+    pub fn clusters(&self) -> u32 {
+        ((self.total_sectors as u64).saturating_sub(self.data_start())
+            / self.sectors_per_cluster as u64) as u32
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        FatType::from_clusters(self.clusters())
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+}
+
+/// `label` as the eleven space-padded bytes boot sectors and volume label
+/// entries hold.
+pub fn pad_label(label: &str) -> [u8; 11] {
+    let mut padded = *b"NO NAME    ";
+    if !label.is_empty() {
+        padded = [b' '; 11];
+        for (slot, byte) in padded.iter_mut().zip(label.bytes()) {
+            *slot = byte.to_ascii_uppercase();
+        }
+    }
+    padded
+}
+
+/// FAT32's hint of how many clusters are free and where to look for one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsInfo {
+    pub free_clusters: u32,
+    pub next_free: u32,
+}
+
+impl FsInfo {
+    pub fn decode(sector: &[u8]) -> Option<FsInfo> {
+        (u32_at(sector, 0) == FSINFO_LEAD
+            && u32_at(sector, 484) == FSINFO_STRUCT
+            && u32_at(sector, 508) == FSINFO_TRAIL)
+            .then(|| FsInfo {
+                free_clusters: u32_at(sector, 488),
+                next_free: u32_at(sector, 492),
+            })
+    }
+
+    pub fn encode(&self) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0u8; SECTOR_SIZE];
+        sector[..4].copy_from_slice(&FSINFO_LEAD.to_le_bytes());
+        sector[484..488].copy_from_slice(&FSINFO_STRUCT.to_le_bytes());
+        sector[488..492].copy_from_slice(&self.free_clusters.to_le_bytes());
+        sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        sector[508..512].copy_from_slice(&FSINFO_TRAIL.to_le_bytes());
+        sector
+    }
+}
+
+/// A short directory entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShortEntry {
+    /// Base name and extension, space-padded, as stored.
+    pub name: [u8; 11],
+    pub attr: u8,
+    /// `LOWER_BASE` and `LOWER_EXT`.
+    pub case: u8,
+    pub ctime: u16,
+    pub cdate: u16,
+    pub adate: u16,
+    pub cluster: u32,
+    pub wtime: u16,
+    pub wdate: u16,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn decode(slot: &[u8]) -> ShortEntry {
+        let mut name: [u8; 11] = slot[..11].try_into().unwrap();
+        // A leading 0x05 stands for a real 0xe5.
+        if name[0] == 0x05 {
+            name[0] = DELETED;
+        }
+        ShortEntry {
+            name,
+            attr: slot[11],
+            case: slot[12],
+            ctime: u16_at(slot, 14),
+            cdate: u16_at(slot, 16),
+            adate: u16_at(slot, 18),
+            cluster: (u16_at(slot, 20) as u32) << 16 | u16_at(slot, 26) as u32,
+            wtime: u16_at(slot, 22),
+            wdate: u16_at(slot, 24),
+            size: u32_at(slot, 28),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut slot = [0u8; DIR_ENTRY_SIZE];
+        slot[..11].copy_from_slice(&self.name);
+        if slot[0] == DELETED {
+            slot[0] = 0x05;
+        }
+        slot[11] = self.attr;
+        slot[12] = self.case;
+        slot[14..16].copy_from_slice(&self.ctime.to_le_bytes());
+        slot[16..18].copy_from_slice(&self.cdate.to_le_bytes());
+        slot[18..20].copy_from_slice(&self.adate.to_le_bytes());
+        slot[20..22].copy_from_slice(&((self.cluster >> 16) as u16).to_le_bytes());
+        slot[22..24].copy_from_slice(&self.wtime.to_le_bytes());
+        slot[24..26].copy_from_slice(&self.wdate.to_le_bytes());
+        slot[26..28].copy_from_slice(&(self.cluster as u16).to_le_bytes());
+        slot[28..32].copy_from_slice(&self.size.to_le_bytes());
+        slot
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+/// The checksum of a short name that each of its long-name entries
+/// carries, tying them to it.
+pub fn name_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Byte offsets of the 13 UTF-16 units in a long-name entry.
+const LONG_CHAR_OFFSETS: [usize; LONG_ENTRY_CHARS] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// A long-name entry: its sequence number, the checksum of its short
+/// name and its part of the name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LongEntry {
+    pub order: u8,
+    pub checksum: u8,
+    pub chars: [u16; LONG_ENTRY_CHARS],
+}
+
+impl LongEntry {
+    pub fn decode(slot: &[u8]) -> LongEntry {
+        LongEntry {
+            order: slot[0],
+            checksum: slot[13],
+            chars: LONG_CHAR_OFFSETS.map(|at| u16_at(slot, at)),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut slot = [0u8; DIR_ENTRY_SIZE];
+        slot[0] = self.order;
+        slot[11] = ATTR_LONG_NAME;
+        slot[13] = self.checksum;
+        for (at, unit) in LONG_CHAR_OFFSETS.iter().zip(self.chars) {
+            slot[*at..at + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        slot
+    }
+}
+
+/// The long-name entries for `name`, in the order they are stored.
+pub fn long_entries(name: &str, checksum: u8) -> Vec<LongEntry> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let parts = units.chunks(LONG_ENTRY_CHARS).collect::<Vec<_>>();
+    let count = parts.len();
+    let mut entries = Vec::with_capacity(count);
+    for (n, part) in parts.into_iter().enumerate().rev() {
+        // A short last part ends in a NUL and is padded with 0xffff.
+        let mut chars = [0xffff; LONG_ENTRY_CHARS];
+        chars[..part.len()].copy_from_slice(part);
+        if part.len() < LONG_ENTRY_CHARS {
+            chars[part.len()] = 0;
+        }
+        let mut order = n as u8 + 1;
+        if n + 1 == count {
+            order |= LAST_LONG_ENTRY;
+        }
+        entries.push(LongEntry {
+            order,
+            checksum,
+            chars,
+        });
+    }
+    entries
+}
+
+const DAYS_BEFORE_1980: i64 = 3652;
+
+/// Seconds since the epoch of a DOS date and time, which are taken to
+/// be UTC.
+pub fn from_dos(date: u16, time: u16) -> u32 {
+    if date == 0 {
+        return 0;
+    }
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).clamp(1, 12) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+    let days = days_from_civil(year, month, day);
+    let seconds =
+        (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3f) as i64 * 60 + (time & 0x1f) as i64 * 2;
+    (days * 86400 + seconds) as u32
+}
+
+/// The DOS date and time of `secs` since the epoch, clamped to the
+/// years DOS can express.
+pub fn to_dos(secs: u32) -> (u16, u16) {
+    let days = (secs / 86400) as i64;
+    if days < DAYS_BEFORE_1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let (year, month, day) = civil_from_days(days);
+    if year > 2107 {
+        return ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29);
+    }
+    let rest = secs % 86400;
+    let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time = (((rest / 3600) << 11) | ((rest / 60 % 60) << 5) | ((rest % 60) / 2)) as u16;
+    (date, time)
+}
+
+// Howard Hinnant's conversions between days since the epoch and dates.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,437 @@
+//! FAT12, FAT16 and FAT32 volumes served through `FsServer`, after
+//! 9front's dossrv.
+//!
+//! `FatFs` mounts an image file, or anything else that reads, writes and
+//! seeks, and serves its tree with long file names. FAT records no
+//! owners, so every file belongs to the user and group given at mount
+//! time; directories get mode 0777 and files 0666, less the write bits
+//! when the read-only attribute is set. Names are matched without regard
+//! to case, as DOS and Windows do. `mkfs` formats a volume.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use planten_fs_core::{DMDIR, FsServer, Inode, OEXCL, Wstat};
+
+use fat::{Entry, Fat, Node};
+use format::{
+    ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, BootSector, FatType, ShortEntry, from_dos, to_dos,
+};
+use names::{short_name_string, valid_name};
+
+/// Storage a volume can live on: an image file, or memory.
+pub trait Volume: Read + Write + Seek + Send {}
+
+impl<T: Read + Write + Seek + Send> Volume for T {}
+
+/// Figures for a mounted volume, as `df` would show them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Usage {
+    pub fat_type: FatType,
+    pub cluster_size: u32,
+    pub clusters: u32,
+    pub free_clusters: u32,
+}
+
+/// A mounted volume.
+pub struct FatFs<V> {
+    fat: Mutex<Fat<V>>,
+    uid: String,
+    gid: String,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// Mode bits FAT attributes stand for.
+fn mode_of(short: &ShortEntry) -> u32 {
+    let mode = if short.is_dir() { DMDIR | 0o777 } else { 0o666 };
+    if short.attr & ATTR_READ_ONLY != 0 {
+        mode & !0o222
+    } else {
+        mode
+    }
+}
+
+impl FatFs<File> {
+    /// Mounts the image at `path` for reading and writing.
+    pub fn open(path: impl AsRef<Path>, uid: &str, gid: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::mount(file, uid, gid)
+    }
+}
+
+impl<V: Volume> FatFs<V> {
+    /// Mounts the filesystem on `vol`, giving its files to `uid` and
+    /// `gid`.
+    pub fn mount(vol: V, uid: &str, gid: &str) -> io::Result<Self> {
+        Ok(FatFs {
+            fat: Mutex::new(Fat::mount(vol)?),
+            uid: uid.to_string(),
+            gid: gid.to_string(),
+        })
+    }
+
+    /// Unmounts, handing back the volume. Every operation was written
+    /// through when it returned.
+    pub fn into_volume(self) -> V {
+        self.fat.into_inner().unwrap().into_volume()
+    }
+
+    pub fn boot_sector(&self) -> BootSector {
+        self.fat.lock().unwrap().boot().clone()
+    }
+
+    pub fn usage(&self) -> Usage {
+        let fat = self.fat.lock().unwrap();
+        let boot = fat.boot();
+        Usage {
+            fat_type: boot.fat_type(),
+            cluster_size: boot.cluster_size() as u32,
+            clusters: boot.clusters(),
+            free_clusters: fat.free_clusters(),
+        }
+    }
+
+    /// Runs `change` and writes the allocation table back, whether or not
+    /// it succeeds: a failed change may have allocated clusters it still
+    /// points at.
+    fn change<T>(&self, change: impl FnOnce(&mut Fat<V>) -> io::Result<T>) -> io::Result<T> {
+        let mut fat = self.fat.lock().unwrap();
+        let result = change(&mut fat);
+        fat.flush()?;
+        result
+    }
+
+    fn look<T>(&self, look: impl FnOnce(&mut Fat<V>) -> io::Result<T>) -> io::Result<T> {
+        look(&mut self.fat.lock().unwrap())
+    }
+
+    /// The inode at `path` without its contents, and its length.
+    pub fn metadata(&self, path: &str) -> io::Result<(Inode, u64)> {
+        let node = self.look(|fat| fat.resolve(path))?;
+        Ok(self.inode(node.entry.as_ref()))
+    }
+
+    /// What `entry`, or the root when there is none, stands for.
+    fn inode(&self, entry: Option<&Entry>) -> (Inode, u64) {
+        let (name, mode, atime, mtime, length) = match entry {
+            None => ("/".to_string(), DMDIR | 0o777, 0, 0, 0),
+            Some(entry) => {
+                let short = &entry.short;
+                (
+                    entry.name.clone(),
+                    mode_of(short),
+                    from_dos(short.adate, 0),
+                    from_dos(short.wdate, short.wtime),
+                    if short.is_dir() { 0 } else { short.size as u64 },
+                )
+            }
+        };
+        let inode = Inode {
+            name,
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode,
+            uid: self.uid.clone(),
+            gid: self.gid.clone(),
+            atime,
+            mtime,
+            muid: self.uid.clone(),
+        };
+        (inode, length)
+    }
+
+    /// The 8.3 name DOS sees for the file at `path`.
+    pub fn short_name(&self, path: &str) -> io::Result<String> {
+        let node = self.look(|fat| fat.resolve(path))?;
+        let entry = node.entry.ok_or_else(|| invalid("the root has no name"))?;
+        Ok(short_name_string(&entry.short.name, 0))
+    }
+
+    /// Names in the directory at `path`.
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        self.look(|fat| {
+            let node = fat.resolve(path)?;
+            let dir = fat.dir_of(&node)?;
+            Ok(fat
+                .entries(dir)?
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect())
+        })
+    }
+
+    /// Up to `count` bytes of the file at `path` from `offset`.
+    pub fn read_at(&self, path: &str, offset: u64, count: usize) -> io::Result<Vec<u8>> {
+        self.look(|fat| match fat.resolve(path)?.entry {
+            Some(entry) if !entry.short.is_dir() => fat.read_file(&entry, offset, count),
+            _ => Err(ErrorKind::IsADirectory.into()),
+        })
+    }
+
+    /// Creates `name` in the directory `parent`, with the semantics of
+    /// `FsServer::create`. A `perm` without write bits sets the read-only
+    /// attribute; the other permission bits have nowhere to go.
+    pub fn try_create(&self, parent: &str, name: &str, perm: u32, mode: u32) -> io::Result<()> {
+        if !valid_name(name) {
+            return Err(invalid("bad file name"));
+        }
+        let (date, time) = to_dos(current_timestamp());
+        self.change(|fat| {
+            let node = fat.resolve(parent)?;
+            let dir = fat.dir_of(&node)?;
+            if let Some(mut existing) = fat.find(dir, name)? {
+                if mode & OEXCL != 0 || perm & DMDIR != 0 || existing.short.is_dir() {
+                    return Err(ErrorKind::AlreadyExists.into());
+                }
+                fat.truncate(&mut existing, 0)?;
+                existing.short.wdate = date;
+                existing.short.wtime = time;
+                return fat.put_short(dir, &existing);
+            }
+
+            let mut short = ShortEntry {
+                attr: if perm & DMDIR != 0 {
+                    ATTR_DIRECTORY
+                } else {
+                    ATTR_ARCHIVE
+                },
+                ctime: time,
+                cdate: date,
+                adate: date,
+                wtime: time,
+                wdate: date,
+                ..ShortEntry::default()
+            };
+            if perm & 0o222 == 0 {
+                short.attr |= ATTR_READ_ONLY;
+            }
+            if perm & DMDIR != 0 {
+                short.cluster = fat.new_dir(dir, &short)?;
+            }
+            let cluster = short.cluster;
+            fat.add(dir, name, short).map(|_| ()).inspect_err(|_| {
+                if cluster != 0 {
+                    // Give back the new directory's cluster.
+                    let mut orphan = Entry {
+                        name: String::new(),
+                        short,
+                        first_slot: 0,
+                        slot: 0,
+                    };
+                    let _ = fat.free_clusters_of(&mut orphan);
+                }
+            })
+        })
+    }
+
+    /// Writes `data` at `offset` of the file at `path`. Returns the count
+    /// written.
+    pub fn try_write(&self, path: &str, offset: u64, data: &[u8]) -> io::Result<u32> {
+        let (date, time) = to_dos(current_timestamp());
+        self.change(|fat| {
+            let Node { parent, entry } = fat.resolve(path)?;
+            let mut entry = match entry {
+                Some(entry) if !entry.short.is_dir() => entry,
+                _ => return Err(ErrorKind::IsADirectory.into()),
+            };
+            let written = fat.write_file(&mut entry, offset, data);
+            entry.short.wdate = date;
+            entry.short.wtime = time;
+            entry.short.attr |= ATTR_ARCHIVE;
+            // Record whatever clusters the write got, even if it failed.
+            fat.put_short(parent, &entry)?;
+            written.map(|()| data.len() as u32)
+        })
+    }
+
+    /// Empties the file at `path`, as an open with OTRUNC does, giving
+    /// its clusters back.
+    pub fn try_truncate(&self, path: &str) -> io::Result<()> {
+        let (date, time) = to_dos(current_timestamp());
+        self.change(|fat| {
+            let Node { parent, entry } = fat.resolve(path)?;
+            let mut entry = match entry {
+                Some(entry) if !entry.short.is_dir() => entry,
+                _ => return Err(ErrorKind::IsADirectory.into()),
+            };
+            fat.truncate(&mut entry, 0)?;
+            entry.short.wdate = date;
+            entry.short.wtime = time;
+            entry.short.attr |= ATTR_ARCHIVE;
+            fat.put_short(parent, &entry)
+        })
+    }
+
+    /// Removes the file or empty directory at `path`.
+    pub fn try_remove(&self, path: &str) -> io::Result<()> {
+        self.change(|fat| {
+            let node = fat.resolve(path)?;
+            let Some(mut entry) = node.entry.clone() else {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "cannot remove the root",
+                ));
+            };
+            if entry.short.is_dir() {
+                let dir = fat.dir_of(&node)?;
+                if !fat.entries(dir)?.is_empty() {
+                    return Err(ErrorKind::DirectoryNotEmpty.into());
+                }
+            }
+            fat.unlink(node.parent, &entry)?;
+            fat.free_clusters_of(&mut entry)
+        })
+    }
+
+    /// Applies `change` whole or not at all, without checking who asks.
+    /// FAT keeps only the write bits of a mode, as the read-only
+    /// attribute, has no owners to change and holds files below 4GB.
+    pub fn try_wstat(&self, path: &str, change: &Wstat) -> io::Result<()> {
+        for (owner, ours) in [(&change.uid, &self.uid), (&change.gid, &self.gid)] {
+            if owner.as_ref().is_some_and(|owner| owner != ours) {
+                return Err(invalid("FAT files have no owners"));
+            }
+        }
+        self.change(|fat| {
+            let Node { parent, entry } = fat.resolve(path)?;
+            let (old, length) = self.inode(entry.as_ref());
+            change.check(&old, length).map_err(invalid)?;
+            let new = change.apply(&old);
+            let Some(mut entry) = entry else {
+                // The root has no entry to keep anything in.
+                if change.renames(&old.name) {
+                    return Err(invalid("cannot rename the root"));
+                }
+                return Ok(());
+            };
+            let short = &mut entry.short;
+            if change.mode.is_some() {
+                if new.mode & 0o222 == 0 {
+                    short.attr |= ATTR_READ_ONLY;
+                } else {
+                    short.attr &= !ATTR_READ_ONLY;
+                }
+            }
+            if change.atime.is_some() {
+                short.adate = to_dos(new.atime).0;
+            }
+            if change.mtime.is_some() {
+                (short.wdate, short.wtime) = to_dos(new.mtime);
+            }
+            if let Some(length) = change.new_length(length) {
+                if length > u32::MAX as u64 {
+                    return Err(ErrorKind::FileTooLarge.into());
+                }
+                fat.truncate(&mut entry, length)?;
+            }
+            fat.put_short(parent, &entry)?;
+
+            if change.renames(&entry.name) {
+                let name = new.name.as_str();
+                if !valid_name(name) {
+                    return Err(invalid("bad file name"));
+                }
+                let taken = fat
+                    .find(parent, name)?
+                    .is_some_and(|other| other.slot != entry.slot);
+                if taken {
+                    return Err(ErrorKind::AlreadyExists.into());
+                }
+                // Freeing the old slots first lets a rename that only
+                // changes case keep the short name.
+                fat.unlink(parent, &entry)?;
+                if let Err(err) = fat.add(parent, name, entry.short) {
+                    fat.add(parent, &entry.name, entry.short)?;
+                    return Err(err);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<V: Volume> FsServer for FatFs<V> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.metadata(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.read_at(path, 0, usize::MAX).ok()
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], _uname: &str) -> Option<u32> {
+        self.try_write(path, offset, data).ok()
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.try_remove(path).ok()
+    }
+
+    /// Leaves the contents out; `read_at` reads them.
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.metadata(path).ok().map(|(inode, _)| inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        FatFs::metadata(self, path).ok()
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        FatFs::read_at(self, path, offset, count as usize).ok()
+    }
+
+    /// Runtimes check the client's permissions first.
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.try_wstat(path, change).ok()
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        self.try_create(parent, name, perm, mode).ok()
+    }
+
+    fn truncate(&mut self, path: &str, _uname: &str) -> Option<()> {
+        self.try_truncate(path).ok()
+    }
+
+    fn attach(&self, aname: &str) -> Option<String> {
+        let (inode, _) = self.metadata(aname).ok()?;
+        inode.is_dir().then(|| {
+            let parts: Vec<&str> = aname.split('/').filter(|part| !part.is_empty()).collect();
+            format!("/{}", parts.join("/"))
+        })
+    }
+}
+
+fn current_timestamp() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32
+}
+
+mod fat;
+pub mod format;
+pub mod mkfs;
+mod names;
@@ -0,0 +1,167 @@
+//! Formatting a volume as an empty FAT12, FAT16 or FAT32 filesystem.
+
+use std::io::{self, ErrorKind, SeekFrom};
+
+use crate::Volume;
+use crate::fat::put_entry;
+use crate::format::{
+    ATTR_VOLUME_ID, BootSector, DIR_ENTRY_SIZE, FatType, FsInfo, SECTOR_SIZE, ShortEntry,
+    pad_label, to_dos,
+};
+
+/// Shape of a new filesystem. `Default` picks everything from the size
+/// of the volume.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// `None` is FAT12 below 8 MiB, FAT16 below 512 MiB and FAT32 above.
+    pub fat_type: Option<FatType>,
+    /// `None` is the smallest that keeps the cluster count within the
+    /// type's range, and on FAT32 at least 8 when it can be.
+    pub sectors_per_cluster: Option<u8>,
+    /// Up to 11 ASCII characters; empty for none.
+    pub label: String,
+    /// `None` derives one from the time.
+    pub volume_id: Option<u32>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message.into())
+}
+
+/// The boot sector of a `total`-sector volume of `fat_type` with
+/// `spc`-sector clusters, if its cluster count fits the type.
+fn layout(total: u64, fat_type: FatType, spc: u8) -> Option<BootSector> {
+    let (reserved, root_entries) = match fat_type {
+        FatType::Fat12 => (1u16, 224u16),
+        FatType::Fat16 => (1, 512),
+        FatType::Fat32 => (32, 0),
+    };
+    let root_sectors = (root_entries as u64 * DIR_ENTRY_SIZE as u64).div_ceil(SECTOR_SIZE as u64);
+    // Growing the tables shrinks the data area, so this settles quickly.
+    let mut fat_sectors = 1u64;
+    let clusters = loop {
+        let meta = reserved as u64 + 2 * fat_sectors + root_sectors;
+        let clusters = total.checked_sub(meta)? / spc as u64;
+        let need = fat_type
+            .table_bytes(clusters + 2)
+            .div_ceil(SECTOR_SIZE as u64);
+        if need <= fat_sectors {
+            break clusters;
+        }
+        fat_sectors = need;
+    };
+    let (min, max) = fat_type.cluster_range();
+    if !(min as u64..=max as u64).contains(&clusters) {
+        return None;
+    }
+    let fat32 = fat_type == FatType::Fat32;
+    Some(BootSector {
+        bytes_per_sector: SECTOR_SIZE as u16,
+        sectors_per_cluster: spc,
+        reserved_sectors: reserved,
+        fats: 2,
+        root_entries,
+        total_sectors: u32::try_from(total).ok()?,
+        media: 0xf8,
+        fat_sectors: fat_sectors as u32,
+        ext_flags: 0,
+        root_cluster: if fat32 { 2 } else { 0 },
+        fs_info: if fat32 { 1 } else { 0 },
+        backup_boot: if fat32 { 6 } else { 0 },
+        volume_id: 0,
+        label: String::new(),
+    })
+}
+
+/// Formats `vol`, all of it, as an empty filesystem and returns its boot
+/// sector. Everything on the volume is lost.
+pub fn mkfs<V: Volume>(vol: &mut V, options: &Options) -> io::Result<BootSector> {
+    if options.label.len() > 11 || !options.label.is_ascii() {
+        return Err(invalid("label must be at most 11 ASCII characters"));
+    }
+    let size = vol.seek(SeekFrom::End(0))?;
+    let total = size / SECTOR_SIZE as u64;
+    let fat_type = options.fat_type.unwrap_or(if size < 8 << 20 {
+        FatType::Fat12
+    } else if size < 512 << 20 {
+        FatType::Fat16
+    } else {
+        FatType::Fat32
+    });
+    let candidates: &[u8] = match (options.sectors_per_cluster, fat_type) {
+        (Some(spc), _) => &[spc],
+        (None, FatType::Fat32) => &[8, 16, 32, 64, 128, 4, 2, 1],
+        (None, _) => &[1, 2, 4, 8, 16, 32, 64, 128],
+    };
+    if candidates.iter().any(|spc| !spc.is_power_of_two()) {
+        return Err(invalid("sectors per cluster must be a power of two"));
+    }
+    let mut boot = candidates
+        .iter()
+        .find_map(|&spc| layout(total, fat_type, spc))
+        .ok_or_else(|| invalid(format!("a {} byte volume cannot hold {}", size, fat_type)))?;
+    let now = crate::current_timestamp();
+    boot.volume_id = options.volume_id.unwrap_or(now);
+    boot.label = options.label.clone();
+
+    let bps = SECTOR_SIZE as u64;
+    let zero = [0u8; SECTOR_SIZE];
+    let put = |vol: &mut V, sector: u64, bytes: &[u8]| -> io::Result<()> {
+        vol.seek(SeekFrom::Start(sector * bps))?;
+        vol.write_all(bytes)
+    };
+    // Whatever filesystem was here stops being one before anything else
+    // changes.
+    put(vol, 0, &zero)?;
+    vol.flush()?;
+    let mut clear = boot.data_start();
+    if fat_type == FatType::Fat32 {
+        clear += boot.sectors_per_cluster as u64;
+    }
+    for sector in 1..clear {
+        put(vol, sector, &zero)?;
+    }
+
+    let mut first = [0u8; SECTOR_SIZE];
+    let eoc = fat_type.end_of_chain();
+    put_entry(&mut first, fat_type, 0, (eoc & !0xff) | boot.media as u32);
+    put_entry(&mut first, fat_type, 1, eoc);
+    if fat_type == FatType::Fat32 {
+        // The root directory's single cluster.
+        put_entry(&mut first, fat_type, 2, eoc);
+    }
+    for copy in 0..boot.fats as u64 {
+        put(
+            vol,
+            boot.reserved_sectors as u64 + copy * boot.fat_sectors as u64,
+            &first,
+        )?;
+    }
+
+    if !boot.label.is_empty() {
+        let (date, time) = to_dos(now);
+        let label = ShortEntry {
+            name: pad_label(&boot.label),
+            attr: ATTR_VOLUME_ID,
+            wdate: date,
+            wtime: time,
+            ..ShortEntry::default()
+        };
+        // On FAT32 there is no fixed root and this is cluster 2, the root's.
+        put(vol, boot.root_dir_start(), &label.encode())?;
+    }
+    if fat_type == FatType::Fat32 {
+        let info = FsInfo {
+            free_clusters: boot.clusters() - 1,
+            next_free: 3,
+        };
+        put(vol, boot.fs_info as u64, &info.encode())?;
+        put(vol, boot.backup_boot as u64 + 1, &info.encode())?;
+        put(vol, boot.backup_boot as u64, &boot.encode())?;
+    }
+
+    vol.flush()?;
+    put(vol, 0, &boot.encode())?;
+    vol.flush()?;
+    Ok(boot)
+}
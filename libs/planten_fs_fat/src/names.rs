@@ -0,0 +1,122 @@
+//! Long names and the short names that stand in for them.
+
+use crate::format::{LOWER_BASE, LOWER_EXT, MAX_NAME};
+
+/// Whether `name` can be a long name: not `.` or `..`, free of the
+/// characters FAT forbids, not ending in a dot or space (Windows strips
+/// those) and at most `MAX_NAME` UTF-16 units.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.ends_with(['.', ' '])
+        && name.encode_utf16().count() <= MAX_NAME
+        && !name
+            .chars()
+            .any(|c| c < ' ' || "\"*/:<>?\\|".contains(c) || c == '\u{7f}')
+}
+
+fn short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// The short entry name and case bits that spell `name` exactly, if
+/// there are any: an 8.3 name whose parts are each in one case.
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || base.contains('.') {
+        return None;
+    }
+    let mut case = 0;
+    for (part, lower) in [(base, LOWER_BASE), (ext, LOWER_EXT)] {
+        let upper = part.to_ascii_uppercase();
+        if !upper.chars().all(short_char) {
+            return None;
+        }
+        if part != upper {
+            if part != part.to_ascii_lowercase() {
+                return None;
+            }
+            case |= lower;
+        }
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    Some((short, case))
+}
+
+/// The basis of a generated short name, as Windows derives it: upper
+/// case, unusable characters replaced by `_`, spaces and leading dots
+/// dropped, then the first eight characters before the last dot and
+/// three after it.
+pub fn basis_name(name: &str) -> [u8; 11] {
+    let squeeze = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if short_char(c) { c as u8 } else { b'_' }
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) => (squeeze(base), squeeze(ext)),
+        None => (squeeze(trimmed), Vec::new()),
+    };
+    let mut short = [b' '; 11];
+    let base = if base.is_empty() { b"_".to_vec() } else { base };
+    for (slot, byte) in short[..8].iter_mut().zip(&base) {
+        *slot = *byte;
+    }
+    for (slot, byte) in short[8..].iter_mut().zip(&ext) {
+        *slot = *byte;
+    }
+    short
+}
+
+/// `basis` with the numeric tail `~n`, shortening the base to fit.
+pub fn with_tail(basis: &[u8; 11], n: u32) -> [u8; 11] {
+    let tail = format!("~{}", n);
+    let base_len = basis[..8]
+        .iter()
+        .position(|&byte| byte == b' ')
+        .unwrap_or(8)
+        .min(8 - tail.len());
+    let mut short = *basis;
+    short[..8].fill(b' ');
+    short[..base_len].copy_from_slice(&basis[..base_len]);
+    short[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+    short
+}
+
+/// The name a short entry spells, honouring its case bits. Bytes outside
+/// ASCII are read as Latin-1.
+pub fn short_name_string(short: &[u8; 11], case: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let end = bytes
+            .iter()
+            .rposition(|&byte| byte != b' ')
+            .map_or(0, |at| at + 1);
+        bytes[..end]
+            .iter()
+            .map(|&byte| {
+                let c = byte as char;
+                if lower { c.to_ascii_lowercase() } else { c }
+            })
+            .collect()
+    };
+    let base = part(&short[..8], case & LOWER_BASE != 0);
+    let ext = part(&short[8..], case & LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+/// Whether two names are the same to FAT, which ignores case.
+pub fn same_name(a: &str, b: &str) -> bool {
+    a == b || a.to_lowercase() == b.to_lowercase()
+}
@@ -0,0 +1,442 @@
+use std::io::{Cursor, ErrorKind};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use planten_9p::P9Client;
+use planten_9p::server::run_server;
+use planten_fs_core::{DMDIR, FsServer, OEXCL, OREAD, OTRUNC, OWRITE, Wstat};
+use planten_fs_fat::format::{
+    BootSector, DIR_ENTRY_SIZE, FatType, SECTOR_SIZE, ShortEntry, long_entries, name_checksum,
+};
+use planten_fs_fat::mkfs::{Options, mkfs};
+use planten_fs_fat::{FatFs, Volume};
+
+type Image = Cursor<Vec<u8>>;
+
+fn image(size: usize, fat_type: FatType) -> Image {
+    let mut image = Cursor::new(vec![0; size]);
+    let options = Options {
+        fat_type: Some(fat_type),
+        label: "PLANTEN".to_string(),
+        volume_id: Some(0x1234_5678),
+        ..Options::default()
+    };
+    mkfs(&mut image, &options).unwrap();
+    image
+}
+
+fn mounted(size: usize, fat_type: FatType) -> FatFs<Image> {
+    FatFs::mount(image(size, fat_type), "glenda", "sys").unwrap()
+}
+
+fn remount<V: Volume>(fs: FatFs<V>) -> FatFs<V> {
+    FatFs::mount(fs.into_volume(), "glenda", "sys").unwrap()
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+/// Bytes that differ from one cluster to the next.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|n| (n * 7 + n / 251) as u8).collect()
+}
+
+#[test]
+fn every_fat_type_keeps_a_tree() {
+    let sizes = [
+        (1_474_560, FatType::Fat12),
+        (16 << 20, FatType::Fat16),
+        (40 << 20, FatType::Fat32),
+    ];
+    for (size, fat_type) in sizes {
+        let mut fs = mounted(size, fat_type);
+        let usage = fs.usage();
+        assert_eq!(usage.fat_type, fat_type);
+        assert_eq!(fs.boot_sector().label, "PLANTEN");
+        assert!(fs.walk("/").unwrap().is_empty(), "{}", fat_type);
+
+        fs.create("/", "src", DMDIR | 0o755, OREAD, "glenda")
+            .unwrap();
+        fs.create("/src", "main.c", 0o644, OWRITE, "glenda")
+            .unwrap();
+        let big = pattern(usage.cluster_size as usize * 3 + 100);
+        assert_eq!(
+            fs.write("/src/main.c", 0, &big, "glenda"),
+            Some(big.len() as u32)
+        );
+        fs.create("/", "empty", 0o644, OWRITE, "glenda").unwrap();
+
+        let mut fs = remount(fs);
+        assert_eq!(sorted(fs.walk("/").unwrap()), vec!["empty", "src"]);
+        assert_eq!(fs.walk("/src").unwrap(), vec!["main.c"]);
+        assert_eq!(fs.read("/src/main.c").unwrap(), big);
+        assert_eq!(fs.read("/empty").unwrap(), b"");
+        assert_eq!(fs.read_at("/src/main.c", 10, 5).unwrap(), big[10..15]);
+        let stat = fs.stat("/src").unwrap();
+        assert_eq!(stat.mode, DMDIR | 0o777);
+        assert_eq!((stat.uid.as_str(), stat.gid.as_str()), ("glenda", "sys"));
+        assert_eq!(fs.stat("/empty").unwrap().mode, 0o666);
+        assert_eq!(fs.stat("/").unwrap().name, "/");
+        assert_eq!(
+            fs.usage().free_clusters,
+            usage.free_clusters - 5,
+            "{}: one cluster for /src and four for main.c",
+            fat_type
+        );
+
+        fs.remove("/src/main.c").unwrap();
+        fs.remove("/src").unwrap();
+        let fs = remount(fs);
+        assert_eq!(fs.usage().free_clusters, usage.free_clusters);
+    }
+}
+
+#[test]
+fn long_names_get_short_aliases() {
+    let mut fs = mounted(1_474_560, FatType::Fat12);
+    let names = [
+        "readme.txt",
+        "README.TXT~",
+        "Makefile",
+        "A long file name.markdown",
+        "A long file name.md",
+        "ünïcödé ✓",
+        "with.several.dots.tar.gz",
+        "exactly-thirteen-plus-more-to-span-many-long-entries.text",
+    ];
+    for name in names {
+        fs.create("/", name, 0o644, OWRITE, "glenda").unwrap();
+        fs.write(&format!("/{}", name), 0, name.as_bytes(), "glenda")
+            .unwrap();
+    }
+
+    let mut fs = remount(fs);
+    let mut expected: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    expected.sort();
+    assert_eq!(sorted(fs.walk("/").unwrap()), expected);
+    for name in names {
+        assert_eq!(fs.read(&format!("/{}", name)).unwrap(), name.as_bytes());
+    }
+
+    let short = |path: &str| fs.short_name(path).unwrap();
+    assert_eq!(short("/readme.txt"), "README.TXT");
+    assert_eq!(short("/Makefile"), "MAKEFI~1");
+    assert_eq!(short("/A long file name.markdown"), "ALONGF~1.MAR");
+    assert_eq!(short("/A long file name.md"), "ALONGF~1.MD");
+    assert_eq!(short("/with.several.dots.tar.gz"), "WITHSE~1.GZ");
+    assert_eq!(short("/ünïcödé ✓"), "_N_C_D~1");
+
+    // Lookups ignore case, and so does the check for existing names.
+    assert_eq!(fs.read("/MAKEFILE").unwrap(), b"Makefile");
+    assert_eq!(
+        fs.read("/a LONG file NAME.md").unwrap(),
+        b"A long file name.md"
+    );
+    assert!(
+        fs.try_create("/", "ReadMe.TXT", 0o644, OWRITE | OEXCL)
+            .is_err()
+    );
+    for bad in ["a/b", "what?", "trailing.", "", "..", "tab\there"] {
+        assert!(
+            fs.create("/", bad, 0o644, OWRITE, "glenda").is_none(),
+            "{:?}",
+            bad
+        );
+    }
+    assert!(
+        fs.create("/", &"x".repeat(256), 0o644, OWRITE, "glenda")
+            .is_none()
+    );
+    fs.create("/", &"x".repeat(255), 0o644, OWRITE, "glenda")
+        .unwrap();
+}
+
+/// Writes `slots` into the fixed root directory of a FAT12 or FAT16
+/// image from its first slot on, as another system might have.
+fn put_root_slots(image: &mut Image, slots: &[[u8; DIR_ENTRY_SIZE]]) {
+    let bytes = image.get_mut();
+    let boot = BootSector::decode(&bytes[..SECTOR_SIZE]).unwrap();
+    let mut at = boot.root_dir_start() as usize * SECTOR_SIZE;
+    for slot in slots {
+        bytes[at..at + DIR_ENTRY_SIZE].copy_from_slice(slot);
+        at += DIR_ENTRY_SIZE;
+    }
+}
+
+#[test]
+fn foreign_long_entries_are_read() {
+    let mut image = image(1_474_560, FatType::Fat12);
+    let short = |name: &[u8; 11]| ShortEntry {
+        name: *name,
+        attr: 0x20,
+        wdate: (35 << 9) | (3 << 5) | 14,
+        wtime: (15 << 11) | (9 << 5) | 13,
+        ..ShortEntry::default()
+    };
+    let alias = *b"PROGRA~1   ";
+    let mut slots: Vec<[u8; DIR_ENTRY_SIZE]> = long_entries("Program Files", name_checksum(&alias))
+        .iter()
+        .map(|long| long.encode())
+        .collect();
+    slots.push(short(&alias).encode());
+    // Long entries whose checksum does not match the short name after them
+    // were left behind by a system that knew nothing of long names.
+    let stale = *b"NOTES   TXT";
+    slots.extend(
+        long_entries("Meeting notes.txt", 0)
+            .iter()
+            .map(|long| long.encode()),
+    );
+    slots.push(short(&stale).encode());
+    // The label written by mkfs is overwritten; this one stays hidden.
+    slots.push(
+        ShortEntry {
+            name: *b"VOLUME     ",
+            attr: 0x08,
+            ..ShortEntry::default()
+        }
+        .encode(),
+    );
+    put_root_slots(&mut image, &slots);
+
+    let fs = FatFs::mount(image, "glenda", "sys").unwrap();
+    assert_eq!(
+        sorted(fs.list("/").unwrap()),
+        vec!["NOTES.TXT", "Program Files"]
+    );
+    // 2015-03-14 15:09:26 UTC.
+    assert_eq!(fs.stat("/Program Files").unwrap().mtime, 1_426_345_766);
+    assert_eq!(fs.short_name("/notes.txt").unwrap(), "NOTES.TXT");
+}
+
+#[test]
+fn writes_grow_and_truncations_free() {
+    let mut fs = mounted(16 << 20, FatType::Fat16);
+    let free = fs.usage().free_clusters;
+    let cluster = fs.usage().cluster_size as u64;
+    fs.create("/", "log", 0o644, OWRITE, "glenda").unwrap();
+    fs.write("/log", 0, b"head", "glenda").unwrap();
+    // Writing past the end leaves a hole of zeros.
+    fs.write("/log", cluster * 2, b"tail", "glenda").unwrap();
+    let data = fs.read("/log").unwrap();
+    assert_eq!(data.len() as u64, cluster * 2 + 4);
+    assert_eq!(&data[..4], b"head");
+    assert!(data[4..cluster as usize * 2].iter().all(|&byte| byte == 0));
+    assert_eq!(fs.usage().free_clusters, free - 3);
+
+    let mut stat = Wstat {
+        length: Some(2),
+        ..Wstat::default()
+    };
+    fs.try_wstat("/log", &stat).unwrap();
+    assert_eq!(fs.read("/log").unwrap(), b"he");
+    assert_eq!(fs.usage().free_clusters, free - 1);
+    stat.length = Some(0);
+    fs.try_wstat("/log", &stat).unwrap();
+    assert_eq!(fs.usage().free_clusters, free);
+
+    // Creating an existing file truncates it.
+    fs.write("/log", 0, b"again", "glenda").unwrap();
+    fs.create("/", "LOG", 0o644, OWRITE, "glenda").unwrap();
+    assert_eq!(fs.read("/log").unwrap(), b"");
+    assert_eq!(fs.walk("/").unwrap(), vec!["log"]);
+    assert!(
+        fs.create("/", "log", DMDIR | 0o755, OREAD, "glenda")
+            .is_none()
+    );
+
+    // Filling the volume fails cleanly.
+    fs.create("/", "huge", 0o644, OWRITE, "glenda").unwrap();
+    let err = fs.try_write("/huge", 17 << 20, b"x").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert_eq!(fs.read("/huge").unwrap(), b"");
+    assert_eq!(fs.usage().free_clusters, free);
+}
+
+#[test]
+fn wstat_renames_and_sets_attributes() {
+    let mut fs = mounted(40 << 20, FatType::Fat32);
+    fs.create("/", "dir", DMDIR | 0o755, OREAD, "glenda")
+        .unwrap();
+    fs.create("/dir", "old name.txt", 0o644, OWRITE, "glenda")
+        .unwrap();
+    fs.write("/dir/old name.txt", 0, b"contents", "glenda")
+        .unwrap();
+
+    let stat = Wstat {
+        name: Some("New Name.txt".to_string()),
+        mode: Some(0o444),
+        mtime: Some(1_700_000_000),
+        ..Wstat::default()
+    };
+    fs.try_wstat("/dir/old name.txt", &stat).unwrap();
+
+    let mut fs = remount(fs);
+    assert_eq!(fs.walk("/dir").unwrap(), vec!["New Name.txt"]);
+    let inode = fs.stat("/dir/New Name.txt").unwrap();
+    assert_eq!(fs.read("/dir/New Name.txt").unwrap(), b"contents");
+    assert_eq!(inode.mode, 0o444);
+    assert_eq!(inode.mtime, 1_700_000_000);
+
+    // Only the case changes, and the short name stays.
+    let alias = fs.short_name("/dir/new name.txt").unwrap();
+    let stat = Wstat {
+        name: Some("NEW NAME.TXT".to_string()),
+        mode: Some(0o644),
+        ..Wstat::default()
+    };
+    fs.try_wstat("/dir/New Name.txt", &stat).unwrap();
+    assert_eq!(fs.walk("/dir").unwrap(), vec!["NEW NAME.TXT"]);
+    assert_eq!(fs.short_name("/dir/new name.txt").unwrap(), alias);
+    assert_eq!(fs.stat("/dir/new name.txt").unwrap().mode, 0o666);
+
+    fs.create("/dir", "other", 0o644, OWRITE, "glenda").unwrap();
+    let stat = Wstat {
+        name: Some("Other".to_string()),
+        ..Wstat::default()
+    };
+    let err = fs.try_wstat("/dir/NEW NAME.TXT", &stat).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    let stat = Wstat {
+        uid: Some("bootes".to_string()),
+        ..Wstat::default()
+    };
+    assert!(fs.try_wstat("/dir/other", &stat).is_err());
+    let stat = Wstat {
+        mode: Some(DMDIR | 0o777),
+        ..Wstat::default()
+    };
+    assert!(fs.try_wstat("/dir/other", &stat).is_err());
+
+    // The runtime's wstat hands over the same changes.
+    let stat = Wstat {
+        name: Some("renamed".to_string()),
+        ..Wstat::default()
+    };
+    fs.wstat("/dir/other", &stat).unwrap();
+    assert_eq!(
+        sorted(fs.walk("/dir").unwrap()),
+        vec!["NEW NAME.TXT", "renamed"]
+    );
+}
+
+#[test]
+fn directories_grow_and_the_fixed_root_fills() {
+    let mut fs = mounted(1_474_560, FatType::Fat12);
+    fs.create("/", "many", DMDIR | 0o755, OREAD, "glenda")
+        .unwrap();
+    // 512-byte clusters hold 16 slots; long names take two or three each.
+    let names: Vec<String> = (0..40).map(|n| format!("file number {}", n)).collect();
+    for name in &names {
+        fs.create("/many", name, 0o644, OWRITE, "glenda").unwrap();
+    }
+    fs.create("/many", "sub", DMDIR | 0o755, OREAD, "glenda")
+        .unwrap();
+    fs.create("/many/sub", "deep", 0o644, OWRITE, "glenda")
+        .unwrap();
+    let mut fs = remount(fs);
+    assert_eq!(fs.walk("/many").unwrap().len(), 41);
+    assert_eq!(fs.walk("/many/sub").unwrap(), vec!["deep"]);
+    assert!(fs.remove("/many").is_none(), "not empty");
+    fs.remove(&format!("/many/{}", names[3])).unwrap();
+    assert_eq!(fs.walk("/many").unwrap().len(), 40);
+    // The freed slots are reused.
+    fs.create("/many", "file number 3", 0o644, OWRITE, "glenda")
+        .unwrap();
+    assert_eq!(fs.walk("/many").unwrap().len(), 41);
+
+    // 224 slots, one taken by the volume label and one by /many.
+    for n in 0..222 {
+        fs.create("/", &format!("F{}", n), 0o644, OWRITE, "glenda")
+            .unwrap();
+    }
+    let err = fs.try_create("/", "F222", 0o644, OWRITE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert!(fs.remove("/").is_none());
+}
+
+#[test]
+fn mkfs_refuses_impossible_volumes() {
+    let format = |size: usize, fat_type| {
+        let mut image = Cursor::new(vec![0; size]);
+        let options = Options {
+            fat_type: Some(fat_type),
+            ..Options::default()
+        };
+        mkfs(&mut image, &options).map(|boot| boot.fat_type())
+    };
+    assert!(format(1 << 20, FatType::Fat16).is_err());
+    assert!(format(8 << 20, FatType::Fat32).is_err());
+    assert_eq!(format(34 << 20, FatType::Fat32).unwrap(), FatType::Fat32);
+    assert_eq!(format(200 << 20, FatType::Fat12).unwrap(), FatType::Fat12);
+    let mut image = Cursor::new(vec![0; 1 << 20]);
+    let options = Options {
+        label: "FAR TOO LONG A LABEL".to_string(),
+        ..Options::default()
+    };
+    assert!(mkfs(&mut image, &options).is_err());
+    assert!(FatFs::mount(Cursor::new(vec![0; 1 << 20]), "glenda", "sys").is_err());
+}
+
+#[test]
+fn images_are_served_over_9p() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("usb.img");
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.set_len(16 << 20).unwrap();
+    mkfs(&mut file, &Options::default()).unwrap();
+    let fs = FatFs::open(&path, "glenda", "sys").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || run_server(listener, Arc::new(Mutex::new(fs))));
+    let mut client = P9Client::new(&addr).unwrap();
+    client.version(8192, "9P2000").unwrap();
+    client.attach(1, None, "glenda", "").unwrap();
+    assert_eq!(client.walk(1, 2, &[]).unwrap(), 0);
+    client
+        .create(2, "Photos From Trip", DMDIR | 0o755, 0)
+        .unwrap();
+    client.clunk(2).unwrap();
+    assert_eq!(client.walk(1, 2, &["Photos From Trip"]).unwrap(), 1);
+    client
+        .create(2, "IMG 0001.jpeg", 0o644, OWRITE as u8)
+        .unwrap();
+    assert_eq!(client.write(2, 0, b"\xff\xd8jpeg").unwrap(), 6);
+    client.clunk(2).unwrap();
+    assert_eq!(
+        client
+            .walk(1, 3, &["photos from trip", "img 0001.JPEG"])
+            .unwrap(),
+        2
+    );
+    client.open(3, 0).unwrap();
+    assert_eq!(client.read(3, 0, 100).unwrap(), b"\xff\xd8jpeg");
+    client.clunk(3).unwrap();
+    assert_eq!(client.walk(1, 4, &["Photos From Trip"]).unwrap(), 1);
+    client.create(4, "notes.txt", 0o644, OWRITE as u8).unwrap();
+    client.write(4, 0, &pattern(4000)).unwrap();
+    client.clunk(4).unwrap();
+    assert_eq!(
+        client
+            .walk(1, 4, &["Photos From Trip", "notes.txt"])
+            .unwrap(),
+        2
+    );
+    client.open(4, (OWRITE | OTRUNC) as u8).unwrap();
+    assert_eq!(client.stat(4).unwrap().length, 0);
+    client.clunk(4).unwrap();
+
+    let fs = FatFs::open(&path, "glenda", "sys").unwrap();
+    assert_eq!(fs.usage().fat_type, FatType::Fat16);
+    assert_eq!(
+        fs.read_at("/Photos From Trip/IMG 0001.jpeg", 0, 100)
+            .unwrap(),
+        b"\xff\xd8jpeg"
+    );
+    let (_, length) = fs.metadata("/Photos From Trip/notes.txt").unwrap();
+    assert_eq!(length, 0);
+}
@@ -0,0 +1,51 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::{run_server, run_stdio};
+use planten_fs_git::GitFs;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5674";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_git_server [--listen host:port | --stdio] [--owner user] [--group group] [repository]\n\
+         Serves the branches, commits and objects of a git repository read-only;\n\
+         the repository defaults to the current directory."
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut stdio = false;
+    let mut owner = "user".to_string();
+    let mut group = "group".to_string();
+    let mut repository = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--stdio" => stdio = true,
+            "--owner" => owner = args.next().unwrap_or_else(|| usage()),
+            "--group" => group = args.next().unwrap_or_else(|| usage()),
+            _ if arg.starts_with('-') || repository.is_some() => usage(),
+            _ => repository = Some(arg),
+        }
+    }
+    let repository = repository.unwrap_or_else(|| ".".to_string());
+
+    let fs = GitFs::open(&repository, &owner, &group).unwrap_or_else(|err| {
+        eprintln!("cannot open {}: {}", repository, err);
+        process::exit(1);
+    });
+    let fs = Arc::new(Mutex::new(fs));
+    if stdio {
+        return run_stdio(fs);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!("GitFs 9P server for {} on {}", repository, listen);
+    run_server(listener, fs)
+}
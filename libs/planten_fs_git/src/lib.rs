@@ -0,0 +1,393 @@
+//! A git repository served read-only through `FsServer`, after 9front's
+//! git/fs.
+//!
+//! ```text
+//! /HEAD/              the commit HEAD points at
+//! /branch/<name>/     the commit at the tip of each branch
+//! /object/<hash>      any object: commits and trees are directories,
+//!                     blobs and tags files
+//! /ctl                the repository, the branch checked out and HEAD
+//! ```
+//!
+//! A commit directory holds `author`, `committer`, `hash`, `msg` and
+//! `parent` (one hash per line) and the directory `tree`. Everything
+//! under a commit carries its commit time as mtime, so qid versions
+//! change when a branch moves. Refs are read on every lookup, so the
+//! tree follows commits made while it is served. `/object` lists as
+//! empty but any hash can be walked to.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use planten_fs_core::{DMDIR, FsServer, Inode, Wstat};
+
+use object::{Commit, EntryKind, Hash, Kind, parse_tree};
+use repo::{Head, Repository};
+
+const COMMIT_FILES: [&str; 6] = ["author", "committer", "hash", "msg", "parent", "tree"];
+
+/// What a path names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Root,
+    Ctl,
+    /// `/branch`, or a directory of it holding branches named with
+    /// slashes, by its path below `/branch`.
+    Branches(String),
+    Objects,
+    Commit(Hash),
+    /// A file of a commit directory.
+    CommitFile(Hash, &'static str),
+    Tree(Hash, u32),
+    File {
+        hash: Hash,
+        executable: bool,
+        mtime: u32,
+    },
+    /// A commit of a submodule, shown as an empty directory.
+    Submodule(u32),
+    /// An annotated tag object, shown as its text.
+    Tag(Hash),
+}
+
+pub struct GitFs {
+    repo: Repository,
+    uid: String,
+    gid: String,
+}
+
+fn not_found() -> io::Error {
+    ErrorKind::NotFound.into()
+}
+
+impl GitFs {
+    /// Serves the repository at `path` with every file owned by `uid`
+    /// and `gid`.
+    pub fn open(path: impl AsRef<Path>, uid: &str, gid: &str) -> io::Result<GitFs> {
+        Ok(GitFs {
+            repo: Repository::open(path)?,
+            uid: uid.to_string(),
+            gid: gid.to_string(),
+        })
+    }
+
+    pub fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    fn lookup(&self, path: &str) -> io::Result<Node> {
+        let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        match parts.as_slice() {
+            [] => Ok(Node::Root),
+            ["ctl"] => Ok(Node::Ctl),
+            ["HEAD", rest @ ..] => {
+                let head = self.repo.head()?.commit.ok_or_else(not_found)?;
+                self.in_commit(head, rest)
+            }
+            ["branch", rest @ ..] => self.in_branches(rest),
+            ["object"] => Ok(Node::Objects),
+            ["object", hash, rest @ ..] => {
+                let hash: Hash = hash.parse().map_err(|_| not_found())?;
+                match self.repo.read(&hash)?.kind {
+                    Kind::Commit => self.in_commit(hash, rest),
+                    Kind::Tree => self.in_tree(hash, 0, rest),
+                    Kind::Blob if rest.is_empty() => Ok(Node::File {
+                        hash,
+                        executable: false,
+                        mtime: 0,
+                    }),
+                    Kind::Tag if rest.is_empty() => Ok(Node::Tag(hash)),
+                    _ => Err(not_found()),
+                }
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    fn in_branches(&self, parts: &[&str]) -> io::Result<Node> {
+        let branches = self.repo.branches()?;
+        let mut prefix = String::new();
+        for (n, part) in parts.iter().enumerate() {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(part);
+            if let Some(&commit) = branches.get(&prefix) {
+                return self.in_commit(commit, &parts[n + 1..]);
+            }
+        }
+        let dir = format!("{}/", prefix);
+        if prefix.is_empty() || branches.keys().any(|name| name.starts_with(&dir)) {
+            Ok(Node::Branches(prefix))
+        } else {
+            Err(not_found())
+        }
+    }
+
+    fn in_commit(&self, hash: Hash, parts: &[&str]) -> io::Result<Node> {
+        match parts {
+            [] => Ok(Node::Commit(hash)),
+            ["tree", rest @ ..] => {
+                let commit = self.repo.commit(&hash)?;
+                self.in_tree(commit.tree, commit.time(), rest)
+            }
+            [file] => COMMIT_FILES
+                .iter()
+                .find(|name| *name == file)
+                .map(|name| Node::CommitFile(hash, name))
+                .ok_or_else(not_found),
+            _ => Err(not_found()),
+        }
+    }
+
+    fn in_tree(&self, mut tree: Hash, mtime: u32, parts: &[&str]) -> io::Result<Node> {
+        for (n, part) in parts.iter().enumerate() {
+            let entries = parse_tree(&self.repo.read(&tree)?.data)?;
+            let entry = entries
+                .into_iter()
+                .find(|entry| entry.name == *part)
+                .ok_or_else(not_found)?;
+            let last = n + 1 == parts.len();
+            match entry.kind {
+                EntryKind::Tree => tree = entry.hash,
+                EntryKind::Submodule if last => return Ok(Node::Submodule(mtime)),
+                EntryKind::File | EntryKind::Symlink | EntryKind::Executable if last => {
+                    return Ok(Node::File {
+                        hash: entry.hash,
+                        executable: entry.kind == EntryKind::Executable,
+                        mtime,
+                    });
+                }
+                _ => return Err(not_found()),
+            }
+        }
+        Ok(Node::Tree(tree, mtime))
+    }
+
+    fn commit_time(&self, hash: &Hash) -> u32 {
+        self.repo.commit(hash).map_or(0, |commit| commit.time())
+    }
+
+    fn children(&self, node: &Node) -> io::Result<Vec<String>> {
+        match node {
+            Node::Root => {
+                let mut names = vec!["branch", "ctl", "object"];
+                if self.repo.head()?.commit.is_some() {
+                    names.insert(0, "HEAD");
+                }
+                Ok(names.into_iter().map(str::to_string).collect())
+            }
+            Node::Branches(prefix) => {
+                let dir = if prefix.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", prefix)
+                };
+                let mut names: Vec<String> = self
+                    .repo
+                    .branches()?
+                    .into_keys()
+                    .filter_map(|name| {
+                        let rest = name.strip_prefix(&dir)?;
+                        Some(rest.split('/').next().unwrap_or(rest).to_string())
+                    })
+                    .collect();
+                names.dedup();
+                Ok(names)
+            }
+            Node::Objects | Node::Submodule(_) => Ok(Vec::new()),
+            Node::Commit(_) => Ok(COMMIT_FILES.iter().map(|name| name.to_string()).collect()),
+            Node::Tree(hash, _) => Ok(parse_tree(&self.repo.read(hash)?.data)?
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect()),
+            _ => Err(ErrorKind::NotADirectory.into()),
+        }
+    }
+
+    fn contents(&self, node: &Node) -> io::Result<Vec<u8>> {
+        match node {
+            Node::Ctl => Ok(self.ctl()?.into_bytes()),
+            Node::CommitFile(hash, file) => {
+                let commit: Commit = self.repo.commit(hash)?;
+                Ok(match *file {
+                    "author" => format!("{}\n", commit.author),
+                    "committer" => format!("{}\n", commit.committer),
+                    "hash" => format!("{}\n", hash),
+                    "msg" => commit.message,
+                    "parent" => commit
+                        .parents
+                        .iter()
+                        .map(|parent| format!("{}\n", parent))
+                        .collect(),
+                    _ => return Err(ErrorKind::IsADirectory.into()),
+                }
+                .into_bytes())
+            }
+            Node::File { hash, .. } | Node::Tag(hash) => Ok(self.repo.read(hash)?.data.clone()),
+            _ => Err(ErrorKind::IsADirectory.into()),
+        }
+    }
+
+    /// Up to `count` bytes of the file `node` from `offset`. Blobs and
+    /// tags are sliced out of the cached object rather than copied whole.
+    fn range(&self, node: &Node, offset: u64, count: u64) -> io::Result<Vec<u8>> {
+        let slice = |data: &[u8]| {
+            let start = usize::try_from(offset)
+                .unwrap_or(usize::MAX)
+                .min(data.len());
+            let end = start
+                .saturating_add(usize::try_from(count).unwrap_or(usize::MAX))
+                .min(data.len());
+            data[start..end].to_vec()
+        };
+        match node {
+            Node::File { hash, .. } | Node::Tag(hash) => Ok(slice(&self.repo.read(hash)?.data)),
+            _ => Ok(slice(&self.contents(node)?)),
+        }
+    }
+
+    fn length(&self, node: &Node) -> io::Result<u64> {
+        match node {
+            Node::File { hash, .. } | Node::Tag(hash) => {
+                Ok(self.repo.read(hash)?.data.len() as u64)
+            }
+            _ => Ok(self.contents(node)?.len() as u64),
+        }
+    }
+
+    fn ctl(&self) -> io::Result<String> {
+        let Head { branch, commit } = self.repo.head()?;
+        Ok(format!(
+            "repo {}\nbranch {}\nhead {}\n",
+            self.repo.git_dir().display(),
+            branch.as_deref().unwrap_or("none"),
+            commit.map_or("none".to_string(), |hash| hash.to_string())
+        ))
+    }
+
+    /// The names in the directory at `path`.
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        self.children(&self.lookup(path)?)
+    }
+
+    /// The contents of the file at `path`.
+    pub fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
+        self.contents(&self.lookup(path)?)
+    }
+
+    /// Up to `count` bytes of the file at `path` from `offset`.
+    pub fn read_range(&self, path: &str, offset: u64, count: u64) -> io::Result<Vec<u8>> {
+        self.range(&self.lookup(path)?, offset, count)
+    }
+
+    /// The inode at `path` without its contents, and the file's length.
+    fn metadata(&self, path: &str) -> io::Result<(Inode, u64)> {
+        let node = self.lookup(path)?;
+        let (mode, mtime) = match &node {
+            Node::Root | Node::Branches(_) | Node::Objects => (DMDIR | 0o555, 0),
+            Node::Commit(hash) => (DMDIR | 0o555, self.commit_time(hash)),
+            Node::Tree(_, mtime) | Node::Submodule(mtime) => (DMDIR | 0o555, *mtime),
+            Node::CommitFile(hash, _) => (0o444, self.commit_time(hash)),
+            Node::File {
+                executable, mtime, ..
+            } => (if *executable { 0o555 } else { 0o444 }, *mtime),
+            Node::Ctl | Node::Tag(_) => (0o444, 0),
+        };
+        let length = if mode & DMDIR != 0 {
+            0
+        } else {
+            self.length(&node)?
+        };
+        let name = path
+            .split('/')
+            .rfind(|part| !part.is_empty())
+            .unwrap_or("/");
+        let inode = Inode {
+            name: name.to_string(),
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode,
+            uid: self.uid.clone(),
+            gid: self.gid.clone(),
+            atime: mtime,
+            mtime,
+            muid: self.uid.clone(),
+        };
+        Ok((inode, length))
+    }
+
+    fn inode(&self, path: &str) -> io::Result<Inode> {
+        let (mut inode, _) = self.metadata(path)?;
+        if !inode.is_dir() {
+            inode.data = self.read_file(path)?;
+        }
+        Ok(inode)
+    }
+}
+
+impl FsServer for GitFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.lookup(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.read_file(path).ok()
+    }
+
+    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+        None
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inode(path).ok()
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        GitFs::metadata(self, path).ok()
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.read_range(path, offset, count as u64).ok()
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    fn attach(&self, aname: &str) -> Option<String> {
+        let inode = self.inode(aname).ok()?;
+        inode.is_dir().then(|| {
+            let parts: Vec<&str> = aname.split('/').filter(|part| !part.is_empty()).collect();
+            format!("/{}", parts.join("/"))
+        })
+    }
+}
+
+pub mod object;
+pub mod pack;
+pub mod repo;
@@ -0,0 +1,205 @@
+//! Git objects: commits, trees, blobs and tags, named by the SHA-1 of
+//! their type, length and contents.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+pub const HASH_SIZE: usize = 20;
+
+/// The SHA-1 naming an object.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash(pub [u8; HASH_SIZE]);
+
+impl Hash {
+    /// The name git gives an object of `kind` holding `data`.
+    pub fn of(kind: Kind, data: &[u8]) -> Hash {
+        let mut sha = sha1_smol::Sha1::new();
+        sha.update(format!("{} {}\0", kind, data.len()).as_bytes());
+        sha.update(data);
+        Hash(sha.digest().bytes())
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash({})", self)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = &'static str;
+
+    /// Parses 40 hex digits, in either case.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.len() != 2 * HASH_SIZE || !text.is_ascii() {
+            return Err("a hash is 40 hex digits");
+        }
+        let mut hash = [0; HASH_SIZE];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)
+                .map_err(|_| "a hash is 40 hex digits")?;
+        }
+        Ok(Hash(hash))
+    }
+}
+
+pub(crate) fn corrupt(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Kind {
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "commit" => Some(Kind::Commit),
+            "tree" => Some(Kind::Tree),
+            "blob" => Some(Kind::Blob),
+            "tag" => Some(Kind::Tag),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Commit => "commit",
+            Kind::Tree => "tree",
+            Kind::Blob => "blob",
+            Kind::Tag => "tag",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Object {
+    pub kind: Kind,
+    pub data: Vec<u8>,
+}
+
+/// The parsed header and message of a commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: Hash,
+    pub parents: Vec<Hash>,
+    /// `Name <email> seconds zone`, as recorded.
+    pub author: String,
+    pub committer: String,
+    pub message: String,
+}
+
+fn hash_field(value: &str) -> io::Result<Hash> {
+    value.parse().map_err(|_| corrupt("bad hash in commit"))
+}
+
+impl Commit {
+    pub fn parse(data: &[u8]) -> io::Result<Commit> {
+        let text = String::from_utf8_lossy(data);
+        let (header, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let mut tree = None;
+        let mut commit = Commit {
+            tree: Hash([0; HASH_SIZE]),
+            parents: Vec::new(),
+            author: String::new(),
+            committer: String::new(),
+            message: message.to_string(),
+        };
+        // Continuation lines, as in signatures, start with a space.
+        for line in header.lines().filter(|line| !line.starts_with(' ')) {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "tree" => tree = Some(hash_field(value)?),
+                "parent" => commit.parents.push(hash_field(value)?),
+                "author" => commit.author = value.to_string(),
+                "committer" => commit.committer = value.to_string(),
+                _ => {}
+            }
+        }
+        commit.tree = tree.ok_or_else(|| corrupt("commit without a tree"))?;
+        Ok(commit)
+    }
+
+    /// Seconds since the epoch at which it was committed.
+    pub fn time(&self) -> u32 {
+        signature_time(&self.committer)
+    }
+}
+
+/// The time in a `Name <email> seconds zone` signature, or 0.
+pub fn signature_time(signature: &str) -> u32 {
+    signature
+        .rsplit(' ')
+        .nth(1)
+        .and_then(|seconds| seconds.parse::<i64>().ok())
+        .map_or(0, |seconds| seconds.clamp(0, u32::MAX as i64) as u32)
+}
+
+/// What a tree entry's mode says it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Executable,
+    Symlink,
+    Tree,
+    /// A commit of another repository.
+    Submodule,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub hash: Hash,
+}
+
+/// The entries of a tree object, in their stored order.
+pub fn parse_tree(data: &[u8]) -> io::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&byte| byte == b' ')
+            .ok_or_else(|| corrupt("tree entry without a mode"))?;
+        let nul = rest
+            .iter()
+            .position(|&byte| byte == 0)
+            .filter(|&nul| nul > space && nul + 1 + HASH_SIZE <= rest.len())
+            .ok_or_else(|| corrupt("truncated tree entry"))?;
+        let mode = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .ok_or_else(|| corrupt("bad tree entry mode"))?;
+        let kind = match mode & 0o170000 {
+            0o040000 => EntryKind::Tree,
+            0o120000 => EntryKind::Symlink,
+            0o160000 => EntryKind::Submodule,
+            _ if mode & 0o111 != 0 => EntryKind::Executable,
+            _ => EntryKind::File,
+        };
+        entries.push(TreeEntry {
+            name: String::from_utf8_lossy(&rest[space + 1..nul]).into_owned(),
+            kind,
+            hash: Hash(rest[nul + 1..nul + 1 + HASH_SIZE].try_into().unwrap()),
+        });
+        rest = &rest[nul + 1 + HASH_SIZE..];
+    }
+    Ok(entries)
+}
@@ -0,0 +1,280 @@
+//! Pack files and their version 2 indexes.
+//!
+//! An index holds a fan-out table of 256 counts, the sorted hashes of
+//! the pack's objects and each one's offset in the pack. In the pack an
+//! object is a type and length header followed by zlib data; deltas name
+//! their base by a backwards offset in the same pack or by hash, and hold
+//! copy and insert instructions that rebuild the object from the base.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+
+use crate::object::{HASH_SIZE, Hash, Kind, Object, corrupt};
+
+const IDX_MAGIC: &[u8; 4] = b"\xfftOc";
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+/// Longest chain of deltas followed before giving up.
+const MAX_DELTA_DEPTH: usize = 64;
+
+/// Reads a file from a position onwards.
+struct At<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl Read for At<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read_at(buf, self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// A pack with its index loaded.
+pub struct Pack {
+    file: File,
+    /// The index: fan-out, hashes, checksums and offsets.
+    index: Vec<u8>,
+    count: usize,
+}
+
+fn be32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+impl Pack {
+    /// Opens the pack `path` (ending in `.pack`) and its `.idx`.
+    pub fn open(path: &Path) -> io::Result<Pack> {
+        let index = fs::read(path.with_extension("idx"))?;
+        if index.len() < 8 + 256 * 4 || &index[..4] != IDX_MAGIC || be32(&index, 4) != 2 {
+            return Err(corrupt("only version 2 pack indexes are understood"));
+        }
+        let count = be32(&index, 8 + 255 * 4) as usize;
+        if index.len() < 8 + 256 * 4 + count * (HASH_SIZE + 8) {
+            return Err(corrupt("pack index is truncated"));
+        }
+        let file = File::open(path)?;
+        let mut header = [0; 12];
+        file.read_exact_at(&mut header, 0)?;
+        if &header[..4] != b"PACK" || be32(&header, 8) as usize != count {
+            return Err(corrupt("pack does not match its index"));
+        }
+        Ok(Pack { file, index, count })
+    }
+
+    fn hash_at(&self, n: usize) -> &[u8] {
+        let at = 8 + 256 * 4 + n * HASH_SIZE;
+        &self.index[at..at + HASH_SIZE]
+    }
+
+    /// Where `hash` is in the pack, if it is there.
+    pub fn find(&self, hash: &Hash) -> Option<u64> {
+        let first = hash.0[0] as usize;
+        let mut low = if first == 0 {
+            0
+        } else {
+            be32(&self.index, 8 + (first - 1) * 4) as usize
+        };
+        let mut high = be32(&self.index, 8 + first * 4) as usize;
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.hash_at(mid).cmp(&hash.0[..]) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(self.offset_at(mid)),
+            }
+        }
+        None
+    }
+
+    fn offset_at(&self, n: usize) -> u64 {
+        let offsets = 8 + 256 * 4 + self.count * (HASH_SIZE + 4);
+        let offset = be32(&self.index, offsets + n * 4);
+        if offset & 0x8000_0000 == 0 {
+            return offset as u64;
+        }
+        // Offsets past 2 GiB live in a table of their own.
+        let at = offsets + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        self.index.get(at..at + 8).map_or(u64::MAX, |bytes| {
+            u64::from_be_bytes(bytes.try_into().unwrap())
+        })
+    }
+
+    /// The object at `offset`, with any deltas applied. Bases named by
+    /// hash are looked up with `base`.
+    pub fn read(
+        &self,
+        offset: u64,
+        base: &dyn Fn(&Hash) -> io::Result<Object>,
+    ) -> io::Result<Object> {
+        let mut deltas = Vec::new();
+        let mut at = offset;
+        let base_object = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(corrupt("delta chain too long"));
+            }
+            let (kind, size, data_at) = self.header(at)?;
+            match kind {
+                OFS_DELTA => {
+                    let (distance, data_at) = self.distance(data_at)?;
+                    deltas.push(self.inflate(data_at, size)?);
+                    at = at
+                        .checked_sub(distance)
+                        .ok_or_else(|| corrupt("delta base before the pack"))?;
+                }
+                REF_DELTA => {
+                    let mut hash = [0; HASH_SIZE];
+                    self.file.read_exact_at(&mut hash, data_at)?;
+                    deltas.push(self.inflate(data_at + HASH_SIZE as u64, size)?);
+                    let hash = Hash(hash);
+                    break match self.find(&hash) {
+                        Some(found) => self.read(found, base)?,
+                        None => base(&hash)?,
+                    };
+                }
+                _ => {
+                    let kind = match kind {
+                        1 => Kind::Commit,
+                        2 => Kind::Tree,
+                        3 => Kind::Blob,
+                        4 => Kind::Tag,
+                        _ => return Err(corrupt(format!("unknown pack object type {}", kind))),
+                    };
+                    break Object {
+                        kind,
+                        data: self.inflate(data_at, size)?,
+                    };
+                }
+            }
+        };
+        let mut object = base_object;
+        for delta in deltas.iter().rev() {
+            object.data = apply_delta(&object.data, delta)?;
+        }
+        Ok(object)
+    }
+
+    /// The type, length and data offset of the object at `offset`.
+    fn header(&self, offset: u64) -> io::Result<(u8, u64, u64)> {
+        let mut bytes = [0; 10];
+        let n = self.file.read_at(&mut bytes, offset)?;
+        let kind = (bytes[0] >> 4) & 7;
+        let mut size = (bytes[0] & 0x0f) as u64;
+        let mut shift = 4;
+        let mut used = 1;
+        let mut byte = bytes[0];
+        while byte & 0x80 != 0 {
+            if used >= n || shift > 60 {
+                return Err(corrupt("bad pack object header"));
+            }
+            byte = bytes[used];
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            used += 1;
+        }
+        Ok((kind, size, offset + used as u64))
+    }
+
+    /// The base distance of an offset delta, and where its data starts.
+    fn distance(&self, at: u64) -> io::Result<(u64, u64)> {
+        let mut bytes = [0; 10];
+        let n = self.file.read_at(&mut bytes, at)?;
+        let mut distance = (bytes[0] & 0x7f) as u64;
+        let mut used = 1;
+        while bytes[used - 1] & 0x80 != 0 {
+            if used >= n {
+                return Err(corrupt("bad delta offset"));
+            }
+            distance = ((distance + 1) << 7) | (bytes[used] & 0x7f) as u64;
+            used += 1;
+        }
+        Ok((distance, at + used as u64))
+    }
+
+    fn inflate(&self, at: u64, size: u64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(size.min(1 << 24) as usize);
+        ZlibDecoder::new(At {
+            file: &self.file,
+            offset: at,
+        })
+        .take(size)
+        .read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            return Err(corrupt("pack object shorter than its header says"));
+        }
+        Ok(data)
+    }
+}
+
+/// A little-endian base-128 length at the start of `delta`.
+fn delta_length(delta: &mut &[u8]) -> io::Result<u64> {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = delta
+            .split_first()
+            .ok_or_else(|| corrupt("truncated delta"))?;
+        *delta = rest;
+        length |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(length);
+        }
+        shift += 7;
+        if shift > 63 {
+            return Err(corrupt("bad delta length"));
+        }
+    }
+}
+
+/// Rebuilds an object from `base` and the instructions in `delta`.
+pub fn apply_delta(base: &[u8], mut delta: &[u8]) -> io::Result<Vec<u8>> {
+    if delta_length(&mut delta)? != base.len() as u64 {
+        return Err(corrupt("delta does not fit its base"));
+    }
+    let size = delta_length(&mut delta)?;
+    let mut out = Vec::with_capacity(size.min(1 << 24) as usize);
+    while let Some((&op, rest)) = delta.split_first() {
+        delta = rest;
+        if op & 0x80 != 0 {
+            // Copy: bits 0-3 say which offset bytes follow, 4-6 which
+            // length bytes.
+            let mut fields = [0u64; 2];
+            for bit in 0..7 {
+                if op & (1 << bit) != 0 {
+                    let (&byte, rest) = delta
+                        .split_first()
+                        .ok_or_else(|| corrupt("truncated delta"))?;
+                    delta = rest;
+                    let (field, shift) = if bit < 4 { (0, bit) } else { (1, bit - 4) };
+                    fields[field] |= (byte as u64) << (8 * shift);
+                }
+            }
+            let [offset, length] = fields;
+            let length = if length == 0 { 0x10000 } else { length };
+            let piece = usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(offset + length).ok())
+                .and_then(|(start, end)| base.get(start..end))
+                .ok_or_else(|| corrupt("delta copies past its base"))?;
+            out.extend_from_slice(piece);
+        } else if op != 0 {
+            let len = op as usize;
+            if delta.len() < len {
+                return Err(corrupt("truncated delta"));
+            }
+            out.extend_from_slice(&delta[..len]);
+            delta = &delta[len..];
+        } else {
+            return Err(corrupt("reserved delta instruction"));
+        }
+    }
+    if out.len() as u64 != size {
+        return Err(corrupt("delta result has the wrong length"));
+    }
+    Ok(out)
+}
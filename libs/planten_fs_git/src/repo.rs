@@ -0,0 +1,306 @@
+//! Reading a repository on disk: loose and packed objects, refs and
+//! `HEAD`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use flate2::read::ZlibDecoder;
+
+use crate::object::{Commit, Hash, Kind, Object, corrupt};
+use crate::pack::Pack;
+
+/// Objects kept decoded, mostly trees walked again and again.
+const CACHE_OBJECTS: usize = 512;
+/// Symbolic refs followed before assuming a loop.
+const MAX_SYMREF_DEPTH: usize = 8;
+
+/// Where `HEAD` points.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Head {
+    /// The branch checked out, without `refs/heads/`; `None` when
+    /// detached.
+    pub branch: Option<String>,
+    /// `None` on a branch with no commits yet.
+    pub commit: Option<Hash>,
+}
+
+pub struct Repository {
+    git_dir: PathBuf,
+    /// Where refs and objects are; the git directory itself except in a
+    /// linked worktree.
+    common_dir: PathBuf,
+    /// The object directory and its alternates.
+    object_dirs: Vec<PathBuf>,
+    packs: Mutex<Vec<(PathBuf, Arc<Pack>)>>,
+    cache: Mutex<HashMap<Hash, Arc<Object>>>,
+}
+
+fn not_found(what: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, what.into())
+}
+
+impl Repository {
+    /// Opens the repository at `path`: a work tree with a `.git`
+    /// directory or file, or a bare repository.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Repository> {
+        let path = path.as_ref();
+        let dot_git = path.join(".git");
+        let git_dir = if dot_git.is_dir() {
+            dot_git
+        } else if dot_git.is_file() {
+            // Worktrees and submodules point elsewhere.
+            let text = fs::read_to_string(&dot_git)?;
+            let target = text
+                .trim()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| corrupt(".git file without a gitdir line"))?;
+            path.join(target)
+        } else {
+            path.to_path_buf()
+        };
+        if !git_dir.join("HEAD").is_file() {
+            return Err(not_found(format!(
+                "{} is not a git repository",
+                path.display()
+            )));
+        }
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => git_dir.join(common.trim()),
+            Err(_) => git_dir.clone(),
+        };
+        let objects = common_dir.join("objects");
+        let mut object_dirs = vec![objects.clone()];
+        if let Ok(alternates) = fs::read_to_string(objects.join("info/alternates")) {
+            object_dirs.extend(
+                alternates
+                    .lines()
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| objects.join(line)),
+            );
+        }
+        let repo = Repository {
+            git_dir,
+            common_dir,
+            object_dirs,
+            packs: Mutex::new(Vec::new()),
+            cache: Mutex::new(HashMap::new()),
+        };
+        repo.rescan_packs()?;
+        Ok(repo)
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// Opens packs that appeared since the last scan, after a fetch or a
+    /// repack, and drops those that went away.
+    fn rescan_packs(&self) -> io::Result<()> {
+        let mut paths = Vec::new();
+        for dir in &self.object_dirs {
+            let Ok(entries) = fs::read_dir(dir.join("pack")) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "pack") {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+        let mut packs = self.packs.lock().unwrap();
+        let mut kept = Vec::with_capacity(paths.len());
+        for path in paths {
+            match packs.iter().find(|(known, _)| *known == path) {
+                Some((_, pack)) => kept.push((path, Arc::clone(pack))),
+                // A pack still being written has no index yet.
+                None => {
+                    if let Ok(pack) = Pack::open(&path) {
+                        kept.push((path, Arc::new(pack)));
+                    }
+                }
+            }
+        }
+        *packs = kept;
+        Ok(())
+    }
+
+    fn in_packs(&self, hash: &Hash) -> Option<io::Result<Object>> {
+        let packs: Vec<Arc<Pack>> = self
+            .packs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, pack)| Arc::clone(pack))
+            .collect();
+        packs.iter().find_map(|pack| {
+            let offset = pack.find(hash)?;
+            Some(pack.read(offset, &|base| {
+                self.read(base).map(|object| (*object).clone())
+            }))
+        })
+    }
+
+    fn loose(&self, hash: &Hash) -> io::Result<Object> {
+        let name = hash.to_string();
+        for dir in &self.object_dirs {
+            let file = match fs::File::open(dir.join(&name[..2]).join(&name[2..])) {
+                Ok(file) => file,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            let mut raw = Vec::new();
+            ZlibDecoder::new(file).read_to_end(&mut raw)?;
+            let nul = raw
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or_else(|| corrupt("loose object without a header"))?;
+            let header = String::from_utf8_lossy(&raw[..nul]);
+            let (kind, len) = header
+                .split_once(' ')
+                .and_then(|(kind, len)| Some((Kind::from_name(kind)?, len.parse::<usize>().ok()?)))
+                .ok_or_else(|| corrupt("bad loose object header"))?;
+            if len != raw.len() - nul - 1 {
+                return Err(corrupt("loose object has the wrong length"));
+            }
+            raw.drain(..=nul);
+            return Ok(Object { kind, data: raw });
+        }
+        Err(not_found(format!("object {} not found", hash)))
+    }
+
+    /// The object `hash`, from a pack or a loose file.
+    pub fn read(&self, hash: &Hash) -> io::Result<Arc<Object>> {
+        if let Some(object) = self.cache.lock().unwrap().get(hash) {
+            return Ok(Arc::clone(object));
+        }
+        let object = match self.in_packs(hash) {
+            Some(object) => object?,
+            None => match self.loose(hash) {
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    self.rescan_packs()?;
+                    self.in_packs(hash).ok_or(err)??
+                }
+                object => object?,
+            },
+        };
+        let object = Arc::new(object);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_OBJECTS {
+            cache.clear();
+        }
+        cache.insert(*hash, Arc::clone(&object));
+        Ok(object)
+    }
+
+    pub fn commit(&self, hash: &Hash) -> io::Result<Commit> {
+        let object = self.read(hash)?;
+        if object.kind != Kind::Commit {
+            return Err(corrupt(format!(
+                "{} is a {}, not a commit",
+                hash, object.kind
+            )));
+        }
+        Commit::parse(&object.data)
+    }
+
+    /// The packed refs, by full name.
+    fn packed_refs(&self) -> io::Result<BTreeMap<String, Hash>> {
+        let text = match fs::read_to_string(self.common_dir.join("packed-refs")) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(text
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| {
+                let (hash, name) = line.split_once(' ')?;
+                Some((name.to_string(), hash.parse().ok()?))
+            })
+            .collect())
+    }
+
+    /// The hash the ref `name` (like `refs/heads/main` or `HEAD`) leads
+    /// to, following symbolic refs; `None` if it does not exist.
+    pub fn resolve_ref(&self, name: &str) -> io::Result<Option<Hash>> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let dir = if name == "HEAD" {
+                &self.git_dir
+            } else {
+                &self.common_dir
+            };
+            let text = match fs::read_to_string(dir.join(&name)) {
+                Ok(text) => text,
+                Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::IsADirectory) => {
+                    return Ok(self.packed_refs()?.get(&name).copied());
+                }
+                Err(err) => return Err(err),
+            };
+            let text = text.trim();
+            match text.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None => {
+                    return text
+                        .parse()
+                        .map(Some)
+                        .map_err(|_| corrupt(format!("bad ref {}", name)));
+                }
+            }
+        }
+        Err(corrupt("symbolic refs loop"))
+    }
+
+    pub fn head(&self) -> io::Result<Head> {
+        let text = fs::read_to_string(self.git_dir.join("HEAD"))?;
+        let branch = text
+            .trim()
+            .strip_prefix("ref: refs/heads/")
+            .map(str::to_string);
+        Ok(Head {
+            branch,
+            commit: self.resolve_ref("HEAD")?,
+        })
+    }
+
+    /// Every branch and the commit at its tip, by name without
+    /// `refs/heads/`.
+    pub fn branches(&self) -> io::Result<BTreeMap<String, Hash>> {
+        let mut branches: BTreeMap<String, Hash> = self
+            .packed_refs()?
+            .into_iter()
+            .filter_map(|(name, hash)| Some((name.strip_prefix("refs/heads/")?.to_string(), hash)))
+            .collect();
+        let heads = self.common_dir.join("refs/heads");
+        let mut dirs = vec![heads.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Ok(name) = path.strip_prefix(&heads) else {
+                    continue;
+                };
+                let name = name.to_string_lossy().into_owned();
+                // Lock files of a ref being updated.
+                if name.ends_with(".lock") {
+                    continue;
+                }
+                if let Some(hash) = self.resolve_ref(&format!("refs/heads/{}", name))? {
+                    branches.insert(name, hash);
+                }
+            }
+        }
+        Ok(branches)
+    }
+}
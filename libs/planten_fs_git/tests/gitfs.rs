@@ -0,0 +1,256 @@
+use std::fs;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use planten_9p::P9Client;
+use planten_9p::server::run_server;
+use planten_fs_core::{DMDIR, FsServer};
+use planten_fs_git::GitFs;
+use planten_fs_git::object::{Hash, Kind};
+use planten_fs_git::pack::apply_delta;
+
+/// Runs git in `dir` with fixed names and dates, returning its output.
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "Glenda")
+        .env("GIT_AUTHOR_EMAIL", "glenda@plan9.example")
+        .env("GIT_AUTHOR_DATE", "1700000000 +0000")
+        .env("GIT_COMMITTER_NAME", "Bootes")
+        .env("GIT_COMMITTER_EMAIL", "bootes@plan9.example")
+        .env("GIT_COMMITTER_DATE", "1700000100 +0000")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {:?}: {:?}", args, output);
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// A repository on `main` with two commits: a README, an executable
+/// script in `bin/` and, in the second, a changed README.
+fn repository(dir: &Path) -> (String, String) {
+    git(dir, &["init", "-q", "-b", "main"]);
+    fs::write(dir.join("README"), "first\n").unwrap();
+    fs::create_dir(dir.join("bin")).unwrap();
+    fs::write(dir.join("bin/run"), "#!/bin/rc\necho hi\n").unwrap();
+    fs::set_permissions(dir.join("bin/run"), fs::Permissions::from_mode(0o755)).unwrap();
+    git(dir, &["add", "."]);
+    git(dir, &["commit", "-q", "-m", "first"]);
+    let first = git(dir, &["rev-parse", "HEAD"]);
+    fs::write(dir.join("README"), "second\n").unwrap();
+    git(dir, &["commit", "-q", "-a", "-m", "second\n\nwith a body"]);
+    let second = git(dir, &["rev-parse", "HEAD"]);
+    (first, second)
+}
+
+fn text(fs: &GitFs, path: &str) -> String {
+    String::from_utf8(fs.read_file(path).unwrap()).unwrap()
+}
+
+#[test]
+fn serves_commits_from_loose_objects() {
+    let dir = tempfile::tempdir().unwrap();
+    let (first, second) = repository(dir.path());
+    let fs = GitFs::open(dir.path(), "bootes", "sys").unwrap();
+
+    assert_eq!(fs.list("/").unwrap(), ["HEAD", "branch", "ctl", "object"]);
+    assert_eq!(fs.list("/branch").unwrap(), ["main"]);
+    assert_eq!(
+        fs.list("/HEAD").unwrap(),
+        ["author", "committer", "hash", "msg", "parent", "tree"]
+    );
+    assert_eq!(text(&fs, "/HEAD/hash"), format!("{}\n", second));
+    assert_eq!(text(&fs, "/branch/main/parent"), format!("{}\n", first));
+    assert_eq!(text(&fs, "/HEAD/msg"), "second\n\nwith a body\n");
+    assert_eq!(
+        text(&fs, "/HEAD/author"),
+        "Glenda <glenda@plan9.example> 1700000000 +0000\n"
+    );
+    assert_eq!(
+        text(&fs, "/HEAD/committer"),
+        "Bootes <bootes@plan9.example> 1700000100 +0000\n"
+    );
+    assert_eq!(text(&fs, "/HEAD/tree/README"), "second\n");
+    assert_eq!(fs.list("/HEAD/tree").unwrap(), ["README", "bin"]);
+    assert_eq!(text(&fs, "/HEAD/tree/bin/run"), "#!/bin/rc\necho hi\n");
+    assert_eq!(text(&fs, &format!("/object/{}/parent", first)), "");
+    assert_eq!(
+        text(&fs, &format!("/object/{}/tree/README", first)),
+        "first\n"
+    );
+    assert_eq!(
+        text(&fs, "/ctl"),
+        format!(
+            "repo {}\nbranch main\nhead {}\n",
+            dir.path().join(".git").display(),
+            second
+        )
+    );
+
+    let run = fs.stat("/HEAD/tree/bin/run").unwrap();
+    assert_eq!(run.mode, 0o555);
+    assert_eq!(run.mtime, 1_700_000_100);
+    assert_eq!(run.uid, "bootes");
+    assert_eq!(run.gid, "sys");
+    assert_eq!(fs.stat("/HEAD/tree/README").unwrap().mode, 0o444);
+    assert_eq!(fs.stat("/HEAD/tree/bin").unwrap().mode, DMDIR | 0o555);
+    assert_eq!(fs.stat("/HEAD").unwrap().mtime, 1_700_000_100);
+    assert!(fs.stat("/HEAD/tree/missing").is_none());
+    assert!(fs.stat("/HEAD/tree/README/deeper").is_none());
+    assert!(fs.stat("/HEAD/nonsense").is_none());
+}
+
+#[test]
+fn packed_objects_and_deltas_read_the_same() {
+    let dir = tempfile::tempdir().unwrap();
+    git(dir.path(), &["init", "-q", "-b", "main"]);
+    let mut expected = Vec::new();
+    let mut body: String = (0..400).map(|n| format!("line {}\n", n)).collect();
+    for round in 0..6 {
+        body.push_str(&format!("round {}\n", round));
+        fs::write(dir.path().join("log"), &body).unwrap();
+        git(dir.path(), &["add", "log"]);
+        git(
+            dir.path(),
+            &["commit", "-q", "-m", &format!("round {}", round)],
+        );
+        expected.push((git(dir.path(), &["rev-parse", "HEAD"]), body.clone()));
+    }
+    git(
+        dir.path(),
+        &["repack", "-a", "-d", "-f", "-q", "--depth=10"],
+    );
+    git(dir.path(), &["prune-packed"]);
+    git(dir.path(), &["pack-refs", "--all"]);
+    assert!(!dir.path().join(".git/refs/heads/main").exists());
+
+    let fs = GitFs::open(dir.path(), "bootes", "sys").unwrap();
+    for (commit, body) in &expected {
+        assert_eq!(text(&fs, &format!("/object/{}/tree/log", commit)), *body);
+    }
+    let (last, _) = expected.last().unwrap();
+    assert_eq!(text(&fs, "/branch/main/hash"), format!("{}\n", last));
+
+    // Every object read back hashes to its name.
+    let blob: Hash = git(dir.path(), &["rev-parse", "HEAD~3:log"])
+        .parse()
+        .unwrap();
+    let object = fs.repository().read(&blob).unwrap();
+    assert_eq!(object.kind, Kind::Blob);
+    assert_eq!(Hash::of(object.kind, &object.data), blob);
+}
+
+#[test]
+fn branches_with_slashes_are_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let (first, second) = repository(dir.path());
+    git(dir.path(), &["branch", "feature/fat/lfn", &first]);
+    git(dir.path(), &["branch", "feature/gitfs"]);
+    git(dir.path(), &["pack-refs", "--all"]);
+    git(dir.path(), &["branch", "fix", &first]);
+    let fs = GitFs::open(dir.path(), "bootes", "sys").unwrap();
+
+    assert_eq!(fs.list("/branch").unwrap(), ["feature", "fix", "main"]);
+    assert_eq!(fs.list("/branch/feature").unwrap(), ["fat", "gitfs"]);
+    assert_eq!(fs.list("/branch/feature/fat").unwrap(), ["lfn"]);
+    assert_eq!(fs.stat("/branch/feature/fat").unwrap().mode, DMDIR | 0o555);
+    assert_eq!(
+        text(&fs, "/branch/feature/fat/lfn/hash"),
+        format!("{}\n", first)
+    );
+    assert_eq!(
+        text(&fs, "/branch/feature/gitfs/hash"),
+        format!("{}\n", second)
+    );
+    assert_eq!(text(&fs, "/branch/fix/tree/README"), "first\n");
+    assert!(fs.stat("/branch/feat").is_none());
+
+    // Refs are read again on every lookup.
+    git(dir.path(), &["checkout", "-q", "--detach", &first]);
+    assert_eq!(text(&fs, "/HEAD/tree/README"), "first\n");
+    assert_eq!(text(&fs, "/ctl").lines().nth(1).unwrap(), "branch none");
+    git(dir.path(), &["branch", "-q", "-D", "fix"]);
+    assert!(fs.stat("/branch/fix").is_none());
+}
+
+#[test]
+fn objects_are_reached_by_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, second) = repository(dir.path());
+    git(dir.path(), &["tag", "-a", "-m", "release", "v1"]);
+    let fs = GitFs::open(dir.path(), "bootes", "sys").unwrap();
+
+    assert!(fs.list("/object").unwrap().is_empty());
+    let blob = git(dir.path(), &["rev-parse", "HEAD:README"]);
+    assert_eq!(text(&fs, &format!("/object/{}", blob)), "second\n");
+    assert_eq!(
+        text(&fs, &format!("/object/{}", blob.to_uppercase())),
+        "second\n"
+    );
+    let tree = git(dir.path(), &["rev-parse", "HEAD:bin"]);
+    assert_eq!(fs.list(&format!("/object/{}", tree)).unwrap(), ["run"]);
+    assert_eq!(fs.stat(&format!("/object/{}/run", tree)).unwrap().mtime, 0);
+    let tag = git(dir.path(), &["rev-parse", "v1"]);
+    let tag_text = text(&fs, &format!("/object/{}", tag));
+    assert!(tag_text.starts_with(&format!("object {}\ntype commit\ntag v1\n", second)));
+    assert!(fs.stat(&format!("/object/{}", "0".repeat(40))).is_none());
+    assert!(fs.stat("/object/xyz").is_none());
+}
+
+#[test]
+fn the_tree_is_read_only() {
+    let dir = tempfile::tempdir().unwrap();
+    repository(dir.path());
+    let mut fs = GitFs::open(dir.path(), "bootes", "sys").unwrap();
+    assert!(fs.write("/HEAD/tree/README", 0, b"x", "glenda").is_none());
+    assert!(fs.create("/HEAD/tree", "new", 0o644, 1, "glenda").is_none());
+    assert!(fs.remove("/HEAD/tree/README").is_none());
+    assert_eq!(text(&fs, "/HEAD/tree/README"), "second\n");
+    assert!(GitFs::open(dir.path().join("bin"), "bootes", "sys").is_err());
+}
+
+#[test]
+fn delta_instructions_copy_and_insert() {
+    let base = b"the quick brown fox";
+    // Lengths 19 and 14, copy "quick " from offset 4, insert "slow ",
+    // then copy "fox" from offset 16.
+    let mut delta = vec![19, 14, 0x91, 4, 6, 5];
+    delta.extend_from_slice(b"slow ");
+    delta.extend_from_slice(&[0x91, 16, 3]);
+    assert_eq!(apply_delta(base, &delta).unwrap(), b"quick slow fox");
+    assert!(apply_delta(b"short", &delta).is_err());
+}
+
+#[test]
+fn anames_mount_a_revision() {
+    let dir = tempfile::tempdir().unwrap();
+    let (first, _) = repository(dir.path());
+    let fs = GitFs::open(dir.path(), "bootes", "sys").unwrap();
+    let aname = format!("/object/{}/tree/bin/", first);
+    assert_eq!(
+        fs.attach(&aname).unwrap(),
+        format!("/object/{}/tree/bin", first)
+    );
+    assert_eq!(fs.attach("").unwrap(), "/");
+    assert!(fs.attach("/HEAD/tree/README").is_none());
+    assert!(fs.attach("/branch/missing").is_none());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || run_server(listener, Arc::new(Mutex::new(fs))));
+    let mut client = P9Client::new(&addr).unwrap();
+    client.version(8192, "9P2000").unwrap();
+    client
+        .attach(1, None, "glenda", "/branch/main/tree")
+        .unwrap();
+    assert_eq!(client.walk(1, 2, &["bin", "run"]).unwrap(), 2);
+    client.open(2, 0).unwrap();
+    assert_eq!(client.read(2, 0, 100).unwrap(), b"#!/bin/rc\necho hi\n");
+    assert!(client.open(1, 1).is_err());
+}
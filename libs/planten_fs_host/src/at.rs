@@ -0,0 +1,259 @@
+//! Directories held open, and the `*at` calls that look names up in
+//! them. Nothing here takes a host path beyond the export root itself,
+//! and nothing follows a symbolic link: a name that turns out to be one
+//! is refused, or, for `stat`, reported as one.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+use libc::c_int;
+
+/// Flags a directory is opened with to look names up in it: held open
+/// without the right to list it, as walking a path needs.
+const SEARCH: c_int = libc::O_PATH;
+
+fn cstr(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// What `fstatat` says of an entry, itself and not what it links to.
+pub(crate) struct Meta(libc::stat);
+
+impl Meta {
+    fn kind(&self) -> libc::mode_t {
+        self.0.st_mode & libc::S_IFMT
+    }
+
+    pub(crate) fn is_dir(&self) -> bool {
+        self.kind() == libc::S_IFDIR
+    }
+
+    pub(crate) fn is_file(&self) -> bool {
+        self.kind() == libc::S_IFREG
+    }
+
+    pub(crate) fn is_link(&self) -> bool {
+        self.kind() == libc::S_IFLNK
+    }
+
+    /// Permission bits, with setuid, setgid and sticky.
+    pub(crate) fn mode(&self) -> u32 {
+        self.0.st_mode & 0o7777
+    }
+
+    pub(crate) fn uid(&self) -> u32 {
+        self.0.st_uid
+    }
+
+    pub(crate) fn gid(&self) -> u32 {
+        self.0.st_gid
+    }
+
+    pub(crate) fn atime(&self) -> i64 {
+        self.0.st_atime
+    }
+
+    pub(crate) fn mtime(&self) -> i64 {
+        self.0.st_mtime
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.0.st_size as u64
+    }
+}
+
+/// A host directory held open.
+pub(crate) struct Dir(OwnedFd);
+
+impl Dir {
+    /// Opens the host directory `path`, following links on the way.
+    pub(crate) fn open_path(path: &Path) -> io::Result<Dir> {
+        let path = cstr(path.as_os_str())?;
+        let flags = SEARCH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let fd = check(unsafe { libc::open(path.as_ptr(), flags) })?;
+        Ok(Dir(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Dir> {
+        Ok(Dir(self.0.try_clone()?))
+    }
+
+    fn fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+
+    fn open_fd(&self, name: &OsStr, flags: c_int, mode: u32) -> io::Result<OwnedFd> {
+        let name = cstr(name)?;
+        let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let fd = check(unsafe { libc::openat(self.fd(), name.as_ptr(), flags, mode) })?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Opens the directory `name` in this one to look names up in.
+    pub(crate) fn dir(&self, name: impl AsRef<OsStr>) -> io::Result<Dir> {
+        self.open_fd(name.as_ref(), SEARCH | libc::O_DIRECTORY, 0)
+            .map(Dir)
+    }
+
+    /// Opens `name` with `flags`; `mode` is for `O_CREAT`.
+    pub(crate) fn open(
+        &self,
+        name: impl AsRef<OsStr>,
+        flags: c_int,
+        mode: u32,
+    ) -> io::Result<File> {
+        self.open_fd(name.as_ref(), flags, mode).map(File::from)
+    }
+
+    pub(crate) fn stat(&self, name: impl AsRef<OsStr>) -> io::Result<Meta> {
+        let name = cstr(name.as_ref())?;
+        let mut stat = unsafe { std::mem::zeroed() };
+        check(unsafe {
+            libc::fstatat(
+                self.fd(),
+                name.as_ptr(),
+                &mut stat,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+        Ok(Meta(stat))
+    }
+
+    pub(crate) fn read_link(&self, name: impl AsRef<OsStr>) -> io::Result<OsString> {
+        let name = cstr(name.as_ref())?;
+        let mut buf = vec![0u8; 256];
+        loop {
+            let len = unsafe {
+                libc::readlinkat(self.fd(), name.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // A target that fills the buffer may have been cut short.
+            if (len as usize) < buf.len() {
+                buf.truncate(len as usize);
+                return Ok(OsString::from_vec(buf));
+            }
+            buf.resize(buf.len() * 2, 0);
+        }
+    }
+
+    /// Names in the directory `name`, without `.` and `..`.
+    pub(crate) fn entries(&self, name: impl AsRef<OsStr>) -> io::Result<Vec<OsString>> {
+        let fd = self
+            .open_fd(name.as_ref(), libc::O_RDONLY | libc::O_DIRECTORY, 0)?
+            .into_raw_fd();
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let err = io::Error::last_os_error();
+            drop(unsafe { OwnedFd::from_raw_fd(fd) });
+            return Err(err);
+        }
+        let mut names = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                break;
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsString::from_vec(name.to_vec()));
+            }
+        }
+        // Closing the stream closes `fd`.
+        unsafe { libc::closedir(stream) };
+        Ok(names)
+    }
+
+    pub(crate) fn mkdir(&self, name: impl AsRef<OsStr>, mode: u32) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        check(unsafe { libc::mkdirat(self.fd(), name.as_ptr(), mode as libc::mode_t) })?;
+        Ok(())
+    }
+
+    /// Removes `name`, which must be a directory when `dir` is set and
+    /// must not be one otherwise.
+    pub(crate) fn unlink(&self, name: impl AsRef<OsStr>, dir: bool) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        let flags = if dir { libc::AT_REMOVEDIR } else { 0 };
+        check(unsafe { libc::unlinkat(self.fd(), name.as_ptr(), flags) })?;
+        Ok(())
+    }
+
+    /// Renames `from` to `to`, both in this directory.
+    pub(crate) fn rename(&self, from: impl AsRef<OsStr>, to: impl AsRef<OsStr>) -> io::Result<()> {
+        let from = cstr(from.as_ref())?;
+        let to = cstr(to.as_ref())?;
+        check(unsafe { libc::renameat(self.fd(), from.as_ptr(), self.fd(), to.as_ptr()) })?;
+        Ok(())
+    }
+
+    pub(crate) fn chmod(&self, name: impl AsRef<OsStr>, mode: u32) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        check(unsafe {
+            libc::fchmodat(
+                self.fd(),
+                name.as_ptr(),
+                mode as libc::mode_t,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Changes the owner, the group, or both; `None` leaves one alone.
+    pub(crate) fn chown(
+        &self,
+        name: impl AsRef<OsStr>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        check(unsafe {
+            libc::fchownat(
+                self.fd(),
+                name.as_ptr(),
+                uid.unwrap_or(u32::MAX),
+                gid.unwrap_or(u32::MAX),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Sets the access and modification times, in seconds.
+    pub(crate) fn set_times(
+        &self,
+        name: impl AsRef<OsStr>,
+        atime: u32,
+        mtime: u32,
+    ) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        let time = |secs: u32| libc::timespec {
+            tv_sec: secs as libc::time_t,
+            tv_nsec: 0,
+        };
+        let times = [time(atime), time(mtime)];
+        check(unsafe {
+            libc::utimensat(
+                self.fd(),
+                name.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::run_server;
+use planten_fs_core::ReadOnly;
+use planten_fs_host::HostFs;
+use planten_fs_host::users::UserMap;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5664";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_host_server [--listen host:port] [--read-only]\n\
+         \t[--passwd file --groups file] [--map-user uid=name]... [--map-group gid=name]... dir\n\
+         Owners are named from the host's /etc/passwd and /etc/group unless\n\
+         --passwd and --groups name others; --map-user and --map-group rename ids."
+    );
+    process::exit(1);
+}
+
+/// An `id=name` argument.
+fn mapping(args: &mut impl Iterator<Item = String>) -> (u32, String) {
+    args.next()
+        .and_then(|arg| {
+            let (id, name) = arg.split_once('=')?;
+            Some((id.parse().ok()?, name.to_string()))
+        })
+        .filter(|(_, name)| !name.is_empty())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut read_only = false;
+    let (mut passwd, mut groups) = (None, None);
+    let (mut user_maps, mut group_maps) = (Vec::new(), Vec::new());
+    let mut dir = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--read-only" => read_only = true,
+            "--passwd" => passwd = Some(args.next().unwrap_or_else(|| usage())),
+            "--groups" => groups = Some(args.next().unwrap_or_else(|| usage())),
+            "--map-user" => user_maps.push(mapping(&mut args)),
+            "--map-group" => group_maps.push(mapping(&mut args)),
+            _ if arg.starts_with('-') || dir.is_some() => usage(),
+            _ => dir = Some(arg),
+        }
+    }
+    let dir = dir.unwrap_or_else(|| usage());
+
+    let mut users = match (passwd, groups) {
+        (None, None) => UserMap::system(),
+        (Some(passwd), Some(groups)) => UserMap::from_files(passwd, groups)?,
+        _ => usage(),
+    };
+    for (uid, name) in user_maps {
+        users.set_user(uid, &name);
+    }
+    for (gid, name) in group_maps {
+        users.set_group(gid, &name);
+    }
+    let fs = HostFs::new(&dir, users).unwrap_or_else(|err| {
+        eprintln!("cannot export {}: {}", dir, err);
+        process::exit(1);
+    });
+    let listener = TcpListener::bind(&listen)?;
+    println!(
+        "HostFs 9P server exporting {} on {}",
+        fs.root().display(),
+        listen
+    );
+    if read_only {
+        run_server(listener, Arc::new(Mutex::new(ReadOnly::new(fs))))
+    } else {
+        run_server(listener, Arc::new(Mutex::new(fs)))
+    }
+}
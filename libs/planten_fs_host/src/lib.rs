@@ -0,0 +1,448 @@
+//! Exports a host directory through `FsServer`, the way u9fs does, so 9P
+//! clients can reach real files.
+//!
+//! Every client path is resolved beneath the export root one component at
+//! a time, each looked up with `openat` and friends in the directory held
+//! open before it, so nothing is found by host path. Symbolic links are
+//! followed by the server, never the kernel, as if the root were `/`:
+//! absolute targets start again at the export root and `..` stops there,
+//! so no name reaches outside it. Every lookup refuses links, so one
+//! swapped in after resolution is refused rather than followed. Only
+//! regular files and directories are visible; devices, fifos, sockets and
+//! dangling links are not listed.
+//!
+//! Host uids and gids show as names through a `UserMap`. Plan 9 mode bits
+//! the host cannot keep (DMAPPEND, DMEXCL) are dropped.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::path::{Component, Path, PathBuf};
+
+use planten_fs_core::{DMDIR, FsServer, Inode, OEXCL, Wstat, create_perm};
+
+mod at;
+pub mod users;
+
+use at::{Dir, Meta};
+use users::UserMap;
+
+/// Links followed while resolving one path before giving up, as
+/// `MAXSYMLINKS` does on Linux.
+const MAX_LINKS: usize = 40;
+
+pub struct HostFs {
+    root: PathBuf,
+    dir: Dir,
+    users: UserMap,
+}
+
+/// Where a client path leads: the directory holding the entry, open, and
+/// the entry's name in it, `.` when the path names that directory itself.
+struct Found {
+    dir: Dir,
+    name: OsString,
+    host: PathBuf,
+}
+
+fn denied(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, message)
+}
+
+/// Whether `name` can name an entry of a directory.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\0')
+}
+
+/// `path` split into its parent and final name; the root has neither.
+fn split(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((if parent.is_empty() { "/" } else { parent }, name))
+}
+
+fn visible(meta: &Meta) -> bool {
+    meta.is_file() || meta.is_dir()
+}
+
+fn seconds(secs: i64) -> u32 {
+    secs.clamp(0, u32::MAX as i64) as u32
+}
+
+impl HostFs {
+    /// Exports the directory `root`, naming owners through `users`.
+    pub fn new(root: impl AsRef<Path>, users: UserMap) -> io::Result<Self> {
+        let root = fs::canonicalize(root)?;
+        if !fs::metadata(&root)?.is_dir() {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+        let dir = Dir::open_path(&root)?;
+        Ok(HostFs { root, dir, users })
+    }
+
+    /// The export root, with its own links resolved.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn users(&self) -> &UserMap {
+        &self.users
+    }
+
+    /// Host path the client path `path` leads to now, with every symbolic
+    /// link along it, the last included, resolved beneath the export root.
+    /// The server itself never opens files by this path.
+    pub fn host_path(&self, path: &str) -> io::Result<PathBuf> {
+        Ok(self.resolve(path, true)?.host)
+    }
+
+    /// Walks `path` from the export root, following links on the way and
+    /// the last one too when `follow` is set.
+    fn resolve(&self, path: &str, follow: bool) -> io::Result<Found> {
+        let mut pending: Vec<OsString> = path
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .rev()
+            .map(OsString::from)
+            .collect();
+        let mut dirs: Vec<Dir> = Vec::new();
+        let mut host = self.root.clone();
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if name == "." {
+                continue;
+            }
+            if name == ".." {
+                if dirs.pop().is_some() {
+                    host.pop();
+                }
+                continue;
+            }
+            let dir = dirs.last().unwrap_or(&self.dir);
+            let last = pending.is_empty();
+            if dir.stat(&name)?.is_link() && (follow || !last) {
+                links += 1;
+                if links > MAX_LINKS {
+                    return Err(io::Error::other("too many levels of symbolic links"));
+                }
+                let target = PathBuf::from(dir.read_link(&name)?);
+                if target.has_root() {
+                    dirs.clear();
+                    host = self.root.clone();
+                }
+                for component in target.components().rev() {
+                    match component {
+                        Component::Normal(name) => pending.push(name.to_owned()),
+                        Component::ParentDir => pending.push(OsString::from("..")),
+                        Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+                    }
+                }
+                continue;
+            }
+            if last {
+                let dir = match dirs.pop() {
+                    Some(dir) => dir,
+                    None => self.dir.try_clone()?,
+                };
+                host.push(&name);
+                return Ok(Found { dir, name, host });
+            }
+            let next = dir.dir(&name)?;
+            dirs.push(next);
+            host.push(&name);
+        }
+        let dir = match dirs.pop() {
+            Some(dir) => dir,
+            None => self.dir.try_clone()?,
+        };
+        Ok(Found {
+            dir,
+            name: OsString::from("."),
+            host,
+        })
+    }
+
+    /// A visible file or directory, and what the host says of it.
+    fn lookup(&self, path: &str) -> io::Result<(Found, Meta)> {
+        let found = self.resolve(path, true)?;
+        let meta = found.dir.stat(&found.name)?;
+        if !visible(&meta) {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok((found, meta))
+    }
+
+    /// The directory `path` names, open.
+    fn open_dir(&self, path: &str) -> io::Result<Dir> {
+        let found = self.resolve(path, true)?;
+        found.dir.dir(&found.name)
+    }
+
+    /// The entry `path` names, its last component not followed: the link
+    /// itself when it is one. Returns the directory holding it and its name.
+    fn entry<'a>(&self, path: &'a str) -> io::Result<(Dir, &'a str)> {
+        let (parent, name) = split(path).ok_or_else(|| denied("the export root"))?;
+        if !valid_name(name) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "bad file name"));
+        }
+        Ok((self.open_dir(parent)?, name))
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let (found, meta) = self.lookup(path)?;
+        if !meta.is_dir() {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+        let mut names = Vec::new();
+        for name in found.dir.entries(&found.name)? {
+            let Ok(name) = name.into_string() else {
+                continue;
+            };
+            let child = format!("{}/{}", path.trim_end_matches('/'), name);
+            if self.lookup(&child).is_ok() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Opens the file `path` with `flags`.
+    fn open_file(&self, path: &str, flags: libc::c_int) -> io::Result<File> {
+        let (found, meta) = self.lookup(path)?;
+        if meta.is_dir() {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        found.dir.open(&found.name, flags, 0)
+    }
+
+    /// Up to `count` bytes of the file at `path` from `offset`.
+    pub fn read_at(&self, path: &str, offset: u64, count: usize) -> io::Result<Vec<u8>> {
+        let file = self.open_file(path, libc::O_RDONLY)?;
+        let available = file.metadata()?.len().saturating_sub(offset);
+        let mut data = vec![0; count.min(usize::try_from(available).unwrap_or(usize::MAX))];
+        let mut filled = 0;
+        while filled < data.len() {
+            match file.read_at(&mut data[filled..], offset + filled as u64)? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        data.truncate(filled);
+        Ok(data)
+    }
+
+    fn to_inode(&self, path: &str, meta: &Meta) -> (Inode, u64) {
+        let mut mode = meta.mode() & 0o777;
+        if meta.is_dir() {
+            mode |= DMDIR;
+        }
+        let uid = self.users.user_name(meta.uid());
+        let inode = Inode {
+            name: split(path).map_or("/", |(_, name)| name).to_string(),
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode,
+            gid: self.users.group_name(meta.gid()),
+            atime: seconds(meta.atime()),
+            mtime: seconds(meta.mtime()),
+            muid: uid.clone(),
+            uid,
+        };
+        (inode, if meta.is_dir() { 0 } else { meta.len() })
+    }
+
+    /// The inode at `path` without its contents, and its length.
+    fn inode(&self, path: &str) -> io::Result<(Inode, u64)> {
+        let (_, meta) = self.lookup(path)?;
+        Ok(self.to_inode(path, &meta))
+    }
+
+    fn write_file(&self, path: &str, offset: u64, data: &[u8]) -> io::Result<u32> {
+        self.open_file(path, libc::O_WRONLY)?
+            .write_all_at(data, offset)?;
+        Ok(data.len() as u32)
+    }
+
+    /// Empties the file at `path`, opened through its held directory, with
+    /// `ftruncate`.
+    fn truncate_file(&self, path: &str) -> io::Result<()> {
+        self.open_file(path, libc::O_WRONLY)?.set_len(0)
+    }
+
+    fn remove_entry(&self, path: &str) -> io::Result<()> {
+        let (dir, name) = self.entry(path)?;
+        let is_dir = dir.stat(name)?.is_dir();
+        dir.unlink(name, is_dir)
+    }
+
+    /// Creates `name` in `parent`. New files get `uname` as owner and the
+    /// directory's group when the server may give them away, which in
+    /// practice means when it runs as root; otherwise they belong to the
+    /// server's own account, as with u9fs.
+    fn create_entry(
+        &self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> io::Result<()> {
+        if !valid_name(name) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "bad file name"));
+        }
+        let (found, dir_meta) = self.lookup(parent)?;
+        if !dir_meta.is_dir() {
+            return Err(ErrorKind::NotADirectory.into());
+        }
+        let dir = found.dir.dir(&found.name)?;
+        if dir.stat(name).is_ok() {
+            let child = format!("{}/{}", parent.trim_end_matches('/'), name);
+            let (found, meta) = self.lookup(&child)?;
+            if mode & OEXCL != 0 || perm & DMDIR != 0 || meta.is_dir() {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            found
+                .dir
+                .open(&found.name, libc::O_WRONLY | libc::O_TRUNC, 0)?;
+            return Ok(());
+        }
+
+        let bits = create_perm(perm, dir_meta.mode() & 0o777) & 0o777;
+        if perm & DMDIR != 0 {
+            dir.mkdir(name, bits)?;
+        } else {
+            dir.open(name, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL, bits)?;
+        }
+        // Creation modes pass through the umask; the client asked for these.
+        dir.chmod(name, bits)?;
+        let _ = dir.chown(name, None, Some(dir_meta.gid()));
+        if let Some(uid) = self.users.uid(uname) {
+            let _ = dir.chown(name, Some(uid), None);
+        }
+        Ok(())
+    }
+
+    /// Applies `change`. Everything that can fail for lack of a name or a free name
+    /// is checked first; the changes themselves are separate host calls,
+    /// so a host error part way leaves the earlier ones made.
+    fn wstat_entry(&self, path: &str, change: &Wstat) -> io::Result<()> {
+        let (found, meta) = self.lookup(path)?;
+        let (old, length) = self.to_inode(path, &meta);
+        change.check(&old, length).map_err(denied)?;
+        let inode = change.apply(&old);
+        let uid = match inode.uid != old.uid {
+            true => Some(
+                self.users
+                    .uid(&inode.uid)
+                    .ok_or_else(|| denied("unknown user"))?,
+            ),
+            false => None,
+        };
+        let gid = match inode.gid != old.gid {
+            true => Some(
+                self.users
+                    .gid(&inode.gid)
+                    .ok_or_else(|| denied("unknown group"))?,
+            ),
+            false => None,
+        };
+        let rename = if change.renames(&old.name) {
+            if !valid_name(&inode.name) {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "bad file name"));
+            }
+            let (dir, name) = self.entry(path)?;
+            if dir.stat(&inode.name).is_ok() {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            Some((dir, name))
+        } else {
+            None
+        };
+
+        let Found { dir, name, .. } = found;
+        if uid.is_some() || gid.is_some() {
+            dir.chown(&name, uid, gid)?;
+        }
+        if inode.mode & 0o777 != old.mode & 0o777 {
+            dir.chmod(&name, (meta.mode() & !0o777) | (inode.mode & 0o777))?;
+        }
+        if let Some(length) = change.new_length(length) {
+            dir.open(&name, libc::O_WRONLY, 0)?.set_len(length)?;
+        }
+        if inode.atime != old.atime || inode.mtime != old.mtime {
+            dir.set_times(&name, inode.atime, inode.mtime)?;
+        }
+        if let Some((dir, from)) = rename {
+            dir.rename(from, &inode.name)?;
+        }
+        Ok(())
+    }
+}
+
+impl FsServer for HostFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.lookup(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        HostFs::read_at(self, path, 0, usize::MAX).ok()
+    }
+
+    /// The host records no muid, so `uname` goes unused.
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], _uname: &str) -> Option<u32> {
+        self.write_file(path, offset, data).ok()
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.remove_entry(path).ok()
+    }
+
+    /// Leaves the contents out; `read_at` reads them.
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inode(path).ok().map(|(inode, _)| inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        self.inode(path).ok()
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        HostFs::read_at(self, path, offset, count as usize).ok()
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.wstat_entry(path, change).ok()
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.create_entry(parent, name, perm, mode, uname).ok()
+    }
+
+    fn truncate(&mut self, path: &str, _uname: &str) -> Option<()> {
+        self.truncate_file(path).ok()
+    }
+
+    fn is_member(&self, _path: &str, uname: &str, group: &str) -> bool {
+        self.users.is_member(uname, group)
+    }
+}
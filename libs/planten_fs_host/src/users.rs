@@ -0,0 +1,143 @@
+//! Translation between host uids and gids and the names planten uses.
+//!
+//! The tables come from passwd(5) and group(5) files, by default the
+//! host's own, and explicit entries override them, so a host account can
+//! appear under its Plan 9 name (`1000` as `glenda`). Ids with no name
+//! show as their decimal number, which also maps back.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct UserMap {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+    /// Each uid's primary group, from passwd.
+    primary: HashMap<u32, u32>,
+    /// Host login names, which group member lists use.
+    logins: HashMap<String, u32>,
+    /// Supplementary members of each group, by login.
+    members: HashMap<u32, Vec<String>>,
+}
+
+/// The fields of each non-comment line of a colon-separated file.
+fn records(text: &str) -> impl Iterator<Item = Vec<&str>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split(':').collect())
+}
+
+impl UserMap {
+    /// The host's accounts, from `/etc/passwd` and `/etc/group`. Missing
+    /// files leave the map empty, so every id shows as a number.
+    pub fn system() -> Self {
+        let read = |path: &str| fs::read_to_string(path).unwrap_or_default();
+        Self::parse(&read("/etc/passwd"), &read("/etc/group"))
+    }
+
+    pub fn from_files(passwd: impl AsRef<Path>, group: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(
+            &fs::read_to_string(passwd)?,
+            &fs::read_to_string(group)?,
+        ))
+    }
+
+    /// A map from the text of passwd and group files. Malformed lines are
+    /// skipped.
+    pub fn parse(passwd: &str, group: &str) -> Self {
+        let mut map = UserMap::default();
+        for fields in records(passwd) {
+            let (Some(name), Some(uid), Some(gid)) = (
+                fields.first(),
+                fields.get(2).and_then(|uid| uid.parse().ok()),
+                fields.get(3).and_then(|gid| gid.parse().ok()),
+            ) else {
+                continue;
+            };
+            map.users.entry(uid).or_insert_with(|| name.to_string());
+            map.primary.entry(uid).or_insert(gid);
+            map.logins.insert(name.to_string(), uid);
+        }
+        for fields in records(group) {
+            let (Some(name), Some(gid)) = (
+                fields.first(),
+                fields.get(2).and_then(|gid| gid.parse().ok()),
+            ) else {
+                continue;
+            };
+            map.groups.entry(gid).or_insert_with(|| name.to_string());
+            let members = fields
+                .get(3)
+                .map(|list| {
+                    list.split(',')
+                        .filter(|member| !member.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            map.members.insert(gid, members);
+        }
+        map
+    }
+
+    /// Shows host user `uid` as `name`, which then maps back to `uid`.
+    pub fn set_user(&mut self, uid: u32, name: &str) {
+        self.users.insert(uid, name.to_string());
+    }
+
+    /// Shows host group `gid` as `name`.
+    pub fn set_group(&mut self, gid: u32, name: &str) {
+        self.groups.insert(gid, name.to_string());
+    }
+
+    pub fn user_name(&self, uid: u32) -> String {
+        self.users
+            .get(&uid)
+            .cloned()
+            .unwrap_or_else(|| uid.to_string())
+    }
+
+    pub fn group_name(&self, gid: u32) -> String {
+        self.groups
+            .get(&gid)
+            .cloned()
+            .unwrap_or_else(|| gid.to_string())
+    }
+
+    pub fn uid(&self, name: &str) -> Option<u32> {
+        self.users
+            .iter()
+            .find(|(_, user)| *user == name)
+            .map(|(uid, _)| *uid)
+            .or_else(|| name.parse().ok())
+    }
+
+    pub fn gid(&self, name: &str) -> Option<u32> {
+        self.groups
+            .iter()
+            .find(|(_, group)| *group == name)
+            .map(|(gid, _)| *gid)
+            .or_else(|| name.parse().ok())
+    }
+
+    /// Whether `user` belongs to `group`, as its primary group or a
+    /// listed member. Following Plan 9, everyone is also a member of the
+    /// group named after them.
+    pub fn is_member(&self, user: &str, group: &str) -> bool {
+        if user == group {
+            return true;
+        }
+        let (Some(uid), Some(gid)) = (self.uid(user), self.gid(group)) else {
+            return false;
+        };
+        self.primary.get(&uid) == Some(&gid)
+            || self.members.get(&gid).is_some_and(|members| {
+                members
+                    .iter()
+                    .any(|login| self.logins.get(login) == Some(&uid))
+            })
+    }
+}
@@ -0,0 +1,264 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt, symlink};
+use std::path::Path;
+
+use planten_fs_core::{DMAPPEND, DMDIR, FsServer, OEXCL, OWRITE, Wstat};
+use planten_fs_host::HostFs;
+use planten_fs_host::users::UserMap;
+use tempfile::TempDir;
+
+/// A scratch directory holding `export/` and, beside it, `outside/secret`
+/// that no client may reach.
+fn exported() -> (TempDir, HostFs) {
+    let dir = tempfile::tempdir().unwrap();
+    let export = dir.path().join("export");
+    fs::create_dir_all(export.join("src/lib")).unwrap();
+    fs::write(export.join("README"), "read me\n").unwrap();
+    fs::write(export.join("src/lib/mod.rs"), "pub mod fs;\n").unwrap();
+    fs::create_dir(dir.path().join("outside")).unwrap();
+    fs::write(dir.path().join("outside/secret"), "secret\n").unwrap();
+    let fs = HostFs::new(&export, UserMap::default()).unwrap();
+    (dir, fs)
+}
+
+fn export(dir: &TempDir) -> std::path::PathBuf {
+    dir.path().join("export")
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+#[test]
+fn serves_files_and_directories() {
+    let (_dir, fs) = exported();
+    assert_eq!(sorted(fs.walk("/").unwrap()), vec!["README", "src"]);
+    assert_eq!(fs.walk("/src/lib").unwrap(), vec!["mod.rs"]);
+    assert_eq!(fs.read("/README").unwrap(), b"read me\n");
+
+    let stat = fs.stat("/src").unwrap();
+    assert!(stat.is_dir());
+    assert_eq!(stat.name, "src");
+    let stat = fs.stat("/src/lib/mod.rs").unwrap();
+    assert!(stat.data.is_empty());
+    assert_eq!(stat.mode & DMDIR, 0);
+    assert_eq!(fs.metadata("/src/lib/mod.rs").unwrap().1, 12);
+    assert_eq!(fs.read_at("/src/lib/mod.rs", 4, 3).unwrap(), b"mod");
+    assert_eq!(fs.read_at("/src/lib/mod.rs", 100, 3).unwrap(), b"");
+    assert!(fs.stat("/missing").is_none());
+}
+
+#[test]
+fn links_resolve_as_if_the_export_were_the_root() {
+    let (dir, fs) = exported();
+    let root = export(&dir);
+    symlink("/", root.join("slash")).unwrap();
+    symlink("../../../..", root.join("src/up")).unwrap();
+    symlink(dir.path().join("outside"), root.join("abs")).unwrap();
+    symlink("../outside/secret", root.join("rel")).unwrap();
+    symlink("src/lib", root.join("lib")).unwrap();
+
+    // Absolute targets and runs of `..` stop at the export root.
+    assert_eq!(fs.host_path("/slash").unwrap(), fs.root());
+    assert_eq!(fs.host_path("/src/up").unwrap(), fs.root());
+    assert_eq!(fs.read("/slash/README").unwrap(), b"read me\n");
+    assert_eq!(fs.read("/src/up/README").unwrap(), b"read me\n");
+    // So a link naming a host path outside finds nothing.
+    assert!(fs.stat("/abs").is_none());
+    assert!(fs.stat("/rel").is_none());
+    assert!(fs.read("/abs/secret").is_none());
+    assert!(fs.read("/../outside/secret").is_none());
+    // Links that stay inside work, and those that resolve nowhere are
+    // not listed.
+    assert_eq!(fs.read("/lib/mod.rs").unwrap(), b"pub mod fs;\n");
+    assert_eq!(
+        sorted(fs.walk("/").unwrap()),
+        vec!["README", "lib", "slash", "src"]
+    );
+}
+
+#[test]
+fn link_loops_are_refused() {
+    let (dir, fs) = exported();
+    let root = export(&dir);
+    symlink("b", root.join("a")).unwrap();
+    symlink("a", root.join("b")).unwrap();
+    assert!(fs.stat("/a").is_none());
+    assert!(!fs.walk("/").unwrap().contains(&"a".to_string()));
+}
+
+#[test]
+fn special_files_are_hidden() {
+    let (dir, fs) = exported();
+    let fifo = CString::new(export(&dir).join("fifo").to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+    assert!(!fs.walk("/").unwrap().contains(&"fifo".to_string()));
+    assert!(fs.stat("/fifo").is_none());
+    assert!(fs.open("/fifo").is_none());
+}
+
+#[test]
+fn creates_writes_and_removes_host_files() {
+    let (dir, mut fs) = exported();
+    let root = export(&dir);
+    fs.create("/src", "new.rs", 0o666, OWRITE, "nobody")
+        .unwrap();
+    fs.write("/src/new.rs", 0, b"fn main() {}\n", "nobody")
+        .unwrap();
+    fs.write("/src/new.rs", 3, b"test", "nobody").unwrap();
+    assert_eq!(
+        fs::read(root.join("src/new.rs")).unwrap(),
+        b"fn test() {}\n"
+    );
+    let dir_mode = fs::metadata(root.join("src")).unwrap().mode() & 0o777;
+    assert_eq!(fs.stat("/src/new.rs").unwrap().mode, 0o666 & dir_mode);
+
+    fs.create("/src", "new.rs", 0o666, OWRITE, "nobody")
+        .unwrap();
+    assert_eq!(fs.read("/src/new.rs").unwrap(), b"");
+    assert!(
+        fs.create("/src", "new.rs", 0o666, OWRITE | OEXCL, "nobody")
+            .is_none()
+    );
+    assert!(fs.create("/", "src", DMDIR | 0o777, 0, "nobody").is_none());
+    assert!(fs.create("/", "..", 0o666, 0, "nobody").is_none());
+
+    fs.create("/", "log", DMAPPEND | 0o644, OWRITE, "nobody")
+        .unwrap();
+    assert_eq!(fs.stat("/log").unwrap().mode, 0o644);
+    fs.create("/", "dir", DMDIR | 0o700, 0, "nobody").unwrap();
+    assert_eq!(fs.stat("/dir").unwrap().mode, DMDIR | 0o700);
+
+    fs.create("/dir", "f", 0o600, OWRITE, "nobody").unwrap();
+    assert!(fs.remove("/dir").is_none());
+    fs.remove("/dir/f").unwrap();
+    fs.remove("/dir").unwrap();
+    assert!(!root.join("dir").exists());
+    assert!(fs.remove("/").is_none());
+}
+
+#[test]
+fn otrunc_empties_host_files_but_not_through_links_out() {
+    let (dir, mut fs) = exported();
+    let root = export(&dir);
+    fs.truncate("/src/lib/mod.rs", "nobody").unwrap();
+    assert_eq!(fs::read(root.join("src/lib/mod.rs")).unwrap(), b"");
+    assert_eq!(fs.metadata("/src/lib/mod.rs").unwrap().1, 0);
+    assert!(fs.truncate("/src", "nobody").is_none());
+
+    symlink("../outside/secret", root.join("rel")).unwrap();
+    assert!(fs.truncate("/rel", "nobody").is_none());
+    assert_eq!(
+        fs::read(dir.path().join("outside/secret")).unwrap(),
+        b"secret\n"
+    );
+}
+
+#[test]
+fn removing_a_link_removes_only_the_link() {
+    let (dir, mut fs) = exported();
+    let root = export(&dir);
+    symlink("README", root.join("link")).unwrap();
+    fs.remove("/link").unwrap();
+    assert!(fs::symlink_metadata(root.join("link")).is_err());
+    assert_eq!(fs::read(root.join("README")).unwrap(), b"read me\n");
+}
+
+#[test]
+fn wstat_renames_truncates_and_changes_modes() {
+    let (dir, mut fs) = exported();
+    let root = export(&dir);
+
+    let change = Wstat {
+        name: Some("README.md".to_string()),
+        mode: Some(0o600),
+        length: Some(4),
+        mtime: Some(1_000_000),
+        ..Wstat::default()
+    };
+    fs.wstat("/README", &change).unwrap();
+    assert!(!root.join("README").exists());
+    let meta = fs::metadata(root.join("README.md")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+    assert_eq!(meta.mtime(), 1_000_000);
+    assert_eq!(fs.read("/README.md").unwrap(), b"read");
+
+    let refused = [
+        Wstat {
+            name: Some("src".to_string()),
+            ..Wstat::default()
+        },
+        Wstat {
+            mode: Some(DMDIR | 0o600),
+            ..Wstat::default()
+        },
+        Wstat {
+            uid: Some("no-such-user".to_string()),
+            ..Wstat::default()
+        },
+    ];
+    for change in &refused {
+        assert!(fs.wstat("/README.md", change).is_none());
+    }
+    assert!(root.join("README.md").exists());
+}
+
+const PASSWD: &str = "\
+# comment
+root:x:0:0:root:/root:/bin/sh
+glenda:x:1000:1000:Glenda:/home/glenda:/bin/rc
+bootes:x:1001:100::/home/bootes:/bin/rc
+broken line
+";
+
+const GROUP: &str = "\
+root:x:0:
+users:x:100:
+glenda:x:1000:
+sys:x:3:glenda,bootes
+";
+
+#[test]
+fn user_map_names_ids_both_ways() {
+    let mut users = UserMap::parse(PASSWD, GROUP);
+    assert_eq!(users.user_name(1000), "glenda");
+    assert_eq!(users.user_name(4242), "4242");
+    assert_eq!(users.group_name(100), "users");
+    assert_eq!(users.uid("bootes"), Some(1001));
+    assert_eq!(users.uid("4242"), Some(4242));
+    assert_eq!(users.uid("nobody"), None);
+    assert_eq!(users.gid("sys"), Some(3));
+
+    assert!(users.is_member("bootes", "users"));
+    assert!(users.is_member("glenda", "sys"));
+    assert!(users.is_member("glenda", "glenda"));
+    assert!(!users.is_member("root", "sys"));
+
+    // Renaming keeps the account's groups.
+    users.set_user(1000, "gl");
+    assert_eq!(users.user_name(1000), "gl");
+    assert_eq!(users.uid("gl"), Some(1000));
+    assert!(users.is_member("gl", "sys"));
+    assert!(users.is_member("gl", "glenda"));
+}
+
+#[test]
+fn owners_are_shown_through_the_map() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("f"), "").unwrap();
+    let meta = fs::metadata(dir.path().join("f")).unwrap();
+    let mut users = UserMap::default();
+    users.set_user(meta.uid(), "glenda");
+    users.set_group(meta.gid(), "plan9");
+    let fs = HostFs::new(dir.path(), users).unwrap();
+    let stat = fs.stat("/f").unwrap();
+    assert_eq!(
+        (stat.uid.as_str(), stat.gid.as_str(), stat.muid.as_str()),
+        ("glenda", "plan9", "glenda")
+    );
+    assert!(fs.is_member("/f", "glenda", "glenda"));
+    assert!(HostFs::new(dir.path().join("f"), UserMap::default()).is_err());
+    assert!(Path::new(fs.root()).is_absolute());
+}
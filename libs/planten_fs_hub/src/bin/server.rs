@@ -0,0 +1,59 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::{run_server, run_stdio};
+use planten_fs_hub::HubFs;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5676";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_hub_server [--listen host:port | --stdio] [--owner user] [--group group] [--limit bytes]\n\
+         Serves hubs: files whose writes are buffered and passed on to every\n\
+         reader, who gets the history and then waits for more. --limit sets the\n\
+         bytes each hub keeps; the ctl file changes it and freezes the hubs."
+    );
+    process::exit(1);
+}
+
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut stdio = false;
+    let mut owner = "user".to_string();
+    let mut group = "group".to_string();
+    let mut limit = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--stdio" => stdio = true,
+            "--owner" => owner = args.next().unwrap_or_else(|| usage()),
+            "--group" => group = args.next().unwrap_or_else(|| usage()),
+            "--limit" => limit = Some(value(&mut args)),
+            _ => usage(),
+        }
+    }
+
+    let mut fs = HubFs::new(&owner, &group);
+    if let Some(limit) = limit
+        && fs.set_limit(limit).is_err()
+    {
+        usage();
+    }
+    let fs = Arc::new(Mutex::new(fs));
+    if stdio {
+        return run_stdio(fs);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!("HubFs 9P server on {}", listen);
+    run_server(listener, fs)
+}
@@ -0,0 +1,375 @@
+//! Hubs for attaching to long-lived programs across connections, after
+//! 9front's hubfs.
+//!
+//! Creating a file in the root makes a hub. Everything written to a hub
+//! is kept and passed on to every reader: a reader that opens it gets the
+//! buffered history first and then blocks until more is written. A shell
+//! served this way (its input, output and errors on three hubs) can be
+//! left running and picked up again from another connection.
+//!
+//! Each hub keeps at most `limit` bytes, dropping the oldest. Offsets
+//! are positions in the whole stream and a newly opened hub starts at the
+//! oldest byte kept (`FsServer::open_offset`); a reader that falls so far
+//! behind that what it wants was dropped gets an error. The `ctl` file
+//! takes one command per line:
+//!
+//! ```text
+//! limit bytes   keep at most bytes of each hub, trimming them now
+//! freeze        reads stop blocking and writes to hubs fail
+//! melt          undo freeze
+//! ```
+//!
+//! Frozen hubs read like plain files, so copying one gets its history
+//! and ends. Reading `ctl` reports the settings and, for each hub, its
+//! name and the stream offsets of the oldest and next byte.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use planten_fs_core::wait::{Notifier, ReadWait};
+use planten_fs_core::{DMDIR, FsServer, Inode, OEXCL, Wstat, create_perm};
+
+/// The control file in the root.
+pub const CTL_FILE: &str = "ctl";
+/// Bytes each hub keeps unless told otherwise, the size of a 9front hub
+/// bucket.
+pub const DEFAULT_LIMIT: usize = 777_777;
+const ROOT_MODE: u32 = DMDIR | 0o775;
+const CTL_MODE: u32 = 0o664;
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as u32
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message.into())
+}
+
+struct Hub {
+    /// Stream offset of `data[0]`.
+    start: u64,
+    data: Vec<u8>,
+    mode: u32,
+    uid: String,
+    muid: String,
+    mtime: u32,
+    notifier: Arc<Notifier>,
+}
+
+impl Hub {
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    /// Drops the oldest bytes beyond `limit`.
+    fn trim(&mut self, limit: usize) {
+        if self.data.len() > limit {
+            let excess = self.data.len() - limit;
+            self.data.drain(..excess);
+            self.start += excess as u64;
+        }
+    }
+}
+
+pub struct HubFs {
+    hubs: BTreeMap<String, Hub>,
+    limit: usize,
+    frozen: bool,
+    uid: String,
+    gid: String,
+    started: u32,
+}
+
+/// The hub `path` names, if it names one at all.
+fn hub_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix('/').unwrap_or(path);
+    (!name.is_empty() && !name.contains('/') && name != CTL_FILE).then_some(name)
+}
+
+fn is_root(path: &str) -> bool {
+    path.split('/').all(|part| part.is_empty())
+}
+
+fn is_ctl(path: &str) -> bool {
+    path.strip_prefix('/').unwrap_or(path) == CTL_FILE
+}
+
+impl HubFs {
+    /// An empty server whose root and `ctl` belong to `uid` and `gid`.
+    pub fn new(uid: &str, gid: &str) -> HubFs {
+        HubFs {
+            hubs: BTreeMap::new(),
+            limit: DEFAULT_LIMIT,
+            frozen: false,
+            uid: uid.to_string(),
+            gid: gid.to_string(),
+            started: now(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Keeps at most `limit` bytes of each hub from now on, trimming the
+    /// hubs straight away.
+    pub fn set_limit(&mut self, limit: usize) -> io::Result<()> {
+        if limit == 0 {
+            return Err(invalid("a hub must keep at least one byte"));
+        }
+        self.limit = limit;
+        for hub in self.hubs.values_mut() {
+            hub.trim(limit);
+        }
+        Ok(())
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Stops reads from blocking and writes to hubs from landing, waking
+    /// every parked reader so it sees the end.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+        for hub in self.hubs.values() {
+            hub.notifier.notify();
+        }
+    }
+
+    pub fn melt(&mut self) {
+        self.frozen = false;
+    }
+
+    pub fn hubs(&self) -> Vec<String> {
+        self.hubs.keys().cloned().collect()
+    }
+
+    /// Makes the hub `name` owned by `uname`. An existing hub is emptied
+    /// unless `exclusive`, in which case it is an error.
+    pub fn try_create(
+        &mut self,
+        name: &str,
+        perm: u32,
+        uname: &str,
+        exclusive: bool,
+    ) -> io::Result<()> {
+        if hub_name(name) != Some(name) || name == "." || name == ".." {
+            return Err(invalid(format!("{:?} cannot be a hub", name)));
+        }
+        if perm & DMDIR != 0 {
+            return Err(invalid("hubs cannot hold directories"));
+        }
+        if let Some(hub) = self.hubs.get_mut(name) {
+            if exclusive {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            hub.start = hub.end();
+            hub.data.clear();
+            hub.muid = uname.to_string();
+            hub.mtime = now();
+            return Ok(());
+        }
+        self.hubs.insert(
+            name.to_string(),
+            Hub {
+                start: 0,
+                data: Vec::new(),
+                mode: create_perm(perm, ROOT_MODE) & 0o777,
+                uid: uname.to_string(),
+                muid: uname.to_string(),
+                mtime: now(),
+                notifier: Notifier::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Adds `data` to the hub `name` and wakes its readers.
+    pub fn try_write(&mut self, name: &str, data: &[u8], uname: &str) -> io::Result<()> {
+        if self.frozen {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "hubs are frozen",
+            ));
+        }
+        let hub = self.hubs.get_mut(name).ok_or(ErrorKind::NotFound)?;
+        hub.data.extend_from_slice(data);
+        hub.trim(self.limit);
+        hub.muid = uname.to_string();
+        hub.mtime = now();
+        hub.notifier.notify();
+        Ok(())
+    }
+
+    /// Up to `count` bytes of the hub `name` from the stream offset
+    /// `offset`; fails if they have been dropped.
+    pub fn read_hub(&self, name: &str, offset: u64, count: usize) -> io::Result<Vec<u8>> {
+        let hub = self.hubs.get(name).ok_or(ErrorKind::NotFound)?;
+        let from = offset.checked_sub(hub.start).ok_or_else(|| {
+            io::Error::other(format!("{} lost bytes before offset {}", name, hub.start))
+        })?;
+        let from = usize::try_from(from)
+            .unwrap_or(usize::MAX)
+            .min(hub.data.len());
+        let to = from.saturating_add(count).min(hub.data.len());
+        Ok(hub.data[from..to].to_vec())
+    }
+
+    /// The stream offsets of the oldest byte `name` keeps and of the next
+    /// one to be written.
+    pub fn span(&self, name: &str) -> Option<(u64, u64)> {
+        self.hubs.get(name).map(|hub| (hub.start, hub.end()))
+    }
+
+    /// Carries out the commands written to `ctl`, one per line.
+    pub fn ctl(&mut self, commands: &str) -> io::Result<()> {
+        for line in commands.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["limit", bytes] => {
+                    let limit = bytes
+                        .parse()
+                        .map_err(|_| invalid(format!("bad limit {:?}", bytes)))?;
+                    self.set_limit(limit)?;
+                }
+                ["freeze"] => self.freeze(),
+                ["melt"] => self.melt(),
+                _ => return Err(invalid(format!("unknown ctl command {:?}", line.trim()))),
+            }
+        }
+        Ok(())
+    }
+
+    /// What reading `ctl` returns.
+    pub fn status(&self) -> String {
+        let mut status = format!(
+            "limit {}\n{}\n",
+            self.limit,
+            if self.frozen { "frozen" } else { "melted" }
+        );
+        for (name, hub) in &self.hubs {
+            status.push_str(&format!("hub {} {} {}\n", name, hub.start, hub.end()));
+        }
+        status
+    }
+}
+
+impl FsServer for HubFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        if is_root(path) {
+            let mut names = vec![CTL_FILE.to_string()];
+            names.extend(self.hubs());
+            return Some(names);
+        }
+        self.stat(path).map(|_| Vec::new())
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.stat(path).map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        if is_ctl(path) {
+            return Some(self.status().into_bytes());
+        }
+        Some(self.hubs.get(hub_name(path)?)?.data.clone())
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        match hub_name(path) {
+            Some(name) => self.read_hub(name, offset, count as usize).ok(),
+            None => {
+                let status = self.read(path)?;
+                let from = usize::try_from(offset)
+                    .unwrap_or(usize::MAX)
+                    .min(status.len());
+                let to = from.saturating_add(count as usize).min(status.len());
+                Some(status[from..to].to_vec())
+            }
+        }
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        hub_name(path)
+            .and_then(|name| self.span(name))
+            .map_or(0, |(start, _)| start)
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        let hub = self.hubs.get(hub_name(path)?)?;
+        (!self.frozen && offset >= hub.end()).then(|| hub.notifier.waiter())
+    }
+
+    fn write(&mut self, path: &str, _offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        if is_ctl(path) {
+            self.ctl(&String::from_utf8_lossy(data)).ok()?;
+        } else {
+            self.try_write(hub_name(path)?, data, uname).ok()?;
+        }
+        Some(data.len() as u32)
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        let hub = self.hubs.remove(hub_name(path)?)?;
+        // Parked readers find the hub gone and fail.
+        hub.notifier.notify();
+        Some(())
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        let mut inode = if is_root(path) {
+            let mut inode = Inode::new("/", ROOT_MODE, &self.uid, &self.gid);
+            inode.mtime = self.started;
+            inode
+        } else if is_ctl(path) {
+            let mut inode = Inode::new(CTL_FILE, CTL_MODE, &self.uid, &self.gid);
+            inode.mtime = self.started;
+            inode
+        } else {
+            let name = hub_name(path)?;
+            let hub = self.hubs.get(name)?;
+            // A stream has no length, as with pipes.
+            let mut inode = Inode::new(name, hub.mode, &hub.uid, &self.gid);
+            inode.muid = hub.muid.clone();
+            inode.mtime = hub.mtime;
+            inode
+        };
+        inode.atime = inode.mtime;
+        Some(inode)
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        if !is_root(parent) {
+            return None;
+        }
+        self.try_create(name, perm, uname, mode & OEXCL != 0).ok()
+    }
+
+    /// A hub is a stream; opening it with OTRUNC keeps the history, as
+    /// 9front's hubfs does.
+    fn truncate(&mut self, _path: &str, _uname: &str) -> Option<()> {
+        Some(())
+    }
+}
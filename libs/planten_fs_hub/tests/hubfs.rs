@@ -0,0 +1,162 @@
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use planten_9p::P9Client;
+use planten_9p::server::run_server;
+use planten_fs_core::{DMDIR, FsServer, OEXCL, OREAD, OWRITE};
+use planten_fs_hub::{CTL_FILE, DEFAULT_LIMIT, HubFs};
+
+#[test]
+fn hubs_keep_their_history_for_every_reader() {
+    let mut fs = HubFs::new("bootes", "sys");
+    fs.create("/", "shell", 0o660, OWRITE | OEXCL, "glenda")
+        .unwrap();
+    assert_eq!(fs.walk("/").unwrap(), [CTL_FILE, "shell"]);
+    assert_eq!(fs.write("/shell", 0, b"% ls\n", "glenda"), Some(5));
+    assert_eq!(fs.write("/shell", 0, b"bin lib\n", "glenda"), Some(8));
+
+    // Every reader starts at the beginning and sees all of it.
+    assert_eq!(fs.open_offset("/shell"), 0);
+    assert_eq!(fs.read_at("/shell", 0, 100).unwrap(), b"% ls\nbin lib\n");
+    assert_eq!(fs.read_at("/shell", 5, 3).unwrap(), b"bin");
+    assert!(fs.read_wait("/shell", 5).is_none());
+    assert!(fs.read_wait("/shell", 13).is_some());
+
+    let hub = fs.stat("/shell").unwrap();
+    assert_eq!(hub.mode, 0o660);
+    assert_eq!(hub.uid, "glenda");
+    assert_eq!(hub.gid, "sys");
+    assert_eq!(fs.stat("/").unwrap().mode, DMDIR | 0o775);
+    assert_eq!(fs.stat("/ctl").unwrap().uid, "bootes");
+
+    // Only plain files in the root, and never over ctl.
+    assert!(fs.create("/", "ctl", 0o660, OWRITE, "glenda").is_none());
+    assert!(
+        fs.create("/", "dir", DMDIR | 0o775, OREAD, "glenda")
+            .is_none()
+    );
+    assert!(fs.create("/shell", "x", 0o660, OWRITE, "glenda").is_none());
+    assert!(
+        fs.create("/", "shell", 0o660, OWRITE | OEXCL, "glenda")
+            .is_none()
+    );
+    assert!(fs.write("/missing", 0, b"x", "glenda").is_none());
+
+    // Creating it again empties it without moving the stream back.
+    fs.create("/", "shell", 0o660, OWRITE, "glenda").unwrap();
+    assert_eq!(fs.span("shell"), Some((13, 13)));
+    assert_eq!(fs.open_offset("/shell"), 13);
+
+    fs.remove("/shell").unwrap();
+    assert!(fs.stat("/shell").is_none());
+    assert!(fs.remove("/ctl").is_none());
+}
+
+#[test]
+fn the_limit_drops_the_oldest_bytes() {
+    let mut fs = HubFs::new("bootes", "sys");
+    assert_eq!(fs.limit(), DEFAULT_LIMIT);
+    fs.try_create("log", 0o644, "glenda", true).unwrap();
+    fs.try_write("log", b"0123456789", "glenda").unwrap();
+    fs.ctl("limit 4\n").unwrap();
+    assert_eq!(fs.limit(), 4);
+    assert_eq!(fs.span("log"), Some((6, 10)));
+    assert_eq!(fs.open_offset("/log"), 6);
+    assert_eq!(fs.read_at("/log", 6, 100).unwrap(), b"6789");
+    assert!(fs.read_hub("log", 2, 100).is_err());
+    assert!(fs.read_at("/log", 2, 100).is_none());
+
+    fs.try_write("log", b"ab", "glenda").unwrap();
+    assert_eq!(fs.read_at("/log", 8, 100).unwrap(), b"89ab");
+    assert_eq!(
+        String::from_utf8(fs.read("/ctl").unwrap()).unwrap(),
+        "limit 4\nmelted\nhub log 8 12\n"
+    );
+
+    for bad in ["limit 0", "limit lots", "limit", "thaw"] {
+        assert!(fs.ctl(bad).is_err(), "{}", bad);
+    }
+    assert!(fs.write("/ctl", 0, b"explode\n", "glenda").is_none());
+    assert_eq!(fs.limit(), 4);
+}
+
+#[test]
+fn frozen_hubs_read_like_files() {
+    let mut fs = HubFs::new("bootes", "sys");
+    fs.try_create("out", 0o644, "glenda", true).unwrap();
+    fs.try_write("out", b"done\n", "glenda").unwrap();
+    let parked = fs.read_wait("/out", 5).unwrap();
+
+    fs.write("/ctl", 0, b"freeze\n", "bootes").unwrap();
+    assert!(fs.is_frozen());
+    // The parked reader is woken and finds the end.
+    assert!(parked.wait(&planten_fs_core::Flush::new()));
+    assert!(fs.read_wait("/out", 5).is_none());
+    assert!(fs.read_at("/out", 5, 100).unwrap().is_empty());
+    assert!(fs.write("/out", 0, b"more", "glenda").is_none());
+    assert!(
+        String::from_utf8(fs.read("/ctl").unwrap())
+            .unwrap()
+            .contains("\nfrozen\n")
+    );
+
+    fs.ctl("melt").unwrap();
+    assert!(fs.read_wait("/out", 5).is_some());
+    assert_eq!(fs.write("/out", 0, b"more", "glenda"), Some(4));
+}
+
+fn connect(addr: &str, uname: &str) -> P9Client {
+    let mut client = P9Client::new(addr).unwrap();
+    client.version(8192, "9P2000").unwrap();
+    client.attach(1, None, uname, "").unwrap();
+    client
+}
+
+#[test]
+fn readers_attach_and_detach_across_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let fs = Arc::new(Mutex::new(HubFs::new("glenda", "sys")));
+    let server = Arc::clone(&fs);
+    thread::spawn(move || run_server(listener, server));
+
+    let mut shell = connect(&addr, "glenda");
+    shell.walk(1, 2, &[]).unwrap();
+    shell.create(2, "io", 0o660, OWRITE as u8).unwrap();
+    shell.write(2, 0, b"% date\n").unwrap();
+
+    // A reader gets the history, then waits for the next write.
+    let reader_addr = addr.clone();
+    let reader = thread::spawn(move || {
+        let mut client = connect(&reader_addr, "glenda");
+        client.walk(1, 2, &["io"]).unwrap();
+        client.open(2, OREAD as u8).unwrap();
+        let history = client.read(2, 0, 100).unwrap();
+        let next = client.read(2, history.len() as u64, 100).unwrap();
+        (history, next)
+    });
+    thread::sleep(Duration::from_millis(100));
+    shell.write(2, 0, b"Sat Oct 17\n").unwrap();
+    let (history, next) = reader.join().unwrap();
+    assert_eq!(history, b"% date\n");
+    assert_eq!(next, b"Sat Oct 17\n");
+
+    // Once the history is trimmed a new reader starts at what is kept.
+    let mut ctl = connect(&addr, "glenda");
+    ctl.walk(1, 2, &["ctl"]).unwrap();
+    ctl.open(2, OWRITE as u8).unwrap();
+    ctl.write(2, 0, b"limit 6\n").unwrap();
+    let mut late = connect(&addr, "glenda");
+    late.walk(1, 2, &["io"]).unwrap();
+    late.open(2, OREAD as u8).unwrap();
+    assert_eq!(late.read(2, 0, 100).unwrap(), b"ct 17\n");
+    shell.write(2, 0, b"% ").unwrap();
+    assert_eq!(late.read(2, 6, 100).unwrap(), b"% ");
+    assert_eq!(fs.lock().unwrap().span("io"), Some((14, 20)));
+
+    // Freezing lets a reader at the end finish instead of waiting.
+    ctl.write(2, 0, b"freeze\n").unwrap();
+    assert!(late.read(2, 8, 100).unwrap().is_empty());
+}
@@ -0,0 +1,9 @@
+use std::io;
+
+use planten_fs_mux::server;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5650";
+
+fn main() -> io::Result<()> {
+    server::start_server(LISTEN_ADDR)
+}
@@ -0,0 +1,243 @@
+//! Composite filesystem that serves several `FsServer`s under one tree, the
+//! way a Plan 9 kernel's device table puts every device behind one attach.
+//!
+//! Each child is mounted under a named directory of the root. A path such
+//! as `/proc/1/status` is handed to the `proc` child as `/1/status`, and a
+//! Tattach naming `proc` as its aname starts directly at `/proc`.
+
+use planten_fs_core::{DMDIR, FsServer, Inode, ReadWait, Wstat};
+
+pub mod server;
+
+/// Bits of a mux node id that carry the child's own id; the child's index
+/// in the table sits above them, so ids from different children never
+/// collide.
+const NODE_BITS: u32 = 48;
+
+pub struct MuxFs {
+    children: Vec<(String, Box<dyn FsServer + Send>)>,
+}
+
+impl MuxFs {
+    pub fn new() -> Self {
+        MuxFs {
+            children: Vec::new(),
+        }
+    }
+
+    /// Mounts `server` at `/<name>`, replacing any child of the same name.
+    pub fn mount<S>(&mut self, name: &str, server: S)
+    where
+        S: FsServer + Send + 'static,
+    {
+        let server: Box<dyn FsServer + Send> = Box::new(server);
+        match self.children.iter_mut().find(|(n, _)| n == name) {
+            Some(child) => child.1 = server,
+            None => self.children.push((name.to_string(), server)),
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.children.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn route(&self, path: &str) -> Option<(&dyn FsServer, String)> {
+        let (name, rest) = split(path)?;
+        self.children
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, child)| (child.as_ref() as &dyn FsServer, rest))
+    }
+
+    fn route_mut(&mut self, path: &str) -> Option<(&mut (dyn FsServer + Send + 'static), String)> {
+        let (name, rest) = split(path)?;
+        self.children
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .map(|(_, child)| (child.as_mut(), rest))
+    }
+}
+
+impl Default for MuxFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Splits `/name/rest` into the child name and the path inside the child.
+/// Returns `None` for the root itself.
+fn split(path: &str) -> Option<(&str, String)> {
+    let trimmed = path.trim_matches('/');
+    if trimmed.is_empty() {
+        return None;
+    }
+    match trimmed.split_once('/') {
+        Some((name, rest)) => Some((name, format!("/{}", rest))),
+        None => Some((trimmed, "/".to_string())),
+    }
+}
+
+fn is_root(path: &str) -> bool {
+    path.trim_matches('/').is_empty()
+}
+
+impl FsServer for MuxFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        if is_root(path) {
+            return Some(self.names());
+        }
+        let (child, rest) = self.route(path)?;
+        child.walk(&rest)
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        if is_root(path) {
+            return Some(());
+        }
+        let (child, rest) = self.route(path)?;
+        child.open(&rest)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        if is_root(path) {
+            return Some(self.names().join("\n").into_bytes());
+        }
+        let (child, rest) = self.route(path)?;
+        child.read(&rest)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        let (child, rest) = self.route_mut(path)?;
+        child.write(&rest, offset, data, uname)
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        if is_root(path) {
+            return Some(());
+        }
+        let (child, rest) = self.route(path)?;
+        child.clunk(&rest)
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        // Mount points are part of the table, not of the child.
+        let (child, rest) = self.route_mut(path)?;
+        if is_root(&rest) {
+            return None;
+        }
+        child.remove(&rest)
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        if is_root(path) {
+            return Some(Inode::new("/", 0o555 | DMDIR, "root", "root"));
+        }
+        let (name, _) = split(path)?;
+        let (child, rest) = self.route(path)?;
+        let mut inode = child.stat(&rest)?;
+        if is_root(&rest) {
+            inode.name = name.to_string();
+        }
+        Some(inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        if is_root(path) {
+            return Some((Inode::new("/", 0o555 | DMDIR, "root", "root"), 0));
+        }
+        let (name, _) = split(path)?;
+        let (child, rest) = self.route(path)?;
+        let (mut inode, length) = child.metadata(&rest)?;
+        if is_root(&rest) {
+            inode.name = name.to_string();
+        }
+        Some((inode, length))
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        let (child, rest) = self.route_mut(path)?;
+        if is_root(&rest) {
+            return None;
+        }
+        child.wstat(&rest, change)
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        let (child, rest) = self.route_mut(parent)?;
+        child.create(&rest, name, perm, mode, uname)
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        let (child, rest) = self.route(path)?;
+        child.read_wait(&rest, offset)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        let (child, rest) = self.route(path)?;
+        child.read_at(&rest, offset, count)
+    }
+
+    fn open_offset(&self, path: &str) -> u64 {
+        self.route(path)
+            .map_or(0, |(child, rest)| child.open_offset(&rest))
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        let (child, rest) = self.route_mut(path)?;
+        child.truncate(&rest, uname)
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        self.route(path)
+            .is_none_or(|(child, rest)| child.claim_exclusive(&rest))
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        if let Some((child, rest)) = self.route(path) {
+            child.release_exclusive(&rest);
+        }
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        let (name, rest) = split(path)?;
+        let index = self.children.iter().position(|(n, _)| n == name)?;
+        let id = self.children[index].1.node_id(&rest)?;
+        (id >> NODE_BITS == 0).then_some(((index as u64) << NODE_BITS) | id)
+    }
+
+    fn node_path(&self, id: u64) -> Option<String> {
+        let (name, child) = self.children.get((id >> NODE_BITS) as usize)?;
+        let path = child.node_path(id & ((1 << NODE_BITS) - 1))?;
+        match path.trim_start_matches('/') {
+            "" => Some(format!("/{}", name)),
+            rest => Some(format!("/{}/{}", name, rest)),
+        }
+    }
+
+    /// Children keep their own group files; the one holding `path` is
+    /// asked, so a group in one child grants nothing in another.
+    fn is_member(&self, path: &str, uname: &str, group: &str) -> bool {
+        match self.route(path) {
+            Some((child, rest)) => child.is_member(&rest, uname, group),
+            None => uname == group,
+        }
+    }
+
+    /// An empty aname attaches to the whole table, a child's name to that
+    /// child alone.
+    fn attach(&self, aname: &str) -> Option<String> {
+        if aname.is_empty() {
+            return Some("/".to_string());
+        }
+        self.route(aname)
+            .filter(|(_, rest)| is_root(rest))
+            .map(|_| format!("/{}", aname.trim_matches('/')))
+    }
+}
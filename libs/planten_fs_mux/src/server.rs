@@ -0,0 +1,56 @@
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::run_server;
+use planten_9p::{Qid, Stat};
+use planten_fs_core::DMDIR;
+use planten_fs_dev::DevFs;
+use planten_fs_net::NetFs;
+use planten_fs_proc::fs::ProcFs;
+use planten_fs_ramfs::RamFs;
+use planten_fs_srv::SrvFs;
+
+use crate::MuxFs;
+
+/// The standard device table: `/proc`, `/net`, `/dev`, `/srv` and a RAMFS
+/// scratch area at `/tmp`.
+pub fn standard_table() -> MuxFs {
+    let mut mux = MuxFs::new();
+    mux.mount("proc", ProcFs::new());
+    mux.mount("net", NetFs);
+    mux.mount("dev", DevFs);
+    mux.mount("srv", SrvFs::new());
+    mux.mount("tmp", scratch());
+    mux
+}
+
+/// A RAMFS whose root anyone may create files in.
+fn scratch() -> RamFs {
+    let ramfs = RamFs::new();
+    let stat = Stat {
+        type_: !0,
+        dev: !0,
+        qid: Qid {
+            qtype: !0,
+            version: !0,
+            path: !0,
+        },
+        mode: 0o777 | DMDIR,
+        atime: !0,
+        mtime: !0,
+        length: !0,
+        name: String::new(),
+        uid: String::new(),
+        gid: String::new(),
+        muid: String::new(),
+    };
+    ramfs.wstat_from_stat("/", &stat);
+    ramfs
+}
+
+pub fn start_server(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("MuxFs 9P server listening on {}", addr);
+    run_server(listener, Arc::new(Mutex::new(standard_table())))
+}
@@ -0,0 +1,277 @@
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use planten_9p::messages::*;
+use planten_9p::server::run_single;
+use planten_9p::{
+    RawMessage, build_frame, decode_stat, decode_string, encode_attach_body, encode_open_body,
+    encode_read_body, encode_stat_body, encode_version_body, encode_walk_body,
+};
+use planten_fs_core::perm::{AWRITE, has_access};
+use planten_fs_core::{FsServer, Inode, Wstat};
+use planten_fs_mux::MuxFs;
+use planten_fs_ramfs::RamFs;
+
+fn ramfs_with(path: &str, contents: &[u8]) -> RamFs {
+    let ramfs = RamFs::new();
+    ramfs.create_file(path, contents);
+    ramfs
+}
+
+fn table() -> MuxFs {
+    let mut mux = MuxFs::new();
+    mux.mount("tmp", ramfs_with("/hello.txt", b"hello from tmp"));
+    mux.mount("etc", ramfs_with("/motd", b"welcome"));
+    mux
+}
+
+#[test]
+fn routes_paths_to_children() {
+    let mut mux = table();
+    assert_eq!(mux.walk("/").unwrap(), vec!["tmp", "etc"]);
+    assert_eq!(mux.read("/tmp/hello.txt").unwrap(), b"hello from tmp");
+    assert_eq!(mux.read("/etc/motd").unwrap(), b"welcome");
+    assert!(mux.stat("/missing").is_none());
+
+    let mount_point = mux.stat("/etc").unwrap();
+    assert!(mount_point.is_dir());
+    assert_eq!(mount_point.name, "etc");
+
+    assert_eq!(mux.write("/etc/motd", 0, b"W", "user"), Some(1));
+    assert_eq!(mux.read("/etc/motd").unwrap(), b"Welcome");
+    assert!(mux.remove("/etc").is_none());
+    assert!(mux.create("/", "new", 0o644, 1, "user").is_none());
+}
+
+#[test]
+fn aname_selects_a_child() {
+    let mux = table();
+    assert_eq!(mux.attach("").as_deref(), Some("/"));
+    assert_eq!(mux.attach("etc").as_deref(), Some("/etc"));
+    assert!(mux.attach("nope").is_none());
+    assert!(mux.attach("etc/motd").is_none());
+}
+
+fn send(stream: &mut TcpStream, msg_type: u8, body: Vec<u8>) -> RawMessage {
+    stream.write_all(&build_frame(msg_type, 0, &body)).unwrap();
+    RawMessage::read_from(stream).unwrap()
+}
+
+fn error_text(message: &RawMessage) -> String {
+    decode_string(&mut Cursor::new(message.body.as_slice())).unwrap()
+}
+
+fn read_payload(message: &RawMessage) -> Vec<u8> {
+    let mut cursor = Cursor::new(message.body.as_slice());
+    let mut len = [0u8; 4];
+    cursor.read_exact(&mut len).unwrap();
+    let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+    cursor.read_exact(&mut payload).unwrap();
+    payload
+}
+
+fn connect() -> (TcpStream, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fs = Arc::new(Mutex::new(table()));
+    let server = thread::spawn(move || run_single(listener, fs).unwrap());
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let version = send(&mut stream, TVERSION, encode_version_body(8192, "9P2000"));
+    assert_eq!(version.msg_type, RVERSION);
+    (stream, server)
+}
+
+#[test]
+fn one_endpoint_serves_every_child() {
+    let (mut stream, server) = connect();
+    let attach = send(
+        &mut stream,
+        TATTACH,
+        encode_attach_body(1, None, "user", ""),
+    );
+    assert_eq!(attach.msg_type, RATTACH);
+
+    let walk = send(
+        &mut stream,
+        TWALK,
+        encode_walk_body(1, 2, &["tmp", "hello.txt"]),
+    );
+    assert_eq!(walk.msg_type, RWALK);
+    assert_eq!(
+        send(&mut stream, TOPEN, encode_open_body(2, 0)).msg_type,
+        ROPEN
+    );
+    let read = send(&mut stream, TREAD, encode_read_body(2, 0, 64));
+    assert_eq!(read_payload(&read), b"hello from tmp");
+
+    let walk = send(&mut stream, TWALK, encode_walk_body(1, 3, &["etc"]));
+    assert_eq!(walk.msg_type, RWALK);
+    let stat = send(&mut stream, TSTAT, encode_stat_body(3));
+    let stat = decode_stat(&mut Cursor::new(stat.body.as_slice())).unwrap();
+    assert_eq!(stat.name, "etc");
+
+    drop(stream);
+    server.join().unwrap();
+}
+
+#[test]
+fn attach_by_aname_is_confined_to_the_child() {
+    let (mut stream, server) = connect();
+    let attach = send(
+        &mut stream,
+        TATTACH,
+        encode_attach_body(1, None, "user", "nope"),
+    );
+    assert_eq!(attach.msg_type, RERROR);
+    assert!(error_text(&attach).contains("unknown aname"));
+
+    let attach = send(
+        &mut stream,
+        TATTACH,
+        encode_attach_body(1, None, "user", "etc"),
+    );
+    assert_eq!(attach.msg_type, RATTACH);
+    let walk = send(&mut stream, TWALK, encode_walk_body(1, 2, &["motd"]));
+    assert_eq!(walk.msg_type, RWALK);
+
+    // `..` from the attach point stays there rather than reaching /tmp.
+    let walk = send(&mut stream, TWALK, encode_walk_body(1, 3, &["..", "tmp"]));
+    assert_eq!(walk.msg_type, RWALK);
+    assert_eq!(u16::from_le_bytes([walk.body[0], walk.body[1]]), 1);
+
+    drop(stream);
+    server.join().unwrap();
+}
+
+/// A ramfs whose `sys` group has glenda in it.
+struct Staffed(RamFs);
+
+impl FsServer for Staffed {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.0.walk(path)
+    }
+    fn open(&self, path: &str) -> Option<()> {
+        self.0.open(path)
+    }
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.0.read(path)
+    }
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.0.write(path, offset, data, uname)
+    }
+    fn clunk(&self, path: &str) -> Option<()> {
+        self.0.clunk(path)
+    }
+    fn remove(&mut self, path: &str) -> Option<()> {
+        self.0.remove(path)
+    }
+    fn stat(&self, path: &str) -> Option<Inode> {
+        FsServer::stat(&self.0, path)
+    }
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.0.wstat(path, change)
+    }
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.0.create(parent, name, perm, mode, uname)
+    }
+    fn is_member(&self, _path: &str, uname: &str, group: &str) -> bool {
+        uname == group || (uname == "glenda" && group == "sys")
+    }
+}
+
+#[test]
+fn groups_are_looked_up_in_the_child_holding_the_path() {
+    let mut mux = MuxFs::new();
+    mux.mount("staff", Staffed(ramfs_with("/notes", b"notes")));
+    mux.mount("tmp", ramfs_with("/notes", b"notes"));
+
+    assert!(mux.is_member("/staff/notes", "glenda", "sys"));
+    assert!(!mux.is_member("/tmp/notes", "glenda", "sys"));
+    assert!(!mux.is_member("/", "glenda", "sys"));
+    assert!(mux.is_member("/tmp/notes", "glenda", "glenda"));
+
+    let inode = Inode::new("notes", 0o060, "bootes", "sys");
+    assert!(has_access(&mux, "/staff/notes", &inode, "glenda", AWRITE));
+    assert!(!has_access(&mux, "/tmp/notes", &inode, "glenda", AWRITE));
+}
+
+#[test]
+fn node_ids_are_namespaced_per_child() {
+    let mut mux = table();
+    let tmp = mux.node_id("/tmp/hello.txt").unwrap();
+    let etc = mux.node_id("/etc/motd").unwrap();
+    assert_ne!(tmp, etc);
+    assert_eq!(mux.node_path(tmp).unwrap(), "/tmp/hello.txt");
+    assert_eq!(mux.node_path(etc).unwrap(), "/etc/motd");
+    let mount_point = mux.node_id("/etc").unwrap();
+    assert_eq!(mux.node_path(mount_point).unwrap(), "/etc");
+    assert!(mux.node_id("/").is_none());
+
+    let rename = Wstat {
+        name: Some("moved.txt".to_string()),
+        ..Wstat::default()
+    };
+    assert!(mux.wstat("/tmp/hello.txt", &rename).is_some());
+    assert_eq!(mux.node_path(tmp).unwrap(), "/tmp/moved.txt");
+    assert!(mux.remove("/tmp/moved.txt").is_some());
+    assert!(mux.node_path(tmp).is_none());
+}
+
+#[test]
+fn open_fids_follow_their_files_through_the_mux() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fs = Arc::new(Mutex::new(table()));
+    let server_fs = Arc::clone(&fs);
+    let server = thread::spawn(move || run_single(listener, server_fs).unwrap());
+    let mut stream = TcpStream::connect(addr).unwrap();
+    send(&mut stream, TVERSION, encode_version_body(8192, "9P2000"));
+    send(
+        &mut stream,
+        TATTACH,
+        encode_attach_body(1, None, "user", ""),
+    );
+    send(
+        &mut stream,
+        TWALK,
+        encode_walk_body(1, 2, &["tmp", "hello.txt"]),
+    );
+    assert_eq!(
+        send(&mut stream, TOPEN, encode_open_body(2, 0)).msg_type,
+        ROPEN
+    );
+
+    let rename = Wstat {
+        name: Some("moved.txt".to_string()),
+        ..Wstat::default()
+    };
+    assert!(
+        fs.lock()
+            .unwrap()
+            .wstat("/tmp/hello.txt", &rename)
+            .is_some()
+    );
+    let read = send(&mut stream, TREAD, encode_read_body(2, 0, 64));
+    assert_eq!(read_payload(&read), b"hello from tmp");
+
+    {
+        let mut mux = fs.lock().unwrap();
+        assert!(mux.remove("/tmp/moved.txt").is_some());
+        assert!(mux.create("/tmp", "hello.txt", 0o644, 1, "user").is_some());
+    }
+    let read = send(&mut stream, TREAD, encode_read_body(2, 0, 64));
+    assert_eq!(read.msg_type, RERROR);
+    assert_eq!(error_text(&read), "file has been removed");
+
+    drop(stream);
+    server.join().unwrap();
+}
@@ -1,4 +1,4 @@
-use planten_fs_core::{FsServer, Inode};
+use planten_fs_core::{FsServer, Inode, Wstat};
 use std::fs;
 use std::io;
 use std::path::Path;
@@ -78,7 +78,7 @@ impl FsServer for NetFs {
         }
     }
 
-    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8]) -> Option<u32> {
+    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
         None
     }
 
@@ -117,7 +117,18 @@ impl FsServer for NetFs {
         }
     }
 
-    fn wstat(&mut self, _path: &str, _inode: Inode) -> Option<()> {
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
         None
     }
 }
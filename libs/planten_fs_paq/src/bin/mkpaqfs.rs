@@ -0,0 +1,59 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::process;
+
+use planten_fs_host::HostFs;
+use planten_fs_host::users::UserMap;
+use planten_fs_paq::pack::{Options, pack};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_mkpaqfs [--block-size n] [--label text] [--uncompressed] --output image dir\n\
+         Packs a host directory into a paq image; owners are named from the host's\n\
+         passwd and group files."
+    );
+    process::exit(1);
+}
+
+/// The value of a flag, parsed.
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn main() -> io::Result<()> {
+    let mut options = Options::default();
+    let mut output: Option<PathBuf> = None;
+    let mut dir = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--block-size" => options.block_size = value(&mut args),
+            "--label" => options.label = value(&mut args),
+            "--uncompressed" => options.uncompressed = true,
+            "--output" => output = Some(value(&mut args)),
+            _ if arg.starts_with('-') || dir.is_some() => usage(),
+            _ => dir = Some(arg),
+        }
+    }
+    let (Some(output), Some(dir)) = (output, dir) else {
+        usage();
+    };
+
+    let fs = HostFs::new(&dir, UserMap::system())?;
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    let size = pack(&fs, "/", &options, &mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    fs::rename(&partial, &output)?;
+    println!("{}: {} bytes", output.display(), size);
+    Ok(())
+}
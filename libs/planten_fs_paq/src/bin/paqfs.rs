@@ -0,0 +1,62 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::{run_server, run_stdio};
+use planten_fs_paq::{DEFAULT_CACHE_BLOCKS, PaqFs};
+
+const LISTEN_ADDR: &str = "127.0.0.1:5672";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_paqfs [--listen host:port | --stdio] [--cache blocks] [--no-verify] image\n\
+         Serves a paq image read-only. The whole image is checked against its\n\
+         SHA-1 first unless --no-verify is given; blocks are always checked."
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut stdio = false;
+    let mut cache = DEFAULT_CACHE_BLOCKS;
+    let mut verify = true;
+    let mut image = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--stdio" => stdio = true,
+            "--cache" => {
+                cache = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--no-verify" => verify = false,
+            _ if arg.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(arg),
+        }
+    }
+    let image = image.unwrap_or_else(|| usage());
+
+    let opened = if verify {
+        PaqFs::open(&image, cache)
+    } else {
+        PaqFs::open_unverified(&image, cache)
+    };
+    let fs = opened.unwrap_or_else(|err| {
+        eprintln!("cannot open {}: {}", image, err);
+        process::exit(1);
+    });
+    let label = fs.label().to_string();
+    let fs = Arc::new(Mutex::new(fs));
+    if stdio {
+        return run_stdio(fs);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!("PaqFs 9P server for {} ({}) on {}", image, label, listen);
+    run_server(listener, fs)
+}
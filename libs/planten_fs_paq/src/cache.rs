@@ -0,0 +1,75 @@
+//! A least-recently-used cache of decompressed blocks.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks held right now.
+    pub blocks: usize,
+}
+
+pub(crate) struct BlockCache {
+    capacity: usize,
+    /// Each block with the tick of its last use.
+    blocks: HashMap<u64, (Arc<Vec<u8>>, u64)>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl BlockCache {
+    /// A cache of up to `capacity` blocks; 0 turns caching off.
+    pub(crate) fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            blocks: HashMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, offset: u64) -> Option<Arc<Vec<u8>>> {
+        self.tick += 1;
+        match self.blocks.get_mut(&offset) {
+            Some((block, used)) => {
+                *used = self.tick;
+                self.hits += 1;
+                Some(Arc::clone(block))
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Keeps `block`, dropping the least recently used one if full.
+    pub(crate) fn insert(&mut self, offset: u64, block: Arc<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.blocks.len() >= self.capacity && !self.blocks.contains_key(&offset) {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(&offset, _)| offset);
+            if let Some(oldest) = oldest {
+                self.blocks.remove(&oldest);
+            }
+        }
+        self.blocks.insert(offset, (block, self.tick));
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            blocks: self.blocks.len(),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! The paq image format, after 9front's paqfs.
+//!
+//! An image starts with a header: the magic `PLANPAQ1`, the `u32` block
+//! size and a label string. Blocks follow back to back, each an encoding
+//! byte (`RAW` or `DEFLATE`, a zlib stream), the `u32` length of what is
+//! stored, the CRC-32 of the uncompressed contents and the stored bytes.
+//! No block holds more than the block size once uncompressed. Blocks are
+//! addressed by their offset in the image.
+//!
+//! A byte stream, a file's contents or a directory's entries, is cut into
+//! leaf blocks; streams longer than one block get a tree of pointer
+//! blocks holding `u64` offsets, recorded in an `Entry` with its depth and
+//! length. A directory's stream is its `DirEntry`s in name order. The
+//! image ends with a trailer: the offset of the block holding the root
+//! `DirEntry`, the SHA-1 of every byte before the hash, and the magic
+//! `PAQTRAIL`. Integers are little-endian and strings are 9P strings.
+//! Children are written before their parents, so every offset points
+//! backwards.
+
+use std::io::{self, Cursor, Read, Write};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use planten_9p::{decode_string, decode_u8, decode_u32, decode_u64, encode_string};
+use planten_fs_core::DMDIR;
+
+pub const MAGIC: &[u8; 8] = b"PLANPAQ1";
+pub const TRAILER_MAGIC: &[u8; 8] = b"PAQTRAIL";
+pub const DEFAULT_BLOCK_SIZE: u32 = 8192;
+pub const MIN_BLOCK_SIZE: u32 = 512;
+pub const MAX_BLOCK_SIZE: u32 = 65536;
+/// Deepest pointer tree; even 512-byte blocks then address far more than
+/// any image holds.
+pub const MAX_DEPTH: u8 = 8;
+
+pub const RAW: u8 = 0;
+pub const DEFLATE: u8 = 1;
+/// Encoding, stored length and checksum.
+pub const BLOCK_HEADER_SIZE: usize = 1 + 4 + 4;
+/// Root offset, SHA-1 and magic.
+pub const TRAILER_SIZE: usize = 8 + 20 + 8;
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub block_size: u32,
+    pub label: String,
+}
+
+impl Header {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&self.block_size.to_le_bytes());
+        out.extend_from_slice(&encode_string(&self.label));
+        out
+    }
+
+    /// Decodes the header at the start of `data`, returning it and its
+    /// length.
+    pub fn decode(data: &[u8]) -> io::Result<(Header, usize)> {
+        if !data.starts_with(MAGIC) {
+            return Err(invalid("not a paq image"));
+        }
+        let mut cursor = Cursor::new(&data[MAGIC.len()..]);
+        let block_size = decode_u32(&mut cursor)?;
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(invalid("bad block size"));
+        }
+        let label = decode_string(&mut cursor)?;
+        let len = MAGIC.len() + cursor.position() as usize;
+        Ok((Header { block_size, label }, len))
+    }
+}
+
+/// Where a stream's blocks are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Offset of the top block.
+    pub offset: u64,
+    /// Height of the pointer tree; 0 when the top block is the only leaf.
+    pub depth: u8,
+    pub size: u64,
+}
+
+/// One file or directory of an image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub uid: String,
+    pub gid: String,
+    pub mode: u32,
+    pub mtime: u32,
+    pub entry: Entry,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.mode & DMDIR != 0
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        for field in [&self.name, &self.uid, &self.gid] {
+            out.extend_from_slice(&encode_string(field));
+        }
+        out.extend_from_slice(&self.mode.to_le_bytes());
+        out.extend_from_slice(&self.mtime.to_le_bytes());
+        out.extend_from_slice(&self.entry.offset.to_le_bytes());
+        out.push(self.entry.depth);
+        out.extend_from_slice(&self.entry.size.to_le_bytes());
+    }
+
+    pub fn decode(cursor: &mut Cursor<&[u8]>) -> io::Result<DirEntry> {
+        Ok(DirEntry {
+            name: decode_string(cursor)?,
+            uid: decode_string(cursor)?,
+            gid: decode_string(cursor)?,
+            mode: decode_u32(cursor)?,
+            mtime: decode_u32(cursor)?,
+            entry: Entry {
+                offset: decode_u64(cursor)?,
+                depth: decode_u8(cursor)?,
+                size: decode_u64(cursor)?,
+            },
+        })
+    }
+}
+
+/// The stored form of `data`: compressed when `compress` is set and that
+/// makes it smaller.
+pub fn encode_block(data: &[u8], compress: bool) -> Vec<u8> {
+    let mut stored = None;
+    if compress {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        let packed = encoder
+            .write_all(data)
+            .and_then(|_| encoder.finish())
+            .expect("compressing into memory cannot fail");
+        if packed.len() < data.len() {
+            stored = Some((DEFLATE, packed));
+        }
+    }
+    let (encoding, stored) = stored.unwrap_or_else(|| (RAW, data.to_vec()));
+    let mut out = Vec::with_capacity(BLOCK_HEADER_SIZE + stored.len());
+    out.push(encoding);
+    out.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    out.extend_from_slice(&stored);
+    out
+}
+
+/// The encoding, stored length and checksum of a block header.
+pub fn decode_block_header(header: &[u8; BLOCK_HEADER_SIZE]) -> (u8, u32, u32) {
+    (
+        header[0],
+        u32::from_le_bytes(header[1..5].try_into().unwrap()),
+        u32::from_le_bytes(header[5..9].try_into().unwrap()),
+    )
+}
+
+/// The contents of a block from its header fields and stored bytes,
+/// checked against the checksum.
+pub fn decode_block(
+    encoding: u8,
+    checksum: u32,
+    stored: &[u8],
+    block_size: u32,
+) -> io::Result<Vec<u8>> {
+    let data = match encoding {
+        RAW => stored.to_vec(),
+        DEFLATE => {
+            let mut data = Vec::new();
+            ZlibDecoder::new(stored)
+                .take(block_size as u64 + 1)
+                .read_to_end(&mut data)?;
+            data
+        }
+        _ => return Err(invalid("unknown block encoding")),
+    };
+    if data.len() > block_size as usize {
+        return Err(invalid("block larger than the block size"));
+    }
+    if crc32fast::hash(&data) != checksum {
+        return Err(invalid("block checksum mismatch"));
+    }
+    Ok(data)
+}
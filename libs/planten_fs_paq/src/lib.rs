@@ -0,0 +1,369 @@
+//! Compressed read-only images of a file tree, after 9front's paqfs.
+//!
+//! `pack::pack` (the `planten_mkpaqfs` binary) writes a tree into one
+//! image file in the format described in `format`, and `PaqFs` serves such
+//! an image through `FsServer`. Every block is checked against its CRC
+//! when read and decompressed blocks are kept in an LRU cache; opening an
+//! image normally also checks the SHA-1 of the whole file, which
+//! `open_unverified` skips for large images.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use planten_fs_core::{FsServer, Inode, Wstat};
+
+use crate::cache::{BlockCache, CacheStats};
+use crate::format::{
+    BLOCK_HEADER_SIZE, DirEntry, Entry, Header, MAX_BLOCK_SIZE, MAX_DEPTH, TRAILER_MAGIC,
+    TRAILER_SIZE, decode_block, decode_block_header, invalid,
+};
+
+/// Blocks cached when the caller does not choose.
+pub const DEFAULT_CACHE_BLOCKS: usize = 256;
+
+pub struct PaqFs {
+    file: File,
+    header: Header,
+    root: DirEntry,
+    cache: Mutex<BlockCache>,
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path))
+}
+
+/// Reads and decodes the block at `offset`.
+fn load(file: &File, block_size: u32, offset: u64) -> io::Result<Vec<u8>> {
+    let mut header = [0; BLOCK_HEADER_SIZE];
+    file.read_exact_at(&mut header, offset)?;
+    let (encoding, stored_len, checksum) = decode_block_header(&header);
+    // Raw blocks never exceed the block size and compressed ones are only
+    // kept when smaller.
+    if stored_len > block_size {
+        return Err(invalid("block larger than the block size"));
+    }
+    let mut stored = vec![0; stored_len as usize];
+    file.read_exact_at(&mut stored, offset + BLOCK_HEADER_SIZE as u64)?;
+    decode_block(encoding, checksum, &stored, block_size)
+}
+
+impl PaqFs {
+    /// Opens the image at `path` after checking its SHA-1, caching up to
+    /// `cache_blocks` decompressed blocks.
+    pub fn open(path: impl AsRef<Path>, cache_blocks: usize) -> io::Result<PaqFs> {
+        let fs = Self::open_unverified(path, cache_blocks)?;
+        fs.verify()?;
+        Ok(fs)
+    }
+
+    /// Opens the image at `path` checking only the blocks it reads.
+    pub fn open_unverified(path: impl AsRef<Path>, cache_blocks: usize) -> io::Result<PaqFs> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut start = vec![0; (len as usize).min(MAX_BLOCK_SIZE as usize)];
+        file.read_exact_at(&mut start, 0)?;
+        let (header, header_len) = Header::decode(&start)?;
+        if len < (header_len + TRAILER_SIZE) as u64 {
+            return Err(invalid("image is truncated"));
+        }
+        let mut trailer = [0; TRAILER_SIZE];
+        file.read_exact_at(&mut trailer, len - TRAILER_SIZE as u64)?;
+        if &trailer[TRAILER_SIZE - TRAILER_MAGIC.len()..] != TRAILER_MAGIC {
+            return Err(invalid("image has no trailer"));
+        }
+        let root_at = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+
+        let root = DirEntry::decode(&mut Cursor::new(
+            &load(&file, header.block_size, root_at)?[..],
+        ))?;
+        if !root.is_dir() {
+            return Err(invalid("image root is not a directory"));
+        }
+        Ok(PaqFs {
+            file,
+            header,
+            root,
+            cache: Mutex::new(BlockCache::new(cache_blocks)),
+        })
+    }
+
+    /// Checks the SHA-1 in the trailer against the rest of the image.
+    pub fn verify(&self) -> io::Result<()> {
+        let len = self.file.metadata()?.len();
+        let hashed = len - (TRAILER_SIZE - 8) as u64;
+        let mut sha = sha1_smol::Sha1::new();
+        let mut chunk = vec![0; 1 << 16];
+        let mut at = 0;
+        while at < hashed {
+            let n = chunk.len().min((hashed - at) as usize);
+            self.file.read_exact_at(&mut chunk[..n], at)?;
+            sha.update(&chunk[..n]);
+            at += n as u64;
+        }
+        let mut stored = [0; 20];
+        self.file.read_exact_at(&mut stored, hashed)?;
+        if sha.digest().bytes() != stored {
+            return Err(invalid("image checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    pub fn label(&self) -> &str {
+        &self.header.label
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.header.block_size
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    /// The decompressed block at `offset`, from the cache if it is there.
+    fn block(&self, offset: u64) -> io::Result<Arc<Vec<u8>>> {
+        if let Some(block) = self.cache.lock().unwrap().get(offset) {
+            return Ok(block);
+        }
+        let block = Arc::new(load(&self.file, self.header.block_size, offset)?);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(offset, Arc::clone(&block));
+        Ok(block)
+    }
+
+    fn stream(&self, entry: &Entry) -> io::Result<Vec<u8>> {
+        if entry.depth > MAX_DEPTH {
+            return Err(invalid("pointer tree too deep"));
+        }
+        let mut data = Vec::new();
+        self.tree(entry.depth, entry.offset, &mut data)?;
+        if data.len() as u64 != entry.size {
+            return Err(invalid("stream length does not match its entry"));
+        }
+        Ok(data)
+    }
+
+    fn tree(&self, depth: u8, offset: u64, out: &mut Vec<u8>) -> io::Result<()> {
+        let block = self.block(offset)?;
+        if depth == 0 {
+            out.extend_from_slice(&block);
+            return Ok(());
+        }
+        if block.len() % 8 != 0 {
+            return Err(invalid("malformed pointer block"));
+        }
+        for child in block.chunks(8) {
+            let child = u64::from_le_bytes(child.try_into().unwrap());
+            // Children always precede their parents.
+            if child >= offset {
+                return Err(invalid("pointer does not point backwards"));
+            }
+            self.tree(depth - 1, child, out)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes `start..end` of the subtree at `offset`, reading only the
+    /// blocks that hold them. Positions are relative to the subtree.
+    fn span(
+        &self,
+        depth: u8,
+        offset: u64,
+        start: u64,
+        end: u64,
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let block = self.block(offset)?;
+        if depth == 0 {
+            let end = (end as usize).min(block.len());
+            out.extend_from_slice(block.get(start as usize..end).unwrap_or_default());
+            return Ok(());
+        }
+        if block.len() % 8 != 0 {
+            return Err(invalid("malformed pointer block"));
+        }
+        let block_size = self.header.block_size as u64;
+        let child_span = (block_size / 8)
+            .checked_pow(depth as u32 - 1)
+            .and_then(|leaves| leaves.checked_mul(block_size))
+            .unwrap_or(u64::MAX);
+        for (index, child) in block.chunks(8).enumerate() {
+            let child_start = (index as u64).saturating_mul(child_span);
+            if child_start >= end {
+                break;
+            }
+            if child_start.saturating_add(child_span) <= start {
+                continue;
+            }
+            let child = u64::from_le_bytes(child.try_into().unwrap());
+            if child >= offset {
+                return Err(invalid("pointer does not point backwards"));
+            }
+            let child_end = (end - child_start).min(child_span);
+            self.span(
+                depth - 1,
+                child,
+                start.saturating_sub(child_start),
+                child_end,
+                out,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn entries(&self, dir: &DirEntry) -> io::Result<Vec<DirEntry>> {
+        let data = self.stream(&dir.entry)?;
+        let mut cursor = Cursor::new(&data[..]);
+        let mut entries = Vec::new();
+        while (cursor.position() as usize) < data.len() {
+            entries.push(DirEntry::decode(&mut cursor)?);
+        }
+        Ok(entries)
+    }
+
+    pub fn lookup(&self, path: &str) -> io::Result<DirEntry> {
+        let mut entry = self.root.clone();
+        for part in path.split('/').filter(|part| !part.is_empty()) {
+            if !entry.is_dir() {
+                return Err(not_found(path));
+            }
+            entry = self
+                .entries(&entry)?
+                .into_iter()
+                .find(|child| child.name == part)
+                .ok_or_else(|| not_found(path))?;
+        }
+        Ok(entry)
+    }
+
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let dir = self.lookup(path)?;
+        if !dir.is_dir() {
+            return Err(invalid(&format!("{} is not a directory", path)));
+        }
+        Ok(self
+            .entries(&dir)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    pub fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
+        let file = self.lookup(path)?;
+        if file.is_dir() {
+            return Err(invalid(&format!("{} is a directory", path)));
+        }
+        self.stream(&file.entry)
+    }
+
+    /// Up to `count` bytes of the file at `path` from `offset`,
+    /// decompressing only the blocks that hold them.
+    pub fn read_range(&self, path: &str, offset: u64, count: u64) -> io::Result<Vec<u8>> {
+        let file = self.lookup(path)?;
+        if file.is_dir() {
+            return Err(invalid(&format!("{} is a directory", path)));
+        }
+        if file.entry.depth > MAX_DEPTH {
+            return Err(invalid("pointer tree too deep"));
+        }
+        let start = offset.min(file.entry.size);
+        let end = start.saturating_add(count).min(file.entry.size);
+        let mut data = Vec::new();
+        if start < end {
+            self.span(file.entry.depth, file.entry.offset, start, end, &mut data)?;
+        }
+        if data.len() as u64 != end - start {
+            return Err(invalid("stream length does not match its entry"));
+        }
+        Ok(data)
+    }
+
+    /// The inode at `path` without its contents, and the file's length.
+    fn metadata(&self, path: &str) -> io::Result<(Inode, u64)> {
+        let entry = self.lookup(path)?;
+        let length = if entry.is_dir() { 0 } else { entry.entry.size };
+        let inode = Inode {
+            name: entry.name,
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode: entry.mode,
+            uid: entry.uid.clone(),
+            gid: entry.gid,
+            atime: entry.mtime,
+            mtime: entry.mtime,
+            muid: entry.uid,
+        };
+        Ok((inode, length))
+    }
+
+    fn inode(&self, path: &str) -> io::Result<Inode> {
+        let (mut inode, _) = self.metadata(path)?;
+        if !inode.is_dir() {
+            inode.data = self.read_file(path)?;
+        }
+        Ok(inode)
+    }
+}
+
+impl FsServer for PaqFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.lookup(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.read_file(path).ok()
+    }
+
+    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+        None
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inode(path).ok()
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        PaqFs::metadata(self, path).ok()
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.read_range(path, offset, count as u64).ok()
+    }
+}
+
+pub mod cache;
+pub mod format;
+pub mod pack;
@@ -0,0 +1,231 @@
+//! Building paq images, the work of `mkpaqfs`.
+
+use std::io::{self, Write};
+
+use planten_fs_core::FsServer;
+
+use crate::format::{
+    DEFAULT_BLOCK_SIZE, DirEntry, Entry, Header, MAX_BLOCK_SIZE, MAX_DEPTH, MIN_BLOCK_SIZE,
+    TRAILER_MAGIC, encode_block,
+};
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub block_size: u32,
+    pub label: String,
+    /// Store every block raw instead of compressing those that shrink.
+    pub uncompressed: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            block_size: DEFAULT_BLOCK_SIZE,
+            label: String::new(),
+            uncompressed: false,
+        }
+    }
+}
+
+/// An image being written, tracking its length and running checksum.
+struct Image<W> {
+    out: W,
+    offset: u64,
+    sha: sha1_smol::Sha1,
+    block_size: usize,
+    compress: bool,
+}
+
+impl<W: Write> Image<W> {
+    fn put(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.sha.update(bytes);
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Appends a block and returns its offset.
+    fn block(&mut self, data: &[u8]) -> io::Result<u64> {
+        let at = self.offset;
+        self.put(&encode_block(data, self.compress))?;
+        Ok(at)
+    }
+
+    fn stream(&mut self, data: &[u8]) -> io::Result<Entry> {
+        self.stream_with(data.len() as u64, |offset, len| {
+            let start = offset as usize;
+            Some(data[start..start + len].to_vec())
+        })
+        .map(|entry| entry.expect("every leaf is in data"))
+    }
+
+    /// Writes a stream of `size` bytes, asking `read` for one leaf at a
+    /// time by offset and length, so only a block of it is ever held. The
+    /// pointer blocks fill in as the leaves arrive and the tree comes out
+    /// as if the whole stream had been split at once. A leaf that cannot
+    /// be read, or reads short, abandons the stream and returns `None`.
+    fn stream_with<R>(&mut self, size: u64, mut read: R) -> io::Result<Option<Entry>>
+    where
+        R: FnMut(u64, usize) -> Option<Vec<u8>>,
+    {
+        let fanout = self.block_size / 8;
+        let mut blocks = size.div_ceil(self.block_size as u64).max(1);
+        let mut depth = 0;
+        while blocks > 1 {
+            if depth == MAX_DEPTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "file too large for the block size",
+                ));
+            }
+            depth += 1;
+            blocks = blocks.div_ceil(fanout as u64);
+        }
+        // Offsets waiting for a pointer block, one list per level.
+        let mut levels: Vec<Vec<u64>> = vec![Vec::new(); depth as usize + 1];
+        let mut offset = 0;
+        loop {
+            let len = (size - offset).min(self.block_size as u64) as usize;
+            let Some(leaf) = read(offset, len).filter(|leaf| leaf.len() == len) else {
+                return Ok(None);
+            };
+            let mut at = self.block(&leaf)?;
+            offset += len as u64;
+            for level in 0..depth as usize {
+                levels[level].push(at);
+                if levels[level].len() < fanout {
+                    break;
+                }
+                at = self.pointers(&std::mem::take(&mut levels[level]))?;
+                if level + 1 == depth as usize {
+                    levels[depth as usize].push(at);
+                }
+            }
+            if depth == 0 {
+                levels[0].push(at);
+            }
+            if offset == size {
+                break;
+            }
+        }
+        for level in 0..depth as usize {
+            let pending = std::mem::take(&mut levels[level]);
+            if !pending.is_empty() {
+                let at = self.pointers(&pending)?;
+                levels[level + 1].push(at);
+            }
+        }
+        Ok(Some(Entry {
+            offset: levels[depth as usize][0],
+            depth,
+            size,
+        }))
+    }
+
+    /// Appends a pointer block holding `children` and returns its offset.
+    fn pointers(&mut self, children: &[u64]) -> io::Result<u64> {
+        let block: Vec<u8> = children.iter().flat_map(|at| at.to_le_bytes()).collect();
+        self.block(&block)
+    }
+}
+
+/// Packs the tree at `path` of `fs` into an image written to `out` and
+/// returns the image's length. The image depends only on the tree and
+/// `options`, so packing an unchanged tree reproduces it byte for byte.
+pub fn pack<F, W>(fs: &F, path: &str, options: &Options, out: W) -> io::Result<u64>
+where
+    F: FsServer + ?Sized,
+    W: Write,
+{
+    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&options.block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "block size must be between {} and {}",
+                MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+            ),
+        ));
+    }
+    let mut image = Image {
+        out,
+        offset: 0,
+        sha: sha1_smol::Sha1::new(),
+        block_size: options.block_size as usize,
+        compress: !options.uncompressed,
+    };
+    image.put(
+        &Header {
+            block_size: options.block_size,
+            label: options.label.clone(),
+        }
+        .encode(),
+    )?;
+
+    let mut root = pack_node(&mut image, fs, path)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path))
+    })?;
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory", path),
+        ));
+    }
+    root.name = "/".to_string();
+    let mut encoded = Vec::new();
+    root.encode(&mut encoded);
+    let root_at = image.block(&encoded)?;
+
+    image.put(&root_at.to_le_bytes())?;
+    let digest = image.sha.digest().bytes();
+    image.out.write_all(&digest)?;
+    image.out.write_all(TRAILER_MAGIC)?;
+    image.out.flush()?;
+    Ok(image.offset + (digest.len() + TRAILER_MAGIC.len()) as u64)
+}
+
+fn pack_node<F, W>(image: &mut Image<W>, fs: &F, path: &str) -> io::Result<Option<DirEntry>>
+where
+    F: FsServer + ?Sized,
+    W: Write,
+{
+    let Some((inode, length)) = fs.metadata(path) else {
+        return Ok(None);
+    };
+    let entry = if inode.is_dir() {
+        let Some(mut names) = fs.walk(path) else {
+            return Ok(None);
+        };
+        names.sort();
+        names.dedup();
+        let mut stream = Vec::new();
+        for name in names {
+            let child = format!("{}/{}", path.trim_end_matches('/'), name);
+            if let Some(mut entry) = pack_node(image, fs, &child)? {
+                entry.name = name;
+                entry.encode(&mut stream);
+            }
+        }
+        image.stream(&stream)?
+    } else if length <= image.block_size as u64 {
+        // Synthetic files often report no length at all; reading them
+        // whole is what finds their contents.
+        let Some(data) = fs.read(path) else {
+            return Ok(None);
+        };
+        image.stream(&data)?
+    } else {
+        let read = |offset, len| fs.read_at(path, offset, len as u32);
+        let Some(entry) = image.stream_with(length, read)? else {
+            return Ok(None);
+        };
+        entry
+    };
+    Ok(Some(DirEntry {
+        name: inode.name,
+        uid: inode.uid,
+        gid: inode.gid,
+        mode: inode.mode,
+        mtime: inode.mtime,
+        entry,
+    }))
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use planten_fs_core::{DMDIR, FsServer, OWRITE};
+use planten_fs_paq::PaqFs;
+use planten_fs_paq::format::MIN_BLOCK_SIZE;
+use planten_fs_paq::pack::{Options, pack};
+use planten_fs_ramfs::RamFs;
+use tempfile::TempDir;
+
+/// Bytes that do not compress.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn tree() -> RamFs {
+    let fs = RamFs::owned_by("bootes", "sys");
+    fs.create_file("/README", b"read me\n");
+    fs.create_file("/bin/rc", &noise(3000));
+    fs.create_file("/lib/empty", b"");
+    fs.create_file("/lib/words", &b"planten ".repeat(2000));
+    fs.create_dir("/tmp");
+    fs
+}
+
+fn packed(fs: &RamFs, options: &Options) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("root.paq");
+    let mut image = Vec::new();
+    let size = pack(fs, "/", options, &mut image).unwrap();
+    assert_eq!(size, image.len() as u64);
+    fs::write(&path, image).unwrap();
+    (dir, path)
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+#[test]
+fn images_serve_the_packed_tree() {
+    let ramfs = tree();
+    let options = Options {
+        label: "boot".to_string(),
+        ..Options::default()
+    };
+    let (_dir, path) = packed(&ramfs, &options);
+    let mut fs = PaqFs::open(&path, 16).unwrap();
+    assert_eq!(fs.label(), "boot");
+
+    assert_eq!(
+        sorted(fs.walk("/").unwrap()),
+        vec!["README", "bin", "lib", "tmp"]
+    );
+    assert!(fs.walk("/tmp").unwrap().is_empty());
+    assert_eq!(fs.read("/README").unwrap(), b"read me\n");
+    assert_eq!(fs.read("/bin/rc").unwrap(), noise(3000));
+    assert_eq!(fs.read("/lib/empty").unwrap(), b"");
+    assert_eq!(fs.read("/lib/words").unwrap(), b"planten ".repeat(2000));
+    for path in ["/", "/bin", "/bin/rc", "/lib/words"] {
+        let original = ramfs.stat(path).unwrap();
+        let served = fs.stat(path).unwrap();
+        assert_eq!(
+            (served.mode, served.mtime, &served.uid, &served.gid),
+            (original.mode, original.mtime, &original.uid, &original.gid),
+            "{}",
+            path
+        );
+    }
+    assert_eq!(fs.stat("/").unwrap().name, "/");
+    assert_eq!(fs.stat("/lib").unwrap().mode & DMDIR, DMDIR);
+    assert!(fs.stat("/README/x").is_none());
+    assert!(fs.read("/bin").is_none());
+
+    assert!(fs.write("/README", 0, b"x", "bootes").is_none());
+    assert!(fs.create("/", "new", 0o644, OWRITE, "bootes").is_none());
+    assert!(fs.remove("/README").is_none());
+}
+
+#[test]
+fn large_files_use_pointer_trees() {
+    let ramfs = RamFs::new();
+    // 64 pointers fit a 512-byte block, so this needs two levels.
+    let big = noise(MIN_BLOCK_SIZE as usize * 64 + 1);
+    ramfs.create_file("/big", &big);
+    let options = Options {
+        block_size: MIN_BLOCK_SIZE,
+        ..Options::default()
+    };
+    let (_dir, path) = packed(&ramfs, &options);
+    let fs = PaqFs::open(&path, 0).unwrap();
+    assert_eq!(fs.block_size(), MIN_BLOCK_SIZE);
+    assert_eq!(fs.read("/big").unwrap(), big);
+    // Ranged reads cross leaves and pointer blocks.
+    let block = MIN_BLOCK_SIZE as usize;
+    for (offset, count) in [
+        (0, 10),
+        (block - 3, 7),
+        (block * 63 + 5, block),
+        (big.len() - 1, 9),
+    ] {
+        let end = (offset + count).min(big.len());
+        let read = fs.read_at("/big", offset as u64, count as u32).unwrap();
+        assert_eq!(read, &big[offset..end]);
+    }
+    assert_eq!(fs.metadata("/big").unwrap().1, big.len() as u64);
+
+    let options = Options {
+        block_size: 100,
+        ..Options::default()
+    };
+    assert!(pack(&ramfs, "/", &options, Vec::new()).is_err());
+    assert!(pack(&ramfs, "/big", &Options::default(), Vec::new()).is_err());
+}
+
+#[test]
+fn sparse_files_are_packed_a_block_at_a_time() {
+    let ramfs = RamFs::new();
+    // Three levels of pointers at 64 to a block.
+    let hole = MIN_BLOCK_SIZE as u64 * 64 * 64;
+    ramfs.create_file("/sparse", b"");
+    ramfs.write("/sparse", hole, b"end", "user").unwrap();
+    let options = Options {
+        block_size: MIN_BLOCK_SIZE,
+        ..Options::default()
+    };
+    let (_dir, path) = packed(&ramfs, &options);
+    let fs = PaqFs::open(&path, 0).unwrap();
+    assert_eq!(fs.metadata("/sparse").unwrap().1, hole + 3);
+    assert_eq!(fs.read_at("/sparse", hole - 2, 8).unwrap(), b"\0\0end");
+    assert_eq!(fs.read_at("/sparse", 0, 4).unwrap(), [0; 4]);
+}
+
+#[test]
+fn packing_is_reproducible_and_compresses() {
+    let ramfs = tree();
+    let image = |options: &Options| {
+        let mut image = Vec::new();
+        pack(&ramfs, "/", options, &mut image).unwrap();
+        image
+    };
+    let compressed = image(&Options::default());
+    assert_eq!(image(&Options::default()), compressed);
+    let raw = image(&Options {
+        uncompressed: true,
+        ..Options::default()
+    });
+    // Only the repetitive file shrinks; noise is stored as it is.
+    assert!(compressed.len() + 10_000 < raw.len());
+    assert!(raw.len() > 3000 + 16_000);
+}
+
+fn flip(path: &Path, needle: &[u8]) {
+    let mut image = fs::read(path).unwrap();
+    let at = image
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .unwrap();
+    image[at] ^= 0xff;
+    fs::write(path, image).unwrap();
+}
+
+#[test]
+fn damage_is_detected() {
+    let options = Options {
+        uncompressed: true,
+        ..Options::default()
+    };
+    let (_dir, path) = packed(&tree(), &options);
+    flip(&path, b"read me");
+
+    let err = PaqFs::open(&path, 16).err().unwrap();
+    assert!(err.to_string().contains("checksum"), "{}", err);
+    // Without the whole-image check the damage shows up on reading.
+    let fs = PaqFs::open_unverified(&path, 16).unwrap();
+    assert!(fs.read("/README").is_none());
+    assert_eq!(fs.read("/bin/rc").unwrap(), noise(3000));
+
+    let image = fs::read(&path).unwrap();
+    fs::write(&path, &image[..image.len() - 1]).unwrap();
+    assert!(PaqFs::open_unverified(&path, 16).is_err());
+    fs::write(&path, b"PLANPAQ1").unwrap();
+    assert!(PaqFs::open_unverified(&path, 16).is_err());
+}
+
+#[test]
+fn blocks_are_cached() {
+    let ramfs = tree();
+    let (_dir, path) = packed(&ramfs, &Options::default());
+
+    let fs = PaqFs::open(&path, 64).unwrap();
+    fs.read("/bin/rc").unwrap();
+    let first = fs.cache_stats();
+    assert_eq!(first.hits, 0);
+    fs.read("/bin/rc").unwrap();
+    let second = fs.cache_stats();
+    assert_eq!(second.misses, first.misses, "a second read is all hits");
+    assert_eq!(second.hits, first.misses);
+    assert_eq!(second.blocks, first.misses as usize);
+
+    // A tiny cache keeps only the latest blocks.
+    let fs = PaqFs::open(&path, 1).unwrap();
+    fs.read("/bin/rc").unwrap();
+    fs.read("/lib/words").unwrap();
+    assert_eq!(fs.cache_stats().blocks, 1);
+    let fs = PaqFs::open(&path, 0).unwrap();
+    fs.read("/README").unwrap();
+    fs.read("/README").unwrap();
+    let stats = fs.cache_stats();
+    assert_eq!((stats.hits, stats.blocks), (0, 0));
+}
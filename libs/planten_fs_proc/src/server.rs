@@ -6,6 +6,7 @@ use planten_9p::{
     encode_qid_bytes, encode_stat_payload, encode_string,
 };
 use planten_fs_core::FsServer;
+use planten_fs_core::perm::{may_open, may_walk};
 use std::collections::HashMap;
 use std::io::{self, Cursor, Write};
 use std::net::{TcpListener, TcpStream};
@@ -36,6 +37,7 @@ struct FidState {
     path: String,
     qid: Qid,
     open_mode: Option<u8>,
+    uname: String,
 }
 
 fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<ProcFs>>) -> io::Result<()> {
@@ -92,6 +94,7 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<ProcFs>>) -> io::Result<()
                         path: "/".to_string(),
                         qid: root_qid.clone(),
                         open_mode: None,
+                        uname,
                     },
                 );
 
@@ -112,9 +115,17 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<ProcFs>>) -> io::Result<()
                 let fs_locked = fs.lock().unwrap();
                 let mut qids = Vec::new();
                 let mut current_path = fids.get(&fid).map(|f| f.path.clone()).unwrap_or_default();
-                let mut walked_successfully = true;
+                let uname = fids
+                    .get(&fid)
+                    .map(|f| f.uname.clone())
+                    .unwrap_or_else(|| "none".to_string());
+                let mut failure = None;
 
                 for name in names {
+                    if let Err(message) = may_walk(&*fs_locked, &current_path, &uname) {
+                        failure = Some(message);
+                        break;
+                    }
                     let next_path = if current_path == "/" {
                         format!("/{}", name)
                     } else {
@@ -133,12 +144,14 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<ProcFs>>) -> io::Result<()
                         });
                         current_path = next_path;
                     } else {
-                        walked_successfully = false;
+                        failure = Some("file not found");
                         break;
                     }
                 }
 
-                if walked_successfully {
+                if let Some(message) = failure {
+                    Some((RERROR, encode_string(message)))
+                } else {
                     let mut body = Vec::new();
                     body.extend_from_slice(&(qids.len() as u16).to_le_bytes());
                     for qid in &qids {
@@ -154,11 +167,10 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<ProcFs>>) -> io::Result<()
                                 path: 0,
                             }),
                             open_mode: None,
+                            uname,
                         },
                     );
                     Some((RWALK, body))
-                } else {
-                    Some((RERROR, encode_string("file not found")))
                 }
             }
             planten_9p::messages::TOPEN => {
@@ -168,14 +180,15 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<ProcFs>>) -> io::Result<()
 
                 let fs_locked = fs.lock().unwrap();
                 if let Some(fid_state) = fids.get_mut(&fid) {
-                    if let Some(_inode) = fs_locked.stat(&fid_state.path) {
-                        fid_state.open_mode = Some(mode);
-                        let mut body = Vec::new();
-                        body.extend_from_slice(&encode_qid_bytes(&fid_state.qid));
-                        body.extend_from_slice(&MAX_MSG_SIZE.to_le_bytes()); // iounit
-                        Some((ROPEN, body))
-                    } else {
-                        Some((RERROR, encode_string("file not found")))
+                    match may_open(&*fs_locked, &fid_state.path, &fid_state.uname, mode as u32) {
+                        Ok(()) => {
+                            fid_state.open_mode = Some(mode);
+                            let mut body = Vec::new();
+                            body.extend_from_slice(&encode_qid_bytes(&fid_state.qid));
+                            body.extend_from_slice(&MAX_MSG_SIZE.to_le_bytes()); // iounit
+                            Some((ROPEN, body))
+                        }
+                        Err(message) => Some((RERROR, encode_string(message))),
                     }
                 } else {
                     Some((RERROR, encode_string("fid not found")))
@@ -1,21 +1,274 @@
+use std::env;
+use std::fs;
 use std::io;
 use std::net::TcpListener;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-use planten_fs_ramfs::{RamFs, server};
+use planten_9p::server::{self, Config, MIN_MSIZE};
+use planten_fs_ramfs::quota::Limits;
+use planten_fs_ramfs::{RamFs, dump, snapshot};
+use signal_hook::consts::{SIGTERM, SIGUSR1};
+use signal_hook::iterator::Signals;
 
 const LISTEN_ADDR: &str = "127.0.0.1:5640";
+/// Prefix that makes `--listen` name a Unix domain socket.
+const UNIX_PREFIX: &str = "unix:";
+/// Flags that take no value; in a config file they are set to `true` or
+/// `false`.
+const SWITCHES: [&str; 4] = ["restore", "dump", "read-only", "events"];
+
+struct Options {
+    listen: String,
+    owner: String,
+    group: String,
+    snapshot: Option<PathBuf>,
+    restore: bool,
+    snapshot_interval: Option<Duration>,
+    dump: bool,
+    events: bool,
+    server_limits: Limits,
+    user_limits: Limits,
+    seed: Option<PathBuf>,
+    export_tar: Option<PathBuf>,
+    config: Config,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_ramfs [--config file] [--listen host:port|unix:path]\n\
+         \t[--owner user] [--group group] [--read-only] [--msize n] [--max-connections n]\n\
+         \t[--dump] [--events] [--snapshot file [--restore] [--snapshot-interval secs]]\n\
+         \t[--seed dir|tar] [--export-tar file]\n\
+         \t[--max-bytes n] [--max-inodes n] [--user-max-bytes n] [--user-max-inodes n]\n\
+         A config file holds one `flag = value` per line, naming flags without\n\
+         their dashes; flags given after --config override it."
+    );
+    process::exit(1);
+}
+
+/// The value of a flag, parsed.
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| usage())
+}
+
+/// Turns a config file into the flags it stands for. Blank lines and
+/// lines starting with `#` are skipped.
+fn config_args(path: &str) -> Vec<String> {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {}", path, err);
+        process::exit(1);
+    });
+    let mut args = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fail = |message: &str| -> ! {
+            eprintln!("{}:{}: {}", path, number + 1, message);
+            process::exit(1);
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            fail("expected `flag = value`");
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key == "config" {
+            fail("config files cannot include others");
+        }
+        if SWITCHES.contains(&key) {
+            match value {
+                "true" => args.push(format!("--{}", key)),
+                "false" => {}
+                _ => fail("expected true or false"),
+            }
+        } else {
+            args.push(format!("--{}", key));
+            args.push(value.to_string());
+        }
+    }
+    args
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        listen: LISTEN_ADDR.to_string(),
+        owner: "user".to_string(),
+        group: "group".to_string(),
+        snapshot: None,
+        restore: false,
+        snapshot_interval: None,
+        dump: false,
+        events: false,
+        server_limits: Limits::default(),
+        user_limits: Limits::default(),
+        seed: None,
+        export_tar: None,
+        config: Config::default(),
+    };
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // Splice each config file in where it was named, so later flags win.
+    while let Some(at) = args.iter().position(|arg| arg == "--config") {
+        let path = args.get(at + 1).cloned().unwrap_or_else(|| usage());
+        args.splice(at..at + 2, config_args(&path));
+    }
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => options.listen = value(&mut args),
+            "--owner" => options.owner = value(&mut args),
+            "--group" => options.group = value(&mut args),
+            "--read-only" => options.config.read_only = true,
+            "--msize" => {
+                options.config.max_msize = value(&mut args);
+                if options.config.max_msize < MIN_MSIZE {
+                    usage();
+                }
+            }
+            "--max-connections" => options.config.max_connections = Some(value(&mut args)),
+            "--snapshot" => options.snapshot = Some(value(&mut args)),
+            "--restore" => options.restore = true,
+            "--seed" => options.seed = Some(value(&mut args)),
+            "--export-tar" => options.export_tar = Some(value(&mut args)),
+            "--dump" => options.dump = true,
+            "--events" => options.events = true,
+            "--max-bytes" => options.server_limits.bytes = Some(value(&mut args)),
+            "--max-inodes" => options.server_limits.inodes = Some(value(&mut args)),
+            "--user-max-bytes" => options.user_limits.bytes = Some(value(&mut args)),
+            "--user-max-inodes" => options.user_limits.inodes = Some(value(&mut args)),
+            "--snapshot-interval" => match value(&mut args) {
+                0 => usage(),
+                secs => options.snapshot_interval = Some(Duration::from_secs(secs)),
+            },
+            _ => usage(),
+        }
+    }
+    if options.snapshot.is_none() && (options.restore || options.snapshot_interval.is_some()) {
+        usage();
+    }
+    options
+}
+
+/// The starting tree: a copy of `seed`, which is a host directory or a tar
+/// archive, or else two sample files.
+fn seeded(seed: Option<&Path>, uid: &str, gid: &str) -> io::Result<RamFs> {
+    match seed {
+        Some(dir) if dir.is_dir() => return RamFs::from_dir(dir, uid, gid),
+        Some(archive) => return RamFs::load_tar(archive, uid, gid),
+        None => {}
+    }
+    let ramfs = RamFs::owned_by(uid, gid);
+    ramfs.create_file("/hello.txt", b"hello 9p!!");
+    ramfs.create_file("/readme.txt", b"RAMFS as a 9P server");
+    Ok(ramfs)
+}
+
+fn listen(addr: &str, ramfs: Arc<RamFs>, config: Config) -> io::Result<()> {
+    if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+        return listen_unix(Path::new(path), ramfs, config);
+    }
+    let listener = TcpListener::bind(addr)?;
+    println!(
+        "planten_fs_ramfs 9P server listening on {}",
+        listener.local_addr()?
+    );
+    server::serve_all(listener.incoming(), ramfs, config)
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &Path, ramfs: Arc<RamFs>, config: Config) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by an earlier run would make the bind fail.
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    println!(
+        "planten_fs_ramfs 9P server listening on {}{}",
+        UNIX_PREFIX,
+        path.display()
+    );
+    server::serve_all(listener.incoming(), ramfs, config)
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &Path, _ramfs: Arc<RamFs>, _config: Config) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported here",
+    ))
+}
 
 fn main() -> io::Result<()> {
-    let listener = TcpListener::bind(LISTEN_ADDR)?;
-    let ramfs = Arc::new(Mutex::new(RamFs::new()));
+    let options = parse_args();
+
+    let ramfs = match &options.snapshot {
+        Some(path) if options.restore && path.exists() => {
+            println!("restoring from {}", path.display());
+            RamFs::load_snapshot(path)?
+        }
+        _ => seeded(options.seed.as_deref(), &options.owner, &options.group)?,
+    };
+    ramfs.set_server_limits(options.server_limits);
+    ramfs.set_user_limits(None, options.user_limits);
+    if ramfs.enable_usage().is_none() {
+        eprintln!("cannot serve /.usage: the tree already has one");
+    }
+    if options.dump && ramfs.enable_dump().is_none() {
+        eprintln!("cannot serve dumps: the tree already has a /dump");
+        process::exit(1);
+    }
+    if options.events && ramfs.enable_events().is_none() {
+        eprintln!("cannot serve .events: the tree already has one");
+        process::exit(1);
+    }
+    let ramfs = Arc::new(ramfs);
+    if options.dump {
+        dump::dump_daily(Arc::clone(&ramfs));
+    }
+
+    if let Some(path) = options.snapshot {
+        if let Some(interval) = options.snapshot_interval {
+            snapshot::save_every(Arc::clone(&ramfs), path.clone(), interval);
+        }
+        // Save the snapshot once more on SIGTERM so a clean shutdown loses
+        // nothing.
+        let mut signals = Signals::new([SIGTERM])?;
+        let ramfs = Arc::clone(&ramfs);
+        thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                let status = match ramfs.save_snapshot(&path) {
+                    Ok(()) => 0,
+                    Err(err) => {
+                        eprintln!("snapshot to {} failed: {}", path.display(), err);
+                        1
+                    }
+                };
+                process::exit(status);
+            }
+        });
+    }
 
-    {
-        let mut guard = ramfs.lock().unwrap();
-        guard.create_file("/hello.txt", b"hello 9p!!");
-        guard.create_file("/readme.txt", b"RAMFS as a 9P server");
+    if let Some(path) = options.export_tar {
+        // Export the tree on SIGUSR1, as often as asked.
+        let mut signals = Signals::new([SIGUSR1])?;
+        let ramfs = Arc::clone(&ramfs);
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                match ramfs.save_tar(&path) {
+                    Ok(()) => println!("exported to {}", path.display()),
+                    Err(err) => eprintln!("export to {} failed: {}", path.display(), err),
+                }
+            }
+        });
     }
 
-    println!("planten_fs_ramfs 9P server listening on {}", LISTEN_ADDR);
-    server::run_server(listener, ramfs)
+    listen(&options.listen, ramfs, options.config)
 }
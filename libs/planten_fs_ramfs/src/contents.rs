@@ -0,0 +1,142 @@
+//! Sparse file contents.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+/// Granularity of storage and of copy-on-write sharing with dumps.
+const BLOCK: u64 = 8192;
+
+/// A file's bytes, kept as fixed-size blocks so that only written ranges
+/// take memory: a write far past the end leaves a hole that reads as
+/// zeros. Blocks sit behind `Arc`s, so cloning is cheap and a write copies
+/// only the blocks it touches while a dump still shares them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Contents {
+    len: u64,
+    /// Block index to bytes from the start of that block. A block is cut
+    /// short after the last byte written to it.
+    blocks: BTreeMap<u64, Arc<Vec<u8>>>,
+}
+
+impl Contents {
+    pub fn from_slice(data: &[u8]) -> Self {
+        let mut contents = Contents::default();
+        contents.write(0, data);
+        contents
+    }
+
+    /// Reads `reader` to its end, leaving blocks that are all zeros as
+    /// holes.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut contents = Contents::default();
+        let mut block = vec![0; BLOCK as usize];
+        let mut len = 0u64;
+        loop {
+            let mut filled = 0;
+            while filled < block.len() {
+                match reader.read(&mut block[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            if block[..filled].iter().any(|byte| *byte != 0) {
+                contents.write(len, &block[..filled]);
+            }
+            len += filled as u64;
+            if filled < block.len() {
+                break;
+            }
+        }
+        contents.set_len(len);
+        Ok(contents)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Bytes actually held, which is what quotas charge.
+    pub fn stored(&self) -> u64 {
+        self.blocks.values().map(|block| block.len() as u64).sum()
+    }
+
+    /// How much `stored` would grow by writing `count` bytes at `offset`.
+    pub fn growth(&self, offset: u64, count: u64) -> u64 {
+        let Some(end) = offset.checked_add(count).filter(|_| count > 0) else {
+            return 0;
+        };
+        let mut growth = 0;
+        for index in offset / BLOCK..=(end - 1) / BLOCK {
+            let held = self
+                .blocks
+                .get(&index)
+                .map_or(0, |block| block.len() as u64);
+            let needed = (end - index * BLOCK).min(BLOCK);
+            growth += needed.saturating_sub(held);
+        }
+        growth
+    }
+
+    /// Up to `count` bytes from `offset`, stopping at the end of the file.
+    pub fn read(&self, offset: u64, count: usize) -> Vec<u8> {
+        let end = offset.saturating_add(count as u64).min(self.len);
+        if offset >= end {
+            return Vec::new();
+        }
+        let mut out = vec![0; (end - offset) as usize];
+        for (&index, block) in self.blocks.range(offset / BLOCK..=(end - 1) / BLOCK) {
+            let start = index * BLOCK;
+            let from = offset.max(start);
+            let to = end.min(start.saturating_add(block.len() as u64));
+            if from < to {
+                out[(from - offset) as usize..(to - offset) as usize]
+                    .copy_from_slice(&block[(from - start) as usize..(to - start) as usize]);
+            }
+        }
+        out
+    }
+
+    /// Callers bound `offset + data.len()` first; see `RamFs::write`.
+    pub fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let end = offset + data.len() as u64;
+        for index in offset / BLOCK..=(end - 1) / BLOCK {
+            let start = index * BLOCK;
+            let from = offset.max(start);
+            let to = end.min(start.saturating_add(BLOCK));
+            let block = Arc::make_mut(self.blocks.entry(index).or_default());
+            if block.len() < (to - start) as usize {
+                block.resize((to - start) as usize, 0);
+            }
+            block[(from - start) as usize..(to - start) as usize]
+                .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+        }
+        self.len = self.len.max(end);
+    }
+
+    /// Cuts the file to `len` bytes, or extends it with a hole.
+    pub fn set_len(&mut self, len: u64) {
+        if len < self.len {
+            self.blocks.split_off(&len.div_ceil(BLOCK));
+            if let Some(block) = self.blocks.get_mut(&(len / BLOCK)) {
+                let keep = (len % BLOCK) as usize;
+                if keep < block.len() {
+                    Arc::make_mut(block).truncate(keep);
+                }
+            }
+        }
+        self.len = len;
+    }
+
+    /// The written ranges as `(offset, bytes)`, in order.
+    pub fn extents(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.blocks
+            .iter()
+            .map(|(index, block)| (index * BLOCK, block.as_slice()))
+    }
+}
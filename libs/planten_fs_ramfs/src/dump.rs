@@ -0,0 +1,141 @@
+//! Plan 9 style dumps: frozen, read-only copies of the tree served under
+//! `/dump/YYYY/MMDD`. Taking one copies the metadata of nodes changed since
+//! the last dump; unchanged subtrees are shared with earlier dumps, and
+//! file contents with the live tree until the live side writes them.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{Found, RamFs, current_timestamp};
+
+pub const DUMP_DIR: &str = "dump";
+/// Written to `/dump/ctl` to take a dump.
+pub const SNAP_COMMAND: &str = "snap";
+
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+impl RamFs {
+    /// Serves dumps under `/dump`, whose `ctl` file takes one when sent
+    /// `snap`. Fails if the tree already has a `/dump` of its own.
+    pub fn enable_dump(&self) -> Option<()> {
+        self.reserve_root_name(DUMP_DIR, &self.dump_enabled)
+    }
+
+    /// Freezes the current tree under the UTC date of `now`, adding a
+    /// numeric suffix when that day already has a dump, as the Plan 9 file
+    /// servers do. Returns the new dump's `YYYY/MMDD` name.
+    pub fn snap(&self, now: u32) -> String {
+        let mut root = (*self.freeze()).clone();
+        let (year, day) = dump_date(now);
+        let mut dumps = self.dumps.write().unwrap();
+        let mut name = format!("{}/{}", year, day);
+        let mut suffix = 1;
+        while dumps.contains_key(&name) {
+            name = format!("{}/{}{}", year, day, suffix);
+            suffix += 1;
+        }
+
+        root.name = name[year.len() + 1..].to_string();
+        root.mtime = now;
+        dumps.insert(name.clone(), Arc::new(root));
+        name
+    }
+
+    /// Names of the dumps taken so far, oldest first.
+    pub fn dumps(&self) -> Vec<String> {
+        self.dumps.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Resolves the components of a path below `/dump`.
+    pub(crate) fn find_dump(&self, parts: &[&str]) -> Option<Found> {
+        let root_mtime = self.root_state().mtime;
+        let dumps = self.dumps.read().unwrap();
+        let latest = |prefix: &str| {
+            dumps
+                .iter()
+                .filter(|(name, _)| name.starts_with(prefix))
+                .map(|(_, root)| root.mtime)
+                .max()
+                .unwrap_or(root_mtime)
+        };
+        match parts {
+            [] => {
+                let years: BTreeSet<&str> = dumps
+                    .keys()
+                    .filter_map(|name| name.split('/').next())
+                    .collect();
+                let mut entries: Vec<String> = years.into_iter().map(String::from).collect();
+                entries.push("ctl".to_string());
+                Some(Found::DumpDir {
+                    name: DUMP_DIR.to_string(),
+                    entries,
+                    mtime: latest(""),
+                })
+            }
+            ["ctl"] => Some(Found::DumpCtl),
+            [year] => {
+                let prefix = format!("{}/", year);
+                let entries: Vec<String> = dumps
+                    .keys()
+                    .filter_map(|name| name.strip_prefix(&prefix))
+                    .map(String::from)
+                    .collect();
+                if entries.is_empty() {
+                    return None;
+                }
+                Some(Found::DumpDir {
+                    name: year.to_string(),
+                    entries,
+                    mtime: latest(&prefix),
+                })
+            }
+            [year, day, rest @ ..] => {
+                let mut node = dumps.get(&format!("{}/{}", year, day))?;
+                for part in rest {
+                    node = node.child(part)?;
+                }
+                Some(Found::Frozen(Arc::clone(node)))
+            }
+        }
+    }
+
+    /// Handles a write to `/dump/ctl`.
+    pub(crate) fn dump_ctl(&self, data: &[u8]) -> Option<u32> {
+        let command = std::str::from_utf8(data).ok()?.trim();
+        if command != SNAP_COMMAND {
+            return None;
+        }
+        self.snap(current_timestamp());
+        Some(data.len() as u32)
+    }
+}
+
+/// Year and `MMDD` directory names for the UTC day containing `secs`.
+pub fn dump_date(secs: u32) -> (String, String) {
+    // Days since the epoch to a civil date, after Howard Hinnant's
+    // `civil_from_days`, with eras starting on 0000-03-01.
+    let z = (secs / SECS_PER_DAY) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year.to_string(), format!("{:02}{:02}", month, day))
+}
+
+/// Takes a dump at every UTC midnight until the process exits.
+pub fn dump_daily(ramfs: Arc<RamFs>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            let now = current_timestamp();
+            let wait = SECS_PER_DAY - now % SECS_PER_DAY;
+            thread::sleep(Duration::from_secs(wait.into()));
+            ramfs.snap(current_timestamp());
+        }
+    })
+}
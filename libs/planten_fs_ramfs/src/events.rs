@@ -0,0 +1,171 @@
+//! Change notification. Once `enable_events` is on, every directory serves
+//! a read-only `.events` file listing what happens to its entries, one
+//! line per change:
+//!
+//! ```text
+//! create name
+//! write name
+//! wstat name
+//! wstat old new
+//! remove name
+//! ```
+//!
+//! `wstat` with two names is a rename. Names holding spaces or quotes are
+//! quoted as rc does. A directory starts logging the first time its
+//! `.events` is walked to, so unwatched directories cost nothing, and
+//! reads at the end of the log block until the next change.
+//!
+//! Offsets are positions in the whole stream. Each log keeps only the
+//! last `WINDOW` bytes; reading below that fails with `EVENTS_LOST`, and
+//! the file's length tells a reader where to pick up again. Runtimes
+//! that read whole files through `FsServer::read` see only what is kept.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use planten_fs_core::wait::{Notifier, ReadWait};
+
+use crate::table::Table;
+use crate::{RamFs, current_timestamp};
+
+/// Name of the event file served in every directory once `enable_events`
+/// is on.
+pub const EVENTS_FILE: &str = ".events";
+/// Error for reads at offsets the log no longer holds.
+pub const EVENTS_LOST: &str = "events lost";
+/// Bytes of recent events each directory keeps for slow readers.
+const WINDOW: usize = 64 * 1024;
+
+/// The recent events of one directory.
+pub(crate) struct Log {
+    /// Stream offset of `bytes[0]`.
+    start: u64,
+    bytes: Vec<u8>,
+    /// When logging began, which serves as the file's mtime.
+    started: u32,
+    notifier: Arc<Notifier>,
+}
+
+impl Log {
+    fn new() -> Self {
+        Log {
+            start: 0,
+            bytes: Vec::new(),
+            started: current_timestamp(),
+            notifier: Notifier::new(),
+        }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start + self.bytes.len() as u64
+    }
+
+    pub fn started(&self) -> u32 {
+        self.started
+    }
+
+    pub fn retained(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+
+    /// Adds `line` and wakes every reader parked at the end. Whole lines
+    /// are dropped from the front once the log outgrows its window.
+    fn append(&mut self, line: &str) {
+        self.bytes.extend_from_slice(line.as_bytes());
+        if self.bytes.len() > WINDOW {
+            let excess = self.bytes.len() - WINDOW;
+            let cut = self.bytes[excess..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.bytes.len(), |at| excess + at + 1);
+            self.bytes.drain(..cut);
+            self.start += cut as u64;
+        }
+        self.notifier.notify();
+    }
+
+    pub fn read(&self, offset: u64, count: usize) -> Result<Vec<u8>, &'static str> {
+        let from = offset.checked_sub(self.start).ok_or(EVENTS_LOST)?;
+        let from = usize::try_from(from)
+            .unwrap_or(usize::MAX)
+            .min(self.bytes.len());
+        let to = from.saturating_add(count).min(self.bytes.len());
+        Ok(self.bytes[from..to].to_vec())
+    }
+
+    /// A wait for the next event if nothing is readable at `offset` yet.
+    /// Taken under the events lock, so no event can slip in unseen.
+    pub fn wait_at(&self, offset: u64) -> Option<ReadWait> {
+        (offset >= self.end()).then(|| self.notifier.waiter())
+    }
+}
+
+/// `name` as rc would quote it, so every line splits into fields cleanly.
+fn quote(name: &str) -> Cow<'_, str> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '\'') {
+        Cow::Owned(format!("'{}'", name.replace('\'', "''")))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+impl RamFs {
+    /// Serves `.events` in every directory, unless the tree already has a
+    /// file of that name somewhere.
+    pub fn enable_events(&self) -> Option<()> {
+        // Creates and renames hold the table for writing, so none can race
+        // the check.
+        let table = self.table.read().unwrap();
+        if table.has_name(EVENTS_FILE) {
+            return None;
+        }
+        self.events_enabled.store(true, Ordering::Release);
+        Some(())
+    }
+
+    pub(crate) fn events_enabled(&self) -> bool {
+        self.events_enabled.load(Ordering::Acquire)
+    }
+
+    /// Runs `f` on the log of the live directory `dir`, starting one if
+    /// this is the first look at it.
+    pub(crate) fn with_log<T>(&self, dir: u64, f: impl FnOnce(&Log) -> T) -> Option<T> {
+        // The table lock keeps a removal from dropping `dir` meanwhile.
+        let table = self.table.read().unwrap();
+        table.children(dir)?;
+        let mut logs = self.logs.lock().unwrap();
+        Some(f(logs.entry(dir).or_insert_with(Log::new)))
+    }
+
+    /// Records `change` to the entries `names` in `dir`, if anyone is
+    /// watching it. Callers hold the table lock, so events reach the log
+    /// in the order the changes were made.
+    pub(crate) fn post(&self, dir: Option<u64>, change: &str, names: &[&str]) {
+        let Some(dir) = dir.filter(|_| self.events_enabled()) else {
+            return;
+        };
+        let mut logs = self.logs.lock().unwrap();
+        if let Some(log) = logs.get_mut(&dir) {
+            let mut line = change.to_string();
+            for name in names {
+                line.push(' ');
+                line.push_str(&quote(name));
+            }
+            line.push('\n');
+            log.append(&line);
+        }
+    }
+
+    /// Drops the logs of directories that are no longer in `table`, waking
+    /// their readers so they find the file gone.
+    pub(crate) fn forget_logs(&self, table: &Table) {
+        self.logs.lock().unwrap().retain(|dir, log| {
+            let live = table.children(*dir).is_some();
+            if !live {
+                log.notifier.notify();
+            }
+            live
+        });
+    }
+}
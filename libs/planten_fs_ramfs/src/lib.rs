@@ -1,169 +1,683 @@
-use planten_fs_core::{FsServer, Inode};
+use planten_9p::Stat;
+use planten_9p::server::{READ_ONLY, Shared, stat_change};
+use planten_fs_core::perm::{check_create, check_wstat};
+use planten_fs_core::wait::ReadWait;
+use planten_fs_core::{
+    DMAPPEND, DMDIR, FsServer, Inode, OEXCL, Wstat, create_perm, resolve_step, werrstr,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use contents::Contents;
+use events::{EVENTS_FILE, Log};
+use node::Node;
+use quota::Quotas;
+use table::{Entry, ROOT_ID, State, Table, touch};
+
+/// An in-memory file tree that any number of threads can serve at once.
+///
+/// The directory structure lives in one table behind a `RwLock`, which
+/// creates, removes and renames take for writing. Each node's metadata and
+/// contents sit behind a lock of their own, so reads of one file, writes
+/// to another, walks and stats all proceed in parallel.
 pub struct RamFs {
-    root: Inode,
+    table: RwLock<Table>,
+    /// Frozen trees keyed by `YYYY/MMDD`, sharing unchanged nodes with
+    /// each other and, for file contents, with the live tree.
+    dumps: RwLock<BTreeMap<String, Arc<Node>>>,
+    dump_enabled: AtomicBool,
+    usage_enabled: AtomicBool,
+    events_enabled: AtomicBool,
+    /// Event logs of the directories someone has watched, by node id.
+    logs: Mutex<HashMap<u64, Log>>,
+    /// Ids of the DMEXCL files that some fid currently has open.
+    exclusive: Mutex<HashSet<u64>>,
+    quotas: Mutex<Quotas>,
+}
+
+/// Longest file `read_file` returns whole. A sparse file may be far longer
+/// than memory, so anything larger is read through `read_at` instead.
+pub const WHOLE_READ_LIMIT: u64 = 64 << 20;
+
+const TOO_LARGE: &str = "file too large to read whole";
+
+/// What a path names.
+enum Found {
+    /// A live node, by id.
+    Live(u64),
+    /// A node inside a dump, served read-only.
+    Frozen(Arc<Node>),
+    /// `/dump` or one of its year directories.
+    DumpDir {
+        name: String,
+        entries: Vec<String>,
+        mtime: u32,
+    },
+    DumpCtl,
+    /// `/.usage`.
+    Usage,
+    /// The `.events` file of a live directory, by the directory's id.
+    Events(u64),
+}
+
+/// Whether `uname` may write the directory holding `id`, at `path`.
+fn parent_writable(ramfs: &RamFs, table: &Table, id: u64, path: &str, uname: &str) -> bool {
+    let Some(parent) = table.parent(id).and_then(|parent| table.entry(parent)) else {
+        return false;
+    };
+    let state = parent.state.read().unwrap();
+    let inode = state.to_inode("");
+    let dir = resolve_step(path, "..").unwrap_or_default();
+    check_create(&inode, uname, |group| ramfs.is_member(&dir, uname, group)).is_ok()
+}
+
+/// The root's owner, who also owns the files the host adds.
+fn root_owner(table: &Table) -> (String, String) {
+    let root = table.entry(ROOT_ID).unwrap();
+    let state = root.state.read().unwrap();
+    (state.uid.clone(), state.gid.clone())
+}
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
 }
 
 impl RamFs {
     pub fn new() -> Self {
+        Self::owned_by("user", "group")
+    }
+
+    /// An empty tree whose root, and whatever `create_file` adds, belongs
+    /// to `uid` and `gid`.
+    pub fn owned_by(uid: &str, gid: &str) -> Self {
+        Self::with_table(Table::new(State::new(0o755 | DMDIR, uid, gid)))
+    }
+
+    fn with_table(table: Table) -> Self {
         RamFs {
-            root: Inode::new("/", 0o755 | 0x80000000, "user", "group"),
+            table: RwLock::new(table),
+            dumps: RwLock::new(BTreeMap::new()),
+            dump_enabled: AtomicBool::new(false),
+            usage_enabled: AtomicBool::new(false),
+            events_enabled: AtomicBool::new(false),
+            logs: Mutex::new(HashMap::new()),
+            exclusive: Mutex::new(HashSet::new()),
+            quotas: Mutex::new(Quotas::default()),
         }
     }
 
-    pub fn wstat_from_stat(&mut self, path: &str, stat: &planten_9p::Stat) -> Option<()> {
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        if components.is_empty() {
-            // root
-            if stat.mode != !0u32 {
-                self.root.mode = stat.mode;
+    /// A live tree holding a copy of `root` and everything below it.
+    pub(crate) fn from_tree(root: &Node) -> Self {
+        let mut table = Table::new(State::from_node(root));
+        table.thaw(ROOT_ID, root);
+        let ramfs = Self::with_table(table);
+        {
+            let table = ramfs.table.read().unwrap();
+            let mut quotas = ramfs.quotas.lock().unwrap();
+            for entry in table.nodes() {
+                let state = entry.state.read().unwrap();
+                quotas.charge(&state.uid, state.data.stored(), 1);
             }
-            if stat.mtime != !0u32 {
-                self.root.mtime = stat.mtime;
-            }
-            if !stat.gid.is_empty() {
-                self.root.gid = stat.gid.clone();
-            }
-            return Some(());
         }
+        ramfs
+    }
 
-        let (filename, path_parts) = components.split_last()?;
-        let mut current = &mut self.root;
-        for part in path_parts {
-            current = current.children.get_mut(*part)?;
+    /// An immutable copy of the whole live tree, for dumps and snapshots.
+    pub(crate) fn freeze(&self) -> Arc<Node> {
+        self.table.read().unwrap().freeze(ROOT_ID)
+    }
+
+    /// Id of the live file at `path`; dumps and their control files have
+    /// none.
+    pub fn node_id(&self, path: &str) -> Option<u64> {
+        match self.find(path)? {
+            Found::Live(id) => Some(id),
+            _ => None,
         }
+    }
 
-        let filename = *filename;
-        let mut inode = current.children.get(filename)?.clone();
+    /// Current path of the live node `id`, following any renames of it or
+    /// its ancestors, or `None` once it has been removed.
+    pub fn node_path(&self, id: u64) -> Option<String> {
+        self.table.read().unwrap().path(id)
+    }
 
-        if stat.mode != !0u32 {
-            inode.mode = stat.mode;
+    fn find(&self, path: &str) -> Option<Found> {
+        let parts = components(path);
+        if self.is_dump(&parts) {
+            return self.find_dump(&parts[1..]);
         }
-        if stat.mtime != !0u32 {
-            inode.mtime = stat.mtime;
+        if parts == [quota::USAGE_FILE] && self.usage_enabled.load(Ordering::Acquire) {
+            return Some(Found::Usage);
         }
-        if stat.length != !0u64 {
-            inode.data.resize(stat.length as usize, 0);
+        let table = self.table.read().unwrap();
+        if let Some((&EVENTS_FILE, dir)) = parts.split_last()
+            && self.events_enabled()
+        {
+            let dir = table.resolve(dir)?;
+            table.children(dir)?;
+            return Some(Found::Events(dir));
         }
-        if !stat.gid.is_empty() {
-            inode.gid = stat.gid.clone();
+        table.resolve(&parts).map(Found::Live)
+    }
+
+    fn is_dump(&self, parts: &[&str]) -> bool {
+        self.dump_enabled.load(Ordering::Acquire) && parts.first() == Some(&dump::DUMP_DIR)
+    }
+
+    /// Whether `name` is taken in the directory `dir` by a synthetic file:
+    /// `/dump` or `/.usage` at the root, `.events` anywhere.
+    fn reserved(&self, dir: u64, name: &str) -> bool {
+        let at_root = dir == ROOT_ID
+            && ((name == dump::DUMP_DIR && self.dump_enabled.load(Ordering::Acquire))
+                || (name == quota::USAGE_FILE && self.usage_enabled.load(Ordering::Acquire)));
+        at_root || (name == EVENTS_FILE && self.events_enabled())
+    }
+
+    /// Sets `flag`, which serves a synthetic `name` at the root, unless
+    /// the tree already has a file of that name.
+    fn reserve_root_name(&self, name: &str, flag: &AtomicBool) -> Option<()> {
+        // Creates hold the table for writing, so none can race the check.
+        let table = self.table.read().unwrap();
+        if table.resolve(&[name]).is_some() {
+            return None;
+        }
+        flag.store(true, Ordering::Release);
+        Some(())
+    }
+
+    /// Id of the live node at `path`, or `None` for paths below the dump
+    /// directory.
+    fn live(&self, table: &Table, path: &str) -> Option<u64> {
+        let parts = components(path);
+        if self.is_dump(&parts) {
+            return None;
         }
+        table.resolve(&parts)
+    }
 
-        if !stat.name.is_empty() && stat.name != filename {
-            // rename
-            if current.children.contains_key(&stat.name) {
-                return None; // exists
+    fn root_state(&self) -> State {
+        let table = self.table.read().unwrap();
+        let root = table.entry(ROOT_ID).unwrap();
+        root.state.read().unwrap().clone()
+    }
+
+    /// Gives back the quota charged for nodes that `Table::detach` dropped.
+    fn uncharge(&self, removed: &[Arc<Entry>]) {
+        let mut quotas = self.quotas.lock().unwrap();
+        for entry in removed {
+            let state = entry.state.read().unwrap();
+            quotas.release(&state.uid, state.data.stored(), 1);
+        }
+    }
+
+    /// Applies a wstat from the host, which may change anything, the owner
+    /// included; see `try_wstat`.
+    pub fn wstat_from_stat(&self, path: &str, stat: &Stat) -> Option<()> {
+        self.try_wstat(path, &stat_change(stat), None).ok()
+    }
+
+    /// Applies `change` whole or not at all. With `uname` the change is a
+    /// client's and must pass `perm::check_wstat`; without, it comes from
+    /// the host, which may also give files away. Either way a new name
+    /// must be free in the same directory and `Wstat::check` must pass.
+    pub fn try_wstat(
+        &self,
+        path: &str,
+        change: &Wstat,
+        uname: Option<&str>,
+    ) -> Result<(), &'static str> {
+        let mut table = self.table.write().unwrap();
+        if self.is_dump(&components(path)) {
+            return Err(READ_ONLY);
+        }
+        let id = self.live(&table, path).ok_or("file not found")?;
+        let entry = table.entry(id).ok_or("file not found")?;
+        let mut state = entry.state.write().unwrap();
+
+        let old = state.to_inode(table.name(id));
+        let new = change.apply(&old);
+        let length = change.new_length(state.data.len());
+        let renamed = change.renames(&old.name);
+        change.check(&old, state.data.len())?;
+
+        if let Some(uname) = uname {
+            let parent_writable = !renamed || parent_writable(self, &table, id, path, uname);
+            let member = |group: &str| self.is_member(path, uname, group);
+            check_wstat(&old, &new, uname, member, length.is_some(), parent_writable)?;
+        }
+        if renamed {
+            let parent = table.parent(id).ok_or("cannot rename the root")?;
+            let reserved = self.reserved(parent, &new.name);
+            let taken = table
+                .children(parent)
+                .is_some_and(|dir| dir.contains_key(&new.name));
+            if reserved || taken {
+                return Err("file exists");
             }
-            inode.name = stat.name.clone();
-            current.children.remove(filename);
-            current.children.insert(inode.name.clone(), inode);
+        }
+
+        // Everything is checked; from here on nothing fails.
+        state.mode = new.mode;
+        state.atime = new.atime;
+        state.mtime = new.mtime;
+        state.gid = new.gid;
+        let mut quotas = self.quotas.lock().unwrap();
+        if new.uid != old.uid {
+            let stored = state.data.stored();
+            quotas.release(&state.uid, stored, 1);
+            quotas.charge(&new.uid, stored, 1);
+            state.uid = new.uid;
+        }
+        if let Some(length) = length {
+            // Growing leaves a hole, so only shrinking changes usage.
+            let before = state.data.stored();
+            state.data.set_len(length);
+            quotas.release(&state.uid, before - state.data.stored(), 0);
+        }
+        drop(quotas);
+        drop(state);
+        if renamed {
+            table.rename(id, &new.name);
+            self.post(table.parent(id), "wstat", &[&old.name, &new.name]);
         } else {
-            *current.children.get_mut(filename).unwrap() = inode;
+            self.post(table.parent(id), "wstat", &[&old.name]);
         }
+        touch(&table.chain(id));
+        Ok(())
+    }
 
-        Some(())
+    /// Adds a file at `path`, replacing whatever was there and creating
+    /// missing directories. Meant for seeding a tree, so quotas are
+    /// charged but not enforced.
+    pub fn create_file(&self, path: &str, data: &[u8]) {
+        let components = components(path);
+        let Some((name, dirs)) = components.split_last() else {
+            return;
+        };
+        let mut table = self.table.write().unwrap();
+        let Some(parent) = self.make_dirs(&mut table, dirs) else {
+            return;
+        };
+        if self.reserved(parent, name) {
+            return;
+        }
+        if let Some(&old) = table.children(parent).and_then(|dir| dir.get(*name)) {
+            let removed = table.detach(old);
+            self.uncharge(&removed);
+        }
+        let (uid, gid) = root_owner(&table);
+        let mut file = State::new(0o644, &uid, &gid);
+        file.data = Contents::from_slice(data);
+        self.quotas
+            .lock()
+            .unwrap()
+            .charge(&file.uid, file.data.stored(), 1);
+        table.insert(parent, name, file);
+        self.post(Some(parent), "create", &[name]);
+        touch(&table.chain(parent));
     }
 
-    pub fn create_file(&mut self, path: &str, data: &[u8]) {
-        let mut current = &mut self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        for (i, component) in components.iter().enumerate() {
-            if i == components.len() - 1 {
-                let mut file = Inode::new(component, 0o644, "user", "group");
-                file.data = data.to_vec();
-                current.children.insert(component.to_string(), file);
-            } else {
-                current = current
-                    .children
-                    .entry(component.to_string())
-                    .or_insert_with(|| Inode::new(component, 0o755 | 0x80000000, "user", "group"));
+    /// Walks `dirs` from the root, creating any that are missing. Fails if
+    /// one of them is a file.
+    fn make_dirs(&self, table: &mut Table, dirs: &[&str]) -> Option<u64> {
+        let (uid, gid) = root_owner(table);
+        let mut current = ROOT_ID;
+        for dir in dirs {
+            current = match table.children(current)?.get(*dir) {
+                Some(&id) => id,
+                None if self.reserved(current, dir) => return None,
+                None => {
+                    let state = State::new(0o755 | DMDIR, &uid, &gid);
+                    self.quotas.lock().unwrap().charge(&state.uid, 0, 1);
+                    let id = table.insert(current, dir, state);
+                    self.post(Some(current), "create", &[dir]);
+                    id
+                }
+            };
+        }
+        Some(current)
+    }
+
+    /// Contents of the file at `path`; directories read as empty, and an
+    /// event file as the events it still holds. Files longer than
+    /// `WHOLE_READ_LIMIT` are not read whole; `read_at` reads them a piece
+    /// at a time.
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        self.try_read_file(path).ok()
+    }
+
+    /// Like `read_file`, but says why a read failed.
+    pub fn try_read_file(&self, path: &str) -> Result<Vec<u8>, &'static str> {
+        if let Some(Found::Events(dir)) = self.find(path) {
+            return self.with_log(dir, Log::retained).ok_or("file not found");
+        }
+        let (_, length) = self.metadata(path).ok_or("file not found")?;
+        if length > WHOLE_READ_LIMIT {
+            return Err(TOO_LARGE);
+        }
+        // A write may grow the file between the two looks; read one byte
+        // past the limit to notice.
+        let data = self.try_read_at(path, 0, WHOLE_READ_LIMIT as usize + 1)?;
+        if data.len() as u64 > WHOLE_READ_LIMIT {
+            return Err(TOO_LARGE);
+        }
+        Ok(data)
+    }
+
+    /// Up to `count` bytes of the file at `path`, starting at `offset`.
+    /// Only those bytes are copied, under the file's own read lock.
+    pub fn read_at(&self, path: &str, offset: u64, count: usize) -> Option<Vec<u8>> {
+        self.try_read_at(path, offset, count).ok()
+    }
+
+    /// Like `read_at`, but says why a read failed.
+    pub fn try_read_at(
+        &self,
+        path: &str,
+        offset: u64,
+        count: usize,
+    ) -> Result<Vec<u8>, &'static str> {
+        const NOT_FOUND: &str = "file not found";
+        match self.find(path).ok_or(NOT_FOUND)? {
+            Found::Live(id) => {
+                let entry = self.table.read().unwrap().entry(id).ok_or(NOT_FOUND)?;
+                let state = entry.state.read().unwrap();
+                Ok(state.data.read(offset, count))
+            }
+            Found::Frozen(node) => Ok(node.data.read(offset, count)),
+            Found::Usage => {
+                let report = self.usage_report().into_bytes();
+                let start = usize::try_from(offset)
+                    .unwrap_or(usize::MAX)
+                    .min(report.len());
+                let end = start.saturating_add(count).min(report.len());
+                Ok(report[start..end].to_vec())
             }
+            Found::Events(dir) => self
+                .with_log(dir, |log| log.read(offset, count))
+                .ok_or(NOT_FOUND)?,
+            Found::DumpDir { .. } | Found::DumpCtl => Ok(Vec::new()),
+        }
+    }
+
+    /// A wait for the file at `path` to have something to read at
+    /// `offset`, or `None` if a read would not block. Only event files
+    /// ever block.
+    pub fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        match self.find(path)? {
+            Found::Events(dir) => self.with_log(dir, |log| log.wait_at(offset))?,
+            _ => None,
         }
     }
 
-    pub fn read_file(&self, path: &str) -> Option<&[u8]> {
-        let mut current = &self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        for (i, component) in components.iter().enumerate() {
-            if let Some(node) = current.children.get(*component) {
-                if i == components.len() - 1 {
-                    return Some(&node.data);
+    pub fn list_dir(&self, path: &str) -> Option<Vec<String>> {
+        match self.find(path)? {
+            Found::Live(id) => {
+                let table = self.table.read().unwrap();
+                let mut entries: Vec<String> = table
+                    .children(id)
+                    .map(|dir| dir.keys().cloned().collect())
+                    .unwrap_or_default();
+                if table.children(id).is_some() {
+                    for name in [dump::DUMP_DIR, quota::USAGE_FILE, EVENTS_FILE] {
+                        if self.reserved(id, name) {
+                            entries.push(name.to_string());
+                        }
+                    }
+                    entries.sort();
                 }
-                current = node;
-            } else {
-                return None;
+                Some(entries)
             }
+            Found::Frozen(node) => Some(node.names()),
+            Found::DumpDir { entries, .. } => Some(entries),
+            Found::DumpCtl | Found::Usage | Found::Events(_) => Some(Vec::new()),
         }
-        None
     }
 
-    pub fn list_dir(&self, path: &str) -> Option<Vec<String>> {
-        let mut current = &self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        for component in components {
-            if let Some(node) = current.children.get(component) {
-                current = node;
-            } else {
+    /// Like `stat`, but leaves the contents out and returns their length,
+    /// so walking past a large file does not copy it.
+    pub fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        let metadata = match self.find(path)? {
+            Found::Live(id) => {
+                let table = self.table.read().unwrap();
+                let entry = table.entry(id)?;
+                let state = entry.state.read().unwrap();
+                (state.to_inode(table.name(id)), state.data.len())
+            }
+            Found::Frozen(node) => {
+                let mut inode = node.to_inode();
+                inode.mode &= !0o222;
+                (inode, node.data.len())
+            }
+            Found::DumpDir { name, mtime, .. } => {
+                let root = self.root_state();
+                let mut inode = Inode::new(&name, 0o555 | DMDIR, &root.uid, &root.gid);
+                inode.atime = mtime;
+                inode.mtime = mtime;
+                (inode, 0)
+            }
+            Found::DumpCtl => {
+                let root = self.root_state();
+                (Inode::new("ctl", 0o660, &root.uid, &root.gid), 0)
+            }
+            Found::Usage => {
+                let root = self.root_state();
+                let inode = Inode::new(quota::USAGE_FILE, 0o444, &root.uid, &root.gid);
+                (inode, self.usage_report().len() as u64)
+            }
+            Found::Events(dir) => {
+                let owner = {
+                    let entry = self.table.read().unwrap().entry(dir)?;
+                    let state = entry.state.read().unwrap();
+                    (state.uid.clone(), state.gid.clone())
+                };
+                let (started, end) = self.with_log(dir, |log| (log.started(), log.end()))?;
+                let mut inode = Inode::new(EVENTS_FILE, 0o444, &owner.0, &owner.1);
+                inode.atime = started;
+                inode.mtime = started;
+                (inode, end)
+            }
+        };
+        Some(metadata)
+    }
+
+    /// Empties the file at `path` on behalf of `uname`, as opening it with
+    /// OTRUNC does.
+    pub fn truncate(&self, path: &str, uname: &str) -> Option<()> {
+        let table = self.table.read().unwrap();
+        let id = self.live(&table, path)?;
+        if id == ROOT_ID {
+            return None;
+        }
+        let entry = table.entry(id)?;
+        {
+            let mut state = entry.state.write().unwrap();
+            if state.is_dir() {
                 return None;
             }
+            self.empty(&mut state, uname);
         }
-        let mut entries: Vec<String> = current.children.keys().cloned().collect();
-        entries.sort();
-        Some(entries)
+        self.post(table.parent(id), "write", &[table.name(id)]);
+        touch(&table.chain(id));
+        Some(())
     }
 
-    pub fn create_dir(&mut self, path: &str) {
-        let mut current = &mut self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        for (i, component) in components.iter().enumerate() {
-            if i == components.len() - 1 {
-                current
-                    .children
-                    .entry(component.to_string())
-                    .or_insert_with(|| Inode::new(component, 0o755 | 0x80000000, "user", "group"));
+    fn empty(&self, state: &mut State, uname: &str) {
+        let freed = state.data.stored();
+        self.quotas.lock().unwrap().release(&state.uid, freed, 0);
+        state.data = Contents::default();
+        state.mtime = current_timestamp();
+        state.muid = uname.to_string();
+    }
+
+    pub fn create_dir(&self, path: &str) {
+        let mut table = self.table.write().unwrap();
+        if let Some(id) = self.make_dirs(&mut table, &components(path)) {
+            touch(&table.chain(id));
+        }
+    }
+
+    /// `FsServer::write`, callable through a shared reference.
+    pub fn write(&self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.try_write(path, offset, data, uname).ok()
+    }
+
+    /// Like `write`, but says why a write failed. Holds the table only for
+    /// reading, so writes to different files run in parallel.
+    pub fn try_write(
+        &self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        uname: &str,
+    ) -> Result<u32, &'static str> {
+        const FAILED: &str = "write failed";
+        if let Some(Found::DumpCtl) = self.find(path) {
+            return self.dump_ctl(data).ok_or(FAILED);
+        }
+        let table = self.table.read().unwrap();
+        let id = self.live(&table, path).ok_or(FAILED)?;
+        if id == ROOT_ID {
+            return Err(FAILED);
+        }
+        let entry = table.entry(id).ok_or(FAILED)?;
+        {
+            let mut state = entry.state.write().unwrap();
+            // Append-only files ignore the offset.
+            let start = if state.mode & DMAPPEND != 0 {
+                state.data.len()
             } else {
-                current = current
-                    .children
-                    .entry(component.to_string())
-                    .or_insert_with(|| Inode::new(component, 0o755 | 0x80000000, "user", "group"));
+                offset
+            };
+            if start.checked_add(data.len() as u64).is_none() {
+                return Err(FAILED);
             }
+            // Only the blocks written take memory, so a write far past the
+            // end is charged for what it writes, not for the hole.
+            let growth = state.data.growth(start, data.len() as u64);
+            self.quotas.lock().unwrap().reserve(&state.uid, growth, 0)?;
+            state.data.write(start, data);
+            state.mtime = current_timestamp();
+            state.muid = uname.to_string();
         }
+        self.post(table.parent(id), "write", &[table.name(id)]);
+        touch(&table.chain(id));
+        Ok(data.len() as u32)
+    }
+
+    /// `FsServer::remove`, callable through a shared reference.
+    pub fn remove(&self, path: &str) -> Option<()> {
+        let mut table = self.table.write().unwrap();
+        let id = self.live(&table, path)?;
+        let parent = table.parent(id)?;
+        let name = table.name(id).to_string();
+        let removed = table.detach(id);
+        self.uncharge(&removed);
+        self.post(Some(parent), "remove", &[&name]);
+        self.forget_logs(&table);
+        touch(&table.chain(parent));
+        Some(())
+    }
+
+    /// `FsServer::create`, callable through a shared reference.
+    pub fn create(
+        &self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.try_create(parent, name, perm, mode, uname).ok()
+    }
+
+    /// Like `create`, but says why a create failed.
+    pub fn try_create(
+        &self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Result<(), &'static str> {
+        const FAILED: &str = "create failed";
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(FAILED);
+        }
+        let mut table = self.table.write().unwrap();
+        let dir = self.live(&table, parent).ok_or(FAILED)?;
+        if self.reserved(dir, name) {
+            return Err(FAILED);
+        }
+        let dir_entry = table.entry(dir).ok_or(FAILED)?;
+        let (dir_mode, dir_gid) = {
+            let state = dir_entry.state.read().unwrap();
+            if !state.is_dir() {
+                return Err(FAILED);
+            }
+            (state.mode, state.gid.clone())
+        };
+
+        if let Some(&existing) = table.children(dir).and_then(|dir| dir.get(name)) {
+            let entry = table.entry(existing).ok_or(FAILED)?;
+            {
+                let mut state = entry.state.write().unwrap();
+                if mode & OEXCL != 0 || perm & DMDIR != 0 || state.is_dir() {
+                    return Err(FAILED);
+                }
+                self.empty(&mut state, uname);
+            }
+            self.post(Some(dir), "create", &[name]);
+            touch(&table.chain(existing));
+            return Ok(());
+        }
+
+        self.quotas.lock().unwrap().reserve(uname, 0, 1)?;
+        let state = State::new(create_perm(perm, dir_mode), uname, &dir_gid);
+        table.insert(dir, name, state);
+        self.post(Some(dir), "create", &[name]);
+        dir_entry.state.write().unwrap().mtime = current_timestamp();
+        touch(&table.chain(dir));
+        Ok(())
     }
 }
 
-impl FsServer for RamFs {
+/// The runtime in `planten_9p::server` serves RAMFS through shared
+/// references, so requests from different connections run in parallel.
+impl Shared for RamFs {
+    type Fs<'a> = &'a RamFs;
+
+    fn lock(&self) -> impl DerefMut<Target = &RamFs> {
+        Box::new(self)
+    }
+}
+
+/// Failures leave their reason with `werrstr` for the runtime to send.
+impl FsServer for &RamFs {
     fn walk(&self, path: &str) -> Option<Vec<String>> {
         self.list_dir(path)
     }
 
     fn open(&self, path: &str) -> Option<()> {
-        self.read_file(path).map(|_| ())
+        self.metadata(path).map(|_| ())
     }
 
     fn read(&self, path: &str) -> Option<Vec<u8>> {
-        self.read_file(path).map(|data| data.to_vec())
+        self.try_read_file(path).map_err(werrstr).ok()
     }
 
-    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> Option<u32> {
-        let mut current = &mut self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        let (filename, path_parts) = components.split_last()?;
-        let filename = *filename;
-
-        for part in path_parts {
-            current = current.children.get_mut(*part)?;
-        }
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        RamFs::read_wait(self, path, offset)
+    }
 
-        let node = current.children.get_mut(filename)?;
-        let start = offset as usize;
-        let end = start + data.len();
-        if end > node.data.len() {
-            node.data.resize(end, 0);
-        }
-        node.data[start..end].copy_from_slice(data);
-        node.mtime = current_timestamp();
-        Some(data.len() as u32)
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        self.try_write(path, offset, data, uname)
+            .map_err(werrstr)
+            .ok()
     }
 
     fn clunk(&self, _path: &str) -> Option<()> {
@@ -171,36 +685,147 @@ impl FsServer for RamFs {
     }
 
     fn remove(&mut self, path: &str) -> Option<()> {
-        let mut current = &mut self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        for (i, component) in components.iter().enumerate() {
-            if i == components.len() - 1 {
-                return current.children.remove(*component).map(|_| ());
-            }
-            if let Some(node) = current.children.get_mut(*component) {
-                current = node;
-            } else {
-                return None;
-            }
-        }
-        None
+        RamFs::remove(self, path)
     }
 
+    /// Leaves the contents out, however large the file: a sparse file
+    /// may be far longer than memory. `read_at` reads them.
     fn stat(&self, path: &str) -> Option<Inode> {
-        let mut current = &self.root;
-        let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-        for component in components {
-            if let Some(node) = current.children.get(component) {
-                current = node;
-            } else {
-                return None;
-            }
+        self.metadata(path).map(|(inode, _)| inode)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        RamFs::metadata(self, path)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.try_read_at(path, offset, count as usize)
+            .map_err(werrstr)
+            .ok()
+    }
+
+    /// Runtimes check the client's permissions first, so the change is
+    /// applied as the host's.
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        self.try_wstat(path, change, None).map_err(werrstr).ok()
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        self.try_create(parent, name, perm, mode, uname)
+            .map_err(werrstr)
+            .ok()
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        RamFs::truncate(self, path, uname)
+    }
+
+    /// Only live files can be held; dumps are read-only anyway.
+    fn claim_exclusive(&self, path: &str) -> bool {
+        match self.node_id(path) {
+            Some(id) => self.exclusive.lock().unwrap().insert(id),
+            None => true,
         }
-        Some(current.clone())
     }
 
-    fn wstat(&mut self, _path: &str, _inode: Inode) -> Option<()> {
-        Some(())
+    fn release_exclusive(&self, path: &str) {
+        if let Some(id) = self.node_id(path) {
+            self.exclusive.lock().unwrap().remove(&id);
+        }
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        RamFs::node_id(self, path)
+    }
+
+    fn node_path(&self, id: u64) -> Option<String> {
+        RamFs::node_path(self, id)
+    }
+}
+
+/// An owned RAMFS, for mounting in a `MuxFs` or wrapping in middleware,
+/// is served the same way.
+impl FsServer for RamFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        FsServer::walk(&self, path)
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        FsServer::open(&self, path)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        FsServer::read(&self, path)
+    }
+
+    fn read_wait(&self, path: &str, offset: u64) -> Option<ReadWait> {
+        FsServer::read_wait(&self, path, offset)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8], uname: &str) -> Option<u32> {
+        FsServer::write(&mut &*self, path, offset, data, uname)
+    }
+
+    fn clunk(&self, path: &str) -> Option<()> {
+        FsServer::clunk(&self, path)
+    }
+
+    fn remove(&mut self, path: &str) -> Option<()> {
+        FsServer::remove(&mut &*self, path)
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        FsServer::stat(&self, path)
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        FsServer::metadata(&self, path)
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        FsServer::read_at(&self, path, offset, count)
+    }
+
+    fn wstat(&mut self, path: &str, change: &Wstat) -> Option<()> {
+        FsServer::wstat(&mut &*self, path, change)
+    }
+
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        perm: u32,
+        mode: u32,
+        uname: &str,
+    ) -> Option<()> {
+        FsServer::create(&mut &*self, parent, name, perm, mode, uname)
+    }
+
+    fn truncate(&mut self, path: &str, uname: &str) -> Option<()> {
+        FsServer::truncate(&mut &*self, path, uname)
+    }
+
+    fn claim_exclusive(&self, path: &str) -> bool {
+        FsServer::claim_exclusive(&self, path)
+    }
+
+    fn release_exclusive(&self, path: &str) {
+        FsServer::release_exclusive(&self, path)
+    }
+
+    fn node_id(&self, path: &str) -> Option<u64> {
+        FsServer::node_id(&self, path)
+    }
+
+    fn node_path(&self, id: u64) -> Option<String> {
+        FsServer::node_path(&self, id)
     }
 }
 
@@ -211,4 +836,11 @@ fn current_timestamp() -> u32 {
         .as_secs() as u32
 }
 
-pub mod server;
+mod contents;
+pub mod dump;
+pub mod events;
+mod node;
+pub mod quota;
+pub mod seed;
+pub mod snapshot;
+mod table;
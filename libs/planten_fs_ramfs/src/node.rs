@@ -0,0 +1,57 @@
+//! Frozen copies of the RAMFS tree, as served under `/dump` and written
+//! to snapshots.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use planten_fs_core::{DMDIR, Inode};
+
+use crate::contents::Contents;
+
+/// One file or directory as it was when frozen. File contents are shared
+/// with the live tree until it next writes them, and a subtree that has
+/// not changed between two dumps is shared by both.
+#[derive(Clone, Debug)]
+pub(crate) struct Node {
+    pub name: String,
+    pub data: Contents,
+    pub children: HashMap<String, Arc<Node>>,
+    pub mode: u32,
+    pub uid: String,
+    pub gid: String,
+    pub atime: u32,
+    pub mtime: u32,
+    pub muid: String,
+}
+
+impl Node {
+    pub fn is_dir(&self) -> bool {
+        self.mode & DMDIR != 0
+    }
+
+    /// The node's metadata; children are listed through `walk` and the
+    /// contents read separately.
+    pub fn to_inode(&self) -> Inode {
+        Inode {
+            name: self.name.clone(),
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode: self.mode,
+            uid: self.uid.clone(),
+            gid: self.gid.clone(),
+            atime: self.atime,
+            mtime: self.mtime,
+            muid: self.muid.clone(),
+        }
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Arc<Node>> {
+        self.children.get(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.children.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
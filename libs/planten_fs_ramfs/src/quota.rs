@@ -0,0 +1,148 @@
+//! Byte and inode quotas, for the whole server and for each user.
+//!
+//! Every node but the root is charged to its owner: one inode, plus the
+//! bytes its contents actually hold, so holes in sparse files are free.
+//! Dumps share what they freeze with the live tree and are not charged.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::RamFs;
+
+/// Name of the usage file served at the root once `enable_usage` is on.
+pub const USAGE_FILE: &str = ".usage";
+/// Error returned when a write or create would exceed a quota.
+pub const NO_SPACE: &str = "no space";
+
+/// `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+impl Usage {
+    fn fits(&self, limits: &Limits, bytes: u64, inodes: u64) -> bool {
+        let within = |used: u64, extra: u64, limit: Option<u64>| {
+            extra == 0 || limit.is_none_or(|limit| used.saturating_add(extra) <= limit)
+        };
+        within(self.bytes, bytes, limits.bytes) && within(self.inodes, inodes, limits.inodes)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Quotas {
+    server: Limits,
+    /// Applies to every user without an entry in `users`.
+    user_default: Limits,
+    users: HashMap<String, Limits>,
+    total: Usage,
+    by_user: BTreeMap<String, Usage>,
+}
+
+impl Quotas {
+    fn user_limits(&self, user: &str) -> Limits {
+        self.users.get(user).copied().unwrap_or(self.user_default)
+    }
+
+    /// Charges `user` for `bytes` and `inodes` more if that stays within
+    /// both the server's and the user's limits.
+    pub fn reserve(&mut self, user: &str, bytes: u64, inodes: u64) -> Result<(), &'static str> {
+        let used = self.by_user.get(user).copied().unwrap_or_default();
+        if !self.total.fits(&self.server, bytes, inodes)
+            || !used.fits(&self.user_limits(user), bytes, inodes)
+        {
+            return Err(NO_SPACE);
+        }
+        self.charge(user, bytes, inodes);
+        Ok(())
+    }
+
+    /// Charges without checking, for trees loaded or seeded by the host.
+    pub fn charge(&mut self, user: &str, bytes: u64, inodes: u64) {
+        self.total.bytes += bytes;
+        self.total.inodes += inodes;
+        let used = self.by_user.entry(user.to_string()).or_default();
+        used.bytes += bytes;
+        used.inodes += inodes;
+    }
+
+    pub fn release(&mut self, user: &str, bytes: u64, inodes: u64) {
+        self.total.bytes = self.total.bytes.saturating_sub(bytes);
+        self.total.inodes = self.total.inodes.saturating_sub(inodes);
+        if let Some(used) = self.by_user.get_mut(user) {
+            used.bytes = used.bytes.saturating_sub(bytes);
+            used.inodes = used.inodes.saturating_sub(inodes);
+            if *used == Usage::default() {
+                self.by_user.remove(user);
+            }
+        }
+    }
+
+    /// One line per user after a `total` line: the name, bytes used and
+    /// allowed, then inodes used and allowed, with `-` for no limit.
+    pub fn report(&self) -> String {
+        fn line(out: &mut String, who: &str, used: &Usage, limits: &Limits) {
+            let limit = |limit: Option<u64>| limit.map_or("-".to_string(), |n| n.to_string());
+            let _ = writeln!(
+                out,
+                "{} {} {} {} {}",
+                who,
+                used.bytes,
+                limit(limits.bytes),
+                used.inodes,
+                limit(limits.inodes)
+            );
+        }
+        let mut out = String::new();
+        line(&mut out, "total", &self.total, &self.server);
+        for (user, used) in &self.by_user {
+            line(&mut out, user, used, &self.user_limits(user));
+        }
+        out
+    }
+}
+
+impl RamFs {
+    /// Limits for the server as a whole.
+    pub fn set_server_limits(&self, limits: Limits) {
+        self.quotas.lock().unwrap().server = limits;
+    }
+
+    /// Limits for `user`, or with `None` for every user not given limits
+    /// of their own.
+    pub fn set_user_limits(&self, user: Option<&str>, limits: Limits) {
+        let mut quotas = self.quotas.lock().unwrap();
+        match user {
+            Some(user) => {
+                quotas.users.insert(user.to_string(), limits);
+            }
+            None => quotas.user_default = limits,
+        }
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.quotas.lock().unwrap().total
+    }
+
+    pub fn user_usage(&self, user: &str) -> Usage {
+        let quotas = self.quotas.lock().unwrap();
+        quotas.by_user.get(user).copied().unwrap_or_default()
+    }
+
+    /// Serves the usage report as a read-only `/.usage`. Fails if the
+    /// tree already has a file of that name.
+    pub fn enable_usage(&self) -> Option<()> {
+        self.reserve_root_name(USAGE_FILE, &self.usage_enabled)
+    }
+
+    pub(crate) fn usage_report(&self) -> String {
+        self.quotas.lock().unwrap().report()
+    }
+}
@@ -0,0 +1,294 @@
+//! Seeding a `RamFs` tree from the host, and exporting it as a tar archive.
+//!
+//! A host directory is copied recursively with each entry's permission
+//! bits and mtime; everything in it is owned by the user and group given.
+//! Tar archives load the same way, except that entries naming an owner
+//! keep it. Symbolic links, devices and the like are skipped, while hard
+//! links in an archive become copies of the file they name. `write_tar`
+//! produces an archive that loads back into the same tree, and all-zero
+//! blocks of loaded files are left as holes.
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use planten_fs_core::{DMDIR, Inode};
+use tar::{Archive, Builder, EntryType, Header};
+
+use crate::RamFs;
+use crate::contents::Contents;
+use crate::node::Node;
+
+impl RamFs {
+    /// A tree holding a copy of the host directory `dir`.
+    pub fn from_dir(dir: impl AsRef<Path>, uid: &str, gid: &str) -> io::Result<RamFs> {
+        let dir = dir.as_ref();
+        let meta = fs::metadata(dir)?;
+        if !meta.is_dir() {
+            return Err(invalid(&format!("{} is not a directory", dir.display())));
+        }
+        let mut root = host_node("/", &meta, uid, gid);
+        read_host_dir(dir, &mut root, uid, gid)?;
+        Ok(RamFs::from_tree(&root))
+    }
+
+    pub fn load_tar(path: impl AsRef<Path>, uid: &str, gid: &str) -> io::Result<RamFs> {
+        Self::read_tar(BufReader::new(File::open(path)?), uid, gid)
+    }
+
+    /// A tree holding the contents of a tar archive. `uid` and `gid` own
+    /// the entries that name no owner and the directories the archive
+    /// implies without listing.
+    pub fn read_tar<R: Read>(reader: R, uid: &str, gid: &str) -> io::Result<RamFs> {
+        let mut root = directory("/", 0o755, uid, gid);
+        for entry in Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let names = parts(&path)?;
+            let header = entry.header();
+            let kind = header.entry_type();
+            let owner = |name: Option<&str>, default: &str| {
+                name.filter(|name| !name.is_empty())
+                    .unwrap_or(default)
+                    .to_string()
+            };
+            let mtime = header.mtime()? as u32;
+            let mut node = Node {
+                name: names.last().map_or("/", String::as_str).to_string(),
+                data: Contents::default(),
+                children: Default::default(),
+                mode: header.mode()? & 0o777,
+                uid: owner(header.username().ok().flatten(), uid),
+                gid: owner(header.groupname().ok().flatten(), gid),
+                atime: mtime,
+                mtime,
+                muid: String::new(),
+            };
+            node.muid = node.uid.clone();
+            match kind {
+                EntryType::Directory => node.mode |= DMDIR,
+                EntryType::Link => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| invalid("hard link without a target"))?;
+                    node.data = lookup(&root, &parts(&target)?)
+                        .filter(|target| !target.is_dir())
+                        .ok_or_else(|| {
+                            invalid(&format!("{} links to a missing file", path.display()))
+                        })?
+                        .data
+                        .clone();
+                }
+                kind if kind.is_file() => node.data = Contents::read_from(&mut entry)?,
+                _ => continue,
+            }
+            insert(&mut root, &names, node, uid, gid)?;
+        }
+        Ok(RamFs::from_tree(&root))
+    }
+
+    /// Writes the tree to `path` as a tar archive, replacing it only once
+    /// the archive is complete.
+    pub fn save_tar(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        {
+            let writer = self.write_tar(BufWriter::new(File::create(&partial)?))?;
+            writer
+                .into_inner()
+                .map_err(|err| err.into_error())?
+                .sync_all()?;
+        }
+        fs::rename(&partial, path)
+    }
+
+    /// Writes the tree as a tar archive, root first and then each
+    /// directory before its children in name order. Dumps and `/.usage`
+    /// are not part of the tree and are left out.
+    pub fn write_tar<W: Write>(&self, writer: W) -> io::Result<W> {
+        let mut builder = Builder::new(writer);
+        write_entry(&mut builder, Path::new("."), &self.freeze())?;
+        builder.into_inner()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn directory(name: &str, perm: u32, uid: &str, gid: &str) -> Node {
+    let inode = Inode::new(name, perm | DMDIR, uid, gid);
+    Node {
+        name: inode.name,
+        data: Contents::default(),
+        children: Default::default(),
+        mode: inode.mode,
+        uid: inode.uid,
+        gid: inode.gid,
+        atime: inode.atime,
+        mtime: inode.mtime,
+        muid: inode.muid,
+    }
+}
+
+fn host_node(name: &str, meta: &Metadata, uid: &str, gid: &str) -> Node {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs() as u32);
+    let mut node = directory(name, permissions(meta), uid, gid);
+    if !meta.is_dir() {
+        node.mode &= !DMDIR;
+    }
+    node.atime = mtime;
+    node.mtime = mtime;
+    node
+}
+
+#[cfg(unix)]
+fn permissions(meta: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn permissions(meta: &Metadata) -> u32 {
+    match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, false) => 0o755,
+        (true, true) => 0o555,
+        (false, false) => 0o644,
+        (false, true) => 0o444,
+    }
+}
+
+fn read_host_dir(dir: &Path, node: &mut Node, uid: &str, gid: &str) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| invalid(&format!("{} is not UTF-8", dir.join(name).display())))?;
+        let kind = entry.file_type()?;
+        if !kind.is_dir() && !kind.is_file() {
+            continue;
+        }
+        let mut child = host_node(&name, &entry.metadata()?, uid, gid);
+        if kind.is_dir() {
+            read_host_dir(&entry.path(), &mut child, uid, gid)?;
+        } else {
+            child.data = Contents::read_from(File::open(entry.path())?)?;
+        }
+        node.children.insert(name, Arc::new(child));
+    }
+    Ok(())
+}
+
+/// The names along an archive path, which must stay inside the tree.
+fn parts(path: &Path) -> io::Result<Vec<String>> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => parts.push(
+                name.to_str()
+                    .ok_or_else(|| invalid(&format!("{} is not UTF-8", path.display())))?
+                    .to_string(),
+            ),
+            Component::CurDir | Component::RootDir => {}
+            _ => return Err(invalid(&format!("{} leaves the tree", path.display()))),
+        }
+    }
+    Ok(parts)
+}
+
+fn lookup<'a>(root: &'a Node, parts: &[String]) -> Option<&'a Node> {
+    parts
+        .iter()
+        .try_fold(root, |node, name| node.child(name).map(|child| &**child))
+}
+
+/// Puts `node` at `parts`, creating missing directories on the way. A
+/// directory that is already there keeps its children and takes the
+/// new metadata; anything else is replaced.
+fn insert(
+    root: &mut Node,
+    parts: &[String],
+    mut node: Node,
+    uid: &str,
+    gid: &str,
+) -> io::Result<()> {
+    let Some((name, dirs)) = parts.split_last() else {
+        if !node.is_dir() {
+            return Err(invalid("archive root is not a directory"));
+        }
+        node.children = std::mem::take(&mut root.children);
+        *root = node;
+        return Ok(());
+    };
+    let mut parent = root;
+    for dir in dirs {
+        let child = parent
+            .children
+            .entry(dir.clone())
+            .or_insert_with(|| Arc::new(directory(dir, 0o755, uid, gid)));
+        parent = Arc::make_mut(child);
+        if !parent.is_dir() {
+            return Err(invalid(&format!("{} is not a directory", dir)));
+        }
+    }
+    if let Some(old) = parent.children.get_mut(name)
+        && old.is_dir()
+        && node.is_dir()
+    {
+        node.children = std::mem::take(&mut Arc::make_mut(old).children);
+    }
+    parent.children.insert(name.clone(), Arc::new(node));
+    Ok(())
+}
+
+fn write_entry<W: Write>(builder: &mut Builder<W>, path: &Path, node: &Node) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_mode(node.mode & 0o777);
+    header.set_mtime(node.mtime.into());
+    header.set_uid(0);
+    header.set_gid(0);
+    // Names longer than the header allows are dropped rather than cut.
+    let _ = header.set_username(&node.uid);
+    let _ = header.set_groupname(&node.gid);
+    if node.is_dir() {
+        header.set_entry_type(EntryType::Directory);
+        header.set_size(0);
+        builder.append_data(&mut header, path, io::empty())?;
+        for name in node.names() {
+            write_entry(builder, &path.join(&name), &node.children[&name])?;
+        }
+    } else {
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(node.data.len());
+        let reader = ContentsReader {
+            contents: &node.data,
+            offset: 0,
+        };
+        builder.append_data(&mut header, path, reader)?;
+    }
+    Ok(())
+}
+
+/// Streams a file's contents, holes included, without copying it whole.
+struct ContentsReader<'a> {
+    contents: &'a Contents,
+    offset: u64,
+}
+
+impl Read for ContentsReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = self.contents.read(self.offset, buf.len());
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        self.offset += chunk.len() as u64;
+        Ok(chunk.len())
+    }
+}
@@ -0,0 +1,215 @@
+//! On-disk snapshots of a `RamFs` tree.
+//!
+//! Format, all integers little-endian: the magic `RAMFSNAP`, a `u32`
+//! version, then the root node. A node is its name, mode, uid, gid, muid,
+//! atime, mtime, data and children, where strings are a `u32` length plus
+//! UTF-8 bytes and children are a `u32` count followed by the child nodes
+//! in name order. Data is the file's `u64` length, then a `u32` count of
+//! extents, each a `u64` offset, a `u32` length and that many bytes; the
+//! gaps between extents read as zeros. Version 1 images, which stored data
+//! as a `u64` length plus every byte, still load.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::RamFs;
+use crate::contents::Contents;
+use crate::node::Node;
+
+const MAGIC: &[u8; 8] = b"RAMFSNAP";
+pub const SNAPSHOT_VERSION: u32 = 2;
+/// Longest name or user string accepted when loading.
+const MAX_STRING: u32 = 65535;
+
+impl RamFs {
+    /// Writes the tree to `path`, replacing it only once the new snapshot
+    /// is complete.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save_tree(&self.freeze(), path.as_ref())
+    }
+
+    pub fn load_snapshot(path: impl AsRef<Path>) -> io::Result<RamFs> {
+        Self::read_snapshot(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        write_node(&mut writer, &self.freeze())?;
+        writer.flush()
+    }
+
+    pub fn read_snapshot<R: Read>(mut reader: R) -> io::Result<RamFs> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a RAMFS snapshot"));
+        }
+        let version = read_u32(&mut reader)?;
+        if !(1..=SNAPSHOT_VERSION).contains(&version) {
+            return Err(invalid(&format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+        let root = read_node(&mut reader, version)?;
+        if !root.is_dir() {
+            return Err(invalid("snapshot root is not a directory"));
+        }
+        Ok(RamFs::from_tree(&root))
+    }
+}
+
+/// Saves `ramfs` to `path` every `interval` until the process exits. The
+/// tree is frozen first, so clients keep working while it is written out.
+pub fn save_every(ramfs: Arc<RamFs>, path: PathBuf, interval: Duration) -> JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let root = ramfs.freeze();
+            if let Err(err) = save_tree(&root, &path) {
+                eprintln!("snapshot to {} failed: {}", path.display(), err);
+            }
+        }
+    })
+}
+
+fn save_tree(root: &Node, path: &Path) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    {
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        write_node(&mut writer, root)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+    }
+    fs::rename(&partial, path)
+}
+
+fn write_node<W: Write>(writer: &mut W, inode: &Node) -> io::Result<()> {
+    write_string(writer, &inode.name)?;
+    writer.write_all(&inode.mode.to_le_bytes())?;
+    write_string(writer, &inode.uid)?;
+    write_string(writer, &inode.gid)?;
+    write_string(writer, &inode.muid)?;
+    writer.write_all(&inode.atime.to_le_bytes())?;
+    writer.write_all(&inode.mtime.to_le_bytes())?;
+    writer.write_all(&inode.data.len().to_le_bytes())?;
+    let extents: Vec<(u64, &[u8])> = inode.data.extents().collect();
+    writer.write_all(&(extents.len() as u32).to_le_bytes())?;
+    for (offset, bytes) in extents {
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        writer.write_all(bytes)?;
+    }
+
+    let names = inode.names();
+    writer.write_all(&(names.len() as u32).to_le_bytes())?;
+    for name in names {
+        write_node(writer, &inode.children[&name])?;
+    }
+    Ok(())
+}
+
+fn read_node<R: Read>(reader: &mut R, version: u32) -> io::Result<Node> {
+    let name = read_string(reader)?;
+    let mode = read_u32(reader)?;
+    let uid = read_string(reader)?;
+    let gid = read_string(reader)?;
+    let muid = read_string(reader)?;
+    let atime = read_u32(reader)?;
+    let mtime = read_u32(reader)?;
+    let data = if version == 1 {
+        let len = read_u64(reader)?;
+        Contents::from_slice(&read_bytes(reader, len)?)
+    } else {
+        read_contents(reader)?
+    };
+
+    let count = read_u32(reader)?;
+    let mut children = HashMap::new();
+    for _ in 0..count {
+        let child = read_node(reader, version)?;
+        if child.name.is_empty() || child.name.contains('/') {
+            return Err(invalid("bad file name in snapshot"));
+        }
+        children.insert(child.name.clone(), Arc::new(child));
+    }
+
+    Ok(Node {
+        name,
+        data,
+        children,
+        mode,
+        uid,
+        gid,
+        atime,
+        mtime,
+        muid,
+    })
+}
+
+fn read_contents<R: Read>(reader: &mut R) -> io::Result<Contents> {
+    let len = read_u64(reader)?;
+    let mut contents = Contents::default();
+    for _ in 0..read_u32(reader)? {
+        let offset = read_u64(reader)?;
+        let count = read_u32(reader)?;
+        if offset.checked_add(count.into()).is_none_or(|end| end > len) {
+            return Err(invalid("extent past the end of a file in snapshot"));
+        }
+        contents.write(offset, &read_bytes(reader, count.into())?);
+    }
+    contents.set_len(len);
+    Ok(contents)
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = read_u32(reader)?;
+    if len > MAX_STRING {
+        return Err(invalid("string too long in snapshot"));
+    }
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| invalid("string is not UTF-8"))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,271 @@
+//! The live RAMFS tree: an inode table holding the directory structure,
+//! with each node's metadata and contents behind a lock of its own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use planten_fs_core::{DMDIR, Inode};
+
+use crate::contents::Contents;
+use crate::node::Node;
+
+pub(crate) const ROOT_ID: u64 = 1;
+
+/// What a node's own lock protects.
+#[derive(Clone)]
+pub(crate) struct State {
+    pub mode: u32,
+    pub uid: String,
+    pub gid: String,
+    pub atime: u32,
+    pub mtime: u32,
+    pub muid: String,
+    /// Shares blocks with any dump that froze them.
+    pub data: Contents,
+}
+
+impl State {
+    pub fn new(mode: u32, uid: &str, gid: &str) -> Self {
+        let inode = Inode::new("", mode, uid, gid);
+        State {
+            mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            atime: inode.atime,
+            mtime: inode.mtime,
+            muid: inode.muid,
+            data: Contents::default(),
+        }
+    }
+
+    pub fn from_node(node: &Node) -> Self {
+        State {
+            mode: node.mode,
+            uid: node.uid.clone(),
+            gid: node.gid.clone(),
+            atime: node.atime,
+            mtime: node.mtime,
+            muid: node.muid.clone(),
+            data: node.data.clone(),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & DMDIR != 0
+    }
+
+    /// The node's metadata; callers that want the contents copy them
+    /// separately.
+    pub fn to_inode(&self, name: &str) -> Inode {
+        Inode {
+            name: name.to_string(),
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode: self.mode,
+            uid: self.uid.clone(),
+            gid: self.gid.clone(),
+            atime: self.atime,
+            mtime: self.mtime,
+            muid: self.muid.clone(),
+        }
+    }
+}
+
+pub(crate) struct Entry {
+    pub state: RwLock<State>,
+    /// Bumped whenever the node or anything below it changes.
+    version: AtomicU64,
+    /// The node as last frozen for a dump, and the version it was taken at.
+    frozen: Mutex<Option<(u64, Arc<Node>)>>,
+}
+
+impl Entry {
+    fn new(state: State) -> Self {
+        Entry {
+            state: RwLock::new(state),
+            version: AtomicU64::new(0),
+            frozen: Mutex::new(None),
+        }
+    }
+}
+
+/// Marks a node and its ancestors, as returned by `Table::chain`, changed.
+/// Call it after the change, so a dump racing with it freezes again next
+/// time.
+pub(crate) fn touch(chain: &[Arc<Entry>]) {
+    for entry in chain {
+        entry.version.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+pub(crate) struct Table {
+    entries: HashMap<u64, Arc<Entry>>,
+    /// Parent id and name of every node but the root.
+    links: HashMap<u64, (u64, String)>,
+    /// Children of every directory, by name.
+    dirs: HashMap<u64, BTreeMap<String, u64>>,
+    next_id: u64,
+}
+
+impl Table {
+    pub fn new(root: State) -> Self {
+        let mut table = Table {
+            entries: HashMap::new(),
+            links: HashMap::new(),
+            dirs: HashMap::new(),
+            next_id: ROOT_ID + 1,
+        };
+        table.dirs.insert(ROOT_ID, BTreeMap::new());
+        table.entries.insert(ROOT_ID, Arc::new(Entry::new(root)));
+        table
+    }
+
+    pub fn resolve(&self, parts: &[&str]) -> Option<u64> {
+        let mut id = ROOT_ID;
+        for part in parts {
+            id = *self.dirs.get(&id)?.get(*part)?;
+        }
+        Some(id)
+    }
+
+    pub fn entry(&self, id: u64) -> Option<Arc<Entry>> {
+        self.entries.get(&id).cloned()
+    }
+
+    pub fn name(&self, id: u64) -> &str {
+        self.links.get(&id).map_or("/", |(_, name)| name.as_str())
+    }
+
+    pub fn parent(&self, id: u64) -> Option<u64> {
+        self.links.get(&id).map(|(parent, _)| *parent)
+    }
+
+    pub fn children(&self, id: u64) -> Option<&BTreeMap<String, u64>> {
+        self.dirs.get(&id)
+    }
+
+    pub fn path(&self, id: u64) -> Option<String> {
+        let mut names = Vec::new();
+        let mut current = id;
+        while current != ROOT_ID {
+            let (parent, name) = self.links.get(&current)?;
+            names.push(name.as_str());
+            current = *parent;
+        }
+        names.reverse();
+        Some(format!("/{}", names.join("/")))
+    }
+
+    /// `id` followed by its ancestors up to the root.
+    pub fn chain(&self, id: u64) -> Vec<Arc<Entry>> {
+        let mut chain = Vec::new();
+        let mut current = Some(id);
+        while let Some(id) = current {
+            chain.extend(self.entry(id));
+            current = self.parent(id);
+        }
+        chain
+    }
+
+    /// Adds a node named `name` under the directory `parent`, which must
+    /// not already have one; `detach` it first.
+    pub fn insert(&mut self, parent: u64, name: &str, state: State) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if state.is_dir() {
+            self.dirs.insert(id, BTreeMap::new());
+        }
+        self.entries.insert(id, Arc::new(Entry::new(state)));
+        self.links.insert(id, (parent, name.to_string()));
+        self.dirs
+            .entry(parent)
+            .or_default()
+            .insert(name.to_string(), id);
+        id
+    }
+
+    /// Moves `id` to `name` within its directory.
+    pub fn rename(&mut self, id: u64, name: &str) {
+        let Some((parent, old)) = self.links.get_mut(&id) else {
+            return;
+        };
+        let old = std::mem::replace(old, name.to_string());
+        if let Some(dir) = self.dirs.get_mut(parent) {
+            dir.remove(&old);
+            dir.insert(name.to_string(), id);
+        }
+    }
+
+    /// Unlinks `id` and forgets its whole subtree, so fids still on it see
+    /// it as removed. Returns the forgotten entries.
+    pub fn detach(&mut self, id: u64) -> Vec<Arc<Entry>> {
+        if let Some((parent, name)) = self.links.get(&id)
+            && let Some(dir) = self.dirs.get_mut(parent)
+        {
+            dir.remove(name);
+        }
+        let mut removed = Vec::new();
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            removed.extend(self.entries.remove(&id));
+            self.links.remove(&id);
+            if let Some(children) = self.dirs.remove(&id) {
+                pending.extend(children.into_values());
+            }
+        }
+        removed
+    }
+
+    /// Whether any node, anywhere in the tree, is called `name`.
+    pub fn has_name(&self, name: &str) -> bool {
+        self.links.values().any(|(_, other)| other == name)
+    }
+
+    /// Every node but the root.
+    pub fn nodes(&self) -> impl Iterator<Item = &Arc<Entry>> {
+        self.entries
+            .iter()
+            .filter(|(id, _)| **id != ROOT_ID)
+            .map(|(_, entry)| entry)
+    }
+
+    /// An immutable copy of the subtree at `id`, reusing the copy made for
+    /// an earlier dump wherever nothing has changed since.
+    pub fn freeze(&self, id: u64) -> Arc<Node> {
+        let entry = &self.entries[&id];
+        let version = entry.version.load(Ordering::Acquire);
+        if let Some((frozen_at, node)) = &*entry.frozen.lock().unwrap()
+            && *frozen_at == version
+        {
+            return Arc::clone(node);
+        }
+
+        let state = entry.state.read().unwrap().clone();
+        let mut children = HashMap::new();
+        for (name, child) in self.dirs.get(&id).into_iter().flatten() {
+            children.insert(name.clone(), self.freeze(*child));
+        }
+        let node = Arc::new(Node {
+            name: self.name(id).to_string(),
+            data: state.data,
+            children,
+            mode: state.mode,
+            uid: state.uid,
+            gid: state.gid,
+            atime: state.atime,
+            mtime: state.mtime,
+            muid: state.muid,
+        });
+        *entry.frozen.lock().unwrap() = Some((version, Arc::clone(&node)));
+        node
+    }
+
+    /// Copies `node`'s children, recursively, under the directory `parent`.
+    pub fn thaw(&mut self, parent: u64, node: &Node) {
+        for child in node.children.values() {
+            let id = self.insert(parent, &child.name, State::from_node(child));
+            self.thaw(id, child);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Runs the server binary the way CI does: several instances side by
+//! side, each on an address of its own.
+
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use planten_9p::{
+    RawMessage, build_frame, decode_stat, encode_attach_body, encode_open_body, encode_read_body,
+    encode_stat_body, encode_version_body, encode_walk_body, messages::*,
+};
+
+/// A running server that is killed when dropped.
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    fn start(args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line
+            .trim()
+            .rsplit(' ')
+            .next()
+            .expect("listening line")
+            .to_string();
+        Server { child, addr }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ramfs-cli-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn call<S: Read + Write>(stream: &mut S, msg_type: u8, body: Vec<u8>) -> RawMessage {
+    stream.write_all(&build_frame(msg_type, 1, &body)).unwrap();
+    RawMessage::read_from(stream).unwrap()
+}
+
+fn error(response: &RawMessage) -> String {
+    assert_eq!(response.msg_type, RERROR);
+    String::from_utf8(response.body[2..].to_vec()).unwrap()
+}
+
+/// Negotiates and attaches, returning the msize the server chose.
+fn attach<S: Read + Write>(stream: &mut S) -> u32 {
+    let version = call(stream, TVERSION, encode_version_body(65536, "9P2000"));
+    assert_eq!(version.msg_type, RVERSION);
+    let attach = call(stream, TATTACH, encode_attach_body(1, None, "glenda", ""));
+    assert_eq!(attach.msg_type, RATTACH);
+    u32::from_le_bytes(version.body[..4].try_into().unwrap())
+}
+
+#[cfg(unix)]
+#[test]
+fn config_file_sets_up_a_read_only_unix_server() {
+    use std::os::unix::net::UnixStream;
+
+    let dir = scratch_dir("unix");
+    std::fs::create_dir(dir.join("root")).unwrap();
+    std::fs::write(dir.join("root/motd"), b"fixture").unwrap();
+    let socket = dir.join("ramfs.sock");
+    let config = dir.join("ramfs.conf");
+    std::fs::write(
+        &config,
+        format!(
+            "# fixture server\nlisten = unix:{}\nowner = glenda\ngroup = sys\n\
+             read-only = true\nmsize = 8192\nseed = {}\n",
+            socket.display(),
+            dir.join("root").display()
+        ),
+    )
+    .unwrap();
+    let server = Server::start(&["--config", config.to_str().unwrap()]);
+    assert_eq!(server.addr, format!("unix:{}", socket.display()));
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    assert_eq!(attach(&mut stream), 8192);
+    call(&mut stream, TWALK, encode_walk_body(1, 2, &["motd"]));
+    let stat = call(&mut stream, TSTAT, encode_stat_body(2));
+    let stat = decode_stat(&mut Cursor::new(stat.body.as_slice())).unwrap();
+    assert_eq!((stat.uid.as_str(), stat.gid.as_str()), ("glenda", "sys"));
+
+    let refused = call(&mut stream, TOPEN, encode_open_body(2, 1));
+    assert_eq!(error(&refused), "read-only file system");
+    assert_eq!(
+        call(&mut stream, TOPEN, encode_open_body(2, 0)).msg_type,
+        ROPEN
+    );
+    let read = call(&mut stream, TREAD, encode_read_body(2, 0, 100));
+    assert_eq!(&read.body[4..], b"fixture");
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn instances_run_side_by_side_with_connection_limits() {
+    let one = Server::start(&["--listen", "127.0.0.1:0", "--max-connections", "1"]);
+    let two = Server::start(&["--listen", "127.0.0.1:0"]);
+    assert_ne!(one.addr, two.addr);
+
+    let mut first = TcpStream::connect(&one.addr).unwrap();
+    assert_eq!(attach(&mut first), 65536);
+
+    // The second connection is closed without a reply.
+    let mut second = TcpStream::connect(&one.addr).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let _ = second.write_all(&build_frame(
+        TVERSION,
+        0,
+        &encode_version_body(65536, "9P2000"),
+    ));
+    assert!(RawMessage::read_from(&mut second).is_err());
+
+    // Once the first hangs up there is room again.
+    drop(first);
+    let mut third = None;
+    for _ in 0..50 {
+        std::thread::sleep(Duration::from_millis(20));
+        let mut stream = TcpStream::connect(&one.addr).unwrap();
+        stream
+            .write_all(&build_frame(
+                TVERSION,
+                0,
+                &encode_version_body(65536, "9P2000"),
+            ))
+            .unwrap();
+        if RawMessage::read_from(&mut stream).is_ok() {
+            third = Some(stream);
+            break;
+        }
+    }
+    assert!(third.is_some(), "no connection accepted after hangup");
+
+    let mut other = TcpStream::connect(&two.addr).unwrap();
+    attach(&mut other);
+    let walk = call(&mut other, TWALK, encode_walk_body(1, 2, &["hello.txt"]));
+    assert_eq!(walk.msg_type, RWALK);
+}
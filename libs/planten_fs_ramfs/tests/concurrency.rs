@@ -0,0 +1,218 @@
+use std::io::{self, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use planten_9p::server;
+use planten_9p::{
+    RawMessage, build_frame, decode_stat, encode_attach_body, encode_clunk_body,
+    encode_create_body, encode_open_body, encode_read_body, encode_remove_body,
+    encode_version_body, encode_walk_body, encode_write_body, messages::*,
+};
+use planten_fs_ramfs::RamFs;
+
+const CLIENTS: usize = 16;
+const ROUNDS: usize = 25;
+const BIG: usize = 1 << 20;
+
+struct Session {
+    stream: TcpStream,
+    next_tag: u16,
+}
+
+impl Session {
+    fn connect(addr: &str) -> io::Result<Self> {
+        let mut session = Session {
+            stream: TcpStream::connect(addr)?,
+            next_tag: 0,
+        };
+        session.expect(TVERSION, encode_version_body(131072, "9P2000"), RVERSION);
+        session.expect(TATTACH, encode_attach_body(1, None, "user", ""), RATTACH);
+        Ok(session)
+    }
+
+    fn send(&mut self, msg_type: u8, body: Vec<u8>) -> RawMessage {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        self.stream
+            .write_all(&build_frame(msg_type, tag, &body))
+            .unwrap();
+        RawMessage::read_from(&mut self.stream).unwrap()
+    }
+
+    fn expect(&mut self, msg_type: u8, body: Vec<u8>, reply: u8) -> Vec<u8> {
+        let response = self.send(msg_type, body);
+        assert_eq!(response.msg_type, reply, "reply to message {}", msg_type);
+        response.body
+    }
+
+    fn open(&mut self, fid: u32, names: &[&str], mode: u8) {
+        self.expect(TWALK, encode_walk_body(1, fid, names), RWALK);
+        self.expect(TOPEN, encode_open_body(fid, mode), ROPEN);
+    }
+
+    fn read(&mut self, fid: u32, offset: u64, count: u32) -> Vec<u8> {
+        let body = self.expect(TREAD, encode_read_body(fid, offset, count), RREAD);
+        body[4..].to_vec()
+    }
+
+    fn clunk(&mut self, fid: u32) {
+        self.expect(TCLUNK, encode_clunk_body(fid), RCLUNK);
+    }
+
+    fn list(&mut self, fid: u32, names: &[&str]) -> Vec<String> {
+        self.open(fid, names, 0);
+        let bytes = self.read(fid, 0, 65536);
+        self.clunk(fid);
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let mut entries = Vec::new();
+        while (cursor.position() as usize) < bytes.len() {
+            entries.push(decode_stat(&mut cursor).unwrap().name);
+        }
+        entries
+    }
+}
+
+fn serve(ramfs: &Arc<RamFs>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server_ramfs = Arc::clone(ramfs);
+    thread::spawn(move || server::run_server(listener, server_ramfs));
+    addr
+}
+
+/// A 1 MiB file whose every byte is predictable from its offset.
+fn big_file() -> Vec<u8> {
+    (0..BIG).map(|i| (i % 251) as u8).collect()
+}
+
+/// Each client creates, writes and rereads files of its own while reading
+/// slices of one shared large file and listing the directory everyone is
+/// changing.
+#[test]
+fn many_clients_share_one_tree() {
+    let ramfs = Arc::new(RamFs::new());
+    ramfs.create_file("/shared/big.bin", &big_file());
+    let addr = serve(&ramfs);
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|client| {
+            let addr = addr.clone();
+            thread::spawn(move || {
+                let mut session = Session::connect(&addr).unwrap();
+                let big = big_file();
+                for round in 0..ROUNDS {
+                    let name = format!("c{}-{}", client, round);
+                    let contents = format!("client {} round {}", client, round).repeat(50);
+
+                    session.expect(TWALK, encode_walk_body(1, 2, &["shared"]), RWALK);
+                    session.expect(TCREATE, encode_create_body(2, &name, 0o644, 1), RCREATE);
+                    session.clunk(2);
+
+                    session.open(3, &["shared", &name], 2);
+                    for (i, chunk) in contents.as_bytes().chunks(97).enumerate() {
+                        session.expect(
+                            TWRITE,
+                            encode_write_body(3, (i * 97) as u64, chunk),
+                            RWRITE,
+                        );
+                    }
+                    assert_eq!(session.read(3, 0, 65536), contents.as_bytes());
+                    session.clunk(3);
+
+                    session.open(4, &["shared", "big.bin"], 0);
+                    let offset = (client * 65_521 + round * 4099) % (BIG - 8192);
+                    assert_eq!(
+                        session.read(4, offset as u64, 8192),
+                        &big[offset..offset + 8192]
+                    );
+                    session.clunk(4);
+
+                    let listing = session.list(5, &["shared"]);
+                    assert!(listing.contains(&name), "{} missing from listing", name);
+                    assert!(listing.contains(&"big.bin".to_string()));
+
+                    // Every other file is removed again, so removes race
+                    // with the other clients' walks and listings.
+                    if round % 2 == 1 {
+                        session.expect(TWALK, encode_walk_body(1, 6, &["shared", &name]), RWALK);
+                        session.expect(TREMOVE, encode_remove_body(6), RREMOVE);
+                    }
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    let mut expected: Vec<String> = (0..CLIENTS)
+        .flat_map(|client| {
+            (0..ROUNDS)
+                .step_by(2)
+                .map(move |round| format!("c{}-{}", client, round))
+        })
+        .collect();
+    expected.push("big.bin".to_string());
+    expected.sort();
+    assert_eq!(ramfs.list_dir("/shared").unwrap(), expected);
+    for client in 0..CLIENTS {
+        let contents = format!("client {} round {}", client, 0).repeat(50);
+        let path = format!("/shared/c{}-0", client);
+        assert_eq!(ramfs.read_file(&path).unwrap(), contents.as_bytes());
+    }
+    assert_eq!(ramfs.read_file("/shared/big.bin").unwrap(), big_file());
+}
+
+/// Dumps taken while files are being rewritten see each file either
+/// before or after a write, never halfway through one.
+#[test]
+fn dumps_taken_under_load_are_consistent() {
+    let ramfs = Arc::new(RamFs::new());
+    for writer in 0..4 {
+        ramfs.create_file(&format!("/w{}/file", writer), &[0; 4096]);
+    }
+    ramfs.enable_dump().unwrap();
+
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let ramfs = Arc::clone(&ramfs);
+            thread::spawn(move || {
+                let path = format!("/w{}/file", writer);
+                for fill in 1..=200u8 {
+                    ramfs.write(&path, 0, &[fill; 4096], "user").unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let mut taken = Vec::new();
+    for i in 0..50 {
+        taken.push(ramfs.snap(1_700_000_000 + i));
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let mut last = [0u8; 4];
+    for name in taken {
+        for (writer, seen) in last.iter_mut().enumerate() {
+            let data = ramfs
+                .read_file(&format!("/dump/{}/w{}/file", name, writer))
+                .unwrap();
+            assert_eq!(data.len(), 4096);
+            assert!(
+                data.iter().all(|byte| *byte == data[0]),
+                "torn write in {}",
+                name
+            );
+            // Later dumps never go back in time.
+            assert!(data[0] >= *seen);
+            *seen = data[0];
+        }
+    }
+    for writer in 0..4 {
+        let data = ramfs.read_file(&format!("/w{}/file", writer)).unwrap();
+        assert_eq!(data, vec![200; 4096]);
+    }
+}
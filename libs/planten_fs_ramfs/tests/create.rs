@@ -0,0 +1,177 @@
+use planten_fs_core::perm::{may_create, may_open, may_remove, may_walk};
+use planten_fs_core::{DMAPPEND, DMDIR, FsServer, OEXCL, ORCLOSE, OREAD, OWRITE, Wstat};
+use planten_fs_ramfs::RamFs;
+
+#[test]
+fn create_through_trait() {
+    let ramfs = RamFs::new();
+    assert!(
+        ramfs
+            .create("/", "logs", 0o755 | DMDIR, 0, "glenda")
+            .is_some()
+    );
+    assert!(
+        ramfs
+            .create("/logs", "app.log", 0o644 | DMAPPEND, OWRITE, "glenda")
+            .is_some()
+    );
+
+    let dir = ramfs.stat("/logs").unwrap();
+    assert!(dir.is_dir());
+    let file = ramfs.stat("/logs/app.log").unwrap();
+    assert_eq!(file.mode, 0o644 | DMAPPEND);
+
+    assert!(ramfs.create("/missing", "x", 0o644, 0, "glenda").is_none());
+    assert!(
+        ramfs
+            .create("/logs/app.log", "x", 0o644, 0, "glenda")
+            .is_none()
+    );
+    assert!(ramfs.create("/", "..", 0o644, 0, "glenda").is_none());
+}
+
+#[test]
+fn create_truncates_unless_oexcl() {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+
+    assert!(
+        ramfs
+            .create("/", "hello.txt", 0o644, OWRITE | OEXCL, "glenda")
+            .is_none()
+    );
+    assert_eq!(ramfs.read_file("/hello.txt").unwrap(), b"hello");
+
+    assert!(
+        ramfs
+            .create("/", "hello.txt", 0o644, OWRITE, "glenda")
+            .is_some()
+    );
+    assert!(ramfs.read_file("/hello.txt").unwrap().is_empty());
+
+    // Directories are never replaced, with or without OEXCL.
+    assert!(
+        ramfs
+            .create("/", "sub", 0o755 | DMDIR, 0, "glenda")
+            .is_some()
+    );
+    assert!(
+        ramfs
+            .create("/", "sub", 0o755 | DMDIR, 0, "glenda")
+            .is_none()
+    );
+    assert!(ramfs.create("/", "sub", 0o644, OWRITE, "glenda").is_none());
+}
+
+#[test]
+fn create_masks_permissions_with_parent() {
+    let ramfs = RamFs::new();
+    assert!(
+        ramfs
+            .create("/", "private", 0o700 | DMDIR, 0, "glenda")
+            .is_some()
+    );
+    assert!(
+        ramfs
+            .create("/private", "f", 0o666, OWRITE, "glenda")
+            .is_some()
+    );
+    assert!(
+        ramfs
+            .create("/private", "d", 0o777 | DMDIR, 0, "glenda")
+            .is_some()
+    );
+
+    assert_eq!(ramfs.stat("/private/f").unwrap().mode, 0o600);
+    assert_eq!(ramfs.stat("/private/d").unwrap().mode, 0o700 | DMDIR);
+}
+
+#[test]
+fn create_and_write_record_users() {
+    let ramfs = RamFs::new();
+    assert!(
+        ramfs
+            .create("/", "notes", 0o666, OWRITE, "glenda")
+            .is_some()
+    );
+    let file = ramfs.stat("/notes").unwrap();
+    assert_eq!((file.uid.as_str(), file.gid.as_str()), ("glenda", "group"));
+    assert_eq!(file.muid, "glenda");
+
+    assert_eq!(ramfs.write("/notes", 0, b"hi", "bootes"), Some(2));
+    let file = ramfs.stat("/notes").unwrap();
+    assert_eq!(
+        (file.uid.as_str(), file.muid.as_str()),
+        ("glenda", "bootes")
+    );
+}
+
+#[test]
+fn append_only_writes_land_at_the_end() {
+    let ramfs = RamFs::new();
+    ramfs
+        .create("/", "app.log", 0o644 | DMAPPEND, OWRITE, "glenda")
+        .unwrap();
+    ramfs.write("/app.log", 0, b"one\n", "glenda").unwrap();
+    ramfs.write("/app.log", 0, b"two\n", "glenda").unwrap();
+    ramfs.write("/app.log", 2, b"three\n", "glenda").unwrap();
+    assert_eq!(ramfs.read("/app.log").unwrap(), b"one\ntwo\nthree\n");
+}
+
+#[test]
+fn permission_helpers_check_the_right_inode() {
+    let mut ramfs = RamFs::new();
+    ramfs
+        .create("/", "home", 0o755 | DMDIR, 0, "glenda")
+        .unwrap();
+    ramfs
+        .create("/home", "notes", 0o644, OWRITE, "glenda")
+        .unwrap();
+    ramfs
+        .create("/", "tmp", 0o777 | DMDIR, 0, "glenda")
+        .unwrap();
+    // Creating masks the mode with the root's 0755.
+    let open = Wstat {
+        mode: Some(0o777 | DMDIR),
+        ..Wstat::default()
+    };
+    ramfs.wstat("/tmp", &open).unwrap();
+    ramfs
+        .create("/tmp", "scratch", 0o644, OWRITE, "glenda")
+        .unwrap();
+
+    // Walking needs search permission on the directory left behind.
+    assert_eq!(may_walk(&ramfs, "/home", "bob"), Ok(()));
+    ramfs
+        .create("/home", "private", 0o700 | DMDIR, 0, "glenda")
+        .unwrap();
+    assert_eq!(
+        may_walk(&ramfs, "/home/private", "bob"),
+        Err("permission denied")
+    );
+    assert_eq!(may_walk(&ramfs, "/nowhere", "bob"), Err("file not found"));
+
+    // Opening checks the file, and with ORCLOSE its directory as well.
+    assert_eq!(may_open(&ramfs, "/home/notes", "bob", OREAD), Ok(()));
+    assert_eq!(
+        may_open(&ramfs, "/home/notes", "bob", OREAD | ORCLOSE),
+        Err("permission denied")
+    );
+    assert_eq!(
+        may_open(&ramfs, "/tmp/scratch", "bob", OREAD | ORCLOSE),
+        Ok(())
+    );
+    assert_eq!(
+        may_open(&ramfs, "/home", "glenda", OWRITE),
+        Err("is a directory")
+    );
+
+    // Creating and removing check the directory, not the file.
+    assert_eq!(may_create(&ramfs, "/home", "bob"), Err("permission denied"));
+    assert_eq!(may_create(&ramfs, "/tmp", "bob"), Ok(()));
+    assert_eq!(
+        may_remove(&ramfs, "/home/notes", "bob"),
+        Err("permission denied")
+    );
+    assert_eq!(may_remove(&ramfs, "/tmp/scratch", "bob"), Ok(()));
+}
@@ -0,0 +1,128 @@
+use planten_fs_core::perm::{AWRITE, has_access};
+use planten_fs_core::{DMDIR, FsServer, OWRITE};
+use planten_fs_ramfs::RamFs;
+use planten_fs_ramfs::dump::dump_date;
+
+// 2023-11-14 22:13:20 UTC.
+const NOW: u32 = 1_700_000_000;
+
+fn dumping() -> RamFs {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/notes/today.txt", b"first draft");
+    ramfs.create_file("/hello.txt", b"hello");
+    ramfs.enable_dump().unwrap();
+    ramfs
+}
+
+#[test]
+fn dump_dates_follow_the_utc_calendar() {
+    assert_eq!(dump_date(0), ("1970".to_string(), "0101".to_string()));
+    assert_eq!(dump_date(NOW), ("2023".to_string(), "1114".to_string()));
+    // 2024-02-29, a leap day.
+    assert_eq!(
+        dump_date(1_709_164_800),
+        ("2024".to_string(), "0229".to_string())
+    );
+}
+
+#[test]
+fn dump_keeps_the_tree_as_it_was() {
+    let ramfs = dumping();
+    assert_eq!(ramfs.snap(NOW), "2023/1114");
+
+    ramfs
+        .write("/notes/today.txt", 0, b"FINAL", "glenda")
+        .unwrap();
+    ramfs.remove("/hello.txt").unwrap();
+    ramfs.create_file("/new.txt", b"new");
+
+    assert_eq!(ramfs.walk("/").unwrap(), vec!["dump", "new.txt", "notes"]);
+    assert_eq!(ramfs.walk("/dump").unwrap(), vec!["2023", "ctl"]);
+    assert_eq!(ramfs.walk("/dump/2023").unwrap(), vec!["1114"]);
+    assert_eq!(
+        ramfs.walk("/dump/2023/1114").unwrap(),
+        vec!["hello.txt", "notes"]
+    );
+    assert_eq!(
+        ramfs.read("/dump/2023/1114/notes/today.txt").unwrap(),
+        b"first draft"
+    );
+    assert_eq!(ramfs.read("/notes/today.txt").unwrap(), b"FINAL draft");
+
+    let root = ramfs.stat("/dump/2023/1114").unwrap();
+    assert_eq!((root.name.as_str(), root.mtime), ("1114", NOW));
+}
+
+#[test]
+fn same_day_dumps_get_a_suffix() {
+    let ramfs = dumping();
+    assert_eq!(ramfs.snap(NOW), "2023/1114");
+    assert_eq!(ramfs.snap(NOW + 60), "2023/11141");
+    assert_eq!(ramfs.snap(NOW + 120), "2023/11142");
+    assert_eq!(ramfs.dumps(), vec!["2023/1114", "2023/11141", "2023/11142"]);
+    assert_eq!(
+        ramfs.walk("/dump/2023").unwrap(),
+        vec!["1114", "11141", "11142"]
+    );
+}
+
+#[test]
+fn dumps_are_read_only() {
+    let ramfs = dumping();
+    ramfs.snap(NOW);
+
+    let file = "/dump/2023/1114/hello.txt";
+    assert!(ramfs.write(file, 0, b"x", "user").is_none());
+    assert!(ramfs.remove(file).is_none());
+    assert!(ramfs.remove("/dump/2023/1114").is_none());
+    assert!(
+        ramfs
+            .create("/dump/2023/1114", "x", 0o644, OWRITE, "user")
+            .is_none()
+    );
+    assert_eq!(ramfs.read(file).unwrap(), b"hello");
+
+    // Write permission is gone even for the owner, so the 9P runtimes
+    // refuse to open dump files for writing.
+    let inode = ramfs.stat(file).unwrap();
+    assert_eq!(inode.mode & 0o777, 0o444);
+    assert!(!has_access(&ramfs, file, &inode, "user", AWRITE));
+    let dir = ramfs.stat("/dump/2023").unwrap();
+    assert_eq!(dir.mode, 0o555 | DMDIR);
+}
+
+#[test]
+fn ctl_takes_a_dump() {
+    let ramfs = dumping();
+    assert!(ramfs.dumps().is_empty());
+    assert_eq!(ramfs.write("/dump/ctl", 0, b"snap\n", "user"), Some(5));
+    assert_eq!(ramfs.dumps().len(), 1);
+    assert!(ramfs.write("/dump/ctl", 0, b"explode", "user").is_none());
+
+    let ctl = ramfs.stat("/dump/ctl").unwrap();
+    assert!(has_access(&ramfs, "/dump/ctl", &ctl, "user", AWRITE));
+    assert!(!has_access(&ramfs, "/dump/ctl", &ctl, "glenda", AWRITE));
+}
+
+#[test]
+fn dump_name_is_reserved_once_enabled() {
+    let ramfs = dumping();
+    assert!(
+        ramfs
+            .create("/", "dump", 0o755 | DMDIR, 0, "user")
+            .is_none()
+    );
+
+    let taken = RamFs::new();
+    taken.create_dir("/dump");
+    assert!(taken.enable_dump().is_none());
+}
+
+#[test]
+fn without_dumps_the_tree_is_unchanged() {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+    ramfs.snap(NOW);
+    assert_eq!(ramfs.walk("/").unwrap(), vec!["hello.txt"]);
+    assert!(ramfs.stat("/dump").is_none());
+}
@@ -0,0 +1,202 @@
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use planten_9p::server;
+use planten_9p::{
+    RawMessage, build_frame, encode_attach_body, encode_open_body, encode_read_body,
+    encode_version_body, encode_walk_body, messages::*,
+};
+use planten_fs_core::wait::Flush;
+use planten_fs_core::{DMDIR, OWRITE, Wstat};
+use planten_fs_ramfs::RamFs;
+use planten_fs_ramfs::events::{EVENTS_FILE, EVENTS_LOST};
+
+fn watched() -> RamFs {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+    ramfs.enable_events().unwrap();
+    ramfs
+}
+
+fn rename(name: &str) -> Wstat {
+    Wstat {
+        name: Some(name.to_string()),
+        ..Wstat::default()
+    }
+}
+
+fn events(ramfs: &RamFs, dir: &str) -> String {
+    let path = format!("{}/{}", dir.trim_end_matches('/'), EVENTS_FILE);
+    String::from_utf8(ramfs.read_file(&path).unwrap()).unwrap()
+}
+
+#[test]
+fn mutations_are_logged_in_their_directory() {
+    let ramfs = watched();
+    ramfs.create_dir("/logs");
+    // Logging starts at the first look.
+    assert_eq!(events(&ramfs, "/"), "");
+    assert_eq!(events(&ramfs, "/logs"), "");
+
+    ramfs.create("/", "notes", 0o644, OWRITE, "user").unwrap();
+    ramfs.write("/notes", 0, b"text", "user").unwrap();
+    ramfs
+        .try_wstat("/notes", &rename("my notes"), None)
+        .unwrap();
+    ramfs.truncate("/my notes", "user").unwrap();
+    ramfs.create_file("/logs/build.log", b"ok");
+    ramfs.remove("/hello.txt").unwrap();
+
+    assert_eq!(
+        events(&ramfs, "/"),
+        "create notes\nwrite notes\nwstat notes 'my notes'\nwrite 'my notes'\nremove hello.txt\n"
+    );
+    assert_eq!(events(&ramfs, "/logs"), "create build.log\n");
+    let (inode, length) = ramfs.metadata("/.events").unwrap();
+    assert_eq!(
+        (inode.mode, length),
+        (0o444, events(&ramfs, "/").len() as u64)
+    );
+}
+
+#[test]
+fn the_name_is_taken_in_every_directory() {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/src/.events", b"");
+    assert!(ramfs.enable_events().is_none());
+    ramfs.remove("/src/.events").unwrap();
+    ramfs.enable_events().unwrap();
+
+    assert_eq!(ramfs.list_dir("/src").unwrap(), [EVENTS_FILE]);
+    assert!(
+        ramfs
+            .list_dir("/")
+            .unwrap()
+            .contains(&EVENTS_FILE.to_string())
+    );
+    assert!(
+        ramfs
+            .create("/src", EVENTS_FILE, 0o644, 0, "user")
+            .is_none()
+    );
+    ramfs.create("/src", "main.rs", 0o644, 0, "user").unwrap();
+    assert_eq!(
+        ramfs.try_wstat("/src/main.rs", &rename(EVENTS_FILE), None),
+        Err("file exists")
+    );
+    assert!(ramfs.write("/src/.events", 0, b"x", "user").is_none());
+    assert!(ramfs.remove("/src/.events").is_none());
+    // Files have no event log of their own.
+    assert!(ramfs.read_file("/src/main.rs/.events").is_none());
+}
+
+#[test]
+fn slow_readers_lose_old_events() {
+    let ramfs = watched();
+    events(&ramfs, "/");
+    for round in 0..10_000 {
+        ramfs.create_file(&format!("/file{}", round), b"");
+    }
+    let (_, end) = ramfs.metadata("/.events").unwrap();
+    assert!(end > 64 * 1024);
+    assert_eq!(ramfs.try_read_at("/.events", 0, 100), Err(EVENTS_LOST));
+    // What is kept starts on a line of its own.
+    let kept = events(&ramfs, "/");
+    assert!(kept.len() <= 64 * 1024);
+    assert!(kept.starts_with("create file"));
+    assert!(kept.ends_with("create file9999\n"));
+    assert_eq!(
+        ramfs.try_read_at("/.events", end - 5, 100).unwrap(),
+        b"9999\n"
+    );
+}
+
+#[test]
+fn removing_a_directory_ends_its_log() {
+    let ramfs = watched();
+    ramfs.create("/", "tmp", 0o755 | DMDIR, 0, "user").unwrap();
+    events(&ramfs, "/tmp");
+    let wait = ramfs.read_wait("/tmp/.events", 0).unwrap();
+    let waiter = thread::spawn(move || wait.wait(&Flush::new()));
+    ramfs.remove("/tmp").unwrap();
+    assert!(waiter.join().unwrap());
+    assert!(ramfs.read_wait("/tmp/.events", 0).is_none());
+    assert!(ramfs.read_file("/tmp/.events").is_none());
+}
+
+fn send(stream: &mut TcpStream, msg_type: u8, tag: u16, body: Vec<u8>) {
+    stream
+        .write_all(&build_frame(msg_type, tag, &body))
+        .unwrap();
+}
+
+fn call(stream: &mut TcpStream, msg_type: u8, body: Vec<u8>) -> RawMessage {
+    send(stream, msg_type, 1, body);
+    RawMessage::read_from(stream).unwrap()
+}
+
+/// Attaches and opens the root's event file as fid 2.
+fn open_events(addr: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    call(&mut stream, TVERSION, encode_version_body(8192, "9P2000"));
+    call(
+        &mut stream,
+        TATTACH,
+        encode_attach_body(1, None, "user", ""),
+    );
+    let walk = call(&mut stream, TWALK, encode_walk_body(1, 2, &[EVENTS_FILE]));
+    assert_eq!(walk.msg_type, RWALK);
+    let open = call(&mut stream, TOPEN, encode_open_body(2, 0));
+    assert_eq!(open.msg_type, ROPEN);
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn start() -> (String, Arc<RamFs>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let ramfs = Arc::new(watched());
+    let server_ramfs = Arc::clone(&ramfs);
+    thread::spawn(move || server::run_server(listener, server_ramfs));
+    (addr, ramfs)
+}
+
+#[test]
+fn reads_at_the_end_block_until_something_changes() {
+    let (addr, ramfs) = start();
+    let mut stream = open_events(&addr);
+    send(&mut stream, TREAD, 7, encode_read_body(2, 0, 100));
+
+    // The connection keeps serving while the read waits.
+    let walk = call(&mut stream, TWALK, encode_walk_body(1, 3, &["hello.txt"]));
+    assert_eq!((walk.msg_type, walk.tag), (RWALK, 1));
+
+    ramfs.create_file("/artifact", b"built");
+    let read = RawMessage::read_from(&mut stream).unwrap();
+    assert_eq!((read.msg_type, read.tag), (RREAD, 7));
+    assert_eq!(&read.body[4..], b"create artifact\n");
+
+    let again = call(&mut stream, TREAD, encode_read_body(2, 0, 100));
+    assert_eq!(&again.body[4..], b"create artifact\n");
+}
+
+#[test]
+fn flushed_reads_are_never_answered() {
+    let (addr, ramfs) = start();
+    let mut stream = open_events(&addr);
+    send(&mut stream, TREAD, 7, encode_read_body(2, 0, 100));
+    let flush = call(&mut stream, TFLUSH, 7u16.to_le_bytes().to_vec());
+    assert_eq!(flush.msg_type, RFLUSH);
+
+    // The next reply is for the next request, not the flushed read.
+    ramfs.create_file("/artifact", b"built");
+    send(&mut stream, TREAD, 8, encode_read_body(2, 0, 100));
+    let read = RawMessage::read_from(&mut stream).unwrap();
+    assert_eq!((read.msg_type, read.tag), (RREAD, 8));
+    assert_eq!(&read.body[4..], b"create artifact\n");
+}
@@ -1,14 +1,17 @@
 use std::io::{self, Cursor, Read, Write};
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 
+use planten_9p::server;
 use planten_9p::{
-    RawMessage, Stat, build_frame, decode_stat, encode_attach_body, encode_clone_body,
-    encode_create_body, encode_open_body, encode_read_body, encode_remove_body, encode_stat_body,
-    encode_version_body, encode_walk_body, encode_write_body, encode_wstat_body, messages::*,
+    Qid, RawMessage, Stat, build_frame, decode_stat, encode_attach_body, encode_clone_body,
+    encode_clunk_body, encode_create_body, encode_open_body, encode_read_body, encode_remove_body,
+    encode_stat_body, encode_version_body, encode_walk_body, encode_write_body, encode_wstat_body,
+    messages::*,
 };
-use planten_fs_ramfs::{RamFs, server};
+use planten_fs_core::{DMDIR, DMEXCL, FsServer, OWRITE, Wstat};
+use planten_fs_ramfs::RamFs;
 
 struct TestSession {
     stream: TcpStream,
@@ -33,9 +36,13 @@ impl TestSession {
     }
 
     fn handshake(&mut self) -> io::Result<()> {
+        self.handshake_as("user")
+    }
+
+    fn handshake_as(&mut self, uname: &str) -> io::Result<()> {
         let version = self.send(TVERSION, encode_version_body(131072, "9P2000"))?;
         assert_eq!(version.msg_type, RVERSION);
-        let attach = self.send(TATTACH, encode_attach_body(1, None, "user", ""))?;
+        let attach = self.send(TATTACH, encode_attach_body(1, None, uname, ""))?;
         assert_eq!(attach.msg_type, RATTACH);
         Ok(())
     }
@@ -77,13 +84,13 @@ impl TestSession {
     }
 }
 
-fn setup_ramfs_server() -> (TcpListener, Arc<Mutex<RamFs>>) {
+fn setup_ramfs_server() -> (TcpListener, Arc<RamFs>) {
     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-    let ramfs = Arc::new(Mutex::new({
-        let mut base = RamFs::new();
+    let ramfs = Arc::new({
+        let base = RamFs::new();
         base.create_file("/hello.txt", b"hello 9p!!");
         base
-    }));
+    });
     (listener, ramfs)
 }
 
@@ -226,9 +233,11 @@ fn wstat_modifies_file_info() {
     let wstat_response = session.wstat(2, &new_stat).unwrap();
     assert_eq!(wstat_response.msg_type, RWSTAT);
 
-    // Stat again to check changes
-    // We need to walk again because the fid is for the old path.
-    // A better test would re-use the fid if the name is not changed.
+    // The renamed file is reachable by its new name and through the old fid.
+    let stat_response = session.stat(2).unwrap();
+    let mut cursor = Cursor::new(stat_response.body.as_slice());
+    assert_eq!(decode_stat(&mut cursor).unwrap().name, "new_hello.txt");
+
     let walk_response = session.walk(1, 3, &["new_hello.txt"]).unwrap();
     assert_eq!(walk_response.msg_type, RWALK);
 
@@ -254,8 +263,10 @@ fn create_and_remove_file() {
     let mut session = TestSession::connect(&addr.to_string()).unwrap();
     session.handshake().unwrap();
 
-    // Create a new file
-    let create_response = session.create(1, "new_file.txt", 0o644, 1).unwrap(); // fid 1 is root
+    // Create a new file through a clone of the root
+    let clone_response = session.clone_fid(1, 4).unwrap();
+    assert_eq!(clone_response.msg_type, RCLONE);
+    let create_response = session.create(4, "new_file.txt", 0o644, 1).unwrap();
     assert_eq!(create_response.msg_type, RCREATE);
 
     // Walk to the new file to verify it exists
@@ -273,3 +284,493 @@ fn create_and_remove_file() {
     drop(session);
     server_thread.join().unwrap();
 }
+
+#[test]
+fn create_directory_then_nested_file() {
+    let (listener, ramfs) = setup_ramfs_server();
+    let addr = listener.local_addr().unwrap();
+    let server_ramfs = Arc::clone(&ramfs);
+    let server_thread = thread::spawn(move || server::run_single(listener, server_ramfs).unwrap());
+
+    let mut session = TestSession::connect(&addr.to_string()).unwrap();
+    session.handshake().unwrap();
+
+    // Tcreate moves the fid to the new file, so create through a clone.
+    let clone_response = session.clone_fid(1, 4).unwrap();
+    assert_eq!(clone_response.msg_type, RCLONE);
+    let create_response = session.create(4, "dir", 0o755 | 0x80000000, 0).unwrap();
+    assert_eq!(create_response.msg_type, RCREATE);
+    assert_eq!(create_response.body[0], 0x80, "qid should be a directory");
+    assert_eq!(stat_of(&mut session, 4).name, "dir");
+    let create_response = session.create(4, "again", 0o644, 1).unwrap();
+    assert_eq!(create_response.msg_type, RERROR);
+    assert!(decode_error_message(&create_response.body).contains("fid already open"));
+
+    let walk_response = session.walk(1, 2, &["dir"]).unwrap();
+    assert_eq!(walk_response.msg_type, RWALK);
+    let create_response = session.create(2, "inner.txt", 0o644, 1).unwrap();
+    assert_eq!(create_response.msg_type, RCREATE);
+    let write_response = session.write(2, 0, b"inner").unwrap();
+    assert_eq!(write_response.msg_type, RWRITE);
+    assert_eq!(ramfs.read_file("/dir/inner.txt").unwrap(), b"inner");
+
+    let walk_response = session.walk(1, 3, &["dir", "inner.txt"]).unwrap();
+    assert_eq!(walk_response.msg_type, RWALK);
+
+    let create_response = session.create(1, "dir", 0o755 | 0x80000000, 0).unwrap();
+    assert_eq!(create_response.msg_type, RERROR);
+    assert!(decode_error_message(&create_response.body).contains("file exists"));
+
+    drop(session);
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn other_users_get_only_the_other_bits() {
+    let (listener, ramfs) = setup_ramfs_server();
+    let addr = listener.local_addr().unwrap();
+    let server_ramfs = Arc::clone(&ramfs);
+    let server_thread = thread::spawn(move || server::run_single(listener, server_ramfs).unwrap());
+
+    let mut session = TestSession::connect(&addr.to_string()).unwrap();
+    session.handshake_as("glenda").unwrap();
+
+    let walk_response = session.walk(1, 2, &["hello.txt"]).unwrap();
+    assert_eq!(walk_response.msg_type, RWALK);
+    let open_response = session.open(2, 1).unwrap();
+    assert_eq!(open_response.msg_type, RERROR);
+    assert!(decode_error_message(&open_response.body).contains("permission denied"));
+    let open_response = session.open(2, 0).unwrap();
+    assert_eq!(open_response.msg_type, ROPEN);
+
+    let create_response = session.create(1, "mine.txt", 0o644, 1).unwrap();
+    assert_eq!(create_response.msg_type, RERROR);
+    assert!(decode_error_message(&create_response.body).contains("permission denied"));
+
+    let walk_response = session.walk(1, 3, &["hello.txt"]).unwrap();
+    assert_eq!(walk_response.msg_type, RWALK);
+    let remove_response = session.remove(3).unwrap();
+    assert_eq!(remove_response.msg_type, RERROR);
+    assert!(ramfs.read_file("/hello.txt").is_some());
+
+    drop(session);
+    server_thread.join().unwrap();
+}
+
+#[test]
+fn write_records_muid() {
+    let (listener, ramfs) = setup_ramfs_server();
+    let addr = listener.local_addr().unwrap();
+    let server_ramfs = Arc::clone(&ramfs);
+    let server_thread = thread::spawn(move || server::run_single(listener, server_ramfs).unwrap());
+
+    let mut session = TestSession::connect(&addr.to_string()).unwrap();
+    session.handshake().unwrap();
+
+    let walk_response = session.walk(1, 2, &["hello.txt"]).unwrap();
+    assert_eq!(walk_response.msg_type, RWALK);
+    assert_eq!(session.open(2, 1).unwrap().msg_type, ROPEN);
+    assert_eq!(session.write(2, 0, b"HELLO").unwrap().msg_type, RWRITE);
+
+    let stat_response = session.stat(2).unwrap();
+    assert_eq!(stat_response.msg_type, RSTAT);
+    let mut cursor = Cursor::new(stat_response.body.as_slice());
+    let stat = decode_stat(&mut cursor).unwrap();
+    assert_eq!(stat.muid, "user");
+
+    drop(session);
+    server_thread.join().unwrap();
+}
+
+fn start(ramfs: &Arc<RamFs>) -> TestSession {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_ramfs = Arc::clone(ramfs);
+    thread::spawn(move || server::run_server(listener, server_ramfs));
+    let mut session = TestSession::connect(&addr.to_string()).unwrap();
+    session.handshake().unwrap();
+    session
+}
+
+#[test]
+fn otrunc_empties_the_file() {
+    let (_, ramfs) = setup_ramfs_server();
+    let mut session = start(&ramfs);
+
+    assert_eq!(session.walk(1, 2, &["hello.txt"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.open(2, 0x10 | 1).unwrap().msg_type, ROPEN);
+    assert_eq!(ramfs.read_file("/hello.txt").unwrap(), b"");
+
+    // Truncating needs write permission.
+    let mut other = start(&ramfs);
+    other.handshake_as("glenda").unwrap();
+    assert_eq!(other.walk(1, 2, &["hello.txt"]).unwrap().msg_type, RWALK);
+    assert_eq!(other.open(2, 0x10).unwrap().msg_type, RERROR);
+}
+
+#[test]
+fn orclose_removes_on_clunk_and_hangup() {
+    let (_, ramfs) = setup_ramfs_server();
+    ramfs.create_file("/scratch", b"tmp");
+    let mut session = start(&ramfs);
+
+    assert_eq!(session.walk(1, 2, &["hello.txt"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.open(2, 0x40).unwrap().msg_type, ROPEN);
+    assert_eq!(session.clone_fid(2, 3).unwrap().msg_type, RCLONE);
+    // Clunking a copy of the fid leaves the file alone.
+    assert_eq!(
+        session.send(TCLUNK, encode_clunk_body(3)).unwrap().msg_type,
+        RCLUNK
+    );
+    assert!(ramfs.read_file("/hello.txt").is_some());
+    assert_eq!(
+        session.send(TCLUNK, encode_clunk_body(2)).unwrap().msg_type,
+        RCLUNK
+    );
+    assert!(ramfs.read_file("/hello.txt").is_none());
+
+    assert_eq!(session.walk(1, 4, &["scratch"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.open(4, 0x40).unwrap().msg_type, ROPEN);
+    let _ = session.stream.shutdown(std::net::Shutdown::Both);
+    drop(session);
+    // Wait for the server to see the hangup.
+    for _ in 0..100 {
+        if ramfs.read_file("/scratch").is_none() {
+            return;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("ORCLOSE file survived the hangup");
+}
+
+#[test]
+fn exclusive_files_open_once() {
+    let (_, ramfs) = setup_ramfs_server();
+    ramfs
+        .create("/", "lock", 0o666 | DMEXCL, 1, "user")
+        .unwrap();
+    let mut session = start(&ramfs);
+    let mut other = start(&ramfs);
+
+    assert_eq!(session.walk(1, 2, &["lock"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.open(2, 2).unwrap().msg_type, ROPEN);
+
+    assert_eq!(other.walk(1, 2, &["lock"]).unwrap().msg_type, RWALK);
+    let busy = other.open(2, 0).unwrap();
+    assert_eq!(busy.msg_type, RERROR);
+    assert!(decode_error_message(&busy.body).contains("exclusive"));
+
+    assert_eq!(
+        session.send(TCLUNK, encode_clunk_body(2)).unwrap().msg_type,
+        RCLUNK
+    );
+    assert_eq!(other.open(2, 0).unwrap().msg_type, ROPEN);
+}
+
+#[test]
+fn create_honours_orclose_and_dmexcl() {
+    let (_, ramfs) = setup_ramfs_server();
+    let mut session = start(&ramfs);
+    let mut other = start(&ramfs);
+
+    assert_eq!(session.clone_fid(1, 2).unwrap().msg_type, RCLONE);
+    let create = session.create(2, "temp", 0o644, 0x40 | 1).unwrap();
+    assert_eq!(create.msg_type, RCREATE);
+    assert!(ramfs.read_file("/temp").is_some());
+    assert_eq!(
+        session.send(TCLUNK, encode_clunk_body(2)).unwrap().msg_type,
+        RCLUNK
+    );
+    assert!(ramfs.read_file("/temp").is_none());
+
+    assert_eq!(session.clone_fid(1, 3).unwrap().msg_type, RCLONE);
+    let create = session.create(3, "lock", 0o666 | DMEXCL, 2).unwrap();
+    assert_eq!(create.msg_type, RCREATE);
+    assert_eq!(other.walk(1, 2, &["lock"]).unwrap().msg_type, RWALK);
+    let busy = other.open(2, 0).unwrap();
+    assert_eq!(busy.msg_type, RERROR);
+    assert!(decode_error_message(&busy.body).contains("exclusive"));
+
+    assert_eq!(
+        session.send(TCLUNK, encode_clunk_body(3)).unwrap().msg_type,
+        RCLUNK
+    );
+    assert_eq!(other.open(2, 0).unwrap().msg_type, ROPEN);
+}
+
+fn stat_of(session: &mut TestSession, fid: u32) -> Stat {
+    let response = session.stat(fid).unwrap();
+    assert_eq!(response.msg_type, RSTAT);
+    decode_stat(&mut Cursor::new(response.body.as_slice())).unwrap()
+}
+
+fn rename(session: &mut TestSession, fid: u32, name: &str) {
+    let stat = Stat {
+        name: name.to_string(),
+        ..stat_of(session, fid)
+    };
+    assert_eq!(session.wstat(fid, &stat).unwrap().msg_type, RWSTAT);
+}
+
+#[test]
+fn open_fids_follow_renames() {
+    let (_, ramfs) = setup_ramfs_server();
+    ramfs.create_file("/dir/inner.txt", b"inner");
+    let mut session = start(&ramfs);
+
+    assert_eq!(session.walk(1, 2, &["hello.txt"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.open(2, 0).unwrap().msg_type, ROPEN);
+    let qid = stat_of(&mut session, 2).qid;
+    assert_eq!(session.walk(1, 3, &["hello.txt"]).unwrap().msg_type, RWALK);
+    rename(&mut session, 3, "moved.txt");
+
+    let read = session.read(2, 0, 64).unwrap();
+    assert_eq!(read.msg_type, RREAD);
+    assert_eq!(&read.body[4..], b"hello 9p!!");
+    let stat = stat_of(&mut session, 2);
+    assert_eq!(stat.name, "moved.txt");
+    assert_eq!(stat.qid.path, qid.path);
+
+    // Renaming a directory carries the fids inside it along.
+    assert_eq!(
+        session.walk(1, 4, &["dir", "inner.txt"]).unwrap().msg_type,
+        RWALK
+    );
+    assert_eq!(session.walk(1, 5, &["dir"]).unwrap().msg_type, RWALK);
+    rename(&mut session, 5, "renamed");
+    assert_eq!(session.open(4, 0).unwrap().msg_type, ROPEN);
+    assert_eq!(&session.read(4, 0, 64).unwrap().body[4..], b"inner");
+    assert_eq!(stat_of(&mut session, 5).name, "renamed");
+}
+
+#[test]
+fn removed_files_stay_removed_for_open_fids() {
+    let (_, ramfs) = setup_ramfs_server();
+    let mut session = start(&ramfs);
+
+    assert_eq!(session.walk(1, 2, &["hello.txt"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.open(2, 2).unwrap().msg_type, ROPEN);
+    assert_eq!(session.walk(1, 3, &["hello.txt"]).unwrap().msg_type, RWALK);
+    assert_eq!(session.remove(3).unwrap().msg_type, RREMOVE);
+
+    // A new file under the old name is not reachable through the old fid.
+    ramfs.create_file("/hello.txt", b"impostor");
+    for response in [
+        session.read(2, 0, 64).unwrap(),
+        session.write(2, 0, b"x").unwrap(),
+        session.stat(2).unwrap(),
+        session.walk(2, 4, &[]).unwrap(),
+    ] {
+        assert_eq!(response.msg_type, RERROR);
+        assert_eq!(
+            decode_error_message(&response.body),
+            "file has been removed"
+        );
+    }
+    assert_eq!(ramfs.read_file("/hello.txt").unwrap(), b"impostor");
+    // A failed Tremove still clunks the fid.
+    assert_eq!(session.remove(2).unwrap().msg_type, RERROR);
+    let response = session.send(TCLUNK, encode_clunk_body(2)).unwrap();
+    assert_eq!(response.msg_type, RERROR);
+    assert_eq!(decode_error_message(&response.body), "unknown fid");
+}
+
+#[test]
+fn node_ids_track_paths() {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/a/b/c.txt", b"c");
+    let id = ramfs.node_id("/a/b/c.txt").unwrap();
+    assert_eq!(ramfs.node_path(id).unwrap(), "/a/b/c.txt");
+    assert_eq!(ramfs.node_path(ramfs.node_id("/").unwrap()).unwrap(), "/");
+
+    ramfs.remove("/a/b/c.txt").unwrap();
+    assert!(ramfs.node_path(id).is_none());
+    ramfs.create_file("/a/b/c.txt", b"again");
+    assert_ne!(ramfs.node_id("/a/b/c.txt").unwrap(), id);
+}
+
+/// A wstat that leaves every field alone.
+fn dont_touch() -> Stat {
+    Stat {
+        type_: !0,
+        dev: !0,
+        qid: Qid {
+            qtype: !0,
+            version: !0,
+            path: !0,
+        },
+        mode: !0,
+        atime: !0,
+        mtime: !0,
+        length: !0,
+        name: String::new(),
+        uid: String::new(),
+        gid: String::new(),
+        muid: String::new(),
+    }
+}
+
+fn start_as(ramfs: &Arc<RamFs>, uname: &str) -> TestSession {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_ramfs = Arc::clone(ramfs);
+    thread::spawn(move || server::run_server(listener, server_ramfs));
+    let mut session = TestSession::connect(&addr.to_string()).unwrap();
+    session.handshake_as(uname).unwrap();
+    session
+}
+
+fn wstat_error(session: &mut TestSession, fid: u32, stat: &Stat) -> String {
+    let response = session.wstat(fid, stat).unwrap();
+    assert_eq!(response.msg_type, RERROR);
+    decode_error_message(&response.body)
+}
+
+#[test]
+fn wstat_follows_ownership_rules() {
+    // Renames need write permission on the directory, here glenda's.
+    let ramfs = Arc::new(RamFs::owned_by("glenda", "glenda"));
+    ramfs.create("/", "notes", 0o664, OWRITE, "glenda").unwrap();
+    ramfs.write("/notes", 0, b"draft text", "glenda").unwrap();
+    let mut owner = start_as(&ramfs, "glenda");
+    let mut other = start_as(&ramfs, "bootes");
+    for session in [&mut owner, &mut other] {
+        assert_eq!(session.walk(1, 2, &["notes"]).unwrap().msg_type, RWALK);
+    }
+
+    let chmod = Stat {
+        mode: 0o600,
+        ..dont_touch()
+    };
+    assert_eq!(wstat_error(&mut other, 2, &chmod), "not owner");
+    let touch = Stat {
+        atime: 5,
+        ..dont_touch()
+    };
+    assert_eq!(wstat_error(&mut other, 2, &touch), "not owner");
+    let chgrp = Stat {
+        gid: "sys".to_string(),
+        ..dont_touch()
+    };
+    assert_eq!(
+        wstat_error(&mut owner, 2, &chgrp),
+        "not owner or not in group"
+    );
+    let chown = Stat {
+        uid: "bootes".to_string(),
+        ..dont_touch()
+    };
+    assert_eq!(wstat_error(&mut owner, 2, &chown), "cannot change owner");
+    let rename = Stat {
+        name: "taken".to_string(),
+        ..dont_touch()
+    };
+    assert_eq!(wstat_error(&mut other, 2, &rename), "permission denied");
+    let escape = Stat {
+        name: "../notes".to_string(),
+        ..dont_touch()
+    };
+    assert_eq!(
+        wstat_error(&mut owner, 2, &escape),
+        "cannot rename across directories"
+    );
+
+    let all = Stat {
+        mode: 0o640,
+        atime: 5,
+        mtime: 6,
+        length: 5,
+        gid: "glenda".to_string(),
+        name: "notes.old".to_string(),
+        ..dont_touch()
+    };
+    assert_eq!(owner.wstat(2, &all).unwrap().msg_type, RWSTAT);
+    let stat = stat_of(&mut owner, 2);
+    assert_eq!(
+        (
+            stat.name.as_str(),
+            stat.mode,
+            stat.atime,
+            stat.mtime,
+            stat.gid.as_str()
+        ),
+        ("notes.old", 0o640, 5, 6, "glenda")
+    );
+    assert_eq!(ramfs.read_file("/notes.old").unwrap(), b"draft");
+    // Anyone who may write the file may truncate it.
+    assert_eq!(
+        wstat_error(
+            &mut other,
+            2,
+            &Stat {
+                length: 0,
+                ..dont_touch()
+            }
+        ),
+        "permission denied"
+    );
+}
+
+#[test]
+fn failed_wstats_change_nothing() {
+    let ramfs = Arc::new(RamFs::new());
+    ramfs.create_file("/a", b"contents");
+    ramfs.create_file("/b", b"other");
+    let mut session = start(&ramfs);
+    assert_eq!(session.walk(1, 2, &["a"]).unwrap().msg_type, RWALK);
+    let before = stat_of(&mut session, 2);
+
+    // The rename is refused after the mode, times and length were checked
+    // and found fine; none of them is applied.
+    let clash = Stat {
+        name: "b".to_string(),
+        mode: 0o600,
+        mtime: 1,
+        length: 0,
+        ..dont_touch()
+    };
+    assert_eq!(wstat_error(&mut session, 2, &clash), "file exists");
+    assert_eq!(stat_of(&mut session, 2), before);
+    assert_eq!(ramfs.read_file("/a").unwrap(), b"contents");
+
+    let dir_bit = Stat {
+        mode: 0o644 | DMDIR,
+        ..dont_touch()
+    };
+    assert_eq!(
+        wstat_error(&mut session, 2, &dir_bit),
+        "cannot change directory bit"
+    );
+    assert_eq!(stat_of(&mut session, 2), before);
+}
+
+#[test]
+fn the_host_may_give_files_away() {
+    let mut ramfs = RamFs::new();
+    ramfs.create_file("/a", b"12345");
+    let chown = Stat {
+        uid: "glenda".to_string(),
+        ..dont_touch()
+    };
+    ramfs.wstat_from_stat("/a", &chown).unwrap();
+    assert_eq!(ramfs.stat("/a").unwrap().uid, "glenda");
+    assert_eq!(ramfs.user_usage("glenda").bytes, 5);
+    assert_eq!(ramfs.user_usage("user").bytes, 0);
+
+    // Through the trait the changes come as they were asked for.
+    let change = Wstat {
+        name: Some("b".to_string()),
+        length: Some(2),
+        atime: Some(7),
+        ..Wstat::default()
+    };
+    FsServer::wstat(&mut ramfs, "/a", &change).unwrap();
+    assert_eq!(ramfs.read_file("/b").unwrap(), b"12");
+    assert_eq!(ramfs.stat("/b").unwrap().atime, 7);
+    let mode = FsServer::stat(&ramfs, "/").unwrap().mode & !DMDIR;
+    let change = Wstat {
+        mode: Some(mode),
+        ..Wstat::default()
+    };
+    assert!(FsServer::wstat(&mut ramfs, "/", &change).is_none());
+}
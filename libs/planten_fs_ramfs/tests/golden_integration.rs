@@ -3,15 +3,16 @@ use std::fs;
 use std::io::{self, Cursor, Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 
 use planten_9p::RawMessage;
 use planten_9p::messages::{
     RATTACH, RCLONE, RERROR, ROPEN, RREAD, RSTAT, RVERSION, RWRITE, TREAD, TWRITE,
 };
+use planten_9p::server;
 use planten_9p::{build_frame, decode_stat, encode_read_body};
-use planten_fs_ramfs::{RamFs, server};
+use planten_fs_ramfs::RamFs;
 
 fn parse_frames(bytes: &[u8]) -> Vec<(Vec<u8>, RawMessage)> {
     let mut frames = Vec::new();
@@ -96,12 +97,9 @@ fn read_string(cursor: &mut Cursor<&[u8]>) -> io::Result<String> {
 fn golden_trace_matches_server_interaction() {
     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
     let addr = listener.local_addr().unwrap();
-    let ramfs = Arc::new(Mutex::new(RamFs::new()));
-    {
-        let mut guard = ramfs.lock().unwrap();
-        guard.create_file("/hello.txt", b"hello 9p!!");
-        guard.create_file("/readme.txt", b"RAMFS as a 9P server");
-    }
+    let ramfs = Arc::new(RamFs::new());
+    ramfs.create_file("/hello.txt", b"hello 9p!!");
+    ramfs.create_file("/readme.txt", b"RAMFS as a 9P server");
 
     let server_ramfs = Arc::clone(&ramfs);
     let server_thread = thread::spawn(move || {
@@ -0,0 +1,178 @@
+use std::sync::{Arc, Mutex};
+
+use planten_fs_core::perm::{has_access, open_access};
+use planten_fs_core::{
+    FsServer, Logged, OWRITE, RateLimited, ReadOnly, Subtree, Wstat, take_errstr,
+};
+use planten_fs_ramfs::RamFs;
+
+fn sample() -> RamFs {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+    ramfs.create_file("/pub/docs/readme", b"read me");
+    ramfs.create_file("/private/key", b"secret");
+    ramfs
+}
+
+#[test]
+fn read_only_refuses_changes_and_write_opens() {
+    let mut fs = ReadOnly::new(sample());
+    assert_eq!(fs.read("/hello.txt").unwrap(), b"hello");
+    assert!(fs.write("/hello.txt", 0, b"HELLO", "user").is_none());
+    assert!(fs.create("/", "new", 0o644, OWRITE, "user").is_none());
+    assert!(fs.remove("/hello.txt").is_none());
+
+    let inode = fs.stat("/hello.txt").unwrap();
+    assert!(has_access(
+        &fs,
+        "/hello.txt",
+        &inode,
+        "user",
+        open_access(0)
+    ));
+    assert!(!has_access(
+        &fs,
+        "/hello.txt",
+        &inode,
+        "user",
+        open_access(OWRITE)
+    ));
+    assert_eq!(fs.into_inner().read_file("/hello.txt").unwrap(), b"hello");
+}
+
+#[test]
+fn subtree_confines_paths_to_the_prefix() {
+    let mut fs = Subtree::new(sample(), "/pub");
+    assert_eq!(fs.walk("/").unwrap(), vec!["docs"]);
+    assert_eq!(fs.read("/docs/readme").unwrap(), b"read me");
+    assert_eq!(fs.stat("/").unwrap().name, "/");
+    assert!(fs.read("/../private/key").is_none());
+    assert!(fs.stat("/private").is_none());
+    assert!(fs.remove("/").is_none());
+    assert_eq!(fs.attach("").unwrap(), "/");
+    assert!(fs.attach("../private").is_none());
+
+    assert!(fs.create("/docs", "notes", 0o644, OWRITE, "user").is_some());
+    let ramfs = fs.into_inner();
+    assert!(ramfs.read_file("/pub/docs/notes").is_some());
+}
+
+#[test]
+fn logged_reports_operations_with_outcome() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&lines);
+    let mut fs = Logged::with_sink(sample(), move |line| {
+        sink.lock().unwrap().push(line.to_string())
+    });
+
+    assert!(fs.write("/hello.txt", 0, b"HE", "glenda").is_some());
+    assert!(fs.read("/missing").is_none());
+
+    let lines = lines.lock().unwrap();
+    assert_eq!(
+        *lines,
+        vec![
+            "write /hello.txt offset=0 count=2 uname=glenda: ok",
+            "read /missing: failed",
+        ]
+    );
+}
+
+#[test]
+fn rate_limited_rejects_beyond_the_burst() {
+    let fs = RateLimited::new(sample(), 1, 2);
+    assert!(fs.read("/hello.txt").is_some());
+    assert!(fs.read("/hello.txt").is_some());
+    assert!(fs.read("/hello.txt").is_none());
+    assert_eq!(take_errstr().unwrap(), "rate limited");
+    assert!(!fs.retry_after().is_zero());
+    // Stats and clunks are never throttled.
+    assert!(fs.stat("/hello.txt").is_some());
+    assert!(fs.metadata("/hello.txt").is_some());
+    assert!(fs.clunk("/hello.txt").is_some());
+    assert!(take_errstr().is_none());
+}
+
+#[test]
+fn wrappers_compose() {
+    let fs = ReadOnly::new(Subtree::new(sample(), "pub/"));
+    let inode = fs.stat("/docs/readme").unwrap();
+    assert_eq!(inode.mode & 0o222, 0);
+    assert_eq!(fs.read("/docs/readme").unwrap(), b"read me");
+}
+
+#[test]
+fn read_only_refuses_truncate_and_forwards_the_other_hooks() {
+    let mut fs = ReadOnly::new(sample());
+    assert!(fs.truncate("/hello.txt", "user").is_none());
+    assert!(fs.claim_exclusive("/hello.txt"));
+    assert!(!fs.claim_exclusive("/hello.txt"));
+    fs.release_exclusive("/hello.txt");
+    assert!(fs.claim_exclusive("/hello.txt"));
+    let id = fs.node_id("/hello.txt").unwrap();
+    assert_eq!(fs.node_path(id).unwrap(), "/hello.txt");
+    assert_eq!(fs.into_inner().read_file("/hello.txt").unwrap(), b"hello");
+}
+
+#[test]
+fn subtree_maps_the_hooks_through_its_prefix() {
+    let ramfs = sample();
+    let outside = ramfs.node_id("/private/key").unwrap();
+    let mut fs = Subtree::new(ramfs, "/pub");
+    assert!(fs.truncate("/docs/readme", "user").is_some());
+    assert!(fs.claim_exclusive("/docs/readme"));
+    assert!(!fs.claim_exclusive("/docs/readme"));
+    fs.release_exclusive("/docs/readme");
+    assert!(fs.claim_exclusive("/docs/readme"));
+
+    let id = fs.node_id("/docs/readme").unwrap();
+    let rename = Wstat {
+        name: Some("papers".to_string()),
+        ..Wstat::default()
+    };
+    assert!(fs.wstat("/docs", &rename).is_some());
+    assert_eq!(fs.node_path(id).unwrap(), "/papers/readme");
+    assert_eq!(fs.node_path(fs.node_id("/").unwrap()).unwrap(), "/");
+    // Files outside the prefix are out of reach, even by id.
+    assert!(fs.node_path(outside).is_none());
+
+    let ramfs = fs.into_inner();
+    assert_eq!(ramfs.read_file("/pub/papers/readme").unwrap(), b"");
+}
+
+#[test]
+fn logged_reports_truncates_and_forwards_the_other_hooks() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&lines);
+    let mut fs = Logged::with_sink(sample(), move |line| {
+        sink.lock().unwrap().push(line.to_string())
+    });
+    assert!(fs.truncate("/hello.txt", "glenda").is_some());
+    assert!(fs.claim_exclusive("/hello.txt"));
+    assert!(!fs.claim_exclusive("/hello.txt"));
+    fs.release_exclusive("/hello.txt");
+    let id = fs.node_id("/hello.txt").unwrap();
+    assert_eq!(fs.node_path(id).unwrap(), "/hello.txt");
+
+    assert_eq!(
+        *lines.lock().unwrap(),
+        vec!["truncate /hello.txt uname=glenda: ok"]
+    );
+    assert_eq!(fs.into_inner().read_file("/hello.txt").unwrap(), b"");
+}
+
+#[test]
+fn rate_limited_never_throttles_the_open_and_clunk_hooks() {
+    let mut fs = RateLimited::new(sample(), 0, 1);
+    assert!(fs.read("/hello.txt").is_some());
+    assert!(fs.read("/hello.txt").is_none());
+    take_errstr();
+    assert!(fs.truncate("/hello.txt", "glenda").is_some());
+    assert!(fs.claim_exclusive("/hello.txt"));
+    assert!(!fs.claim_exclusive("/hello.txt"));
+    fs.release_exclusive("/hello.txt");
+    let id = fs.node_id("/hello.txt").unwrap();
+    assert_eq!(fs.node_path(id).unwrap(), "/hello.txt");
+    assert!(take_errstr().is_none());
+    assert_eq!(fs.into_inner().read_file("/hello.txt").unwrap(), b"");
+}
@@ -0,0 +1,162 @@
+use planten_fs_core::{DMDIR, FsServer, take_errstr};
+use planten_fs_ramfs::RamFs;
+use planten_fs_ramfs::quota::{Limits, NO_SPACE, USAGE_FILE, Usage};
+
+const TB: u64 = 1 << 40;
+
+fn scratch() -> RamFs {
+    let ramfs = RamFs::new();
+    ramfs.create("/", "tmp", 0o777 | DMDIR, 0, "user").unwrap();
+    ramfs
+}
+
+#[test]
+fn writes_far_past_the_end_are_sparse() {
+    let ramfs = scratch();
+    ramfs.create("/tmp", "big", 0o644, 1, "glenda").unwrap();
+    assert_eq!(ramfs.write("/tmp/big", TB, b"end", "glenda"), Some(3));
+
+    assert_eq!(ramfs.metadata("/tmp/big").unwrap().1, TB + 3);
+    assert_eq!(ramfs.read_at("/tmp/big", TB - 2, 16).unwrap(), b"\0\0end");
+    assert_eq!(ramfs.read_at("/tmp/big", 1 << 30, 4).unwrap(), [0; 4]);
+    assert!(ramfs.user_usage("glenda").bytes < 8192);
+    // Stats, through the trait as the runtimes make them, copy nothing.
+    assert!(FsServer::stat(&ramfs, "/tmp/big").unwrap().data.is_empty());
+    assert_eq!(FsServer::metadata(&ramfs, "/tmp/big").unwrap().1, TB + 3);
+    assert_eq!(
+        FsServer::read_at(&ramfs, "/tmp/big", TB, 8).unwrap(),
+        b"end"
+    );
+    // Whole reads are refused rather than filling memory with zeros.
+    assert!(ramfs.read_file("/tmp/big").is_none());
+    assert!(FsServer::read(&ramfs, "/tmp/big").is_none());
+    assert_eq!(take_errstr().unwrap(), "file too large to read whole");
+
+    // Offsets that would wrap are refused outright.
+    assert!(
+        ramfs
+            .try_write("/tmp/big", u64::MAX, b"x", "glenda")
+            .is_err()
+    );
+}
+
+#[test]
+fn server_byte_limit_refuses_growth_but_not_overwrites() {
+    let ramfs = scratch();
+    ramfs.set_server_limits(Limits {
+        bytes: Some(100),
+        inodes: None,
+    });
+    ramfs.create("/tmp", "f", 0o644, 1, "glenda").unwrap();
+    assert_eq!(ramfs.write("/tmp/f", 0, &[1; 100], "glenda"), Some(100));
+    assert_eq!(
+        ramfs.try_write("/tmp/f", 100, b"x", "glenda"),
+        Err(NO_SPACE)
+    );
+    assert_eq!(ramfs.read_file("/tmp/f").unwrap().len(), 100);
+    assert_eq!(ramfs.write("/tmp/f", 10, b"rewritten", "glenda"), Some(9));
+
+    // Emptying the file gives the space back.
+    ramfs.truncate("/tmp/f", "glenda").unwrap();
+    assert_eq!(ramfs.usage().bytes, 0);
+    assert_eq!(ramfs.write("/tmp/f", 0, &[2; 100], "glenda"), Some(100));
+}
+
+#[test]
+fn user_limits_apply_per_owner() {
+    let ramfs = scratch();
+    ramfs.set_user_limits(
+        None,
+        Limits {
+            bytes: None,
+            inodes: Some(2),
+        },
+    );
+    ramfs.set_user_limits(
+        Some("glenda"),
+        Limits {
+            bytes: Some(10),
+            inodes: None,
+        },
+    );
+
+    for name in ["a", "b"] {
+        ramfs.create("/tmp", name, 0o644, 1, "bob").unwrap();
+    }
+    assert_eq!(
+        ramfs.try_create("/tmp", "c", 0o644, 1, "bob"),
+        Err(NO_SPACE)
+    );
+    for name in ["c", "d", "e"] {
+        ramfs.create("/tmp", name, 0o644, 1, "glenda").unwrap();
+    }
+    assert_eq!(
+        ramfs.try_write("/tmp/c", 0, &[0; 11], "glenda"),
+        Err(NO_SPACE)
+    );
+    // Writes are charged to the file's owner, not the writer.
+    assert_eq!(ramfs.write("/tmp/a", 0, &[0; 11], "glenda"), Some(11));
+    assert_eq!(
+        ramfs.user_usage("bob"),
+        Usage {
+            bytes: 11,
+            inodes: 2
+        }
+    );
+
+    ramfs.remove("/tmp/a").unwrap();
+    assert_eq!(
+        ramfs.user_usage("bob"),
+        Usage {
+            bytes: 0,
+            inodes: 1
+        }
+    );
+    ramfs.create("/tmp", "c2", 0o644, 1, "bob").unwrap();
+}
+
+#[test]
+fn removing_a_directory_releases_everything_below_it() {
+    let ramfs = scratch();
+    ramfs
+        .create("/tmp", "d", 0o777 | DMDIR, 0, "glenda")
+        .unwrap();
+    ramfs.create("/tmp/d", "f", 0o644, 1, "glenda").unwrap();
+    ramfs.write("/tmp/d/f", 0, b"12345", "glenda").unwrap();
+    assert_eq!(
+        ramfs.user_usage("glenda"),
+        Usage {
+            bytes: 5,
+            inodes: 2
+        }
+    );
+    ramfs.remove("/tmp/d").unwrap();
+    assert_eq!(ramfs.user_usage("glenda"), Usage::default());
+}
+
+#[test]
+fn usage_file_reports_use_and_limits() {
+    let ramfs = scratch();
+    ramfs.set_server_limits(Limits {
+        bytes: Some(1000),
+        inodes: None,
+    });
+    ramfs.create("/tmp", "f", 0o644, 1, "glenda").unwrap();
+    ramfs.write("/tmp/f", 0, b"hello", "glenda").unwrap();
+    ramfs.enable_usage().unwrap();
+
+    assert!(ramfs.walk("/").unwrap().contains(&USAGE_FILE.to_string()));
+    let report = String::from_utf8(ramfs.read("/.usage").unwrap()).unwrap();
+    assert_eq!(report, "total 5 1000 2 -\nglenda 5 - 1 -\nuser 0 - 1 -\n");
+    let inode = ramfs.stat("/.usage").unwrap();
+    assert_eq!(inode.mode, 0o444);
+    assert_eq!(ramfs.metadata("/.usage").unwrap().1, report.len() as u64);
+
+    assert!(ramfs.write("/.usage", 0, b"x", "user").is_none());
+    assert!(ramfs.remove("/.usage").is_none());
+    assert!(ramfs.create("/", USAGE_FILE, 0o644, 1, "user").is_none());
+
+    let taken = RamFs::new();
+    taken.create_file("/.usage", b"mine");
+    assert!(taken.enable_usage().is_none());
+}
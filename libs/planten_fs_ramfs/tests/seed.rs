@@ -0,0 +1,166 @@
+use std::fs::{self, File};
+use std::io::{self, Cursor, ErrorKind};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use planten_fs_core::{DMDIR, FsServer, OWRITE};
+use planten_fs_ramfs::RamFs;
+use tar::{Builder, EntryType, Header};
+
+/// A fresh, empty directory under the host's temporary directory.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ramfs-seed-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn header(kind: EntryType, mode: u32, size: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_mode(mode);
+    header.set_mtime(1_700_000_000);
+    header.set_size(size);
+    header
+}
+
+#[cfg(unix)]
+#[test]
+fn host_directories_keep_modes_and_mtimes() {
+    use std::os::unix::fs::{PermissionsExt, symlink};
+
+    let dir = scratch_dir("host");
+    fs::create_dir_all(dir.join("etc/conf.d")).unwrap();
+    fs::write(dir.join("etc/motd"), b"welcome").unwrap();
+    fs::write(dir.join("etc/conf.d/secret"), b"hush").unwrap();
+    symlink("motd", dir.join("etc/link")).unwrap();
+    fs::set_permissions(
+        dir.join("etc/conf.d/secret"),
+        fs::Permissions::from_mode(0o600),
+    )
+    .unwrap();
+    fs::set_permissions(dir.join("etc/conf.d"), fs::Permissions::from_mode(0o750)).unwrap();
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    File::options()
+        .write(true)
+        .open(dir.join("etc/motd"))
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let ramfs = RamFs::from_dir(&dir, "glenda", "sys").unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(ramfs.read_file("/etc/motd").unwrap(), b"welcome");
+    assert_eq!(ramfs.read_file("/etc/conf.d/secret").unwrap(), b"hush");
+    assert_eq!(ramfs.list_dir("/etc").unwrap(), ["conf.d", "motd"]);
+    let motd = ramfs.stat("/etc/motd").unwrap();
+    assert_eq!((motd.mode, motd.mtime), (0o644, 1_600_000_000));
+    assert_eq!((motd.uid.as_str(), motd.gid.as_str()), ("glenda", "sys"));
+    assert_eq!(ramfs.stat("/etc/conf.d").unwrap().mode, 0o750 | DMDIR);
+    assert_eq!(ramfs.stat("/etc/conf.d/secret").unwrap().mode, 0o600);
+    assert_eq!(ramfs.user_usage("glenda").inodes, 4);
+}
+
+#[test]
+fn seeding_a_file_is_an_error() {
+    let dir = scratch_dir("file");
+    fs::write(dir.join("plain"), b"x").unwrap();
+    let err = RamFs::from_dir(dir.join("plain"), "user", "group")
+        .err()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn tar_export_round_trips() {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+    ramfs
+        .create("/", "docs", 0o750 | DMDIR, 0, "glenda")
+        .unwrap();
+    ramfs
+        .create("/docs", "notes", 0o600, OWRITE, "glenda")
+        .unwrap();
+    ramfs.write("/docs/notes", 0, b"private", "glenda").unwrap();
+    ramfs
+        .create("/docs", "sparse", 0o644, OWRITE, "glenda")
+        .unwrap();
+    ramfs
+        .write("/docs/sparse", 1 << 20, b"end", "glenda")
+        .unwrap();
+
+    let archive = ramfs.write_tar(Vec::new()).unwrap();
+    let restored = RamFs::read_tar(Cursor::new(archive), "user", "group").unwrap();
+
+    assert_eq!(restored.read_file("/hello.txt").unwrap(), b"hello");
+    assert_eq!(restored.read_file("/docs/notes").unwrap(), b"private");
+    let sparse = restored.read_file("/docs/sparse").unwrap();
+    assert_eq!(sparse.len(), (1 << 20) + 3);
+    assert!(sparse[..1 << 20].iter().all(|byte| *byte == 0));
+    for path in ["/", "/hello.txt", "/docs", "/docs/notes", "/docs/sparse"] {
+        let (before, after) = (ramfs.stat(path).unwrap(), restored.stat(path).unwrap());
+        assert_eq!(
+            (before.mode, before.mtime, before.uid, before.gid),
+            (after.mode, after.mtime, after.uid, after.gid),
+            "{}",
+            path
+        );
+    }
+    // The hole is not stored again on the way back in.
+    assert_eq!(restored.usage(), ramfs.usage());
+}
+
+#[test]
+fn tar_archives_fill_in_directories_and_links() {
+    let mut builder = Builder::new(Vec::new());
+    let mut file = header(EntryType::Regular, 0o640, 4);
+    file.set_username("adm").unwrap();
+    builder
+        .append_data(&mut file, "lib/ndb/local", &b"sys\n"[..])
+        .unwrap();
+    // A directory listed after its contents takes its metadata without
+    // losing them.
+    builder
+        .append_data(
+            &mut header(EntryType::Directory, 0o700, 0),
+            "lib/",
+            io::empty(),
+        )
+        .unwrap();
+    let mut link = header(EntryType::Link, 0o640, 0);
+    builder
+        .append_link(&mut link, "lib/ndb/copy", "lib/ndb/local")
+        .unwrap();
+    let mut symlink = header(EntryType::Symlink, 0o777, 0);
+    builder
+        .append_link(&mut symlink, "lib/ndb/alias", "local")
+        .unwrap();
+    let archive = builder.into_inner().unwrap();
+
+    let ramfs = RamFs::read_tar(Cursor::new(archive), "user", "group").unwrap();
+    assert_eq!(ramfs.list_dir("/lib/ndb").unwrap(), ["copy", "local"]);
+    assert_eq!(ramfs.read_file("/lib/ndb/copy").unwrap(), b"sys\n");
+    let lib = ramfs.stat("/lib").unwrap();
+    assert_eq!((lib.mode, lib.mtime), (0o700 | DMDIR, 1_700_000_000));
+    let local = ramfs.stat("/lib/ndb/local").unwrap();
+    assert_eq!((local.uid.as_str(), local.gid.as_str()), ("adm", "group"));
+    assert_eq!(ramfs.stat("/lib/ndb").unwrap().mode, 0o755 | DMDIR);
+}
+
+#[test]
+fn tar_entries_cannot_climb_out_of_the_tree() {
+    // Builder refuses `..` itself, so the name is written into the header.
+    let mut escape = header(EntryType::Regular, 0o644, 0);
+    escape.as_old_mut().name[..9].copy_from_slice(b"../passwd");
+    escape.set_cksum();
+    let mut builder = Builder::new(Vec::new());
+    builder.append(&escape, io::empty()).unwrap();
+    let archive = builder.into_inner().unwrap();
+
+    let err = RamFs::read_tar(Cursor::new(archive), "user", "group")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,139 @@
+use std::io::{Cursor, ErrorKind};
+
+use planten_fs_core::{DMDIR, FsServer, OWRITE};
+use planten_fs_ramfs::RamFs;
+use planten_fs_ramfs::snapshot::SNAPSHOT_VERSION;
+
+fn sample() -> RamFs {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/hello.txt", b"hello");
+    ramfs.create_file("/empty", b"");
+    ramfs
+        .create("/", "docs", 0o750 | DMDIR, 0, "glenda")
+        .unwrap();
+    ramfs
+        .create("/docs", "notes", 0o600, OWRITE, "glenda")
+        .unwrap();
+    ramfs.write("/docs/notes", 0, b"private", "glenda").unwrap();
+    ramfs
+}
+
+fn round_trip(ramfs: &RamFs) -> RamFs {
+    let mut image = Vec::new();
+    ramfs.write_snapshot(&mut image).unwrap();
+    RamFs::read_snapshot(Cursor::new(image)).unwrap()
+}
+
+#[test]
+fn snapshot_round_trips_data_and_metadata() {
+    let original = sample();
+    let restored = round_trip(&original);
+
+    assert_eq!(restored.read_file("/hello.txt").unwrap(), b"hello");
+    assert_eq!(restored.read_file("/empty").unwrap(), b"");
+    assert_eq!(restored.read_file("/docs/notes").unwrap(), b"private");
+    for path in ["/", "/hello.txt", "/docs", "/docs/notes"] {
+        let (before, after) = (original.stat(path).unwrap(), restored.stat(path).unwrap());
+        assert_eq!(
+            (
+                &before.name,
+                before.mode,
+                &before.uid,
+                &before.gid,
+                &before.muid
+            ),
+            (&after.name, after.mode, &after.uid, &after.gid, &after.muid),
+            "{}",
+            path
+        );
+        assert_eq!((before.atime, before.mtime), (after.atime, after.mtime));
+    }
+}
+
+#[test]
+fn snapshot_is_deterministic() {
+    let ramfs = sample();
+    let (mut first, mut second) = (Vec::new(), Vec::new());
+    ramfs.write_snapshot(&mut first).unwrap();
+    round_trip(&ramfs).write_snapshot(&mut second).unwrap();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn save_and_load_through_a_file() {
+    let path = std::env::temp_dir().join(format!("ramfs-snapshot-{}", std::process::id()));
+    sample().save_snapshot(&path).unwrap();
+    let restored = RamFs::load_snapshot(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(restored.read_file("/docs/notes").unwrap(), b"private");
+}
+
+#[test]
+fn rejects_foreign_and_future_images() {
+    let mut image = Vec::new();
+    sample().write_snapshot(&mut image).unwrap();
+
+    let mut foreign = image.clone();
+    foreign[0] = b'X';
+    let err = RamFs::read_snapshot(Cursor::new(foreign)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let mut future = image.clone();
+    future[8..12].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+    let err = RamFs::read_snapshot(Cursor::new(future)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_truncated_images() {
+    let mut image = Vec::new();
+    sample().write_snapshot(&mut image).unwrap();
+    image.truncate(image.len() - 3);
+    let err = RamFs::read_snapshot(Cursor::new(image)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn sparse_files_stay_sparse() {
+    let ramfs = RamFs::new();
+    ramfs.create_file("/sparse", b"");
+    ramfs.write("/sparse", 1 << 40, b"tail", "user").unwrap();
+    let mut image = Vec::new();
+    ramfs.write_snapshot(&mut image).unwrap();
+    assert!(image.len() < 4096);
+
+    let restored = RamFs::read_snapshot(Cursor::new(image)).unwrap();
+    assert_eq!(restored.metadata("/sparse").unwrap().1, (1 << 40) + 4);
+    assert_eq!(
+        restored.read_at("/sparse", (1 << 40) - 2, 8).unwrap(),
+        b"\0\0tail"
+    );
+    assert_eq!(restored.usage(), ramfs.usage());
+}
+
+#[test]
+fn version_one_images_still_load() {
+    fn string(image: &mut Vec<u8>, value: &str) {
+        image.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        image.extend_from_slice(value.as_bytes());
+    }
+    fn node(image: &mut Vec<u8>, name: &str, mode: u32, data: &[u8], children: u32) {
+        string(image, name);
+        image.extend_from_slice(&mode.to_le_bytes());
+        for owner in ["user", "group", "user"] {
+            string(image, owner);
+        }
+        image.extend_from_slice(&[0; 8]);
+        image.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        image.extend_from_slice(data);
+        image.extend_from_slice(&children.to_le_bytes());
+    }
+
+    let mut image = b"RAMFSNAP".to_vec();
+    image.extend_from_slice(&1u32.to_le_bytes());
+    node(&mut image, "/", 0o755 | DMDIR, b"", 1);
+    node(&mut image, "old.txt", 0o644, b"from version one", 0);
+
+    let restored = RamFs::read_snapshot(Cursor::new(image)).unwrap();
+    assert_eq!(restored.read_file("/old.txt").unwrap(), b"from version one");
+}
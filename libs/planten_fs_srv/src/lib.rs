@@ -1,4 +1,4 @@
-use planten_fs_core::{FsServer, Inode};
+use planten_fs_core::{FsServer, Inode, Wstat};
 use std::env;
 use std::fs;
 use std::io;
@@ -108,7 +108,7 @@ impl FsServer for SrvFs {
         }
     }
 
-    fn write(&mut self, path: &str, _offset: u64, data: &[u8]) -> Option<u32> {
+    fn write(&mut self, path: &str, _offset: u64, data: &[u8], _uname: &str) -> Option<u32> {
         let comps: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
         if let [service, file] = comps.as_slice() {
             if SERVICE_FILES.contains(file) && !service.is_empty() {
@@ -131,7 +131,8 @@ impl FsServer for SrvFs {
         let timestamp = now();
         match comps.as_slice() {
             [] => {
-                let mut inode = Inode::new("srv", 0o555 | 0x80000000, "root", "root");
+                // Anyone may post a service, as with Plan 9's #s.
+                let mut inode = Inode::new("srv", 0o777 | 0x80000000, "root", "root");
                 inode.atime = timestamp;
                 inode.mtime = timestamp;
                 Some(inode)
@@ -157,9 +158,33 @@ impl FsServer for SrvFs {
         }
     }
 
-    fn wstat(&mut self, _path: &str, _inode: Inode) -> Option<()> {
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
         None
     }
+
+    /// Posting a service creates its directory under the srv root.
+    fn create(
+        &mut self,
+        parent: &str,
+        name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        let comps: Vec<&str> = parent.split('/').filter(|s| !s.is_empty()).collect();
+        if !comps.is_empty() || name.is_empty() || name == "." || name == ".." {
+            return None;
+        }
+        if name.contains('/') || self.list_services().contains(&name.to_string()) {
+            return None;
+        }
+        fs::create_dir_all(srv_root().join(name)).ok()
+    }
+
+    /// Service files keep nothing written to them, so OTRUNC is a no-op.
+    fn truncate(&mut self, _path: &str, _uname: &str) -> Option<()> {
+        Some(())
+    }
 }
 
 pub mod server;
@@ -168,13 +193,34 @@ pub mod server;
 mod tests {
     use super::*;
     use std::fs;
+    use std::sync::Mutex;
+
+    // Tests share PLANTEN_SRV_ROOT through the process environment.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
 
     #[test]
     fn list_services_dir() {
+        let _guard = ENV_LOCK.lock().unwrap();
         let temp = tempfile::tempdir().unwrap();
         fs::create_dir(temp.path().join("foo")).unwrap();
         env::set_var("PLANTEN_SRV_ROOT", temp.path());
         let srv = SrvFs;
         assert!(srv.list_services().contains(&"foo".to_string()));
     }
+
+    #[test]
+    fn create_posts_service() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        env::set_var("PLANTEN_SRV_ROOT", temp.path());
+        let mut srv = SrvFs;
+        assert!(srv
+            .create("/", "bar", 0o555 | 0x80000000, 0, "glenda")
+            .is_some());
+        assert!(temp.path().join("bar").is_dir());
+        assert!(srv
+            .create("/", "bar", 0o555 | 0x80000000, 0, "glenda")
+            .is_none());
+        assert!(srv.create("/bar", "ctl", 0o444, 0, "glenda").is_none());
+    }
 }
@@ -1,13 +1,15 @@
 use planten_9p::messages::{
-    RATTACH, RERROR, ROPEN, RREAD, RSTAT, RVERSION, RWALK, TCLUNK, TSTAT, TVERSION,
+    RATTACH, RCREATE, RERROR, ROPEN, RREAD, RSTAT, RVERSION, RWALK, TCLUNK, TCREATE, TSTAT,
+    TVERSION,
 };
 use planten_9p::{
     build_frame, decode_string, decode_u16, decode_u32, decode_u64, encode_qid_bytes,
     encode_stat_payload, encode_string, Qid, RawMessage, Stat,
 };
-use planten_fs_core::FsServer;
+use planten_fs_core::perm::{may_create, may_open, may_walk};
+use planten_fs_core::{FsServer, OEXCL};
 use std::collections::HashMap;
-use std::io::{self, Cursor, Write};
+use std::io::{self, Cursor, Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -36,6 +38,7 @@ struct FidState {
     path: String,
     qid: Qid,
     open_mode: Option<u8>,
+    uname: String,
 }
 
 fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()> {
@@ -92,6 +95,7 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()>
                         path: "/".to_string(),
                         qid: root_qid.clone(),
                         open_mode: None,
+                        uname,
                     },
                 );
 
@@ -112,9 +116,17 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()>
                 let fs_locked = fs.lock().unwrap();
                 let mut qids = Vec::new();
                 let mut current_path = fids.get(&fid).map(|f| f.path.clone()).unwrap_or_default();
-                let mut walked_successfully = true;
+                let uname = fids
+                    .get(&fid)
+                    .map(|f| f.uname.clone())
+                    .unwrap_or_else(|| "none".to_string());
+                let mut failure = None;
 
                 for name in names {
+                    if let Err(message) = may_walk(&*fs_locked, &current_path, &uname) {
+                        failure = Some(message);
+                        break;
+                    }
                     let next_path = if current_path == "/" {
                         format!("/{}", name)
                     } else {
@@ -133,12 +145,14 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()>
                         });
                         current_path = next_path;
                     } else {
-                        walked_successfully = false;
+                        failure = Some("file not found");
                         break;
                     }
                 }
 
-                if walked_successfully {
+                if let Some(message) = failure {
+                    Some((RERROR, encode_string(message)))
+                } else {
                     let mut body = Vec::new();
                     body.extend_from_slice(&(qids.len() as u16).to_le_bytes());
                     for qid in &qids {
@@ -154,11 +168,10 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()>
                                 path: 0,
                             }),
                             open_mode: None,
+                            uname,
                         },
                     );
                     Some((RWALK, body))
-                } else {
-                    Some((RERROR, encode_string("file not found")))
                 }
             }
             planten_9p::messages::TOPEN => {
@@ -168,14 +181,15 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()>
 
                 let fs_locked = fs.lock().unwrap();
                 if let Some(fid_state) = fids.get_mut(&fid) {
-                    if let Some(_inode) = fs_locked.stat(&fid_state.path) {
-                        fid_state.open_mode = Some(mode);
-                        let mut body = Vec::new();
-                        body.extend_from_slice(&encode_qid_bytes(&fid_state.qid));
-                        body.extend_from_slice(&MAX_MSG_SIZE.to_le_bytes()); // iounit
-                        Some((ROPEN, body))
-                    } else {
-                        Some((RERROR, encode_string("file not found")))
+                    match may_open(&*fs_locked, &fid_state.path, &fid_state.uname, mode as u32) {
+                        Ok(()) => {
+                            fid_state.open_mode = Some(mode);
+                            let mut body = Vec::new();
+                            body.extend_from_slice(&encode_qid_bytes(&fid_state.qid));
+                            body.extend_from_slice(&MAX_MSG_SIZE.to_le_bytes()); // iounit
+                            Some((ROPEN, body))
+                        }
+                        Err(message) => Some((RERROR, encode_string(message))),
                     }
                 } else {
                     Some((RERROR, encode_string("fid not found")))
@@ -234,6 +248,60 @@ fn handle_client(mut stream: TcpStream, fs: Arc<Mutex<SrvFs>>) -> io::Result<()>
                     Some((RERROR, encode_string("fid not found")))
                 }
             }
+            TCREATE => {
+                let fid = decode_u32(&mut cursor)?;
+                let name = decode_string(&mut cursor)?;
+                let perm = decode_u32(&mut cursor)?;
+                let mut mode = [0u8; 1];
+                cursor.read_exact(&mut mode)?;
+                println!("Tcreate: fid={}, name={}, perm={:#o}", fid, name, perm);
+
+                let mut fs_locked = fs.lock().unwrap();
+                if let Some(fid_state) = fids.get_mut(&fid) {
+                    let parent = fid_state.path.clone();
+                    let new_path = if parent == "/" {
+                        format!("/{}", name)
+                    } else {
+                        format!("{}/{}", parent, name)
+                    };
+                    let writable = may_create(&*fs_locked, &parent, &fid_state.uname).is_ok();
+                    let created = if writable {
+                        fs_locked
+                            .create(
+                                &parent,
+                                &name,
+                                perm,
+                                mode[0] as u32 | OEXCL,
+                                &fid_state.uname,
+                            )
+                            .and_then(|_| fs_locked.stat(&new_path))
+                    } else {
+                        None
+                    };
+                    if let Some(inode) = created {
+                        let qid = Qid {
+                            qtype: if inode.mode & 0x80000000 != 0 {
+                                0x80
+                            } else {
+                                0x00
+                            },
+                            version: 0,
+                            path: 0,
+                        };
+                        fid_state.path = new_path;
+                        fid_state.qid = qid.clone();
+                        fid_state.open_mode = Some(mode[0]);
+                        let mut body = Vec::new();
+                        body.extend_from_slice(&encode_qid_bytes(&qid));
+                        body.extend_from_slice(&MAX_MSG_SIZE.to_le_bytes()); // iounit
+                        Some((RCREATE, body))
+                    } else {
+                        Some((RERROR, encode_string("create failed")))
+                    }
+                } else {
+                    Some((RERROR, encode_string("fid not found")))
+                }
+            }
             TCLUNK => {
                 let fid = decode_u32(&mut cursor)?;
                 println!("Tclunk: fid={}", fid);
@@ -0,0 +1,51 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::{run_server, run_stdio};
+use planten_fs_tar::TarFs;
+
+const LISTEN_ADDR: &str = "127.0.0.1:5671";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_fs_tar_server [--listen host:port | --stdio] [--owner user] [--group group] archive\n\
+         Serves a tar or tar.gz archive read-only; --stdio speaks 9P on standard\n\
+         input and output instead of listening."
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut stdio = false;
+    let mut owner = "user".to_string();
+    let mut group = "group".to_string();
+    let mut archive = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--stdio" => stdio = true,
+            "--owner" => owner = args.next().unwrap_or_else(|| usage()),
+            "--group" => group = args.next().unwrap_or_else(|| usage()),
+            _ if arg.starts_with('-') || archive.is_some() => usage(),
+            _ => archive = Some(arg),
+        }
+    }
+    let archive = archive.unwrap_or_else(|| usage());
+
+    let fs = TarFs::open(&archive, &owner, &group).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {}", archive, err);
+        process::exit(1);
+    });
+    let fs = Arc::new(Mutex::new(fs));
+    if stdio {
+        return run_stdio(fs);
+    }
+    let listener = TcpListener::bind(&listen)?;
+    println!("TarFs 9P server for {} on {}", archive, listen);
+    run_server(listener, fs)
+}
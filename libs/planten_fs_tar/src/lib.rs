@@ -0,0 +1,404 @@
+//! Read-only serving of tar archives, after Plan 9's `tapefs/tarfs`.
+//!
+//! Opening an archive reads every header once and records where each
+//! file's data lies; the data itself is read on demand. Gzip-compressed
+//! archives cannot be read at an offset, so they are decompressed into
+//! memory first. Entries keep the permission bits, owner names and mtimes
+//! of their headers. Directories the archive implies without listing are
+//! mode 0555, belong to the owner given when opening and carry the
+//! newest mtime in the archive. Hard links share their target's data;
+//! symbolic links, devices and sparse files are left out.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::path::{Component, Path};
+
+use flate2::read::MultiGzDecoder;
+use planten_fs_core::{DMDIR, FsServer, Inode, Wstat};
+use tar::{Archive, EntryType};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Mode of directories that only appear as part of a longer path.
+const IMPLIED_DIR_MODE: u32 = DMDIR | 0o555;
+
+/// Where the archive's bytes come from.
+enum Source {
+    File(File),
+    Memory(Vec<u8>),
+}
+
+impl Source {
+    fn len(&self) -> io::Result<u64> {
+        match self {
+            Source::File(file) => Ok(file.metadata()?.len()),
+            Source::Memory(bytes) => Ok(bytes.len() as u64),
+        }
+    }
+
+    /// `len` bytes from `offset`. Sizes come from the archive's headers,
+    /// so they are checked against the archive before anything is
+    /// allocated for them.
+    fn read_at(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let past_end = || invalid("entry runs past the end of the archive");
+        let end = offset.checked_add(len).ok_or_else(past_end)?;
+        if end > self.len()? {
+            return Err(past_end());
+        }
+        let mut data = vec![0; usize::try_from(len).map_err(|_| past_end())?];
+        match self {
+            Source::File(file) => file.read_exact_at(&mut data, offset)?,
+            Source::Memory(bytes) => data.copy_from_slice(&bytes[offset as usize..end as usize]),
+        }
+        Ok(data)
+    }
+}
+
+enum Kind {
+    File { offset: u64, size: u64 },
+    Dir(BTreeMap<String, usize>),
+}
+
+struct Node {
+    name: String,
+    mode: u32,
+    uid: String,
+    gid: String,
+    mtime: u32,
+    kind: Kind,
+    /// Made up for a path component the archive never lists.
+    implied: bool,
+}
+
+pub struct TarFs {
+    source: Source,
+    /// Every entry, the root first; directories name children by index.
+    nodes: Vec<Node>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path))
+}
+
+/// The names along an archive path, which must stay inside the tree.
+fn parts(path: &Path) -> io::Result<Vec<String>> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => parts.push(
+                name.to_str()
+                    .ok_or_else(|| invalid(&format!("{} is not UTF-8", path.display())))?
+                    .to_string(),
+            ),
+            Component::CurDir | Component::RootDir => {}
+            _ => return Err(invalid(&format!("{} leaves the tree", path.display()))),
+        }
+    }
+    Ok(parts)
+}
+
+impl TarFs {
+    /// Indexes the tar or tar.gz archive at `path`, telling the two apart
+    /// by content. `uid` and `gid` own the entries that name no owner and
+    /// the directories the archive implies.
+    pub fn open(path: impl AsRef<Path>, uid: &str, gid: &str) -> io::Result<TarFs> {
+        let mut file = File::open(path)?;
+        let mut magic = [0; 2];
+        let compressed = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+        file.seek(SeekFrom::Start(0))?;
+        if compressed {
+            let mut bytes = Vec::new();
+            MultiGzDecoder::new(BufReader::new(file)).read_to_end(&mut bytes)?;
+            return Self::from_bytes(bytes, uid, gid);
+        }
+        let mut fs = TarFs::empty(Source::File(file.try_clone()?), uid, gid);
+        fs.index(BufReader::new(file), uid, gid)?;
+        Ok(fs)
+    }
+
+    /// Serves an archive already in memory, gzip-compressed or not.
+    pub fn from_bytes(bytes: Vec<u8>, uid: &str, gid: &str) -> io::Result<TarFs> {
+        let bytes = if bytes.starts_with(&GZIP_MAGIC) {
+            let mut plain = Vec::new();
+            MultiGzDecoder::new(&bytes[..]).read_to_end(&mut plain)?;
+            plain
+        } else {
+            bytes
+        };
+        let mut fs = TarFs::empty(Source::Memory(Vec::new()), uid, gid);
+        fs.index(&bytes[..], uid, gid)?;
+        fs.source = Source::Memory(bytes);
+        Ok(fs)
+    }
+
+    fn empty(source: Source, uid: &str, gid: &str) -> TarFs {
+        TarFs {
+            source,
+            nodes: vec![Node {
+                name: "/".to_string(),
+                mode: IMPLIED_DIR_MODE,
+                uid: uid.to_string(),
+                gid: gid.to_string(),
+                mtime: 0,
+                kind: Kind::Dir(BTreeMap::new()),
+                implied: true,
+            }],
+        }
+    }
+
+    fn index(&mut self, reader: impl Read, uid: &str, gid: &str) -> io::Result<()> {
+        let mut newest = 0;
+        for entry in Archive::new(reader).entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            let names = parts(&path)?;
+            let header = entry.header();
+            let owner = |name: Option<&str>, default: &str| {
+                name.filter(|name| !name.is_empty())
+                    .unwrap_or(default)
+                    .to_string()
+            };
+            let mtime = header.mtime()? as u32;
+            newest = newest.max(mtime);
+            let kind = match header.entry_type() {
+                EntryType::Directory => Kind::Dir(BTreeMap::new()),
+                EntryType::Regular | EntryType::Continuous => Kind::File {
+                    offset: entry.raw_file_position(),
+                    size: entry.size(),
+                },
+                EntryType::Link => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| invalid("hard link without a target"))?;
+                    match self.find(&parts(&target)?).map(|at| &self.nodes[at].kind) {
+                        Some(&Kind::File { offset, size }) => Kind::File { offset, size },
+                        _ => {
+                            return Err(invalid(&format!(
+                                "{} links to a missing file",
+                                path.display()
+                            )));
+                        }
+                    }
+                }
+                _ => continue,
+            };
+            let is_dir = matches!(kind, Kind::Dir(_));
+            let node = Node {
+                name: names.last().map_or("/", String::as_str).to_string(),
+                mode: header.mode()? & 0o777 | if is_dir { DMDIR } else { 0 },
+                uid: owner(header.username().ok().flatten(), uid),
+                gid: owner(header.groupname().ok().flatten(), gid),
+                mtime,
+                kind,
+                implied: false,
+            };
+            self.insert(&names, node, uid, gid)?;
+        }
+        for node in self.nodes.iter_mut().filter(|node| node.implied) {
+            node.mtime = newest;
+        }
+        Ok(())
+    }
+
+    /// Puts `node` at `names`, creating implied directories on the way. A
+    /// directory already there keeps its children and takes the new
+    /// metadata; anything else is replaced, as a later member of a tar
+    /// archive replaces an earlier one.
+    fn insert(&mut self, names: &[String], node: Node, uid: &str, gid: &str) -> io::Result<()> {
+        let Some((name, dirs)) = names.split_last() else {
+            let root = &mut self.nodes[0];
+            if matches!(node.kind, Kind::Dir(_)) {
+                (root.mode, root.uid, root.gid) = (node.mode, node.uid, node.gid);
+                (root.mtime, root.implied) = (node.mtime, false);
+            }
+            return Ok(());
+        };
+        let mut parent = 0;
+        for dir in dirs {
+            parent = match self.child(parent, dir) {
+                Some(at) if matches!(self.nodes[at].kind, Kind::Dir(_)) => at,
+                _ => {
+                    let implied = Node {
+                        name: dir.clone(),
+                        mode: IMPLIED_DIR_MODE,
+                        uid: uid.to_string(),
+                        gid: gid.to_string(),
+                        mtime: 0,
+                        kind: Kind::Dir(BTreeMap::new()),
+                        implied: true,
+                    };
+                    self.attach_child(parent, dir, implied)
+                }
+            };
+        }
+        match self.child(parent, name) {
+            Some(at) if matches!(node.kind, Kind::Dir(_)) => {
+                if let Kind::Dir(_) = self.nodes[at].kind {
+                    let old = &mut self.nodes[at];
+                    (old.mode, old.uid, old.gid) = (node.mode, node.uid, node.gid);
+                    (old.mtime, old.implied) = (node.mtime, false);
+                } else {
+                    self.attach_child(parent, name, node);
+                }
+            }
+            _ => {
+                self.attach_child(parent, name, node);
+            }
+        }
+        Ok(())
+    }
+
+    fn attach_child(&mut self, parent: usize, name: &str, node: Node) -> usize {
+        let at = self.nodes.len();
+        self.nodes.push(node);
+        if let Kind::Dir(children) = &mut self.nodes[parent].kind {
+            children.insert(name.to_string(), at);
+        }
+        at
+    }
+
+    fn child(&self, dir: usize, name: &str) -> Option<usize> {
+        match &self.nodes[dir].kind {
+            Kind::Dir(children) => children.get(name).copied(),
+            Kind::File { .. } => None,
+        }
+    }
+
+    fn find(&self, names: &[String]) -> Option<usize> {
+        names.iter().try_fold(0, |at, name| self.child(at, name))
+    }
+
+    fn lookup(&self, path: &str) -> io::Result<usize> {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .try_fold(0, |at, name| self.child(at, name))
+            .ok_or_else(|| not_found(path))
+    }
+
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        match &self.nodes[self.lookup(path)?].kind {
+            Kind::Dir(children) => Ok(children.keys().cloned().collect()),
+            Kind::File { .. } => Err(invalid(&format!("{} is not a directory", path))),
+        }
+    }
+
+    pub fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
+        match self.nodes[self.lookup(path)?].kind {
+            Kind::File { offset, size } => self.source.read_at(offset, size),
+            Kind::Dir(_) => Err(invalid(&format!("{} is a directory", path))),
+        }
+    }
+
+    /// Up to `count` bytes of the file at `path` from `offset`, read from
+    /// the archive without the rest of the file.
+    pub fn read_range(&self, path: &str, offset: u64, count: u64) -> io::Result<Vec<u8>> {
+        match self.nodes[self.lookup(path)?].kind {
+            Kind::File {
+                offset: start,
+                size,
+            } => {
+                let offset = offset.min(size);
+                self.source
+                    .read_at(start.saturating_add(offset), count.min(size - offset))
+            }
+            Kind::Dir(_) => Err(invalid(&format!("{} is a directory", path))),
+        }
+    }
+
+    /// The inode at `path` without its contents, and the file's length.
+    fn metadata(&self, path: &str) -> io::Result<(Inode, u64)> {
+        let node = &self.nodes[self.lookup(path)?];
+        let length = match node.kind {
+            Kind::File { size, .. } => size,
+            Kind::Dir(_) => 0,
+        };
+        let inode = Inode {
+            name: node.name.clone(),
+            data: Vec::new(),
+            children: HashMap::new(),
+            mode: node.mode,
+            uid: node.uid.clone(),
+            gid: node.gid.clone(),
+            atime: node.mtime,
+            mtime: node.mtime,
+            muid: node.uid.clone(),
+        };
+        Ok((inode, length))
+    }
+
+    fn inode(&self, path: &str) -> io::Result<Inode> {
+        let (mut inode, _) = self.metadata(path)?;
+        if !inode.is_dir() {
+            inode.data = self.read_file(path)?;
+        }
+        Ok(inode)
+    }
+}
+
+impl FsServer for TarFs {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.lookup(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.read_file(path).ok()
+    }
+
+    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+        None
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inode(path).ok()
+    }
+
+    fn metadata(&self, path: &str) -> Option<(Inode, u64)> {
+        TarFs::metadata(self, path).ok()
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+
+    fn read_at(&self, path: &str, offset: u64, count: u32) -> Option<Vec<u8>> {
+        self.read_range(path, offset, count as u64).ok()
+    }
+
+    /// An aname picks a directory of the archive to serve as the root,
+    /// which is how `10_ns -p9` mounts part of an archive.
+    fn attach(&self, aname: &str) -> Option<String> {
+        let at = self.lookup(aname).ok()?;
+        matches!(self.nodes[at].kind, Kind::Dir(_)).then(|| {
+            let parts: Vec<&str> = aname.split('/').filter(|part| !part.is_empty()).collect();
+            format!("/{}", parts.join("/"))
+        })
+    }
+}
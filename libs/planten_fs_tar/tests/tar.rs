@@ -0,0 +1,247 @@
+use std::fs;
+use std::io::{Cursor, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use planten_9p::messages::*;
+use planten_9p::server::{qid_from_inode, run_server, serve_io};
+use planten_9p::{
+    P9Client, RawMessage, build_frame, decode_qid, decode_u16, encode_attach_body,
+    encode_open_body, encode_read_body, encode_version_body, encode_walk_body,
+};
+use planten_fs_core::{DMDIR, FsServer, OWRITE, Subtree, Wstat};
+use planten_fs_tar::TarFs;
+use tar::{Builder, EntryType, Header};
+
+fn header(kind: EntryType, mode: u32, mtime: u64, size: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_mode(mode);
+    header.set_mtime(mtime);
+    header.set_size(size);
+    header
+}
+
+/// A release tarball: a listed `usr/` directory, files in implied
+/// directories, a hard link, a symbolic link and a later member that
+/// replaces an earlier one.
+fn archive() -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    let mut add = |path: &str, mut header: Header, data: &[u8]| {
+        builder.append_data(&mut header, path, data).unwrap();
+    };
+    let mut readme = header(EntryType::Regular, 0o644, 1_000, 8);
+    readme.set_username("glenda").unwrap();
+    readme.set_groupname("sys").unwrap();
+    add("README", readme, b"read me\n");
+    add("usr/", header(EntryType::Directory, 0o750, 500, 0), b"");
+    add(
+        "usr/lib/libc.a",
+        header(EntryType::Regular, 0o4755, 2_000, 5),
+        b"libc\n",
+    );
+    add(
+        "usr/lib/old",
+        header(EntryType::Regular, 0o644, 10, 3),
+        b"old",
+    );
+    add(
+        "usr/lib/old",
+        header(EntryType::Regular, 0o600, 20, 3),
+        b"new",
+    );
+    let mut hard = header(EntryType::Link, 0o644, 1_000, 0);
+    hard.set_link_name("README").unwrap();
+    add("hard", hard, b"");
+    let mut soft = header(EntryType::Symlink, 0o777, 1_000, 0);
+    soft.set_link_name("README").unwrap();
+    add("soft", soft, b"");
+    builder.into_inner().unwrap()
+}
+
+fn gzipped(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+fn check_tree(fs: &TarFs) {
+    assert_eq!(sorted(fs.walk("/").unwrap()), vec!["README", "hard", "usr"]);
+    assert_eq!(fs.walk("/usr").unwrap(), vec!["lib"]);
+    assert_eq!(sorted(fs.walk("/usr/lib").unwrap()), vec!["libc.a", "old"]);
+    assert_eq!(fs.read("/README").unwrap(), b"read me\n");
+    assert_eq!(fs.read("/hard").unwrap(), b"read me\n");
+    assert_eq!(fs.read("/usr/lib/old").unwrap(), b"new");
+
+    let readme = fs.stat("/README").unwrap();
+    assert_eq!((readme.mode, readme.mtime), (0o644, 1_000));
+    assert_eq!(
+        (readme.uid.as_str(), readme.gid.as_str()),
+        ("glenda", "sys")
+    );
+    let libc = fs.stat("/usr/lib/libc.a").unwrap();
+    assert_eq!((libc.mode, libc.mtime), (0o755, 2_000));
+    assert_eq!((libc.uid.as_str(), libc.gid.as_str()), ("bootes", "adm"));
+    assert_eq!(fs.stat("/usr/lib/old").unwrap().mode, 0o600);
+
+    let usr = fs.stat("/usr").unwrap();
+    assert_eq!((usr.mode, usr.mtime), (DMDIR | 0o750, 500));
+    // Implied directories take the newest mtime in the archive.
+    let lib = fs.stat("/usr/lib").unwrap();
+    assert_eq!((lib.mode, lib.mtime), (DMDIR | 0o555, 2_000));
+    assert_eq!(fs.stat("/").unwrap().mode, DMDIR | 0o555);
+    assert!(fs.stat("/soft").is_none());
+    assert!(fs.stat("/README/x").is_none());
+}
+
+#[test]
+fn serves_headers_and_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("release.tar");
+    fs::write(&path, archive()).unwrap();
+    let fs = TarFs::open(&path, "bootes", "adm").unwrap();
+    check_tree(&fs);
+}
+
+#[test]
+fn gzipped_archives_serve_the_same_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    // The name does not matter; compression is recognised by content.
+    let path = dir.path().join("release.tar");
+    fs::write(&path, gzipped(&archive())).unwrap();
+    check_tree(&TarFs::open(&path, "bootes", "adm").unwrap());
+    check_tree(&TarFs::from_bytes(gzipped(&archive()), "bootes", "adm").unwrap());
+    check_tree(&TarFs::from_bytes(archive(), "bootes", "adm").unwrap());
+}
+
+#[test]
+fn the_tree_is_read_only() {
+    let mut fs = TarFs::from_bytes(archive(), "bootes", "adm").unwrap();
+    assert!(fs.write("/README", 0, b"x", "glenda").is_none());
+    assert!(fs.create("/", "new", 0o644, OWRITE, "glenda").is_none());
+    assert!(fs.remove("/README").is_none());
+    assert!(fs.wstat("/README", &Wstat::default()).is_none());
+    assert_eq!(fs.read("/README").unwrap(), b"read me\n");
+}
+
+#[test]
+fn members_outside_the_tree_are_refused() {
+    let mut builder = Builder::new(Vec::new());
+    let mut escape = header(EntryType::Regular, 0o644, 0, 0);
+    escape.as_old_mut().name[..9].copy_from_slice(b"../escape");
+    escape.set_cksum();
+    builder.append(&escape, &b""[..]).unwrap();
+    let data = builder.into_inner().unwrap();
+    assert!(TarFs::from_bytes(data, "bootes", "adm").is_err());
+    assert!(TarFs::from_bytes(b"not a tar archive".to_vec(), "bootes", "adm").is_err());
+}
+
+#[test]
+fn entries_past_the_end_of_the_archive_are_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("release.tar");
+    fs::write(&path, archive()).unwrap();
+    let fs = TarFs::open(&path, "bootes", "adm").unwrap();
+    // The archive shrinks under the server; its headers still say more.
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(600)
+        .unwrap();
+    let err = fs.read_file("/usr/lib/libc.a").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(fs.read_range("/usr/lib/libc.a", 2, 100).is_err());
+}
+
+fn send(stream: &mut UnixStream, msg_type: u8, body: Vec<u8>) -> RawMessage {
+    stream.write_all(&build_frame(msg_type, 0, &body)).unwrap();
+    RawMessage::read_from(stream).unwrap()
+}
+
+#[test]
+fn serves_9p_over_a_pair_of_streams() {
+    let fs = TarFs::from_bytes(archive(), "bootes", "adm").unwrap();
+    let expected = [
+        qid_from_inode("/usr", &fs.stat("/usr").unwrap()),
+        qid_from_inode("/usr/lib", &fs.stat("/usr/lib").unwrap()),
+        qid_from_inode("/usr/lib/libc.a", &fs.stat("/usr/lib/libc.a").unwrap()),
+    ];
+    let (mut client, server) = UnixStream::pair().unwrap();
+    let reader = server.try_clone().unwrap();
+    let handle = thread::spawn(move || serve_io(reader, server, Arc::new(Mutex::new(fs))));
+
+    let reply = send(&mut client, TVERSION, encode_version_body(8192, "9P2000"));
+    assert_eq!(reply.msg_type, RVERSION);
+    let reply = send(
+        &mut client,
+        TATTACH,
+        encode_attach_body(1, None, "bootes", ""),
+    );
+    assert_eq!(reply.msg_type, RATTACH);
+    let reply = send(
+        &mut client,
+        TWALK,
+        encode_walk_body(1, 2, &["usr", "lib", "libc.a"]),
+    );
+    assert_eq!(reply.msg_type, RWALK);
+    let mut cursor = Cursor::new(reply.body.as_slice());
+    assert_eq!(decode_u16(&mut cursor).unwrap(), 3);
+    for want in &expected {
+        let qid = decode_qid(&mut cursor).unwrap();
+        assert_eq!(
+            (qid.qtype, qid.version, qid.path),
+            (want.qtype, want.version, want.path)
+        );
+    }
+    assert_eq!(expected[1].qtype, 0x80);
+    assert_eq!(expected[2].version, 2_000);
+
+    assert_eq!(
+        send(&mut client, TOPEN, encode_open_body(2, 0)).msg_type,
+        ROPEN
+    );
+    let reply = send(&mut client, TREAD, encode_read_body(2, 0, 100));
+    assert_eq!(reply.msg_type, RREAD);
+    assert_eq!(&reply.body[4..], b"libc\n");
+
+    drop(client);
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn anames_mount_a_directory_of_the_archive() {
+    let fs = TarFs::from_bytes(archive(), "bootes", "adm").unwrap();
+    assert_eq!(fs.attach("").unwrap(), "/");
+    assert_eq!(fs.attach("usr/lib/").unwrap(), "/usr/lib");
+    assert!(fs.attach("/README").is_none());
+    assert!(fs.attach("/missing").is_none());
+    // Through a subtree, anames are taken within it.
+    let usr = Subtree::new(
+        TarFs::from_bytes(archive(), "bootes", "adm").unwrap(),
+        "/usr",
+    );
+    assert_eq!(usr.attach("").unwrap(), "/");
+    assert_eq!(usr.attach("lib").unwrap(), "/lib");
+    assert!(usr.attach("../README").is_none());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || run_server(listener, Arc::new(Mutex::new(fs))));
+    let mut client = P9Client::new(&addr).unwrap();
+    client.version(8192, "9P2000").unwrap();
+    client.attach(1, None, "glenda", "/usr/lib").unwrap();
+    assert_eq!(client.walk(1, 2, &["libc.a"]).unwrap(), 1);
+    client.open(2, 0).unwrap();
+    assert_eq!(client.read(2, 0, 100).unwrap(), b"libc\n");
+    assert!(client.attach(3, None, "glenda", "/README").is_err());
+}
@@ -0,0 +1,156 @@
+//! A store kept in one append-only log file.
+//!
+//! Format, all integers little-endian: the magic `VTARENA1`, then records
+//! back to back. A record is the `u32` magic `0xd15cb10c`, the block type
+//! byte, its `u32` length, the 20-byte score and the data. Records are
+//! never rewritten, and the index from score to record is rebuilt by
+//! scanning the log when the arena is opened. A record cut short by a
+//! crash, or whose data no longer hashes to its score, ends the log: it
+//! and anything after it are dropped before new records are appended.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::{MAX_BLOCK, SCORE_SIZE, Score, Store, check_size, not_found};
+
+const MAGIC: &[u8; 8] = b"VTARENA1";
+const RECORD_MAGIC: u32 = 0xd15c_b10c;
+/// Record magic, type, length and score.
+const HEADER_SIZE: usize = 4 + 1 + 4 + SCORE_SIZE;
+
+struct Log {
+    file: File,
+    /// Offset and length of each block's data.
+    index: HashMap<(Score, u8), (u64, u32)>,
+    end: u64,
+}
+
+pub struct Arena {
+    log: Mutex<Log>,
+}
+
+impl Arena {
+    /// Opens the arena at `path`, creating an empty one if the file does
+    /// not exist yet.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Arena> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all_at(MAGIC, 0)?;
+            file.sync_all()?;
+        }
+        let mut reader = BufReader::new(&file);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a venti arena",
+            ));
+        }
+
+        let mut index = HashMap::new();
+        let mut end = MAGIC.len() as u64;
+        while let Some((score, btype, len)) = next_record(&mut reader)? {
+            index.insert((score, btype), (end + HEADER_SIZE as u64, len));
+            end += (HEADER_SIZE + len as usize) as u64;
+        }
+        drop(reader);
+        if file.metadata()?.len() > end {
+            file.set_len(end)?;
+            file.sync_all()?;
+        }
+        Ok(Arena {
+            log: Mutex::new(Log { file, index, end }),
+        })
+    }
+
+    /// Number of distinct blocks stored.
+    pub fn len(&self) -> usize {
+        self.log.lock().unwrap().index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of log in use, header included.
+    pub fn size(&self) -> u64 {
+        self.log.lock().unwrap().end
+    }
+}
+
+/// Reads the next whole, intact record, or `None` at the end of the log.
+fn next_record(reader: &mut impl Read) -> io::Result<Option<(Score, u8, u32)>> {
+    let mut header = [0; HEADER_SIZE];
+    if !read_full(reader, &mut header)? {
+        return Ok(None);
+    }
+    let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let btype = header[4];
+    let len = u32::from_le_bytes(header[5..9].try_into().unwrap());
+    if magic != RECORD_MAGIC || len as usize > MAX_BLOCK {
+        return Ok(None);
+    }
+    let score = Score(header[9..].try_into().unwrap());
+    let mut data = vec![0; len as usize];
+    if !read_full(reader, &mut data)? || Score::of(&data) != score {
+        return Ok(None);
+    }
+    Ok(Some((score, btype, len)))
+}
+
+/// Fills `buf`, returning false if the input ends first.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+impl Store for Arena {
+    fn write(&self, btype: u8, data: &[u8]) -> io::Result<Score> {
+        check_size(data)?;
+        let score = Score::of(data);
+        let mut log = self.log.lock().unwrap();
+        if log.index.contains_key(&(score, btype)) {
+            return Ok(score);
+        }
+        let mut record = Vec::with_capacity(HEADER_SIZE + data.len());
+        record.extend_from_slice(&RECORD_MAGIC.to_le_bytes());
+        record.push(btype);
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&score.0);
+        record.extend_from_slice(data);
+        let at = log.end;
+        log.file.write_all_at(&record, at)?;
+        log.end += record.len() as u64;
+        log.index
+            .insert((score, btype), (at + HEADER_SIZE as u64, data.len() as u32));
+        Ok(score)
+    }
+
+    fn read(&self, score: &Score, btype: u8) -> io::Result<Vec<u8>> {
+        let log = self.log.lock().unwrap();
+        let &(at, len) = log
+            .index
+            .get(&(*score, btype))
+            .ok_or_else(|| not_found(score, btype))?;
+        let mut data = vec![0; len as usize];
+        log.file.read_exact_at(&mut data, at)?;
+        Ok(data)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.log.lock().unwrap().file.sync_data()
+    }
+}
@@ -0,0 +1,39 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::Arc;
+
+use planten_venti::arena::Arena;
+use planten_venti::proto::serve;
+
+const LISTEN_ADDR: &str = "127.0.0.1:17034";
+
+fn usage() -> ! {
+    eprintln!("usage: planten_venti_server [--listen host:port] arena");
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            _ if arg.starts_with('-') || path.is_some() => usage(),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let arena = Arena::open(&path)?;
+    let listener = TcpListener::bind(&listen)?;
+    println!(
+        "Venti block server with {} blocks from {} on {}",
+        arena.len(),
+        path,
+        listen
+    );
+    serve(listener, Arc::new(arena))
+}
@@ -0,0 +1,61 @@
+use std::env;
+use std::io;
+use std::process;
+
+use planten_fs_host::HostFs;
+use planten_fs_host::users::UserMap;
+use planten_fs_ramfs::RamFs;
+use planten_venti::Store;
+use planten_venti::arena::Arena;
+use planten_venti::proto::Client;
+use planten_venti::vac::archive;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_vac (--venti host:port | --arena file) [--name name] (--ramfs snapshot | dir)\n\
+         Archives a host directory, or a RAMFS snapshot file, and prints its vac: score."
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let (mut venti, mut arena) = (None, None);
+    let mut name = None;
+    let mut snapshot = None;
+    let mut dir = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--venti" => venti = Some(args.next().unwrap_or_else(|| usage())),
+            "--arena" => arena = Some(args.next().unwrap_or_else(|| usage())),
+            "--name" => name = Some(args.next().unwrap_or_else(|| usage())),
+            "--ramfs" => snapshot = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') || dir.is_some() => usage(),
+            _ => dir = Some(arg),
+        }
+    }
+    let store: Box<dyn Store> = match (venti, arena) {
+        (Some(addr), None) => Box::new(Client::connect(addr)?),
+        (None, Some(path)) => Box::new(Arena::open(path)?),
+        _ => usage(),
+    };
+
+    let score = match (snapshot, dir) {
+        (Some(snapshot), None) => {
+            let fs = RamFs::load_snapshot(&snapshot)?;
+            archive(
+                store.as_ref(),
+                &fs,
+                "/",
+                name.as_deref().unwrap_or(&snapshot),
+            )?
+        }
+        (None, Some(dir)) => {
+            let fs = HostFs::new(&dir, UserMap::system())?;
+            archive(store.as_ref(), &fs, "/", name.as_deref().unwrap_or(&dir))?
+        }
+        _ => usage(),
+    };
+    println!("vac:{}", score);
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use planten_9p::server::run_server;
+use planten_venti::arena::Arena;
+use planten_venti::proto::Client;
+use planten_venti::{Score, Store, VacFs};
+
+const LISTEN_ADDR: &str = "127.0.0.1:5670";
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: planten_vacfs [--listen host:port] (--venti host:port | --arena file) [vac:]score"
+    );
+    process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut listen = LISTEN_ADDR.to_string();
+    let (mut venti, mut arena) = (None, None);
+    let mut score = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--venti" => venti = Some(args.next().unwrap_or_else(|| usage())),
+            "--arena" => arena = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') || score.is_some() => usage(),
+            _ => score = Some(arg),
+        }
+    }
+    let score = score.unwrap_or_else(|| usage());
+    let score: Score = score
+        .strip_prefix("vac:")
+        .unwrap_or(&score)
+        .parse()
+        .unwrap_or_else(|err| {
+            eprintln!("{}: {}", score, err);
+            process::exit(1);
+        });
+    let store: Arc<dyn Store> = match (venti, arena) {
+        (Some(addr), None) => Arc::new(Client::connect(addr)?),
+        (None, Some(path)) => Arc::new(Arena::open(path)?),
+        _ => usage(),
+    };
+
+    let fs = VacFs::open(store, &score)?;
+    let listener = TcpListener::bind(&listen)?;
+    println!(
+        "VacFs 9P server for {} (vac:{}) on {}",
+        fs.root().name,
+        score,
+        listen
+    );
+    run_server(listener, Arc::new(Mutex::new(fs)))
+}
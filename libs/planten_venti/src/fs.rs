@@ -0,0 +1,165 @@
+//! Read-only serving of a vac archive.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use planten_fs_core::{FsServer, Inode, Wstat};
+
+use crate::vac::{self, DirEntry, Root};
+use crate::{Score, Store};
+
+/// Serves the archive named by a root score. Directory listings are
+/// decoded once and kept; file contents are read from the store each time.
+pub struct VacFs<S> {
+    store: S,
+    root: Root,
+    dirs: Mutex<HashMap<String, Arc<Vec<DirEntry>>>>,
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path))
+}
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|part| !part.is_empty()).collect()
+}
+
+impl<S: Store> VacFs<S> {
+    pub fn open(store: S, score: &Score) -> io::Result<Self> {
+        let root = vac::read_root(&store, score)?;
+        if !root.top.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "archive root is not a directory",
+            ));
+        }
+        Ok(VacFs {
+            store,
+            root,
+            dirs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+
+    /// The entries of the directory at `path`, which `dir` describes.
+    fn entries(&self, path: &str, dir: &DirEntry) -> io::Result<Arc<Vec<DirEntry>>> {
+        if let Some(entries) = self.dirs.lock().unwrap().get(path) {
+            return Ok(Arc::clone(entries));
+        }
+        let entries = Arc::new(vac::read_dir(&self.store, dir)?);
+        self.dirs
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), Arc::clone(&entries));
+        Ok(entries)
+    }
+
+    pub fn lookup(&self, path: &str) -> io::Result<DirEntry> {
+        let mut entry = self.root.top.clone();
+        let mut walked = String::from("/");
+        for part in components(path) {
+            if !entry.is_dir() {
+                return Err(not_found(path));
+            }
+            let entries = self.entries(&walked, &entry)?;
+            entry = entries
+                .iter()
+                .find(|child| child.name == part)
+                .cloned()
+                .ok_or_else(|| not_found(path))?;
+            if walked.len() > 1 {
+                walked.push('/');
+            }
+            walked.push_str(part);
+        }
+        Ok(entry)
+    }
+
+    pub fn list(&self, path: &str) -> io::Result<Vec<String>> {
+        let dir = self.lookup(path)?;
+        let path = format!("/{}", components(path).join("/"));
+        Ok(self
+            .entries(&path, &dir)?
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect())
+    }
+
+    pub fn read_file(&self, path: &str) -> io::Result<Vec<u8>> {
+        vac::read_file(&self.store, &self.lookup(path)?)
+    }
+
+    fn inode(&self, path: &str) -> io::Result<Inode> {
+        let entry = self.lookup(path)?;
+        let data = if entry.is_dir() {
+            Vec::new()
+        } else {
+            vac::read_file(&self.store, &entry)?
+        };
+        let name = if components(path).is_empty() {
+            "/".to_string()
+        } else {
+            entry.name
+        };
+        Ok(Inode {
+            name,
+            data,
+            children: HashMap::new(),
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            atime: entry.mtime,
+            mtime: entry.mtime,
+            muid: entry.muid,
+        })
+    }
+}
+
+impl<S: Store> FsServer for VacFs<S> {
+    fn walk(&self, path: &str) -> Option<Vec<String>> {
+        self.list(path).ok()
+    }
+
+    fn open(&self, path: &str) -> Option<()> {
+        self.lookup(path).ok().map(|_| ())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.read_file(path).ok()
+    }
+
+    fn write(&mut self, _path: &str, _offset: u64, _data: &[u8], _uname: &str) -> Option<u32> {
+        None
+    }
+
+    fn clunk(&self, _path: &str) -> Option<()> {
+        Some(())
+    }
+
+    fn remove(&mut self, _path: &str) -> Option<()> {
+        None
+    }
+
+    fn stat(&self, path: &str) -> Option<Inode> {
+        self.inode(path).ok()
+    }
+
+    fn wstat(&mut self, _path: &str, _change: &Wstat) -> Option<()> {
+        None
+    }
+
+    fn create(
+        &mut self,
+        _parent: &str,
+        _name: &str,
+        _perm: u32,
+        _mode: u32,
+        _uname: &str,
+    ) -> Option<()> {
+        None
+    }
+}
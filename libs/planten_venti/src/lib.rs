@@ -0,0 +1,173 @@
+//! Write-once block storage addressed by the SHA-1 of each block, after
+//! Plan 9's Venti, and the vac archive format layered on top of it.
+//!
+//! A block is stored under its score, the hash of its contents, together
+//! with a small type tag. Writing the same bytes twice yields the same
+//! score and stores nothing new, and no block can ever be changed, so a
+//! single score names an immutable tree of blocks. `MemStore` keeps blocks
+//! in memory, `arena::Arena` in an append-only log file, and
+//! `proto::Client` on a remote server speaking the protocol in `proto`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Largest block a store accepts, as in Venti.
+pub const MAX_BLOCK: usize = 56 * 1024;
+pub const SCORE_SIZE: usize = 20;
+
+/// The SHA-1 hash naming a block.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Score(pub [u8; SCORE_SIZE]);
+
+impl Score {
+    pub fn of(data: &[u8]) -> Score {
+        Score(sha1_smol::Sha1::from(data).digest().bytes())
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Score({})", self)
+    }
+}
+
+impl FromStr for Score {
+    type Err = &'static str;
+
+    /// Parses the 40 hex digits `Display` produces.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.len() != 2 * SCORE_SIZE || !text.is_ascii() {
+            return Err("a score is 40 hex digits");
+        }
+        let mut score = [0; SCORE_SIZE];
+        for (i, byte) in score.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)
+                .map_err(|_| "a score is 40 hex digits")?;
+        }
+        Ok(Score(score))
+    }
+}
+
+/// A block store. Blocks are looked up by score and type together, so the
+/// same bytes stored under two types are two blocks.
+pub trait Store: Send + Sync {
+    /// Stores `data` unless an identical block is already present and
+    /// returns its score.
+    fn write(&self, btype: u8, data: &[u8]) -> io::Result<Score>;
+    /// The block `score` of type `btype`; `NotFound` if there is none.
+    fn read(&self, score: &Score, btype: u8) -> io::Result<Vec<u8>>;
+    /// Makes every block written so far durable.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Store + ?Sized> Store for Arc<S> {
+    fn write(&self, btype: u8, data: &[u8]) -> io::Result<Score> {
+        (**self).write(btype, data)
+    }
+
+    fn read(&self, score: &Score, btype: u8) -> io::Result<Vec<u8>> {
+        (**self).read(score, btype)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+impl<S: Store + ?Sized> Store for &S {
+    fn write(&self, btype: u8, data: &[u8]) -> io::Result<Score> {
+        (**self).write(btype, data)
+    }
+
+    fn read(&self, score: &Score, btype: u8) -> io::Result<Vec<u8>> {
+        (**self).read(score, btype)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        (**self).sync()
+    }
+}
+
+/// Error for a block over `MAX_BLOCK`, shared by the stores.
+pub(crate) fn check_size(data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_BLOCK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("block of {} bytes exceeds {}", data.len(), MAX_BLOCK),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn not_found(score: &Score, btype: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no block {} of type {}", score, btype),
+    )
+}
+
+type Blocks = HashMap<(Score, u8), Arc<[u8]>>;
+
+/// A store that lives only as long as the process.
+#[derive(Default)]
+pub struct MemStore {
+    blocks: Mutex<Blocks>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct blocks stored.
+    pub fn len(&self) -> usize {
+        self.blocks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Store for MemStore {
+    fn write(&self, btype: u8, data: &[u8]) -> io::Result<Score> {
+        check_size(data)?;
+        let score = Score::of(data);
+        self.blocks
+            .lock()
+            .unwrap()
+            .entry((score, btype))
+            .or_insert_with(|| data.into());
+        Ok(score)
+    }
+
+    fn read(&self, score: &Score, btype: u8) -> io::Result<Vec<u8>> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .get(&(*score, btype))
+            .map(|data| data.to_vec())
+            .ok_or_else(|| not_found(score, btype))
+    }
+}
+
+pub mod arena;
+pub mod fs;
+pub mod proto;
+pub mod vac;
+
+pub use fs::VacFs;
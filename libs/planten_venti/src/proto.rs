@@ -0,0 +1,202 @@
+//! The block server protocol, a cut-down venti(6) carried in 9P framing:
+//! `size[4] type[1] tag[2]` followed by the body. All integers are
+//! little-endian and strings are 9P strings.
+//!
+//! ```text
+//! Thello  version[s]                     Rhello
+//! Tping                                  Rping
+//! Tread   score[20] type[1] count[4]     Rread   data
+//! Twrite  type[1] data                   Rwrite  score[20]
+//! Tsync                                  Rsync
+//!                                        Rerror  error[s]
+//! ```
+//!
+//! `data` runs to the end of the frame. A client sends Thello first and
+//! waits for each reply before sending the next request.
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use planten_9p::{RawMessage, build_frame, decode_string, decode_u8, decode_u32, encode_string};
+
+use crate::{MAX_BLOCK, SCORE_SIZE, Score, Store};
+
+pub const VERSION: &str = "planten-venti-1";
+
+pub const RERROR: u8 = 1;
+pub const TPING: u8 = 2;
+pub const RPING: u8 = 3;
+pub const THELLO: u8 = 4;
+pub const RHELLO: u8 = 5;
+pub const TREAD: u8 = 12;
+pub const RREAD: u8 = 13;
+pub const TWRITE: u8 = 14;
+pub const RWRITE: u8 = 15;
+pub const TSYNC: u8 = 16;
+pub const RSYNC: u8 = 17;
+
+/// Largest frame either side accepts: a whole block plus headers.
+const MAX_FRAME: u32 = MAX_BLOCK as u32 + 64;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_message(reader: &mut impl Read) -> io::Result<RawMessage> {
+    let msg = RawMessage::read_from(reader)?;
+    if msg.size > MAX_FRAME {
+        return Err(invalid("message too large"));
+    }
+    Ok(msg)
+}
+
+fn read_score(cursor: &mut Cursor<&[u8]>) -> io::Result<Score> {
+    let mut score = [0; SCORE_SIZE];
+    cursor.read_exact(&mut score)?;
+    Ok(Score(score))
+}
+
+/// Serves `store` to every client that connects, one thread each.
+pub fn serve<S: Store + 'static>(listener: TcpListener, store: Arc<S>) -> io::Result<()> {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let store = Arc::clone(&store);
+                thread::spawn(move || {
+                    if let Err(err) = handle_client(stream, store.as_ref()) {
+                        eprintln!("connection error: {}", err);
+                    }
+                });
+            }
+            Err(err) => eprintln!("accept error: {}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Answers one client's requests until it hangs up.
+pub fn handle_client<S: Store + ?Sized>(mut stream: TcpStream, store: &S) -> io::Result<()> {
+    let hello = read_message(&mut stream)?;
+    let version = decode_string(&mut Cursor::new(&hello.body[..]))?;
+    if hello.msg_type != THELLO || version != VERSION {
+        let reply = encode_string(&format!("unsupported version {:?}", version));
+        stream.write_all(&build_frame(RERROR, hello.tag, &reply))?;
+        return Ok(());
+    }
+    stream.write_all(&build_frame(RHELLO, hello.tag, &[]))?;
+
+    loop {
+        let msg = match read_message(&mut stream) {
+            Ok(msg) => msg,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let (kind, body) = match answer(store, &msg) {
+            Ok(reply) => reply,
+            Err(err) => (RERROR, encode_string(&err.to_string())),
+        };
+        stream.write_all(&build_frame(kind, msg.tag, &body))?;
+    }
+}
+
+fn answer<S: Store + ?Sized>(store: &S, msg: &RawMessage) -> io::Result<(u8, Vec<u8>)> {
+    let mut cursor = Cursor::new(&msg.body[..]);
+    match msg.msg_type {
+        TPING => Ok((RPING, Vec::new())),
+        TREAD => {
+            let score = read_score(&mut cursor)?;
+            let btype = decode_u8(&mut cursor)?;
+            let count = decode_u32(&mut cursor)? as usize;
+            let mut data = store.read(&score, btype)?;
+            data.truncate(count);
+            Ok((RREAD, data))
+        }
+        TWRITE => {
+            let btype = decode_u8(&mut cursor)?;
+            let score = store.write(btype, &msg.body[1..])?;
+            Ok((RWRITE, score.0.to_vec()))
+        }
+        TSYNC => {
+            store.sync()?;
+            Ok((RSYNC, Vec::new()))
+        }
+        other => Err(invalid(&format!("unknown message type {}", other))),
+    }
+}
+
+/// A connection to a block server, used as a `Store`.
+pub struct Client {
+    stream: Mutex<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Client> {
+        let client = Client {
+            stream: Mutex::new(TcpStream::connect(addr)?),
+        };
+        client.rpc(THELLO, &encode_string(VERSION))?;
+        Ok(client)
+    }
+
+    pub fn ping(&self) -> io::Result<()> {
+        self.rpc(TPING, &[]).map(|_| ())
+    }
+
+    /// Sends one request and returns the body of its reply, turning
+    /// Rerror into an error.
+    fn rpc(&self, kind: u8, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = self.stream.lock().unwrap();
+        stream.write_all(&build_frame(kind, 0, body))?;
+        let reply = read_message(&mut *stream)?;
+        if reply.msg_type == RERROR {
+            let err = decode_string(&mut Cursor::new(&reply.body[..]))?;
+            let kind = if err.starts_with("no block") {
+                io::ErrorKind::NotFound
+            } else {
+                io::ErrorKind::Other
+            };
+            return Err(io::Error::new(kind, err));
+        }
+        if reply.msg_type != kind + 1 {
+            return Err(invalid("mismatched reply"));
+        }
+        Ok(reply.body)
+    }
+}
+
+impl Store for Client {
+    fn write(&self, btype: u8, data: &[u8]) -> io::Result<Score> {
+        crate::check_size(data)?;
+        let mut body = Vec::with_capacity(1 + data.len());
+        body.push(btype);
+        body.extend_from_slice(data);
+        let reply = self.rpc(TWRITE, &body)?;
+        let score = reply
+            .try_into()
+            .map(Score)
+            .map_err(|_| invalid("short Rwrite"))?;
+        if score != Score::of(data) {
+            return Err(invalid("server returned the wrong score"));
+        }
+        Ok(score)
+    }
+
+    /// Checks the block against its score, so a faulty server cannot
+    /// substitute other data.
+    fn read(&self, score: &Score, btype: u8) -> io::Result<Vec<u8>> {
+        let mut body = score.0.to_vec();
+        body.push(btype);
+        body.extend_from_slice(&(MAX_BLOCK as u32).to_le_bytes());
+        let data = self.rpc(TREAD, &body)?;
+        if Score::of(&data) != *score {
+            return Err(invalid("block does not match its score"));
+        }
+        Ok(data)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.rpc(TSYNC, &[]).map(|_| ())
+    }
+}
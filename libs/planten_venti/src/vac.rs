@@ -0,0 +1,346 @@
+//! The vac archive format: a file tree stored as blocks, named by the
+//! score of a single root block.
+//!
+//! A byte stream (a file's contents, or a directory's encoded entries) is
+//! cut into `BLOCK_SIZE` leaf blocks. A stream of one block is that block;
+//! longer streams get a tree of pointer blocks, each holding up to
+//! `POINTERS` child scores, and an `Entry` records the top score, the
+//! tree depth and the stream's length. As in Venti, a pointer block of
+//! height `h` over leaves of type `t` has type `t + h`.
+//!
+//! A directory's stream is its `DirEntry`s in name order. Each holds the
+//! child's name, owner, group, last modifier, mode, mtime and content
+//! `Entry`. The root block (type `ROOT_TYPE`) holds the magic `vac1`, the
+//! archive's name and the `DirEntry` of the top directory. Integers are
+//! little-endian and strings are 9P strings. Access times are left out,
+//! so archiving an unchanged tree twice gives the same score.
+
+use std::io::{self, Cursor, Read};
+
+use planten_9p::{decode_string, decode_u8, decode_u32, decode_u64, encode_string};
+use planten_fs_core::{DMDIR, FsServer};
+
+use crate::{SCORE_SIZE, Score, Store};
+
+/// Size of the leaf blocks streams are cut into.
+pub const BLOCK_SIZE: usize = 8192;
+/// Scores per pointer block.
+pub const POINTERS: usize = BLOCK_SIZE / SCORE_SIZE;
+/// Deepest pointer tree, which keeps pointer types within their range.
+pub const MAX_DEPTH: u8 = 7;
+
+pub const DATA_TYPE: u8 = 0;
+pub const DIR_TYPE: u8 = 8;
+pub const ROOT_TYPE: u8 = 16;
+
+const ROOT_MAGIC: &[u8; 4] = b"vac1";
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Where a stream's blocks are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub score: Score,
+    /// Height of the pointer tree; 0 when `score` is the only leaf.
+    pub depth: u8,
+    pub size: u64,
+}
+
+impl Entry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.score.0);
+        out.push(self.depth);
+        out.extend_from_slice(&self.size.to_le_bytes());
+    }
+
+    fn decode(cursor: &mut Cursor<&[u8]>) -> io::Result<Entry> {
+        let mut score = [0; SCORE_SIZE];
+        cursor.read_exact(&mut score)?;
+        Ok(Entry {
+            score: Score(score),
+            depth: decode_u8(cursor)?,
+            size: decode_u64(cursor)?,
+        })
+    }
+}
+
+/// One file or directory of an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub uid: String,
+    pub gid: String,
+    pub muid: String,
+    pub mode: u32,
+    pub mtime: u32,
+    pub entry: Entry,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.mode & DMDIR != 0
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for field in [&self.name, &self.uid, &self.gid, &self.muid] {
+            out.extend_from_slice(&encode_string(field));
+        }
+        out.extend_from_slice(&self.mode.to_le_bytes());
+        out.extend_from_slice(&self.mtime.to_le_bytes());
+        self.entry.encode(out);
+    }
+
+    fn decode(cursor: &mut Cursor<&[u8]>) -> io::Result<DirEntry> {
+        Ok(DirEntry {
+            name: decode_string(cursor)?,
+            uid: decode_string(cursor)?,
+            gid: decode_string(cursor)?,
+            muid: decode_string(cursor)?,
+            mode: decode_u32(cursor)?,
+            mtime: decode_u32(cursor)?,
+            entry: Entry::decode(cursor)?,
+        })
+    }
+}
+
+/// The contents of a root block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Root {
+    pub name: String,
+    pub top: DirEntry,
+}
+
+/// Stores `data` as a stream of `leaf_type` blocks.
+pub fn write_stream<S: Store + ?Sized>(store: &S, leaf_type: u8, data: &[u8]) -> io::Result<Entry> {
+    let read = |offset: u64, len| {
+        let start = offset as usize;
+        Some(data[start..start + len].to_vec())
+    };
+    write_stream_with(store, leaf_type, data.len() as u64, read)
+        .map(|entry| entry.expect("every leaf is in data"))
+}
+
+/// Stores a stream of `size` bytes, asking `read` for one leaf at a time
+/// by offset and length, so only a block of it is ever held. The pointer
+/// blocks fill in as the leaves arrive, and the scores come out the same
+/// as `write_stream` on the whole stream. A leaf that cannot be read, or
+/// reads short, abandons the stream and returns `None`.
+pub fn write_stream_with<S, R>(
+    store: &S,
+    leaf_type: u8,
+    size: u64,
+    mut read: R,
+) -> io::Result<Option<Entry>>
+where
+    S: Store + ?Sized,
+    R: FnMut(u64, usize) -> Option<Vec<u8>>,
+{
+    let mut blocks = size.div_ceil(BLOCK_SIZE as u64).max(1);
+    let mut depth = 0;
+    while blocks > 1 {
+        if depth == MAX_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stream too long",
+            ));
+        }
+        depth += 1;
+        blocks = blocks.div_ceil(POINTERS as u64);
+    }
+    let pointers = |level: usize, children: &[Score]| {
+        let block: Vec<u8> = children.iter().flat_map(|score| score.0).collect();
+        store.write(leaf_type + level as u8 + 1, &block)
+    };
+    // Scores waiting for a pointer block, one list per level.
+    let mut levels: Vec<Vec<Score>> = vec![Vec::new(); depth as usize + 1];
+    let mut offset = 0;
+    loop {
+        let len = (size - offset).min(BLOCK_SIZE as u64) as usize;
+        let Some(leaf) = read(offset, len).filter(|leaf| leaf.len() == len) else {
+            return Ok(None);
+        };
+        let mut score = store.write(leaf_type, &leaf)?;
+        offset += len as u64;
+        for level in 0..depth as usize {
+            levels[level].push(score);
+            if levels[level].len() < POINTERS {
+                break;
+            }
+            score = pointers(level, &std::mem::take(&mut levels[level]))?;
+            if level + 1 == depth as usize {
+                levels[depth as usize].push(score);
+            }
+        }
+        if depth == 0 {
+            levels[0].push(score);
+        }
+        if offset == size {
+            break;
+        }
+    }
+    for level in 0..depth as usize {
+        let pending = std::mem::take(&mut levels[level]);
+        if !pending.is_empty() {
+            let score = pointers(level, &pending)?;
+            levels[level + 1].push(score);
+        }
+    }
+    Ok(Some(Entry {
+        score: levels[depth as usize][0],
+        depth,
+        size,
+    }))
+}
+
+/// Reads back a stream written by `write_stream`.
+pub fn read_stream<S: Store + ?Sized>(
+    store: &S,
+    leaf_type: u8,
+    entry: &Entry,
+) -> io::Result<Vec<u8>> {
+    if entry.depth > MAX_DEPTH {
+        return Err(invalid("pointer tree too deep"));
+    }
+    let mut data = Vec::new();
+    read_tree(store, leaf_type, entry.depth, &entry.score, &mut data)?;
+    if data.len() as u64 != entry.size {
+        return Err(invalid("stream length does not match its entry"));
+    }
+    Ok(data)
+}
+
+fn read_tree<S: Store + ?Sized>(
+    store: &S,
+    leaf_type: u8,
+    depth: u8,
+    score: &Score,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    let block = store.read(score, leaf_type + depth)?;
+    if depth == 0 {
+        out.extend_from_slice(&block);
+        return Ok(());
+    }
+    if block.len() % SCORE_SIZE != 0 {
+        return Err(invalid("malformed pointer block"));
+    }
+    for child in block.chunks(SCORE_SIZE) {
+        let child = Score(child.try_into().unwrap());
+        read_tree(store, leaf_type, depth - 1, &child, out)?;
+    }
+    Ok(())
+}
+
+/// The entries of the directory `dir`.
+pub fn read_dir<S: Store + ?Sized>(store: &S, dir: &DirEntry) -> io::Result<Vec<DirEntry>> {
+    if !dir.is_dir() {
+        return Err(invalid("not a directory"));
+    }
+    let data = read_stream(store, DIR_TYPE, &dir.entry)?;
+    let mut cursor = Cursor::new(&data[..]);
+    let mut entries = Vec::new();
+    while (cursor.position() as usize) < data.len() {
+        entries.push(DirEntry::decode(&mut cursor)?);
+    }
+    Ok(entries)
+}
+
+/// The contents of the file `file`.
+pub fn read_file<S: Store + ?Sized>(store: &S, file: &DirEntry) -> io::Result<Vec<u8>> {
+    if file.is_dir() {
+        return Err(invalid("is a directory"));
+    }
+    read_stream(store, DATA_TYPE, &file.entry)
+}
+
+pub fn read_root<S: Store + ?Sized>(store: &S, score: &Score) -> io::Result<Root> {
+    let block = store.read(score, ROOT_TYPE)?;
+    if block.len() < ROOT_MAGIC.len() || &block[..ROOT_MAGIC.len()] != ROOT_MAGIC {
+        return Err(invalid("not a vac root block"));
+    }
+    let mut cursor = Cursor::new(&block[ROOT_MAGIC.len()..]);
+    Ok(Root {
+        name: decode_string(&mut cursor)?,
+        top: DirEntry::decode(&mut cursor)?,
+    })
+}
+
+pub fn write_root<S: Store + ?Sized>(store: &S, root: &Root) -> io::Result<Score> {
+    let mut block = ROOT_MAGIC.to_vec();
+    block.extend_from_slice(&encode_string(&root.name));
+    root.top.encode(&mut block);
+    store.write(ROOT_TYPE, &block)
+}
+
+/// Archives the tree at `path` of `fs` into `store` and returns the score
+/// of its root block. Only blocks the store lacks are written, so
+/// archiving a tree again costs little more than reading it. Entries that
+/// vanish while the walk is under way are left out.
+pub fn archive<S, F>(store: &S, fs: &F, path: &str, name: &str) -> io::Result<Score>
+where
+    S: Store + ?Sized,
+    F: FsServer + ?Sized,
+{
+    let top = archive_node(store, fs, path)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path))
+    })?;
+    let score = write_root(
+        store,
+        &Root {
+            name: name.to_string(),
+            top,
+        },
+    )?;
+    store.sync()?;
+    Ok(score)
+}
+
+fn archive_node<S, F>(store: &S, fs: &F, path: &str) -> io::Result<Option<DirEntry>>
+where
+    S: Store + ?Sized,
+    F: FsServer + ?Sized,
+{
+    let Some((inode, length)) = fs.metadata(path) else {
+        return Ok(None);
+    };
+    let entry = if inode.is_dir() {
+        let Some(mut names) = fs.walk(path) else {
+            return Ok(None);
+        };
+        names.sort();
+        names.dedup();
+        let mut stream = Vec::new();
+        for name in names {
+            let child = format!("{}/{}", path.trim_end_matches('/'), name);
+            if let Some(mut entry) = archive_node(store, fs, &child)? {
+                entry.name = name;
+                entry.encode(&mut stream);
+            }
+        }
+        write_stream(store, DIR_TYPE, &stream)?
+    } else if length <= BLOCK_SIZE as u64 {
+        // Synthetic files often report no length at all; reading them
+        // whole is what finds their contents.
+        let Some(data) = fs.read(path) else {
+            return Ok(None);
+        };
+        write_stream(store, DATA_TYPE, &data)?
+    } else {
+        let read = |offset, len| fs.read_at(path, offset, len as u32);
+        let Some(entry) = write_stream_with(store, DATA_TYPE, length, read)? else {
+            return Ok(None);
+        };
+        entry
+    };
+    Ok(Some(DirEntry {
+        name: inode.name,
+        uid: inode.uid,
+        gid: inode.gid,
+        muid: inode.muid,
+        mode: inode.mode,
+        mtime: inode.mtime,
+        entry,
+    }))
+}
@@ -0,0 +1,131 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use planten_venti::arena::Arena;
+use planten_venti::proto::{Client, serve};
+use planten_venti::{MAX_BLOCK, MemStore, Score, Store};
+
+#[test]
+fn scores_are_sha1_and_round_trip_as_hex() {
+    let empty = Score::of(b"");
+    assert_eq!(
+        empty.to_string(),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+    );
+    assert_eq!(empty.to_string().parse::<Score>(), Ok(empty));
+    assert!("da39".parse::<Score>().is_err());
+    assert!("zz".repeat(20).parse::<Score>().is_err());
+}
+
+#[test]
+fn blocks_are_written_once_and_keyed_by_type() {
+    let store = MemStore::new();
+    let score = store.write(0, b"hello").unwrap();
+    assert_eq!(score, Score::of(b"hello"));
+    assert_eq!(store.write(0, b"hello").unwrap(), score);
+    assert_eq!(store.len(), 1);
+
+    assert_eq!(store.read(&score, 0).unwrap(), b"hello");
+    assert_eq!(
+        store.read(&score, 1).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    store.write(1, b"hello").unwrap();
+    assert_eq!(store.len(), 2);
+
+    let huge = vec![0; MAX_BLOCK + 1];
+    assert_eq!(
+        store.write(0, &huge).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn arenas_keep_blocks_across_opens() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("arena");
+    let arena = Arena::open(&path).unwrap();
+    let a = arena.write(0, b"first").unwrap();
+    let b = arena.write(8, &vec![7; MAX_BLOCK]).unwrap();
+    let size = arena.size();
+    arena.write(0, b"first").unwrap();
+    assert_eq!(arena.size(), size, "a repeated block is not appended");
+    arena.sync().unwrap();
+    drop(arena);
+
+    let arena = Arena::open(&path).unwrap();
+    assert_eq!(arena.len(), 2);
+    assert_eq!(arena.read(&a, 0).unwrap(), b"first");
+    assert_eq!(arena.read(&b, 8).unwrap(), vec![7; MAX_BLOCK]);
+    assert!(arena.read(&a, 8).is_err());
+
+    fs::write(dir.path().join("other"), b"not an arena").unwrap();
+    assert!(Arena::open(dir.path().join("other")).is_err());
+}
+
+#[test]
+fn a_torn_tail_is_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("arena");
+    let arena = Arena::open(&path).unwrap();
+    let kept = arena.write(0, b"kept").unwrap();
+    let size = arena.size();
+    let lost = arena.write(0, b"lost in the crash").unwrap();
+    drop(arena);
+
+    // Cut the second record off in the middle of its data.
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(size + 35).unwrap();
+    drop(file);
+
+    let arena = Arena::open(&path).unwrap();
+    assert_eq!(arena.len(), 1);
+    assert_eq!(arena.size(), size);
+    assert_eq!(arena.read(&kept, 0).unwrap(), b"kept");
+    assert!(arena.read(&lost, 0).is_err());
+    let again = arena.write(0, b"after").unwrap();
+    drop(arena);
+
+    // Records appended after recovery are readable, and garbage at the
+    // end is ignored.
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(b"garbage")
+        .unwrap();
+    let arena = Arena::open(&path).unwrap();
+    assert_eq!(arena.len(), 2);
+    assert_eq!(arena.read(&again, 0).unwrap(), b"after");
+}
+
+#[test]
+fn clients_use_a_remote_store() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store = Arc::new(MemStore::new());
+    let served = Arc::clone(&store);
+    thread::spawn(move || serve(listener, served));
+
+    let client = Client::connect(addr).unwrap();
+    client.ping().unwrap();
+    let score = client.write(3, b"over the wire").unwrap();
+    assert_eq!(score, Score::of(b"over the wire"));
+    assert_eq!(store.read(&score, 3).unwrap(), b"over the wire");
+    assert_eq!(client.read(&score, 3).unwrap(), b"over the wire");
+    assert_eq!(
+        client.read(&score, 4).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    client.sync().unwrap();
+
+    let big = vec![1; MAX_BLOCK];
+    let score = client.write(0, &big).unwrap();
+    assert_eq!(client.read(&score, 0).unwrap(), big);
+    // A second client sees the same blocks.
+    let other = Client::connect(addr).unwrap();
+    assert_eq!(other.read(&score, 0).unwrap(), big);
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use planten_fs_core::{DMDIR, FsServer, OWRITE, Wstat};
+use planten_fs_host::HostFs;
+use planten_fs_host::users::UserMap;
+use planten_fs_ramfs::RamFs;
+use planten_venti::arena::Arena;
+use planten_venti::proto::{Client, serve};
+use planten_venti::vac::{
+    BLOCK_SIZE, DATA_TYPE, POINTERS, archive, read_root, read_stream, write_stream,
+    write_stream_with,
+};
+use planten_venti::{MemStore, VacFs};
+
+fn tree() -> RamFs {
+    let fs = RamFs::new();
+    fs.create_file("/README", b"read me\n");
+    fs.create_file("/src/main.rs", b"fn main() {}\n");
+    fs.create_file("/src/lib/empty", b"");
+    fs.create_dir("/doc");
+    fs
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+#[test]
+fn streams_of_every_depth_read_back() {
+    let store = MemStore::new();
+    let cases = [0, 1, BLOCK_SIZE, BLOCK_SIZE + 1, BLOCK_SIZE * POINTERS + 5];
+    for len in cases {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let entry = write_stream(&store, DATA_TYPE, &data).unwrap();
+        assert_eq!(entry.size, len as u64);
+        let depth = match len {
+            n if n <= BLOCK_SIZE => 0,
+            n if n <= BLOCK_SIZE * POINTERS => 1,
+            _ => 2,
+        };
+        assert_eq!(entry.depth, depth, "{} bytes", len);
+        assert_eq!(read_stream(&store, DATA_TYPE, &entry).unwrap(), data);
+    }
+}
+
+#[test]
+fn streams_read_a_leaf_at_a_time_match_whole_ones() {
+    let store = MemStore::new();
+    let data: Vec<u8> = (0..BLOCK_SIZE * POINTERS + 5)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let mut reads = 0;
+    let read = |offset: u64, len: usize| {
+        reads += 1;
+        assert!(len <= BLOCK_SIZE);
+        Some(data[offset as usize..offset as usize + len].to_vec())
+    };
+    let entry = write_stream_with(&store, DATA_TYPE, data.len() as u64, read).unwrap();
+    assert_eq!(Some(write_stream(&store, DATA_TYPE, &data).unwrap()), entry);
+    assert_eq!(reads, POINTERS + 1);
+    // A leaf that reads short abandons the stream.
+    let short = write_stream_with(&store, DATA_TYPE, 100, |_, _| Some(vec![0; 10]));
+    assert_eq!(short.unwrap(), None);
+
+    // Archives read large files the same way, holes and all.
+    let ramfs = RamFs::new();
+    ramfs.create_file("/sparse", b"");
+    let hole = (BLOCK_SIZE * POINTERS) as u64;
+    ramfs.write("/sparse", hole, b"end", "user").unwrap();
+    let store = Arc::new(MemStore::new());
+    let score = archive(&store, &ramfs, "/", "sparse").unwrap();
+    let fs = VacFs::open(Arc::clone(&store), &score).unwrap();
+    assert_eq!(fs.metadata("/sparse").unwrap().1, hole + 3);
+    assert_eq!(fs.read_at("/sparse", hole - 2, 8).unwrap(), b"\0\0end");
+}
+
+#[test]
+fn archives_serve_the_tree_read_only() {
+    let ramfs = tree();
+    let store = Arc::new(MemStore::new());
+    let score = archive(&store, &ramfs, "/", "tree").unwrap();
+    let mut fs = VacFs::open(Arc::clone(&store), &score).unwrap();
+    assert_eq!(fs.root().name, "tree");
+
+    assert_eq!(sorted(fs.walk("/").unwrap()), vec!["README", "doc", "src"]);
+    assert_eq!(sorted(fs.walk("/src").unwrap()), vec!["lib", "main.rs"]);
+    assert!(fs.walk("/doc").unwrap().is_empty());
+    assert_eq!(fs.read("/README").unwrap(), b"read me\n");
+    assert_eq!(fs.read("/src/lib/empty").unwrap(), b"");
+    assert!(fs.read("/src").is_none());
+    assert!(fs.stat("/README/x").is_none());
+    assert!(fs.stat("/missing").is_none());
+
+    for path in ["/", "/src", "/src/main.rs"] {
+        let original = ramfs.stat(path).unwrap();
+        let archived = fs.stat(path).unwrap();
+        assert_eq!(archived.mode, original.mode, "{}", path);
+        assert_eq!(archived.mtime, original.mtime, "{}", path);
+        assert_eq!(archived.uid, original.uid, "{}", path);
+        assert_eq!(archived.gid, original.gid, "{}", path);
+        let length = FsServer::metadata(&ramfs, path).unwrap().1;
+        assert_eq!(fs.metadata(path).unwrap().1, length, "{}", path);
+    }
+    assert_eq!(fs.read("/src/main.rs"), ramfs.read_file("/src/main.rs"));
+    assert_eq!(fs.stat("/").unwrap().mode & DMDIR, DMDIR);
+    assert_eq!(fs.stat("/src/main.rs").unwrap().name, "main.rs");
+
+    assert!(fs.write("/README", 0, b"x", "user").is_none());
+    assert!(fs.create("/", "new", 0o644, OWRITE, "user").is_none());
+    assert!(fs.remove("/README").is_none());
+    assert!(fs.wstat("/README", &Wstat::default()).is_none());
+}
+
+#[test]
+fn unchanged_trees_share_blocks_and_scores() {
+    let ramfs = tree();
+    let store = MemStore::new();
+    let first = archive(&store, &ramfs, "/", "tree").unwrap();
+    let blocks = store.len();
+    assert_eq!(archive(&store, &ramfs, "/", "tree").unwrap(), first);
+    assert_eq!(store.len(), blocks);
+
+    // Changing one file adds its block and new blocks for the directories
+    // above it, but the rest of the tree is shared.
+    ramfs.write("/src/main.rs", 0, b"//", "user").unwrap();
+    let second = archive(&store, &ramfs, "/", "tree").unwrap();
+    assert_ne!(second, first);
+    assert_eq!(store.len(), blocks + 4);
+
+    let old = VacFs::open(&store, &first).unwrap();
+    let new = VacFs::open(&store, &second).unwrap();
+    assert_eq!(old.read("/src/main.rs").unwrap(), b"fn main() {}\n");
+    assert_eq!(new.read("/src/main.rs").unwrap(), b"// main() {}\n");
+    assert_eq!(
+        read_root(&store, &second).unwrap().top.entry,
+        new.lookup("/").unwrap().entry
+    );
+}
+
+#[test]
+fn host_trees_archive_through_a_server() {
+    let dir = tempfile::tempdir().unwrap();
+    let export = dir.path().join("export");
+    fs::create_dir_all(export.join("bin")).unwrap();
+    let big: Vec<u8> = (0..3 * BLOCK_SIZE + 17).map(|i| (i % 13) as u8).collect();
+    fs::write(export.join("bin/tool"), &big).unwrap();
+    fs::write(export.join("VERSION"), "1.0\n").unwrap();
+    let host = HostFs::new(&export, UserMap::default()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let arena = Arc::new(Arena::open(dir.path().join("arena")).unwrap());
+    let served = Arc::clone(&arena);
+    thread::spawn(move || serve(listener, served));
+
+    let client = Client::connect(addr).unwrap();
+    let score = archive(&client, &host, "/", "export").unwrap();
+    let fs = VacFs::open(client, &score).unwrap();
+    assert_eq!(sorted(fs.walk("/").unwrap()), vec!["VERSION", "bin"]);
+    assert_eq!(fs.read("/bin/tool").unwrap(), big);
+    assert_eq!(
+        fs.stat("/VERSION").unwrap().mode,
+        host.stat("/VERSION").unwrap().mode
+    );
+
+    // The arena alone is enough to serve the archive again.
+    let fs = VacFs::open(arena, &score).unwrap();
+    assert_eq!(fs.read("/VERSION").unwrap(), b"1.0\n");
+}
+
+#[test]
+fn scores_that_are_not_archives_are_refused() {
+    let store = MemStore::new();
+    let entry = write_stream(&store, DATA_TYPE, b"plain data").unwrap();
+    assert!(VacFs::open(&store, &entry.score).is_err());
+    assert!(archive(&store, &tree(), "/missing", "x").is_err());
+}
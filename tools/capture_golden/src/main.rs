@@ -2,16 +2,17 @@ use std::fs::{self, File};
 use std::io::{self, Cursor, Write};
 use std::net::{TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 
+use planten_9p::server;
 use planten_9p::{
     build_frame, decode_stat, encode_attach_body, encode_auth_body, encode_clone_body,
     encode_flush_body, encode_open_body, encode_read_body, encode_remove_body, encode_stat_body,
     encode_version_body, encode_walk_body, encode_write_body, encode_wstat_body, messages::*,
     RawMessage,
 };
-use planten_fs_ramfs::{server, RamFs};
+use planten_fs_ramfs::RamFs;
 
 struct TraceRecorder<'a> {
     stream: &'a mut TcpStream,
@@ -57,12 +58,12 @@ fn main() -> io::Result<()> {
     let listener = TcpListener::bind("127.0.0.1:0")?;
     let addr = listener.local_addr()?;
 
-    let ramfs = Arc::new(Mutex::new({
-        let mut base = RamFs::new();
+    let ramfs = Arc::new({
+        let base = RamFs::new();
         base.create_file("/hello.txt", b"hello 9p!!");
         base.create_file("/readme.txt", b"RAMFS as a 9P server");
         base
-    }));
+    });
 
     let server_ramfs = Arc::clone(&ramfs);
     thread::spawn(move || server::run_server(listener, server_ramfs).unwrap());